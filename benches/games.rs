@@ -0,0 +1,68 @@
+//! Runs the bundled games on a fixed sequence of keys with each execution engine: decoding
+//! every step, the decode cache, basic blocks, and (with `--features jit`) native code.
+//!
+//! `cargo bench --bench games`
+
+use lc3_vm_rust::bench::{Engine, Runner};
+use lc3_vm_rust::console::SharedConsole;
+use lc3_vm_rust::vm::{Vm, VmError};
+use std::time::{Duration, Instant};
+
+const ROUNDS: usize = 5;
+
+// Each run feeds the same keys and ends when the game waits for more, or after the step
+// budget: 2048 polls the keyboard instead of blocking in GETC.
+const GAMES: &[(&str, &[u8], u64)] = &[
+    ("assets/2048.obj", b"wasdwasdwasdwasd", 20_000_000),
+    ("assets/rogue.obj", b"ddddssssddddwwwwaaaassss", 20_000_000),
+];
+
+fn run(image: &str, keys: &[u8], budget: u64, engine: Engine) -> (u64, Duration) {
+    let console = SharedConsole::new();
+    let mut lc3 = Vm::with_console(console.clone());
+    lc3.read_image_file(image).unwrap();
+    console.push_input(keys);
+
+    let mut runner = Runner::new(engine, &mut lc3);
+    let start = Instant::now();
+    match runner.run(&mut lc3, budget) {
+        Ok(_) | Err(VmError::WaitingForInput) => {}
+        Err(e) => panic!("{}: {}", image, e),
+    }
+    (runner.instructions(), start.elapsed())
+}
+
+fn best_of(image: &str, keys: &[u8], budget: u64, engine: Engine) -> (u64, Duration) {
+    (0..ROUNDS)
+        .map(|_| run(image, keys, budget, engine))
+        .min_by_key(|(_, elapsed)| *elapsed)
+        .unwrap()
+}
+
+fn main() {
+    for &(image, keys, budget) in GAMES {
+        let results: Vec<(u64, Duration)> = Engine::ALL
+            .iter()
+            .map(|&engine| best_of(image, keys, budget, engine))
+            .collect();
+        let (steps, plain) = results[0];
+        if steps < budget {
+            assert!(
+                results.iter().all(|(n, _)| *n == steps),
+                "{} executed a different number of instructions per engine",
+                image
+            );
+        }
+
+        let mips = |&(steps, elapsed): &(u64, Duration)| steps as f64 / elapsed.as_secs_f64() / 1e6;
+        println!("{} ({} instructions)", image, steps);
+        for (engine, result) in Engine::ALL.iter().zip(&results) {
+            println!(
+                "  {:<8} {:>7.1} MIPS  {:.2}x",
+                engine.name(),
+                mips(result),
+                plain.as_secs_f64() / result.1.as_secs_f64()
+            );
+        }
+    }
+}
@@ -0,0 +1,547 @@
+//! Ahead-of-time translation of an image into a standalone Rust program.
+//!
+//! Code is discovered statically from the origin by following branches, calls and the
+//! return points after them. Every block found becomes a Rust function. The generated
+//! program carries its own interpreter for everything else: code only reached through
+//! computed jumps (`JMP`, `JSRR`, `RET`) until it rejoins a known block, and blocks whose
+//! words the program overwrote. The result only needs `rustc` to build.
+
+use crate::instruction::Instruction;
+use crate::vm::Register;
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+
+/// Generated program and the start address of every block translated into it.
+pub struct Translation {
+    pub source: String,
+    pub blocks: Vec<u16>,
+}
+
+struct Image<'a> {
+    origin: u16,
+    words: &'a [u16],
+}
+
+impl Image<'_> {
+    fn word(&self, addr: u16) -> Option<u16> {
+        self.words
+            .get(addr.wrapping_sub(self.origin) as usize)
+            .copied()
+    }
+
+    /// Addresses where execution can start a block: the origin, branch and call targets,
+    /// and the instructions control returns to after calls and traps.
+    fn leaders(&self) -> BTreeSet<u16> {
+        let mut leaders = BTreeSet::new();
+        let mut pending = vec![self.origin];
+        while let Some(start) = pending.pop() {
+            if self.word(start).is_none() || !leaders.insert(start) {
+                continue;
+            }
+            let mut pc = start;
+            while let Some(word) = self.word(pc) {
+                let next = pc.wrapping_add(1);
+                match Instruction::decode(word) {
+                    Instruction::Br { nzp: 0, .. } => {}
+                    Instruction::Br { nzp: 0x7, offset } => {
+                        pending.push(next.wrapping_add(offset));
+                        break;
+                    }
+                    Instruction::Br { offset, .. } | Instruction::Jsr { offset } => {
+                        pending.extend([next.wrapping_add(offset), next]);
+                        break;
+                    }
+                    Instruction::Jsrr { .. } => {
+                        pending.push(next);
+                        break;
+                    }
+                    // Whatever follows HALT is usually data.
+                    Instruction::Trap { vector } => {
+                        if vector != 0x25 {
+                            pending.push(next);
+                        }
+                        break;
+                    }
+                    Instruction::Jmp { .. } | Instruction::Rti | Instruction::Res => break,
+                    _ => {}
+                }
+                pc = next;
+            }
+        }
+        leaders
+    }
+}
+
+/// Translates the image loaded at `origin`. `name` is only mentioned in the header.
+pub fn translate(origin: u16, words: &[u16], name: &str) -> Translation {
+    let image = Image { origin, words };
+    let leaders = image.leaders();
+
+    let mut blocks = Vec::new();
+    let mut functions = String::new();
+    for &start in &leaders {
+        // Blocks starting with RTI or a reserved opcode are left to the interpreter.
+        if let Some((len, code)) = emit_block(&image, &leaders, start, blocks.len()) {
+            blocks.push((start, len));
+            let _ = writeln!(
+                functions,
+                "\nfn block_x{:04x}(m: &mut Machine) -> bool {{",
+                start
+            );
+            functions.push_str(&code);
+            functions.push_str("}\n");
+        }
+    }
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "// Generated by `lc3-vm-rust translate` from {}. Build with `rustc -O`.",
+        name
+    );
+    let _ = writeln!(
+        out,
+        "// {} block(s) translated; anything else runs on the interpreter below.",
+        blocks.len()
+    );
+    out.push_str(RUNTIME);
+
+    let _ = writeln!(out, "\nconst ORIGIN: u16 = 0x{:04X};\n", origin);
+    let _ = writeln!(out, "static IMAGE: [u16; {}] = [", words.len());
+    for line in words.chunks(8) {
+        let line: Vec<String> = line.iter().map(|w| format!("0x{:04X},", w)).collect();
+        let _ = writeln!(out, "    {}", line.join(" "));
+    }
+    out.push_str("];\n");
+
+    out.push_str("\n// Start address and length of every translated block.\n");
+    let _ = writeln!(out, "static BLOCKS: [(u16, u16); {}] = [", blocks.len());
+    for (start, len) in &blocks {
+        let _ = writeln!(out, "    (0x{:04X}, {}),", start, len);
+    }
+    out.push_str("];\n");
+
+    out.push_str("\nfn run_block(m: &mut Machine) -> Option<bool> {\n    Some(match m.pc {\n");
+    for (id, (start, _)) in blocks.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "        0x{:04X} if m.valid[{}] => block_x{:04x}(m),",
+            start, id, start
+        );
+    }
+    out.push_str("        _ => return None,\n    })\n}\n");
+    out.push_str(&functions);
+
+    Translation {
+        source: out,
+        blocks: blocks.into_iter().map(|(start, _)| start).collect(),
+    }
+}
+
+// Length and function body of block number `id`, which starts at `start`. The function
+// returns whether the machine stopped.
+fn emit_block(
+    image: &Image,
+    leaders: &BTreeSet<u16>,
+    start: u16,
+    id: usize,
+) -> Option<(u16, String)> {
+    let r = |reg: Register| reg as usize;
+    let mut code = String::new();
+    let mut pc = start;
+    let mut len = 0;
+    while let Some(word) = image.word(pc) {
+        let instr = Instruction::decode(word);
+        let next = pc.wrapping_add(1);
+        let target = |offset: u16| next.wrapping_add(offset);
+        let statement = match instr {
+            Instruction::Rti | Instruction::Res => break,
+            Instruction::AddReg { dst, src1, src2 } => format!(
+                "m.set({}, m.r[{}].wrapping_add(m.r[{}]));",
+                r(dst),
+                r(src1),
+                r(src2)
+            ),
+            Instruction::AddImm { dst, src1, imm } => format!(
+                "m.set({}, m.r[{}].wrapping_add(0x{:04X}));",
+                r(dst),
+                r(src1),
+                imm
+            ),
+            Instruction::AndReg { dst, src1, src2 } => {
+                format!("m.set({}, m.r[{}] & m.r[{}]);", r(dst), r(src1), r(src2))
+            }
+            Instruction::AndImm { dst, src1, imm } => {
+                format!("m.set({}, m.r[{}] & 0x{:04X});", r(dst), r(src1), imm)
+            }
+            Instruction::Not { dst, src } => format!("m.set({}, !m.r[{}]);", r(dst), r(src)),
+            Instruction::Lea { dst, offset } => {
+                format!("m.set({}, 0x{:04X});", r(dst), target(offset))
+            }
+            Instruction::Ld { dst, offset } => format!(
+                "let value = m.read(0x{:04X});\n    m.set({}, value);",
+                target(offset),
+                r(dst)
+            ),
+            Instruction::Ldi { dst, offset } => format!(
+                "let addr = m.read(0x{:04X});\n    let value = m.read(addr);\n    \
+                 m.set({}, value);",
+                target(offset),
+                r(dst)
+            ),
+            Instruction::Ldr { dst, base, offset } => format!(
+                "let value = m.read(m.r[{}].wrapping_add(0x{:04X}));\n    m.set({}, value);",
+                r(base),
+                offset,
+                r(dst)
+            ),
+            Instruction::St { src, offset } => store(
+                format!("m.write(0x{:04X}, m.r[{}]);", target(offset), r(src)),
+                id,
+                next,
+            ),
+            Instruction::Sti { src, offset } => store(
+                format!(
+                    "let addr = m.read(0x{:04X});\n    m.write(addr, m.r[{}]);",
+                    target(offset),
+                    r(src)
+                ),
+                id,
+                next,
+            ),
+            Instruction::Str { src, base, offset } => store(
+                format!(
+                    "m.write(m.r[{}].wrapping_add(0x{:04X}), m.r[{}]);",
+                    r(base),
+                    offset,
+                    r(src)
+                ),
+                id,
+                next,
+            ),
+            Instruction::Br { nzp: 0, .. } => String::new(),
+            Instruction::Br { nzp: 0x7, offset } => {
+                format!("m.pc = 0x{:04X};\n    false", target(offset))
+            }
+            Instruction::Br { nzp, offset } => format!(
+                "m.pc = if m.cond & {} != 0 {{ 0x{:04X} }} else {{ 0x{:04X} }};\n    false",
+                nzp,
+                target(offset),
+                next
+            ),
+            Instruction::Jmp { base } => format!("m.pc = m.r[{}];\n    false", r(base)),
+            Instruction::Jsr { offset } => format!(
+                "m.r[7] = 0x{:04X};\n    m.pc = 0x{:04X};\n    false",
+                next,
+                target(offset)
+            ),
+            // PC is taken from the base before R7 is written, as in the emulator.
+            Instruction::Jsrr { base } => format!(
+                "m.pc = m.r[{}];\n    m.r[7] = 0x{:04X};\n    false",
+                r(base),
+                next
+            ),
+            Instruction::Trap { vector } => format!(
+                "m.r[7] = 0x{:04X};\n    m.pc = 0x{:04X};\n    m.trap(0x{:02X})",
+                next, next, vector
+            ),
+        };
+        let _ = writeln!(code, "    // x{:04X}  {}", pc, instr.disassemble(pc, None));
+        if !statement.is_empty() {
+            let _ = writeln!(code, "    {}", statement);
+        }
+        len += 1;
+        pc = next;
+
+        let ends = matches!(
+            instr,
+            Instruction::Br { nzp: 1..=7, .. }
+                | Instruction::Jmp { .. }
+                | Instruction::Jsr { .. }
+                | Instruction::Jsrr { .. }
+                | Instruction::Trap { .. }
+        );
+        if ends {
+            return Some((len, code));
+        }
+        if leaders.contains(&pc) {
+            break;
+        }
+    }
+    if len == 0 {
+        return None;
+    }
+    let _ = writeln!(code, "    m.pc = 0x{:04X};\n    false", pc);
+    Some((len, code))
+}
+
+// A store may overwrite the block it is in; the rest of it then goes to the interpreter.
+fn store(statement: String, id: usize, next: u16) -> String {
+    format!(
+        "{}\n    if !m.valid[{}] {{\n        m.pc = 0x{:04X};\n        return false;\n    }}",
+        statement, id, next
+    )
+}
+
+// Machine state, devices and the fallback interpreter, shared by every generated program.
+// They follow `Vm` and its standard console: same trap output, same fault messages.
+const RUNTIME: &str = r#"
+use std::io::{IsTerminal, Read, Write};
+use std::process::Command;
+use std::sync::mpsc::{self, Receiver};
+
+const KBSR: u16 = 0xFE00;
+const KBDR: u16 = 0xFE02;
+const FLAG_P: u16 = 1;
+const FLAG_Z: u16 = 2;
+const FLAG_N: u16 = 4;
+
+struct Machine {
+    mem: Vec<u16>,
+    r: [u16; 8],
+    pc: u16,
+    cond: u16,
+    // Index + 1 of the translated block holding each address, 0 elsewhere.
+    owner: Vec<u32>,
+    // Cleared when a block's code is overwritten; it is interpreted from then on.
+    valid: Vec<bool>,
+    keys: Receiver<u8>,
+}
+
+fn sext(value: u16, bits: u32) -> u16 {
+    (((value << (16 - bits)) as i16) >> (16 - bits)) as u16
+}
+
+// Prints why the machine stopped, the way the emulator does.
+fn fault(message: &str) -> bool {
+    println!("{}", message);
+    true
+}
+
+impl Machine {
+    fn new() -> Self {
+        let mut mem = vec![0; 1 << 16];
+        for (i, word) in IMAGE.iter().enumerate() {
+            mem[ORIGIN.wrapping_add(i as u16) as usize] = *word;
+        }
+        let mut owner = vec![0; 1 << 16];
+        for (i, &(start, len)) in BLOCKS.iter().enumerate() {
+            for k in 0..len {
+                owner[start.wrapping_add(k) as usize] = i as u32 + 1;
+            }
+        }
+        // Keys arrive on a channel so KBSR can be polled without blocking.
+        let (sender, keys) = mpsc::channel();
+        std::thread::spawn(move || {
+            for key in std::io::stdin().lock().bytes().map_while(Result::ok) {
+                if sender.send(key).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            mem,
+            r: [0; 8],
+            pc: ORIGIN,
+            cond: FLAG_Z,
+            owner,
+            valid: vec![true; BLOCKS.len()],
+            keys,
+        }
+    }
+
+    #[inline]
+    fn set(&mut self, reg: usize, value: u16) {
+        self.r[reg] = value;
+        self.cond = if value == 0 {
+            FLAG_Z
+        } else if value >> 15 == 1 {
+            FLAG_N
+        } else {
+            FLAG_P
+        };
+    }
+
+    #[inline]
+    fn read(&mut self, addr: u16) -> u16 {
+        if addr == KBSR {
+            match self.keys.try_recv() {
+                Ok(key) => {
+                    self.mem[KBSR as usize] = 1 << 15;
+                    self.mem[KBDR as usize] = key as u16;
+                }
+                Err(_) => self.mem[KBSR as usize] = 0,
+            }
+        }
+        self.mem[addr as usize]
+    }
+
+    #[inline]
+    fn write(&mut self, addr: u16, value: u16) {
+        let owner = self.owner[addr as usize];
+        if owner != 0 && self.mem[addr as usize] != value {
+            self.valid[owner as usize - 1] = false;
+        }
+        self.mem[addr as usize] = value;
+    }
+
+    fn output(&mut self, bytes: &[u8]) {
+        let mut stdout = std::io::stdout();
+        let _ = stdout.write_all(bytes);
+        let _ = stdout.flush();
+    }
+
+    // R7 and PC are already set. Returns whether the machine stopped.
+    fn trap(&mut self, vector: u16) -> bool {
+        match vector {
+            0x20 => match self.keys.recv() {
+                Ok(key) => self.r[0] = key as u16,
+                Err(_) => return fault("I/O error: unexpected end of file"),
+            },
+            0x21 => {
+                let char = self.r[0] as u8;
+                self.output(&[char]);
+            }
+            0x22 => {
+                let mut addr = self.r[0];
+                let mut out = Vec::new();
+                loop {
+                    let char = self.read(addr);
+                    if char == 0 {
+                        break;
+                    }
+                    out.push(char as u8);
+                    addr = addr.wrapping_add(1);
+                }
+                self.output(&out);
+            }
+            0x23 => {
+                self.output(b"Enter a character: ");
+                match self.keys.recv() {
+                    Ok(key) => {
+                        self.output(&[key]);
+                        self.r[0] = key as u16;
+                    }
+                    Err(_) => return fault("I/O error: unexpected end of file"),
+                }
+            }
+            0x24 => {
+                let mut addr = self.r[0];
+                let mut out = Vec::new();
+                loop {
+                    let word = self.read(addr);
+                    if word == 0 {
+                        break;
+                    }
+                    out.push(word as u8);
+                    if word >> 8 != 0 {
+                        out.push((word >> 8) as u8);
+                    }
+                    addr = addr.wrapping_add(1);
+                }
+                self.output(&out);
+            }
+            0x25 => {
+                self.output(b"HALT\n");
+                return true;
+            }
+            _ => return fault(&format!("trap not implemented: 0x{:02X}", vector)),
+        }
+        false
+    }
+
+    // Runs one instruction the way the emulator does. Returns whether the machine stopped.
+    fn step(&mut self) -> bool {
+        let instr = self.read(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        let dst = ((instr >> 9) & 7) as usize;
+        let src1 = ((instr >> 6) & 7) as usize;
+        let src2 = (instr & 7) as usize;
+        let pc_offset = self.pc.wrapping_add(sext(instr & 0x1FF, 9));
+        match instr >> 12 {
+            0x0 => {
+                if self.cond & (dst as u16) != 0 {
+                    self.pc = pc_offset;
+                }
+            }
+            0x1 | 0x5 => {
+                let operand = if instr & 0x20 != 0 {
+                    sext(instr & 0x1F, 5)
+                } else {
+                    self.r[src2]
+                };
+                let value = if instr >> 12 == 0x1 {
+                    self.r[src1].wrapping_add(operand)
+                } else {
+                    self.r[src1] & operand
+                };
+                self.set(dst, value);
+            }
+            0x2 => {
+                let value = self.read(pc_offset);
+                self.set(dst, value);
+            }
+            0x3 => self.write(pc_offset, self.r[dst]),
+            0x4 => {
+                // The base is read before R7 is written, like the emulator.
+                let target = if instr & 0x800 != 0 {
+                    self.pc.wrapping_add(sext(instr & 0x7FF, 11))
+                } else {
+                    self.r[src1]
+                };
+                self.r[7] = self.pc;
+                self.pc = target;
+            }
+            0x6 => {
+                let value = self.read(self.r[src1].wrapping_add(sext(instr & 0x3F, 6)));
+                self.set(dst, value);
+            }
+            0x7 => self.write(self.r[src1].wrapping_add(sext(instr & 0x3F, 6)), self.r[dst]),
+            0x8 => return fault("Opcode Rti is unused or reserved"),
+            0x9 => self.set(dst, !self.r[src1]),
+            0xA => {
+                let addr = self.read(pc_offset);
+                let value = self.read(addr);
+                self.set(dst, value);
+            }
+            0xB => {
+                let addr = self.read(pc_offset);
+                self.write(addr, self.r[dst]);
+            }
+            0xC => self.pc = self.r[src1],
+            0xD => return fault("Opcode Res is unused or reserved"),
+            0xE => self.set(dst, pc_offset),
+            _ => {
+                self.r[7] = self.pc;
+                return self.trap(instr & 0xFF);
+            }
+        }
+        false
+    }
+}
+
+fn stty(args: &[&str]) {
+    let _ = Command::new("stty").args(args).status();
+}
+
+fn main() {
+    // Keys reach the program as they are typed, like in the emulator.
+    let terminal = std::io::stdin().is_terminal();
+    if terminal {
+        stty(&["-icanon", "-echo"]);
+    }
+    let mut m = Machine::new();
+    loop {
+        let stopped = match run_block(&mut m) {
+            Some(stopped) => stopped,
+            None => m.step(),
+        };
+        if stopped {
+            break;
+        }
+    }
+    if terminal {
+        stty(&["icanon", "echo"]);
+    }
+}
+"#;
@@ -0,0 +1,476 @@
+//! Two-pass LC-3 assembler.
+//!
+//! Accepts the usual textbook syntax (`.ORIG`, `.FILL`, `.BLKW`, `.STRINGZ`, `.END`, trap
+//! aliases, `RET`) and, besides the image, records where every label and instruction came
+//! from so debuggers and reports can map addresses back to the source.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assembly {
+    pub origin: u16,
+    pub words: Vec<u16>,
+    pub symbols: BTreeMap<String, u16>,
+    /// 1-based source line of every instruction (not data), keyed by address.
+    pub lines: BTreeMap<u16, usize>,
+}
+
+impl Assembly {
+    /// Serializes the image in the big-endian `.obj` format read by `Vm::read_image_file`.
+    pub fn to_obj_bytes(&self) -> Vec<u8> {
+        std::iter::once(self.origin)
+            .chain(self.words.iter().copied())
+            .flat_map(u16::to_be_bytes)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+}
+
+struct Statement {
+    line: usize,
+    address: u16,
+    mnemonic: String,
+    operands: Vec<Token>,
+}
+
+pub fn assemble(source: &str) -> Result<Assembly, AsmError> {
+    let mut origin = None;
+    let mut address = 0u16;
+    let mut symbols = BTreeMap::new();
+    let mut statements = Vec::new();
+
+    // Pass 1: assign addresses to labels and statements.
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let error = |message: String| AsmError { line, message };
+        let mut tokens = tokenize(text).map_err(|m| error(m.to_string()))?;
+        if tokens.is_empty() {
+            continue;
+        }
+
+        if let Token::Word(first) = &tokens[0]
+            && !is_mnemonic(first)
+        {
+            let label = first.trim_end_matches(':').to_string();
+            if origin.is_none() {
+                return Err(error(format!("Label {} before .ORIG", label)));
+            }
+            if symbols.insert(label.clone(), address).is_some() {
+                return Err(error(format!("Duplicate label {}", label)));
+            }
+            tokens.remove(0);
+        }
+
+        let Some(first) = tokens.first() else {
+            continue;
+        };
+        let Token::Word(mnemonic) = first else {
+            return Err(error("Expected an instruction or directive".to_string()));
+        };
+        let mnemonic = mnemonic.to_uppercase();
+        let operands = tokens[1..].to_vec();
+
+        match mnemonic.as_str() {
+            ".ORIG" => {
+                if origin.is_some() {
+                    return Err(error("Only one .ORIG block is supported".to_string()));
+                }
+                let value = match operands.as_slice() {
+                    [Token::Word(w)] => parse_number(w),
+                    _ => None,
+                };
+                let value = value.ok_or_else(|| error(".ORIG expects an address".to_string()))?;
+                address = value as u16;
+                origin = Some(address);
+                continue;
+            }
+            ".END" => break,
+            _ if origin.is_none() => {
+                return Err(error(format!("{} before .ORIG", mnemonic)));
+            }
+            _ => {}
+        }
+
+        let size = match mnemonic.as_str() {
+            ".BLKW" => match operands.as_slice() {
+                [Token::Word(w)] => parse_number(w)
+                    .filter(|n| (0..=0xFFFF).contains(n))
+                    .ok_or_else(|| error(".BLKW expects a word count".to_string()))?
+                    as u16,
+                _ => return Err(error(".BLKW expects a word count".to_string())),
+            },
+            ".STRINGZ" => match operands.as_slice() {
+                [Token::Str(s)] => s.chars().count() as u16 + 1,
+                _ => return Err(error(".STRINGZ expects a string".to_string())),
+            },
+            _ => 1,
+        };
+
+        statements.push(Statement {
+            line,
+            address,
+            mnemonic,
+            operands,
+        });
+        address = address.wrapping_add(size);
+    }
+
+    let origin = origin.ok_or(AsmError {
+        line: source.lines().count(),
+        message: "Missing .ORIG".to_string(),
+    })?;
+
+    // Pass 2: encode.
+    let mut words = Vec::new();
+    let mut lines = BTreeMap::new();
+    for statement in &statements {
+        let encoded = encode(statement, &symbols).map_err(|message| AsmError {
+            line: statement.line,
+            message,
+        })?;
+        if !statement.mnemonic.starts_with('.') {
+            lines.insert(statement.address, statement.line);
+        }
+        words.extend(encoded);
+    }
+
+    Ok(Assembly {
+        origin,
+        words,
+        symbols,
+        lines,
+    })
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, &'static str> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut word = String::new();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => break,
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        None => return Err("Unterminated string"),
+                        Some('"') => break,
+                        Some('\\') => s.push(match chars.next() {
+                            Some('n') => '\n',
+                            Some('t') => '\t',
+                            Some('r') => '\r',
+                            Some('e') => '\x1b',
+                            Some('0') => '\0',
+                            Some('"') => '"',
+                            Some('\\') => '\\',
+                            _ => return Err("Invalid escape in string"),
+                        }),
+                        Some(c) => s.push(c),
+                    }
+                }
+                tokens.push(Token::Str(s));
+            }
+            c if c.is_whitespace() || c == ',' => {
+                if !word.is_empty() {
+                    tokens.push(Token::Word(std::mem::take(&mut word)));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(Token::Word(word));
+    }
+    Ok(tokens)
+}
+
+fn branch_flags(mnemonic: &str) -> Option<u16> {
+    let flags = mnemonic.strip_prefix("BR")?;
+    if flags.is_empty() {
+        return Some(0x7);
+    }
+    let mut bits = 0;
+    for c in flags.chars() {
+        let bit = match c {
+            'N' => 0x4,
+            'Z' => 0x2,
+            'P' => 0x1,
+            _ => return None,
+        };
+        if bits & bit != 0 {
+            return None;
+        }
+        bits |= bit;
+    }
+    Some(bits)
+}
+
+fn is_mnemonic(word: &str) -> bool {
+    let upper = word.to_uppercase();
+    branch_flags(&upper).is_some()
+        || matches!(
+            upper.as_str(),
+            "ADD"
+                | "AND"
+                | "NOT"
+                | "JMP"
+                | "JSR"
+                | "JSRR"
+                | "LD"
+                | "LDI"
+                | "LDR"
+                | "LEA"
+                | "ST"
+                | "STI"
+                | "STR"
+                | "TRAP"
+                | "RET"
+                | "RTI"
+                | "GETC"
+                | "OUT"
+                | "PUTS"
+                | "IN"
+                | "PUTSP"
+                | "HALT"
+                | ".ORIG"
+                | ".FILL"
+                | ".BLKW"
+                | ".STRINGZ"
+                | ".END"
+        )
+}
+
+fn parse_number(word: &str) -> Option<i32> {
+    let (negative, digits) = match word.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, word),
+    };
+    let value = if let Some(dec) = digits.strip_prefix('#') {
+        return parse_number(dec).map(|n| if negative { -n } else { n });
+    } else if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix('x'))
+        .or_else(|| digits.strip_prefix('X'))
+    {
+        i32::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits
+        .strip_prefix('b')
+        .or_else(|| digits.strip_prefix('B'))
+    {
+        i32::from_str_radix(bin, 2).ok()?
+    } else {
+        digits.parse::<i32>().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn register(token: &Token) -> Result<u16, String> {
+    if let Token::Word(w) = token
+        && let [b'R' | b'r', digit @ b'0'..=b'7'] = w.as_bytes()
+    {
+        return Ok((digit - b'0') as u16);
+    }
+    Err(format!("Expected a register, found {}", describe(token)))
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Word(w) => w.clone(),
+        Token::Str(s) => format!("\"{}\"", s),
+    }
+}
+
+/// Encodes `value` into a `bits`-wide field, accepting both signed and unsigned spellings.
+fn field(value: i32, bits: u32) -> Result<u16, String> {
+    let min = -(1 << (bits - 1));
+    let max = (1 << bits) - 1;
+    if value < min || value > max {
+        return Err(format!("Value {} does not fit in {} bits", value, bits));
+    }
+    Ok((value as u16) & ((1 << bits) - 1) as u16)
+}
+
+fn signed_field(value: i32, bits: u32) -> Result<u16, String> {
+    let min = -(1 << (bits - 1));
+    let max = (1 << (bits - 1)) - 1;
+    if value < min || value > max {
+        return Err(format!("Offset {} does not fit in {} bits", value, bits));
+    }
+    Ok((value as u16) & ((1 << bits) - 1) as u16)
+}
+
+fn immediate(token: &Token, bits: u32) -> Result<u16, String> {
+    match token {
+        Token::Word(w) => match parse_number(w) {
+            Some(value) => field(value, bits),
+            None => Err(format!("Expected a number, found {}", w)),
+        },
+        Token::Str(_) => Err(format!("Expected a number, found {}", describe(token))),
+    }
+}
+
+/// A label or literal offset relative to the incremented PC.
+fn pc_offset(
+    token: &Token,
+    address: u16,
+    bits: u32,
+    symbols: &BTreeMap<String, u16>,
+) -> Result<u16, String> {
+    let Token::Word(w) = token else {
+        return Err(format!("Expected a label, found {}", describe(token)));
+    };
+    if let Some(target) = symbols.get(w.as_str()) {
+        let offset = *target as i32 - (address as i32 + 1);
+        return signed_field(offset, bits);
+    }
+    match parse_number(w) {
+        Some(offset) => signed_field(offset, bits),
+        None => Err(format!("Undefined label {}", w)),
+    }
+}
+
+fn encode(statement: &Statement, symbols: &BTreeMap<String, u16>) -> Result<Vec<u16>, String> {
+    let ops = &statement.operands;
+    let address = statement.address;
+    let mnemonic = statement.mnemonic.as_str();
+    let expect = |count: usize| {
+        if ops.len() == count {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} expects {} operand(s), found {}",
+                mnemonic,
+                count,
+                ops.len()
+            ))
+        }
+    };
+
+    if let Some(flags) = branch_flags(mnemonic) {
+        expect(1)?;
+        return Ok(vec![flags << 9 | pc_offset(&ops[0], address, 9, symbols)?]);
+    }
+
+    let word = match mnemonic {
+        "ADD" | "AND" => {
+            expect(3)?;
+            let opcode = if mnemonic == "ADD" { 0x1 } else { 0x5 };
+            let base = opcode << 12 | register(&ops[0])? << 9 | register(&ops[1])? << 6;
+            match register(&ops[2]) {
+                Ok(src2) => base | src2,
+                Err(_) => base | 1 << 5 | immediate(&ops[2], 5)?,
+            }
+        }
+        "NOT" => {
+            expect(2)?;
+            0x9 << 12 | register(&ops[0])? << 9 | register(&ops[1])? << 6 | 0x3F
+        }
+        "JMP" => {
+            expect(1)?;
+            0xC << 12 | register(&ops[0])? << 6
+        }
+        "RET" => {
+            expect(0)?;
+            0xC1C0
+        }
+        "JSR" => {
+            expect(1)?;
+            0x4 << 12 | 1 << 11 | pc_offset(&ops[0], address, 11, symbols)?
+        }
+        "JSRR" => {
+            expect(1)?;
+            0x4 << 12 | register(&ops[0])? << 6
+        }
+        "LD" | "LDI" | "LEA" | "ST" | "STI" => {
+            expect(2)?;
+            let opcode = match mnemonic {
+                "LD" => 0x2,
+                "LDI" => 0xA,
+                "LEA" => 0xE,
+                "ST" => 0x3,
+                _ => 0xB,
+            };
+            opcode << 12 | register(&ops[0])? << 9 | pc_offset(&ops[1], address, 9, symbols)?
+        }
+        "LDR" | "STR" => {
+            expect(3)?;
+            let opcode = if mnemonic == "LDR" { 0x6 } else { 0x7 };
+            let offset = match &ops[2] {
+                Token::Word(w) => parse_number(w)
+                    .ok_or_else(|| format!("Expected an offset, found {}", w))
+                    .and_then(|n| signed_field(n, 6))?,
+                token => return Err(format!("Expected an offset, found {}", describe(token))),
+            };
+            opcode << 12 | register(&ops[0])? << 9 | register(&ops[1])? << 6 | offset
+        }
+        "TRAP" => {
+            expect(1)?;
+            let vector = immediate(&ops[0], 8)?;
+            0xF000 | vector
+        }
+        "RTI" => {
+            expect(0)?;
+            0x8000
+        }
+        "GETC" | "OUT" | "PUTS" | "IN" | "PUTSP" | "HALT" => {
+            expect(0)?;
+            let vector = match mnemonic {
+                "GETC" => 0x20,
+                "OUT" => 0x21,
+                "PUTS" => 0x22,
+                "IN" => 0x23,
+                "PUTSP" => 0x24,
+                _ => 0x25,
+            };
+            0xF000 | vector
+        }
+        ".FILL" => {
+            expect(1)?;
+            match &ops[0] {
+                Token::Word(w) => match symbols.get(w.as_str()) {
+                    Some(target) => *target,
+                    None => parse_number(w)
+                        .ok_or_else(|| format!("Expected a value, found {}", w))
+                        .and_then(|n| field(n, 16))?,
+                },
+                token => return Err(format!("Expected a value, found {}", describe(token))),
+            }
+        }
+        ".BLKW" => {
+            let count = match &ops[0] {
+                Token::Word(w) => parse_number(w).unwrap_or(0) as usize,
+                Token::Str(_) => 0,
+            };
+            return Ok(vec![0; count]);
+        }
+        ".STRINGZ" => {
+            let Token::Str(s) = &ops[0] else {
+                return Err(".STRINGZ expects a string".to_string());
+            };
+            return Ok(s.chars().map(|c| c as u16).chain([0]).collect());
+        }
+        _ => return Err(format!("Unknown instruction {}", mnemonic)),
+    };
+    Ok(vec![word])
+}
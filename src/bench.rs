@@ -0,0 +1,237 @@
+//! Synthetic workloads for measuring the emulator's speed, and a runner that drives a
+//! `Vm` with any of its execution engines.
+
+use crate::asm;
+use crate::blocks::BlockEngine;
+use crate::console::SharedConsole;
+use crate::json::Value;
+use crate::json_object;
+use crate::vm::{Vm, VmError};
+use std::time::{Duration, Instant};
+
+/// A way of executing programs, from plain decoding to native code.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Engine {
+    /// `Vm::step`, decoding every instruction.
+    Decode,
+    /// `Vm::step` with the decode cache, as on the command line.
+    Cache,
+    /// `BlockEngine`.
+    Blocks,
+    #[cfg(feature = "jit")]
+    Jit,
+}
+
+impl Engine {
+    pub const ALL: &[Engine] = &[
+        Engine::Decode,
+        Engine::Cache,
+        Engine::Blocks,
+        #[cfg(feature = "jit")]
+        Engine::Jit,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Engine::Decode => "decode",
+            Engine::Cache => "cache",
+            Engine::Blocks => "blocks",
+            #[cfg(feature = "jit")]
+            Engine::Jit => "jit",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Engine> {
+        Engine::ALL.iter().copied().find(|e| e.name() == name)
+    }
+}
+
+/// Runs a `Vm` with one engine and counts the instructions it executed.
+pub struct Runner {
+    engine: Engine,
+    blocks: Option<BlockEngine>,
+    instructions: u64,
+}
+
+impl Runner {
+    /// Sets up `vm` for `engine`.
+    pub fn new(engine: Engine, vm: &mut Vm) -> Self {
+        vm.set_decode_cache(engine != Engine::Decode);
+        let blocks = match engine {
+            Engine::Decode | Engine::Cache => None,
+            Engine::Blocks => Some(BlockEngine::new()),
+            #[cfg(feature = "jit")]
+            Engine::Jit => Some(BlockEngine::with_jit()),
+        };
+        Self {
+            engine,
+            blocks,
+            instructions: 0,
+        }
+    }
+
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    /// Instructions executed so far, including by runs that failed.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Executes `budget` more instructions, or until the program halts (`Ok(true)`) or
+    /// fails. Every engine stops at the same instruction, even inside a block.
+    pub fn run(&mut self, vm: &mut Vm, budget: u64) -> Result<bool, VmError> {
+        let end = self.instructions + budget;
+        match &mut self.blocks {
+            None => {
+                while self.instructions < end {
+                    let step = vm.step()?;
+                    self.instructions += 1;
+                    if step.halted {
+                        return Ok(true);
+                    }
+                }
+            }
+            Some(blocks) => {
+                while self.instructions < end {
+                    let result = blocks.run(vm, end - self.instructions);
+                    self.instructions = blocks.instructions();
+                    if result?.halted {
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+        Ok(false)
+    }
+}
+
+pub struct Workload {
+    pub name: &'static str,
+    pub description: &'static str,
+    source: &'static str,
+}
+
+/// Every workload loops forever, without traps, so any instruction count can be run.
+pub const WORKLOADS: &[Workload] = &[
+    Workload {
+        name: "arith",
+        description: "ALU operations and a conditional branch",
+        source: "
+        .ORIG x3000
+        AND R0, R0, #0
+LOOP    ADD R1, R1, #7
+        ADD R2, R1, R1
+        AND R3, R2, #15
+        NOT R4, R3
+        ADD R5, R4, R2
+        ADD R0, R0, #-1
+        BRp LOOP
+        ADD R0, R0, #10
+        BRnzp LOOP
+        .END
+",
+    },
+    Workload {
+        name: "memcpy",
+        description: "copies a 64-word buffer with LDR/STR",
+        source: "
+        .ORIG x3000
+OUTER   LEA R1, SRC
+        LEA R2, DST
+        LD R3, COUNT
+COPY    LDR R4, R1, #0
+        STR R4, R2, #0
+        ADD R1, R1, #1
+        ADD R2, R2, #1
+        ADD R3, R3, #-1
+        BRp COPY
+        BRnzp OUTER
+COUNT   .FILL #64
+SRC     .BLKW #64
+DST     .BLKW #64
+        .END
+",
+    },
+    Workload {
+        name: "calls",
+        description: "recursive subroutine calls with a stack",
+        source: "
+        .ORIG x3000
+MAIN    LD R6, STACK
+        AND R0, R0, #0
+        ADD R0, R0, #12
+        JSR SUM
+        BRnzp MAIN
+; R0 = n + (n - 1) + ... + 1, one call per term.
+SUM     ADD R6, R6, #-2
+        STR R7, R6, #0
+        STR R1, R6, #1
+        ADD R1, R0, #0
+        BRz DONE
+        ADD R0, R0, #-1
+        JSR SUM
+        ADD R0, R0, R1
+DONE    LDR R1, R6, #1
+        LDR R7, R6, #0
+        ADD R6, R6, #2
+        RET
+STACK   .FILL xF000
+        .END
+",
+    },
+];
+
+impl Workload {
+    pub fn find(name: &str) -> Option<&'static Workload> {
+        WORKLOADS.iter().find(|w| w.name == name)
+    }
+
+    /// A fresh machine with the workload loaded and nothing attached to its console.
+    pub fn load(&self) -> Vm {
+        let program = asm::assemble(self.source).expect("Workloads assemble");
+        let mut vm = Vm::with_console(SharedConsole::new());
+        vm.load_program(program.origin, &program.words);
+        vm
+    }
+
+    /// Runs about `instructions` instructions of the workload on `engine`.
+    pub fn measure(&self, engine: Engine, instructions: u64) -> Result<Measurement, VmError> {
+        let mut vm = self.load();
+        let mut runner = Runner::new(engine, &mut vm);
+        let start = Instant::now();
+        runner.run(&mut vm, instructions)?;
+        Ok(Measurement {
+            workload: self.name,
+            engine,
+            instructions: runner.instructions(),
+            elapsed: start.elapsed(),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Measurement {
+    pub workload: &'static str,
+    pub engine: Engine,
+    pub instructions: u64,
+    pub elapsed: Duration,
+}
+
+impl Measurement {
+    /// Millions of instructions per second.
+    pub fn mips(&self) -> f64 {
+        self.instructions as f64 / self.elapsed.as_secs_f64() / 1e6
+    }
+
+    pub fn to_json(&self) -> Value {
+        json_object! {
+            "workload" => self.workload,
+            "engine" => self.engine.name(),
+            "instructions" => self.instructions,
+            "seconds" => self.elapsed.as_secs_f64(),
+            "mips" => self.mips(),
+        }
+    }
+}
@@ -0,0 +1,378 @@
+//! Basic-block execution: straight-line code is translated once into operations with
+//! their registers and PC-relative addresses already resolved, then run without fetching
+//! or decoding. Traps, reserved opcodes and device-register accesses are left to
+//! `Vm::step`, so the machine behaves exactly like the interpreter.
+
+use crate::instruction::Instruction;
+#[cfg(feature = "jit")]
+use crate::jit;
+use crate::vm::{DEVICE_PAGE, MEMORY_MAX, Register, Vm, VmError};
+
+const MAX_BLOCK_LEN: usize = 64;
+// Executions of a block before it is compiled to native code.
+#[cfg(feature = "jit")]
+const JIT_THRESHOLD: u32 = 16;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Op {
+    AddReg(Register, Register, Register),
+    AddImm(Register, Register, u16),
+    AndReg(Register, Register, Register),
+    AndImm(Register, Register, u16),
+    Not(Register, Register),
+    Lea(Register, u16),
+    Ld(Register, u16),
+    St(Register, u16),
+    Ldi(Register, u16),
+    Sti(Register, u16),
+    Ldr(Register, Register, u16),
+    Str(Register, Register, u16),
+    Nop,
+    // The last op of a block: where control goes next.
+    Br { nzp: u16, target: u16 },
+    Jump(u16),
+    Jmp(Register),
+    Jsr(u16),
+    Jsrr(Register),
+}
+
+pub(crate) struct Block {
+    pub(crate) start: u16,
+    pub(crate) ops: Vec<Op>,
+    /// PC after the last op when it does not transfer control.
+    pub(crate) end: u16,
+    hits: u32,
+    #[cfg(feature = "jit")]
+    native: Option<jit::NativeBlock>,
+}
+
+pub(crate) enum Exit {
+    /// Every op ran.
+    Done,
+    /// Stopped before op `n`, which the interpreter has to run or the budget left out.
+    Bail(usize),
+    /// Op `n - 1` overwrote translated code.
+    Modified(usize),
+}
+
+impl Block {
+    fn translate(vm: &mut Vm, start: u16) -> Block {
+        let mut ops = Vec::new();
+        let mut pc = start;
+        while ops.len() < MAX_BLOCK_LEN && pc < DEVICE_PAGE {
+            let next = pc.wrapping_add(1);
+            let device = |addr: u16| addr >= DEVICE_PAGE;
+            let (op, ends) = match Instruction::decode(vm.peek_memory(pc)) {
+                Instruction::AddReg { dst, src1, src2 } => (Op::AddReg(dst, src1, src2), false),
+                Instruction::AddImm { dst, src1, imm } => (Op::AddImm(dst, src1, imm), false),
+                Instruction::AndReg { dst, src1, src2 } => (Op::AndReg(dst, src1, src2), false),
+                Instruction::AndImm { dst, src1, imm } => (Op::AndImm(dst, src1, imm), false),
+                Instruction::Not { dst, src } => (Op::Not(dst, src), false),
+                Instruction::Lea { dst, offset } => {
+                    (Op::Lea(dst, next.wrapping_add(offset)), false)
+                }
+                Instruction::Ld { dst, offset } if !device(next.wrapping_add(offset)) => {
+                    (Op::Ld(dst, next.wrapping_add(offset)), false)
+                }
+                Instruction::St { src, offset } if !device(next.wrapping_add(offset)) => {
+                    (Op::St(src, next.wrapping_add(offset)), false)
+                }
+                // The usual way to poll the keyboard is an LDI through a pointer to KBSR.
+                Instruction::Ldi { dst, offset }
+                    if !device(next.wrapping_add(offset))
+                        && !device(vm.peek_memory(next.wrapping_add(offset))) =>
+                {
+                    (Op::Ldi(dst, next.wrapping_add(offset)), false)
+                }
+                Instruction::Sti { src, offset }
+                    if !device(next.wrapping_add(offset))
+                        && !device(vm.peek_memory(next.wrapping_add(offset))) =>
+                {
+                    (Op::Sti(src, next.wrapping_add(offset)), false)
+                }
+                Instruction::Ldr { dst, base, offset } => (Op::Ldr(dst, base, offset), false),
+                Instruction::Str { src, base, offset } => (Op::Str(src, base, offset), false),
+                Instruction::Br { nzp: 0, .. } => (Op::Nop, false),
+                Instruction::Br { nzp: 0x7, offset } => (Op::Jump(next.wrapping_add(offset)), true),
+                Instruction::Br { nzp, offset } => (
+                    Op::Br {
+                        nzp,
+                        target: next.wrapping_add(offset),
+                    },
+                    true,
+                ),
+                Instruction::Jmp { base } => (Op::Jmp(base), true),
+                Instruction::Jsr { offset } => (Op::Jsr(next.wrapping_add(offset)), true),
+                Instruction::Jsrr { base } => (Op::Jsrr(base), true),
+                // Traps, RTI/RES, and loads or stores of device registers.
+                _ => break,
+            };
+            vm.watch_code(pc);
+            ops.push(op);
+            pc = next;
+            if ends {
+                break;
+            }
+        }
+        Block {
+            start,
+            ops,
+            end: pc,
+            hits: 0,
+            #[cfg(feature = "jit")]
+            native: None,
+        }
+    }
+
+    /// Runs the block's ops, stopping after `limit` of them.
+    fn execute(&self, vm: &mut Vm, limit: usize) -> Exit {
+        for (i, op) in self.ops.iter().enumerate().take(limit) {
+            match *op {
+                Op::AddReg(dst, src1, src2) => {
+                    let res = vm.read_register(src1).wrapping_add(vm.read_register(src2));
+                    vm.write_register(dst, res);
+                    vm.update_flags(res);
+                }
+                Op::AddImm(dst, src1, imm) => {
+                    let res = vm.read_register(src1).wrapping_add(imm);
+                    vm.write_register(dst, res);
+                    vm.update_flags(res);
+                }
+                Op::AndReg(dst, src1, src2) => {
+                    let res = vm.read_register(src1) & vm.read_register(src2);
+                    vm.write_register(dst, res);
+                    vm.update_flags(res);
+                }
+                Op::AndImm(dst, src1, imm) => {
+                    let res = vm.read_register(src1) & imm;
+                    vm.write_register(dst, res);
+                    vm.update_flags(res);
+                }
+                Op::Not(dst, src) => {
+                    let res = !vm.read_register(src);
+                    vm.write_register(dst, res);
+                    vm.update_flags(res);
+                }
+                Op::Lea(dst, addr) => {
+                    vm.write_register(dst, addr);
+                    vm.update_flags(addr);
+                }
+                Op::Ld(dst, addr) => {
+                    let val = vm.peek_memory(addr);
+                    vm.write_register(dst, val);
+                    vm.update_flags(val);
+                }
+                Op::Ldr(dst, base, offset) => {
+                    let addr = vm.read_register(base).wrapping_add(offset);
+                    if addr >= DEVICE_PAGE {
+                        return Exit::Bail(i);
+                    }
+                    let val = vm.peek_memory(addr);
+                    vm.write_register(dst, val);
+                    vm.update_flags(val);
+                }
+                Op::Ldi(dst, pointer) => {
+                    let addr = vm.peek_memory(pointer);
+                    if addr >= DEVICE_PAGE {
+                        return Exit::Bail(i);
+                    }
+                    let val = vm.peek_memory(addr);
+                    vm.write_register(dst, val);
+                    vm.update_flags(val);
+                }
+                Op::St(src, addr) => {
+                    vm.write_memory(addr, vm.read_register(src));
+                    if vm.take_code_modified() {
+                        return Exit::Modified(i + 1);
+                    }
+                }
+                Op::Str(src, base, offset) => {
+                    let addr = vm.read_register(base).wrapping_add(offset);
+                    if addr >= DEVICE_PAGE {
+                        return Exit::Bail(i);
+                    }
+                    vm.write_memory(addr, vm.read_register(src));
+                    if vm.take_code_modified() {
+                        return Exit::Modified(i + 1);
+                    }
+                }
+                Op::Sti(src, pointer) => {
+                    let addr = vm.peek_memory(pointer);
+                    if addr >= DEVICE_PAGE {
+                        return Exit::Bail(i);
+                    }
+                    vm.write_memory(addr, vm.read_register(src));
+                    if vm.take_code_modified() {
+                        return Exit::Modified(i + 1);
+                    }
+                }
+                Op::Nop => {}
+                Op::Br { nzp, target } => {
+                    let next = if vm.read_register(Register::Cond) & nzp != 0 {
+                        target
+                    } else {
+                        self.end
+                    };
+                    vm.write_register(Register::PC, next);
+                    return Exit::Done;
+                }
+                Op::Jump(target) => {
+                    vm.write_register(Register::PC, target);
+                    return Exit::Done;
+                }
+                Op::Jmp(base) => {
+                    let target = vm.read_register(base);
+                    vm.write_register(Register::PC, target);
+                    return Exit::Done;
+                }
+                Op::Jsr(target) => {
+                    vm.write_register(Register::R7, self.end);
+                    vm.write_register(Register::PC, target);
+                    return Exit::Done;
+                }
+                Op::Jsrr(base) => {
+                    // The base is read before R7 is written, as in the interpreter.
+                    let target = vm.read_register(base);
+                    vm.write_register(Register::R7, self.end);
+                    vm.write_register(Register::PC, target);
+                    return Exit::Done;
+                }
+            }
+        }
+        if limit < self.ops.len() {
+            return Exit::Bail(limit);
+        }
+        vm.write_register(Register::PC, self.end);
+        Exit::Done
+    }
+}
+
+/// Result of `BlockEngine::run`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Run {
+    pub instructions: u64,
+    pub halted: bool,
+}
+
+/// Runs a `Vm` a block at a time, translating blocks the first time they are reached and
+/// dropping them all when the program (or anything else) writes over translated code.
+pub struct BlockEngine {
+    blocks: Vec<Option<Box<Block>>>,
+    instructions: u64,
+    #[cfg(feature = "jit")]
+    jit: Option<jit::CodeCache>,
+}
+
+impl Default for BlockEngine {
+    fn default() -> Self {
+        Self {
+            blocks: (0..MEMORY_MAX).map(|_| None).collect(),
+            instructions: 0,
+            #[cfg(feature = "jit")]
+            jit: None,
+        }
+    }
+}
+
+impl BlockEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also compiles blocks that keep running into native x86-64 code.
+    #[cfg(feature = "jit")]
+    pub fn with_jit() -> Self {
+        Self {
+            jit: Some(jit::CodeCache::new()),
+            ..Self::default()
+        }
+    }
+
+    /// Instructions executed by every `run` so far, including runs that failed.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    fn flush(&mut self) {
+        self.blocks.iter_mut().for_each(|block| *block = None);
+        #[cfg(feature = "jit")]
+        if let Some(jit) = &mut self.jit {
+            // Safe now that no block refers to the compiled code.
+            jit.clear();
+        }
+    }
+
+    /// Executes `budget` instructions unless the program halts or fails first; a block
+    /// longer than what is left of the budget runs only that far.
+    /// Errors are the interpreter's, with the machine left where `Vm::step` leaves it.
+    /// Cycles are counted by `Vm::step`, so a machine with a cycle model is only stepped.
+    pub fn run(&mut self, vm: &mut Vm, budget: u64) -> Result<Run, VmError> {
+        let mut run = Run::default();
+        while run.instructions < budget {
+            let executed = if vm.cycle_model().is_some() {
+                0
+            } else {
+                self.run_block(vm, budget - run.instructions)
+            };
+            run.instructions += executed as u64;
+            self.instructions += executed as u64;
+            vm.add_instructions(executed as u64);
+
+            // Whatever the block could not run goes through the interpreter.
+            if executed == 0 {
+                let step = vm.step()?;
+                run.instructions += 1;
+                self.instructions += 1;
+                if step.halted {
+                    run.halted = true;
+                    break;
+                }
+            }
+        }
+        Ok(run)
+    }
+
+    /// Runs the block at PC, translating it first if needed, but no more than `limit` of
+    /// its instructions. Returns how many ran.
+    fn run_block(&mut self, vm: &mut Vm, limit: u64) -> usize {
+        if vm.take_code_modified() {
+            self.flush();
+        }
+
+        let pc = vm.read_register(Register::PC);
+        let block =
+            self.blocks[pc as usize].get_or_insert_with(|| Box::new(Block::translate(vm, pc)));
+        block.hits = block.hits.saturating_add(1);
+        #[cfg(feature = "jit")]
+        if let Some(jit) = &mut self.jit
+            && block.native.is_none()
+            && block.hits == JIT_THRESHOLD
+            && !block.ops.is_empty()
+        {
+            block.native = jit.compile(block);
+        }
+
+        let limit = limit.min(block.ops.len() as u64) as usize;
+        #[cfg(feature = "jit")]
+        let exit = match &block.native {
+            // Native code always runs the whole block.
+            Some(native) if limit == block.ops.len() => native.call(vm, limit),
+            _ => block.execute(vm, limit),
+        };
+        #[cfg(not(feature = "jit"))]
+        let exit = block.execute(vm, limit);
+
+        match exit {
+            Exit::Done => block.ops.len(),
+            Exit::Modified(n) => {
+                vm.write_register(Register::PC, block.start.wrapping_add(n as u16));
+                self.flush();
+                n
+            }
+            Exit::Bail(n) => {
+                vm.write_register(Register::PC, block.start.wrapping_add(n as u16));
+                n
+            }
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! Shadow call stack rebuilt from JSR/JSRR and RET (`JMP R7`) as they execute.
+
+use crate::debuginfo::DebugInfo;
+use crate::instruction::Instruction;
+use crate::vm::{Register, Step, Vm};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frame {
+    /// Address of the JSR/JSRR instruction.
+    pub call_site: u16,
+    /// Entry point of the subroutine.
+    pub target: u16,
+    pub return_addr: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CallEvent {
+    Call(Frame),
+    Return(Frame),
+    /// A RET that did not go back to where the innermost call came from, usually because
+    /// R7 was overwritten (e.g. by a nested JSR or a TRAP) and not restored.
+    MismatchedReturn {
+        ret_pc: u16,
+        expected: u16,
+        actual: u16,
+    },
+}
+
+// Programs sometimes use JSR as a plain jump (rogue's `JSR LOOP`), so frames that never
+// return are dropped from the bottom past this depth.
+const MAX_DEPTH: usize = 4096;
+
+#[derive(Clone, Debug, Default)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Innermost call last.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Updates the stack after `step` has executed on `vm`.
+    pub fn observe(&mut self, step: &Step, vm: &Vm) -> Option<CallEvent> {
+        match Instruction::decode(step.instr) {
+            Instruction::Jsr { .. } | Instruction::Jsrr { .. } => {
+                let frame = Frame {
+                    call_site: step.pc,
+                    target: vm.read_register(Register::PC),
+                    return_addr: step.pc.wrapping_add(1),
+                };
+                if self.frames.len() == MAX_DEPTH {
+                    self.frames.remove(0);
+                }
+                self.frames.push(frame);
+                Some(CallEvent::Call(frame))
+            }
+            Instruction::Jmp { base: Register::R7 } => {
+                let actual = vm.read_register(Register::PC);
+                let top = *self.frames.last()?;
+                if top.return_addr == actual {
+                    self.frames.pop();
+                    return Some(CallEvent::Return(top));
+                }
+                // Unwind to a matching outer frame if there is one; otherwise the innermost
+                // subroutine still counts as returned.
+                match self.frames.iter().rposition(|f| f.return_addr == actual) {
+                    Some(index) => self.frames.truncate(index),
+                    None => {
+                        self.frames.pop();
+                    }
+                }
+                Some(CallEvent::MismatchedReturn {
+                    ret_pc: step.pc,
+                    expected: top.return_addr,
+                    actual,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// One line per frame, innermost first, starting with the current `pc`.
+    pub fn backtrace(&self, pc: u16, debug: &DebugInfo) -> Vec<String> {
+        let location = |addr: u16| {
+            if debug.symbols.is_empty() {
+                format!("x{:04X}", addr)
+            } else {
+                format!("x{:04X} {}", addr, debug.describe(addr))
+            }
+        };
+        let subroutine = |index: Option<usize>| match index {
+            Some(i) => debug.describe(self.frames[i].target),
+            None => "<entry>".to_string(),
+        };
+
+        let mut lines = vec![format!(
+            "#0  {} in {}",
+            location(pc),
+            subroutine(self.frames.len().checked_sub(1))
+        )];
+        for (depth, i) in (0..self.frames.len()).rev().enumerate() {
+            lines.push(format!(
+                "#{}  {} in {}",
+                depth + 1,
+                location(self.frames[i].call_site),
+                subroutine(i.checked_sub(1))
+            ));
+        }
+        lines
+    }
+}
+
+impl CallEvent {
+    pub fn describe(&self, debug: &DebugInfo) -> Option<String> {
+        match self {
+            CallEvent::MismatchedReturn {
+                ret_pc,
+                expected,
+                actual,
+            } => Some(format!(
+                "RET at x{:04X} ({}) returned to x{:04X} ({}), expected x{:04X} ({}); R7 may have been clobbered",
+                ret_pc,
+                debug.describe(*ret_pc),
+                actual,
+                debug.describe(*actual),
+                expected,
+                debug.describe(*expected)
+            )),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,390 @@
+//! Static control-flow graph of an image, without running it.
+//!
+//! Code is discovered from an entry point by following branches, calls, the return points
+//! after them, and traps whose vector table entry is part of the image. `JMP` and `JSRR`
+//! are followed when their register was set by `LEA` earlier in the same block; other
+//! computed jumps end the walk. The reachable code is split into basic blocks, and every
+//! block is assigned to the subroutine (the entry point or a call target) that reaches it
+//! without going through a call or another subroutine's entry.
+
+use crate::debuginfo::DebugInfo;
+use crate::instruction::Instruction;
+use crate::vm::Register;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::ops::RangeInclusive;
+
+const HALT: u16 = 0x25;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// To the following instruction: a branch not taken, or the return point of a call.
+    Next,
+    /// A branch taken.
+    Branch,
+    /// A `JMP` to an address loaded with `LEA`.
+    Jump,
+    /// A `JSR`, a resolved `JSRR`, or a trap whose service routine is in the image.
+    Call,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Edge {
+    pub target: u16,
+    pub kind: EdgeKind,
+}
+
+/// How control leaves a block, besides its edges.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exit {
+    /// Only through the edges.
+    Flow,
+    /// `RET`.
+    Return,
+    /// `HALT`.
+    Halt,
+    /// A computed jump that could not be resolved, `RTI` or a reserved opcode.
+    Unknown,
+    /// Execution runs past the last word of the image.
+    OffImage,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub start: u16,
+    /// Address of the last instruction.
+    pub end: u16,
+    pub edges: Vec<Edge>,
+    pub exit: Exit,
+}
+
+impl Block {
+    pub fn addresses(&self) -> RangeInclusive<u16> {
+        self.start..=self.end
+    }
+
+    /// Successors within the same subroutine, i.e. every edge but calls.
+    pub fn local_successors(&self) -> impl Iterator<Item = u16> + '_ {
+        self.edges
+            .iter()
+            .filter(|e| e.kind != EdgeKind::Call)
+            .map(|e| e.target)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subroutine {
+    pub entry: u16,
+    /// Start addresses of the blocks it owns, in address order.
+    pub blocks: Vec<u16>,
+}
+
+pub struct Cfg {
+    pub origin: u16,
+    pub words: Vec<u16>,
+    pub entry: u16,
+    pub blocks: BTreeMap<u16, Block>,
+    /// The entry point's first, then call targets in address order.
+    pub subroutines: Vec<Subroutine>,
+}
+
+impl Cfg {
+    /// Discovers the code reachable from `entry` in the image loaded at `origin`.
+    pub fn build(origin: u16, words: &[u16], entry: u16) -> Self {
+        let mut cfg = Self {
+            origin,
+            words: words.to_vec(),
+            entry,
+            blocks: BTreeMap::new(),
+            subroutines: Vec::new(),
+        };
+        // Targets resolved through LEA are only known once blocks exist, and each one can
+        // split a block, so discovery repeats until no new target turns up.
+        let mut starts = vec![entry];
+        loop {
+            let leaders = cfg.leaders(&starts);
+            cfg.blocks = leaders
+                .iter()
+                .map(|&start| (start, cfg.block(start, &leaders)))
+                .collect();
+            let missing: Vec<u16> = cfg
+                .blocks
+                .values()
+                .flat_map(|b| &b.edges)
+                .map(|e| e.target)
+                .filter(|t| !leaders.contains(t) && cfg.word(*t).is_some())
+                .collect();
+            if missing.is_empty() {
+                break;
+            }
+            starts.extend(missing);
+        }
+        cfg.subroutines = cfg.assign_subroutines();
+        cfg
+    }
+
+    pub fn word(&self, addr: u16) -> Option<u16> {
+        self.words
+            .get(addr.wrapping_sub(self.origin) as usize)
+            .copied()
+    }
+
+    pub fn instruction(&self, addr: u16) -> Option<Instruction> {
+        self.word(addr).map(Instruction::decode)
+    }
+
+    /// Whether `addr` holds a reachable instruction.
+    pub fn is_code(&self, addr: u16) -> bool {
+        self.block_containing(addr).is_some()
+    }
+
+    pub fn block_containing(&self, addr: u16) -> Option<&Block> {
+        self.blocks
+            .range(..=addr)
+            .next_back()
+            .map(|(_, b)| b)
+            .filter(|b| addr <= b.end)
+    }
+
+    /// The subroutine owning the block that starts at `start`.
+    pub fn subroutine_of(&self, start: u16) -> Option<&Subroutine> {
+        self.subroutines
+            .iter()
+            .find(|s| s.blocks.binary_search(&start).is_ok())
+    }
+
+    /// Service routine address of a trap, when the image covers the vector table entry.
+    fn trap_routine(&self, vector: u16) -> Option<u16> {
+        if vector == HALT {
+            return None;
+        }
+        self.word(vector)
+    }
+
+    // Addresses where a block starts: the starts, branch and call targets, and the
+    // instructions control comes back to after calls.
+    fn leaders(&self, starts: &[u16]) -> BTreeSet<u16> {
+        let mut leaders = BTreeSet::new();
+        let mut pending = starts.to_vec();
+        while let Some(start) = pending.pop() {
+            if self.word(start).is_none() || !leaders.insert(start) {
+                continue;
+            }
+            let mut pc = start;
+            while let Some(instr) = self.instruction(pc) {
+                let next = pc.wrapping_add(1);
+                match instr {
+                    Instruction::Br { nzp: 0, .. } => {}
+                    Instruction::Br { nzp: 0x7, offset } => {
+                        pending.push(next.wrapping_add(offset));
+                        break;
+                    }
+                    Instruction::Br { offset, .. } | Instruction::Jsr { offset } => {
+                        pending.extend([next.wrapping_add(offset), next]);
+                        break;
+                    }
+                    Instruction::Jsrr { .. } => {
+                        pending.push(next);
+                        break;
+                    }
+                    Instruction::Trap { vector } if vector == HALT => break,
+                    Instruction::Trap { vector } => {
+                        if let Some(routine) = self.trap_routine(vector) {
+                            pending.extend([routine, next]);
+                            break;
+                        }
+                    }
+                    Instruction::Jmp { .. } | Instruction::Rti | Instruction::Res => break,
+                    _ => {}
+                }
+                pc = next;
+            }
+        }
+        leaders
+    }
+
+    fn block(&self, start: u16, leaders: &BTreeSet<u16>) -> Block {
+        // Addresses loaded into each register by LEA so far in this block.
+        let mut known: [Option<u16>; 8] = [None; 8];
+        let mut pc = start;
+        loop {
+            let next = pc.wrapping_add(1);
+            let block = |edges: Vec<Edge>, exit: Exit| Block {
+                start,
+                end: pc,
+                edges,
+                exit,
+            };
+            let edge = |target: u16, kind: EdgeKind| Edge { target, kind };
+            let instr = self.instruction(pc).expect("Blocks only span the image");
+            match instr {
+                Instruction::Br { nzp: 0, .. } => {}
+                Instruction::Br { nzp: 0x7, offset } => {
+                    let target = next.wrapping_add(offset);
+                    return block(vec![edge(target, EdgeKind::Branch)], Exit::Flow);
+                }
+                Instruction::Br { offset, .. } => {
+                    let target = next.wrapping_add(offset);
+                    let edges = vec![edge(target, EdgeKind::Branch), edge(next, EdgeKind::Next)];
+                    return block(edges, Exit::Flow);
+                }
+                Instruction::Jsr { offset } => {
+                    let target = next.wrapping_add(offset);
+                    let edges = vec![edge(target, EdgeKind::Call), edge(next, EdgeKind::Next)];
+                    return block(edges, Exit::Flow);
+                }
+                Instruction::Jsrr { base } => {
+                    let call = known[base as usize].map(|t| edge(t, EdgeKind::Call));
+                    let edges = call.into_iter().chain([edge(next, EdgeKind::Next)]);
+                    return block(edges.collect(), Exit::Flow);
+                }
+                Instruction::Jmp { base: Register::R7 } => return block(Vec::new(), Exit::Return),
+                Instruction::Jmp { base } => {
+                    return match known[base as usize] {
+                        Some(target) => block(vec![edge(target, EdgeKind::Jump)], Exit::Flow),
+                        None => block(Vec::new(), Exit::Unknown),
+                    };
+                }
+                Instruction::Trap { vector } if vector == HALT => {
+                    return block(Vec::new(), Exit::Halt);
+                }
+                Instruction::Trap { vector } => {
+                    if let Some(routine) = self.trap_routine(vector) {
+                        let edges = vec![edge(routine, EdgeKind::Call), edge(next, EdgeKind::Next)];
+                        return block(edges, Exit::Flow);
+                    }
+                    // GETC and IN write R0; every trap writes R7.
+                    known = [None; 8];
+                }
+                Instruction::Rti | Instruction::Res => return block(Vec::new(), Exit::Unknown),
+                Instruction::Lea { dst, offset } => {
+                    known[dst as usize] = Some(next.wrapping_add(offset));
+                }
+                Instruction::AddReg { dst, .. }
+                | Instruction::AddImm { dst, .. }
+                | Instruction::AndReg { dst, .. }
+                | Instruction::AndImm { dst, .. }
+                | Instruction::Not { dst, .. }
+                | Instruction::Ld { dst, .. }
+                | Instruction::Ldi { dst, .. }
+                | Instruction::Ldr { dst, .. } => known[dst as usize] = None,
+                Instruction::St { .. } | Instruction::Sti { .. } | Instruction::Str { .. } => {}
+            }
+            if leaders.contains(&next) {
+                return block(vec![edge(next, EdgeKind::Next)], Exit::Flow);
+            }
+            if self.word(next).is_none() {
+                return block(Vec::new(), Exit::OffImage);
+            }
+            pc = next;
+        }
+    }
+
+    fn assign_subroutines(&self) -> Vec<Subroutine> {
+        let calls: BTreeSet<u16> = self
+            .blocks
+            .values()
+            .flat_map(|b| &b.edges)
+            .filter(|e| e.kind == EdgeKind::Call && self.blocks.contains_key(&e.target))
+            .map(|e| e.target)
+            .collect();
+        let entries: Vec<u16> = self
+            .blocks
+            .contains_key(&self.entry)
+            .then_some(self.entry)
+            .into_iter()
+            .chain(calls.iter().copied().filter(|&t| t != self.entry))
+            .collect();
+
+        let mut owned = BTreeSet::new();
+        let mut subroutines = Vec::new();
+        for &entry in &entries {
+            let mut blocks = Vec::new();
+            let mut pending = vec![entry];
+            while let Some(start) = pending.pop() {
+                // Flowing into another subroutine's entry is a tail call.
+                let other_entry = start != entry && (start == self.entry || calls.contains(&start));
+                let Some(block) = self.blocks.get(&start) else {
+                    continue;
+                };
+                if other_entry || !owned.insert(start) {
+                    continue;
+                }
+                blocks.push(start);
+                pending.extend(block.local_successors());
+            }
+            blocks.sort_unstable();
+            subroutines.push(Subroutine { entry, blocks });
+        }
+        subroutines
+    }
+
+    /// Graphviz source with one cluster per subroutine, each block listing its
+    /// disassembly, and labels from `debug`.
+    pub fn to_dot(&self, debug: &DebugInfo) -> String {
+        let mut dot = String::from("digraph cfg {\n");
+        dot.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+        for subroutine in &self.subroutines {
+            let _ = writeln!(dot, "    subgraph cluster_x{:04X} {{", subroutine.entry);
+            let _ = writeln!(
+                dot,
+                "        label=\"{}\";",
+                escape(&debug.describe(subroutine.entry))
+            );
+            for start in &subroutine.blocks {
+                if let Some(block) = self.blocks.get(start) {
+                    let _ = writeln!(
+                        dot,
+                        "        x{:04X} [label=\"{}\"];",
+                        start,
+                        self.block_label(block, debug)
+                    );
+                }
+            }
+            dot.push_str("    }\n");
+        }
+        for block in self.blocks.values() {
+            for edge in &block.edges {
+                if !self.blocks.contains_key(&edge.target) {
+                    continue;
+                }
+                let attributes = match edge.kind {
+                    EdgeKind::Next => "",
+                    EdgeKind::Branch => " [label=\"taken\"]",
+                    EdgeKind::Jump => " [label=\"jmp\"]",
+                    EdgeKind::Call => " [label=\"call\", style=dashed]",
+                };
+                let _ = writeln!(
+                    dot,
+                    "    x{:04X} -> x{:04X}{};",
+                    block.start, edge.target, attributes
+                );
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn block_label(&self, block: &Block, debug: &DebugInfo) -> String {
+        let mut label = format!("{}\\l", escape(&debug.describe(block.start)));
+        for addr in block.addresses() {
+            let instr = self.instruction(addr).expect("Blocks only span the image");
+            let _ = write!(
+                label,
+                "x{:04X}  {}\\l",
+                addr,
+                escape(&instr.disassemble(addr, Some(debug)))
+            );
+        }
+        match block.exit {
+            Exit::Unknown => label.push_str("(unresolved)\\l"),
+            Exit::OffImage => label.push_str("(end of image)\\l"),
+            Exit::Flow | Exit::Return | Exit::Halt => {}
+        }
+        label
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
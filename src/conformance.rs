@@ -0,0 +1,299 @@
+//! Conformance vectors: an instruction or a few, the state they start from, and what they
+//! must leave behind. Vectors are TOML:
+//!
+//! ```toml
+//! [[vector]]
+//! name = "ADD imm5 of -16 is sign-extended"
+//! code = 0x1270                 # a word or an array of words, or asm = "ADD R1, R1, #-16"
+//! pc = 0x3000                   # where the code goes; x3000 by default
+//! registers = { R1 = 0 }
+//! cc = "p"                      # before the first step; z by default
+//! memory = { x3050 = 0x8000 }   # as in unit tests
+//! steps = 1                     # instructions to run; 1 by default
+//! expect.registers = { R1 = -16 }
+//! expect.pc = 0x3001
+//! expect.cc = "n"
+//! expect.memory = { x3050 = 0x8000 }
+//! expect.output = ""
+//! expect.error = "Opcode Rti is unused or reserved"   # the last step fails with this
+//! ```
+//!
+//! `Vector::check` takes the function that executes one instruction, so the same vectors
+//! hold every engine (`Vector::check_engine`) and the microarchitecture to the ISA.
+//! Ranges of words that run past xFFFF wrap around to x0000.
+
+use crate::asm;
+use crate::bench::{Engine, Runner};
+use crate::console::SharedConsole;
+use crate::toml::{self, Value};
+use crate::unit::{self, Word};
+use crate::vm::{ConditionalFlag, PC_START, Register, Vm, VmError};
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Vector {
+    pub name: String,
+    pub pc: u16,
+    pub code: Vec<u16>,
+    pub registers: Vec<(Register, u16)>,
+    /// `ConditionalFlag` bits.
+    pub cc: u16,
+    pub memory: Vec<(u16, Vec<u16>)>,
+    pub steps: u32,
+    pub expect: Expected,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Expected {
+    pub registers: Vec<(Register, u16)>,
+    pub pc: Option<u16>,
+    pub cc: Option<u16>,
+    pub memory: Vec<(u16, Vec<u16>)>,
+    pub output: Option<Vec<u8>>,
+    /// The message of the error the last step fails with.
+    pub error: Option<String>,
+}
+
+impl Vector {
+    /// Runs the vector on a fresh machine, executing each instruction with `step` (which
+    /// returns whether the machine halted), and returns one line for each expectation that
+    /// did not hold.
+    pub fn check(&self, mut step: impl FnMut(&mut Vm) -> Result<bool, VmError>) -> Vec<String> {
+        let console = SharedConsole::new();
+        let mut vm = Vm::with_console(console.clone());
+        for (start, words) in &self.memory {
+            vm.load_program(*start, words);
+        }
+        vm.load_program(self.pc, &self.code);
+        for &(reg, value) in &self.registers {
+            vm.write_register(reg, value);
+        }
+        vm.write_register(Register::PC, self.pc);
+        vm.write_register(Register::Cond, self.cc);
+
+        let mut failures = Vec::new();
+        let mut error = None;
+        for i in 1..=self.steps {
+            match step(&mut vm) {
+                Ok(true) => break,
+                Ok(false) => {}
+                Err(e) if i == self.steps && self.expect.error.is_some() => {
+                    error = Some(e.to_string());
+                }
+                Err(e) => {
+                    failures.push(format!("step {} failed: {}", i, e));
+                    return failures;
+                }
+            }
+        }
+        if let Some(expected) = &self.expect.error
+            && error.as_ref() != Some(expected)
+        {
+            failures.push(format!("expected the error {:?}", expected));
+        }
+
+        for &(reg, value) in &self.expect.registers {
+            let actual = vm.read_register(reg);
+            if actual != value {
+                failures.push(format!(
+                    "{:?} is {}, expected {}",
+                    reg,
+                    unit::word(actual),
+                    unit::word(value)
+                ));
+            }
+        }
+        if let Some(pc) = self.expect.pc {
+            let actual = vm.read_register(Register::PC);
+            if actual != pc {
+                failures.push(format!("PC is x{:04X}, expected x{:04X}", actual, pc));
+            }
+        }
+        if let Some(cc) = self.expect.cc {
+            let actual = vm.read_register(Register::Cond);
+            if actual != cc {
+                failures.push(format!(
+                    "condition codes are {}, expected {}",
+                    unit::cc_name(actual),
+                    unit::cc_name(cc)
+                ));
+            }
+        }
+        for (start, words) in &self.expect.memory {
+            for (i, &value) in words.iter().enumerate() {
+                let addr = start.wrapping_add(i as u16);
+                let actual = vm.peek_memory(addr);
+                if actual != value {
+                    failures.push(format!(
+                        "memory at x{:04X} is {}, expected {}",
+                        addr,
+                        unit::word(actual),
+                        unit::word(value)
+                    ));
+                }
+            }
+        }
+        if let Some(expected) = &self.expect.output {
+            let output = console.take_output();
+            if output != *expected {
+                failures.push(format!(
+                    "output is {:?}, expected {:?}",
+                    String::from_utf8_lossy(&output),
+                    String::from_utf8_lossy(expected)
+                ));
+            }
+        }
+        failures
+    }
+
+    /// Checks the vector with a `bench::Runner`, one instruction at a time.
+    pub fn check_engine(&self, engine: Engine) -> Vec<String> {
+        let mut runner = None;
+        self.check(|vm| {
+            runner
+                .get_or_insert_with(|| Runner::new(engine, vm))
+                .run(vm, 1)
+        })
+    }
+
+    /// Reads a `[[vector]]` table. Errors are prefixed with the vector's name.
+    pub fn from_toml(table: &Value, default_name: &str) -> Result<Vector, String> {
+        let name = unit::string(table, "name")?
+            .unwrap_or(default_name)
+            .to_string();
+        parse_vector(table, name.clone()).map_err(|e| format!("{}: {}", name, e))
+    }
+}
+
+/// The vectors of a file.
+pub fn parse(text: &str) -> Result<Vec<Vector>, String> {
+    let spec = toml::parse(text).map_err(|e| e.to_string())?;
+    unit::tables(&spec, "vector")?
+        .iter()
+        .enumerate()
+        .map(|(i, table)| Vector::from_toml(table, &format!("vector {}", i + 1)))
+        .collect()
+}
+
+pub fn load(path: &Path) -> Result<Vec<Vector>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn parse_vector(table: &Value, name: String) -> Result<Vector, String> {
+    let pc = match table.get("pc") {
+        Some(value) => address(value, "pc")?,
+        None => PC_START,
+    };
+    let code = match (table.get("code"), unit::string(table, "asm")?) {
+        (Some(_), Some(_)) => return Err("give either code or asm, not both".to_string()),
+        (Some(value), None) => words(value, "code")?,
+        (None, Some(source)) => {
+            let source = format!(".ORIG x{:04X}\n{}\n.END\n", pc, source);
+            asm::assemble(&source).map_err(|e| e.to_string())?.words
+        }
+        (None, None) => return Err("the vector has no code or asm".to_string()),
+    };
+    let cc = match unit::string(table, "cc")? {
+        Some(name) => cc_bits(name, "cc")?,
+        None => ConditionalFlag::Zro as u16,
+    };
+    let steps = match table.get("steps") {
+        Some(value) => value
+            .as_integer()
+            .and_then(|n| u32::try_from(n).ok())
+            .filter(|&n| n > 0)
+            .ok_or("steps must be a positive integer")?,
+        None => 1,
+    };
+
+    let expect = table.get("expect");
+    let string = |key: &str| match expect {
+        Some(expect) => unit::string(expect, key).map_err(|e| format!("expect.{}", e)),
+        None => Ok(None),
+    };
+    let output = string("output")?.map(|s| s.as_bytes().to_vec());
+    let error = string("error")?.map(String::from);
+    let expect_cc = string("cc")?
+        .map(|name| cc_bits(name, "expect.cc"))
+        .transpose()?;
+    let expect_pc = expect
+        .and_then(|e| e.get("pc"))
+        .map(|value| address(value, "expect.pc"))
+        .transpose()?;
+    Ok(Vector {
+        name,
+        pc,
+        code,
+        registers: registers(table.get("registers"))?,
+        cc,
+        memory: memory(table.get("memory"))?,
+        steps,
+        expect: Expected {
+            registers: registers(expect.and_then(|e| e.get("registers")))?,
+            pc: expect_pc,
+            cc: expect_cc,
+            memory: memory(expect.and_then(|e| e.get("memory")))?,
+            output,
+            error,
+        },
+    })
+}
+
+fn cc_bits(name: &str, key: &str) -> Result<u16, String> {
+    unit::cc_from_name(name).ok_or_else(|| format!("{} must be n, z or p, not {}", key, name))
+}
+
+// Vectors have no program to take labels from.
+fn resolve(word: &Word) -> Result<u16, String> {
+    match word {
+        Word::Literal(value) => Ok(*value),
+        Word::Label(label) => Err(format!("Invalid address: {}", label)),
+    }
+}
+
+fn address(value: &Value, key: &str) -> Result<u16, String> {
+    match value {
+        Value::Integer(n) => unit::integer(*n),
+        Value::String(s) => resolve(&Word::parse(s)?),
+        other => Err(format!(
+            "{} must be an address, not {}",
+            key,
+            other.type_name()
+        )),
+    }
+}
+
+fn words(value: &Value, key: &str) -> Result<Vec<u16>, String> {
+    match value {
+        Value::Integer(n) => Ok(vec![unit::integer(*n)?]),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| {
+                item.as_integer()
+                    .ok_or_else(|| format!("{} must hold integers", key))
+                    .and_then(unit::integer)
+            })
+            .collect(),
+        other => Err(format!(
+            "{} must be a word or an array of words, not {}",
+            key,
+            other.type_name()
+        )),
+    }
+}
+
+fn registers(table: Option<&Value>) -> Result<Vec<(Register, u16)>, String> {
+    unit::registers(table)?
+        .into_iter()
+        .map(|(reg, word)| Ok((reg, resolve(&word)?)))
+        .collect()
+}
+
+fn memory(table: Option<&Value>) -> Result<Vec<(u16, Vec<u16>)>, String> {
+    unit::memory(table)?
+        .into_iter()
+        .map(|(addr, words)| Ok((resolve(&addr)?, words)))
+        .collect()
+}
@@ -0,0 +1,254 @@
+//! Keyboard and display devices seen by the running program.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+pub trait Console: Send {
+    /// Returns a key if one is ready, without blocking. Backs the KBSR/KBDR registers.
+    fn poll_key(&mut self) -> Option<u8>;
+
+    /// Waits for a key (GETC/IN). Consoles that cannot wait return `ErrorKind::WouldBlock`.
+    fn read_key(&mut self) -> io::Result<u8>;
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()>;
+
+    /// Keys that have arrived but were not read by the program yet. They stay queued.
+    fn pending_input(&mut self) -> Vec<u8>;
+
+    /// Queues keys ahead of any new input, e.g. when restoring a snapshot.
+    fn queue_input(&mut self, bytes: &[u8]);
+
+    /// Queues keys after everything already waiting, as if typed later.
+    fn push_input(&mut self, bytes: &[u8]);
+
+    /// Set once the program wanted a key after the console's scripted input ran out, when
+    /// the policy is to stop it. Consoles reading a keyboard never run out.
+    fn exhausted(&self) -> Option<OnExhausted> {
+        None
+    }
+}
+
+/// What happens when a program wants a key after its scripted input ran out.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OnExhausted {
+    /// The machine halts, as if the program had executed HALT.
+    Halt,
+    /// The step fails with `VmError::InputExhausted`.
+    Error,
+    /// GETC/IN wait for more input (`VmError::WaitingForInput`) and KBSR reads as empty.
+    #[default]
+    Block,
+}
+
+impl OnExhausted {
+    pub const ALL: &[OnExhausted] = &[OnExhausted::Halt, OnExhausted::Error, OnExhausted::Block];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            OnExhausted::Halt => "halt",
+            OnExhausted::Error => "error",
+            OnExhausted::Block => "block",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<OnExhausted> {
+        OnExhausted::ALL.iter().copied().find(|p| p.name() == name)
+    }
+}
+
+/// The process' own terminal.
+#[derive(Default)]
+pub struct StdConsole {
+    queued: VecDeque<u8>,
+}
+
+impl Console for StdConsole {
+    fn poll_key(&mut self) -> Option<u8> {
+        if let Some(key) = self.queued.pop_front() {
+            return Some(key);
+        }
+        if !crate::hardware::check_key() {
+            return None;
+        }
+        let mut buffer = [0; 1];
+        io::stdin().read_exact(&mut buffer).ok().map(|_| buffer[0])
+    }
+
+    /// A signal arriving while this waits surfaces as `ErrorKind::Interrupted`.
+    fn read_key(&mut self) -> io::Result<u8> {
+        if let Some(key) = self.queued.pop_front() {
+            return Ok(key);
+        }
+        let mut buffer = [0u8; 1];
+        match io::stdin().read(&mut buffer)? {
+            0 => Err(io::ErrorKind::UnexpectedEof.into()),
+            _ => Ok(buffer[0]),
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let mut stdout = io::stdout();
+        stdout.write_all(bytes)?;
+        stdout.flush()
+    }
+
+    fn pending_input(&mut self) -> Vec<u8> {
+        while crate::hardware::check_key() {
+            let mut buffer = [0; 1];
+            if io::stdin().read(&mut buffer).unwrap_or(0) == 0 {
+                break;
+            }
+            self.queued.push_back(buffer[0]);
+        }
+        self.queued.iter().copied().collect()
+    }
+
+    fn queue_input(&mut self, bytes: &[u8]) {
+        for byte in bytes.iter().rev() {
+            self.queued.push_front(*byte);
+        }
+    }
+
+    /// Keys pushed here are read before the terminal's.
+    fn push_input(&mut self, bytes: &[u8]) {
+        self.queued.extend(bytes);
+    }
+}
+
+/// Keys from a script instead of the terminal, and output to stdout, for headless runs.
+/// Nothing is read from stdin, so it need not be a terminal.
+pub struct ScriptedConsole {
+    input: VecDeque<u8>,
+    on_exhausted: OnExhausted,
+    exhausted: bool,
+}
+
+impl ScriptedConsole {
+    pub fn new(input: &[u8], on_exhausted: OnExhausted) -> Self {
+        Self {
+            input: input.iter().copied().collect(),
+            on_exhausted,
+            exhausted: false,
+        }
+    }
+}
+
+impl Console for ScriptedConsole {
+    fn poll_key(&mut self) -> Option<u8> {
+        let key = self.input.pop_front();
+        self.exhausted = key.is_none();
+        key
+    }
+
+    fn read_key(&mut self) -> io::Result<u8> {
+        self.poll_key()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::WouldBlock))
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let mut stdout = io::stdout();
+        stdout.write_all(bytes)?;
+        stdout.flush()
+    }
+
+    fn pending_input(&mut self) -> Vec<u8> {
+        self.input.iter().copied().collect()
+    }
+
+    fn queue_input(&mut self, bytes: &[u8]) {
+        for byte in bytes.iter().rev() {
+            self.input.push_front(*byte);
+        }
+    }
+
+    fn push_input(&mut self, bytes: &[u8]) {
+        self.input.extend(bytes);
+        self.exhausted = false;
+    }
+
+    fn exhausted(&self) -> Option<OnExhausted> {
+        (self.exhausted && self.on_exhausted != OnExhausted::Block).then_some(self.on_exhausted)
+    }
+}
+
+#[derive(Default)]
+struct Buffers {
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+    on_exhausted: OnExhausted,
+    exhausted: bool,
+}
+
+/// An in-memory console whose input queue and output buffer can be reached from outside
+/// the `Vm` through a cloned handle.
+#[derive(Clone, Default)]
+pub struct SharedConsole {
+    buffers: Arc<Mutex<Buffers>>,
+}
+
+impl SharedConsole {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push_input(&self, bytes: &[u8]) {
+        let mut buffers = self.buffers.lock().unwrap();
+        buffers.input.extend(bytes);
+        buffers.exhausted = false;
+    }
+
+    /// What the program sees once the queued input runs out; by default it waits for more.
+    pub fn set_on_exhausted(&self, on_exhausted: OnExhausted) {
+        self.buffers.lock().unwrap().on_exhausted = on_exhausted;
+    }
+
+    pub fn pending_input(&self) -> usize {
+        self.buffers.lock().unwrap().input.len()
+    }
+
+    /// Removes and returns everything written since the last call.
+    pub fn take_output(&self) -> Vec<u8> {
+        std::mem::take(&mut self.buffers.lock().unwrap().output)
+    }
+}
+
+impl Console for SharedConsole {
+    fn poll_key(&mut self) -> Option<u8> {
+        let mut buffers = self.buffers.lock().unwrap();
+        let key = buffers.input.pop_front();
+        buffers.exhausted = key.is_none();
+        key
+    }
+
+    fn read_key(&mut self) -> io::Result<u8> {
+        self.poll_key()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::WouldBlock))
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.buffers.lock().unwrap().output.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn pending_input(&mut self) -> Vec<u8> {
+        self.buffers.lock().unwrap().input.iter().copied().collect()
+    }
+
+    fn queue_input(&mut self, bytes: &[u8]) {
+        let input = &mut self.buffers.lock().unwrap().input;
+        for byte in bytes.iter().rev() {
+            input.push_front(*byte);
+        }
+    }
+
+    fn push_input(&mut self, bytes: &[u8]) {
+        SharedConsole::push_input(self, bytes);
+    }
+
+    fn exhausted(&self) -> Option<OnExhausted> {
+        let buffers = self.buffers.lock().unwrap();
+        (buffers.exhausted && buffers.on_exhausted != OnExhausted::Block)
+            .then_some(buffers.on_exhausted)
+    }
+}
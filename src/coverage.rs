@@ -0,0 +1,120 @@
+//! Code coverage: how often each address executed and which way each branch went, with
+//! an lcov report by source line when the program was assembled from a known `.asm`.
+
+use crate::debuginfo::DebugInfo;
+use crate::instruction::Instruction;
+use crate::vm::{MEMORY_MAX, Register, Step, Vm};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BranchCounts {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+pub struct Coverage {
+    hits: Vec<u64>,
+    branches: BTreeMap<u16, BranchCounts>,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self {
+            hits: vec![0; MEMORY_MAX],
+            branches: BTreeMap::new(),
+        }
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records `step`, which has just executed on `vm`.
+    pub fn observe(&mut self, step: &Step, vm: &Vm) {
+        self.hits[step.pc as usize] += 1;
+        // Only conditional branches have two directions. A branch to the next address looks
+        // the same either way and counts as not taken.
+        if is_conditional_branch(step.instr) {
+            let counts = self.branches.entry(step.pc).or_default();
+            if vm.read_register(Register::PC) == step.pc.wrapping_add(1) {
+                counts.not_taken += 1;
+            } else {
+                counts.taken += 1;
+            }
+        }
+    }
+
+    pub fn hits(&self, addr: u16) -> u64 {
+        self.hits[addr as usize]
+    }
+
+    /// Directions seen for the conditional branch at `addr`, if it ever executed.
+    pub fn branch(&self, addr: u16) -> Option<BranchCounts> {
+        self.branches.get(&addr).copied()
+    }
+
+    /// One `xADDR count` line per executed address, in address order.
+    pub fn write_hits(&self, writer: &mut impl Write) -> io::Result<()> {
+        for (addr, count) in self.hits.iter().enumerate() {
+            if *count > 0 {
+                writeln!(writer, "x{:04X} {}", addr, count)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// lcov tracefile for the source in `debug`: a `DA` record for every instruction line
+    /// and a pair of `BRDA` records (taken, not taken) for every conditional branch in
+    /// `vm`'s memory, `-` when the branch never executed.
+    pub fn lcov(&self, vm: &Vm, debug: &DebugInfo, test_name: &str) -> String {
+        let source = debug
+            .source
+            .as_ref()
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut out = String::new();
+        let _ = writeln!(out, "TN:{}", test_name);
+        let _ = writeln!(out, "SF:{}", source);
+
+        let (mut found, mut hit) = (0, 0);
+        for (&addr, &line) in &debug.lines {
+            if !is_conditional_branch(vm.peek_memory(addr)) {
+                continue;
+            }
+            let (taken, not_taken) = match self.branch(addr) {
+                Some(counts) => {
+                    hit += (counts.taken > 0) as usize + (counts.not_taken > 0) as usize;
+                    (counts.taken.to_string(), counts.not_taken.to_string())
+                }
+                None => ("-".to_string(), "-".to_string()),
+            };
+            found += 2;
+            let _ = writeln!(out, "BRDA:{},0,0,{}", line, taken);
+            let _ = writeln!(out, "BRDA:{},0,1,{}", line, not_taken);
+        }
+        if found > 0 {
+            let _ = writeln!(out, "BRF:{}", found);
+            let _ = writeln!(out, "BRH:{}", hit);
+        }
+
+        let mut lines: BTreeMap<usize, u64> = BTreeMap::new();
+        for (&addr, &line) in &debug.lines {
+            *lines.entry(line).or_default() += self.hits(addr);
+        }
+        for (line, count) in &lines {
+            let _ = writeln!(out, "DA:{},{}", line, count);
+        }
+        let _ = writeln!(out, "LF:{}", lines.len());
+        let _ = writeln!(out, "LH:{}", lines.values().filter(|c| **c > 0).count());
+        out.push_str("end_of_record\n");
+        out
+    }
+}
+
+fn is_conditional_branch(instr: u16) -> bool {
+    matches!(Instruction::decode(instr), Instruction::Br { nzp, .. } if nzp != 0 && nzp != 0x7)
+}
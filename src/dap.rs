@@ -0,0 +1,652 @@
+//! Debug Adapter Protocol server.
+//!
+//! The program's console is routed through `output` events, and keys for GETC/IN are typed
+//! into the debug console as `input <text>` since stdin carries the protocol itself.
+//!
+//! Requests are read on their own thread. A running program executes in slices, and the
+//! requests that arrived meanwhile (such as `pause`) are handled between them.
+
+use crate::asm;
+use crate::callstack::CallStack;
+use crate::console::SharedConsole;
+use crate::debuginfo::DebugInfo;
+use crate::json::{self, Value};
+use crate::json_object;
+use crate::vm::{Register, Vm, VmError};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+
+const THREAD_ID: u32 = 1;
+const REGISTERS_REF: u32 = 1;
+const MEMORY_REF: u32 = 2;
+// Memory is shown as 256 pages of 256 words; page `p` has reference `MEMORY_PAGE_REF + p`.
+const MEMORY_PAGE_REF: u32 = 0x100;
+
+// Instructions to run between flushes of the program's output, and checks for requests,
+// while the program runs.
+const SLICE: u64 = 4096;
+
+const REGISTERS: [(&str, Register); 10] = [
+    ("R0", Register::R0),
+    ("R1", Register::R1),
+    ("R2", Register::R2),
+    ("R3", Register::R3),
+    ("R4", Register::R4),
+    ("R5", Register::R5),
+    ("R6", Register::R6),
+    ("R7", Register::R7),
+    ("PC", Register::PC),
+    ("Cond", Register::Cond),
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Resume {
+    Continue,
+    Step,
+    // Steps over calls: runs until the call depth is back where it started.
+    Next,
+    StepOut,
+}
+
+// How the program was resumed, while it runs.
+#[derive(Clone, Copy)]
+struct Running {
+    mode: Resume,
+    start_depth: usize,
+}
+
+struct Session {
+    vm: Vm,
+    console: SharedConsole,
+    debug: DebugInfo,
+    breakpoints: HashSet<u16>,
+    calls: CallStack,
+    stop_on_entry: bool,
+    terminated: bool,
+    running: Option<Running>,
+}
+
+struct DapServer<W: Write> {
+    out: W,
+    seq: i64,
+    session: Option<Session>,
+}
+
+/// Serves DAP messages from `input` until the client disconnects.
+pub fn serve<R: BufRead + Send + 'static, W: Write>(mut input: R, output: W) -> io::Result<()> {
+    let (sender, requests) = mpsc::channel();
+    thread::spawn(move || {
+        loop {
+            match read_message(&mut input) {
+                Ok(Some(message)) => {
+                    if sender.send(Ok(message)).is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    let _ = sender.send(Err(e));
+                    break;
+                }
+            }
+        }
+    });
+
+    let mut server = DapServer {
+        out: output,
+        seq: 1,
+        session: None,
+    };
+    loop {
+        let message = if server.running() {
+            server.run_slice()?;
+            match requests.try_recv() {
+                Ok(message) => message?,
+                Err(TryRecvError::Empty) => continue,
+                Err(TryRecvError::Disconnected) => break,
+            }
+        } else {
+            match requests.recv() {
+                Ok(message) => message?,
+                Err(_) => break,
+            }
+        };
+        if !server.handle(&message)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let mut body = vec![0u8; length.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+    let text = String::from_utf8_lossy(&body);
+    json::parse(&text)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn format_word(value: u16) -> String {
+    format!("x{:04X} ({})", value, value as i16)
+}
+
+fn parse_word(text: &str) -> Option<u16> {
+    let text = text.trim();
+    if let Some(hex) = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix('x'))
+        .or_else(|| text.strip_prefix('X'))
+    {
+        return u16::from_str_radix(hex, 16).ok();
+    }
+    let value = text.trim_start_matches('#').parse::<i32>().ok()?;
+    (-0x8000..=0xFFFF).contains(&value).then_some(value as u16)
+}
+
+impl<W: Write> DapServer<W> {
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        if let Value::Object(map) = &mut message {
+            map.insert("seq".to_string(), self.seq.into());
+        }
+        self.seq += 1;
+        let body = message.to_string();
+        write!(self.out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.out.flush()
+    }
+
+    fn respond(&mut self, request: &Value, body: Value) -> io::Result<()> {
+        self.send(json_object! {
+            "type" => "response",
+            "request_seq" => request.get("seq").cloned().unwrap_or(Value::Null),
+            "command" => request.get("command").cloned().unwrap_or(Value::Null),
+            "success" => true,
+            "body" => body,
+        })
+    }
+
+    fn fail(&mut self, request: &Value, message: &str) -> io::Result<()> {
+        self.send(json_object! {
+            "type" => "response",
+            "request_seq" => request.get("seq").cloned().unwrap_or(Value::Null),
+            "command" => request.get("command").cloned().unwrap_or(Value::Null),
+            "success" => false,
+            "message" => message,
+        })
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json_object! {
+            "type" => "event",
+            "event" => event,
+            "body" => body,
+        })
+    }
+
+    fn output(&mut self, category: &str, text: &str) -> io::Result<()> {
+        self.event(
+            "output",
+            json_object! { "category" => category, "output" => text },
+        )
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<&str>) -> io::Result<()> {
+        let mut body = json_object! {
+            "reason" => reason,
+            "threadId" => THREAD_ID,
+            "allThreadsStopped" => true,
+        };
+        if let (Some(text), Value::Object(map)) = (text, &mut body) {
+            map.insert("description".to_string(), text.into());
+            map.insert("text".to_string(), text.into());
+        }
+        self.event("stopped", body)
+    }
+
+    /// Handles one message, returning false once the session is over.
+    fn handle(&mut self, request: &Value) -> io::Result<bool> {
+        if request.get("type").and_then(Value::as_str) != Some("request") {
+            return Ok(true);
+        }
+        let command = request
+            .get("command")
+            .and_then(Value::as_str)
+            .unwrap_or("")
+            .to_string();
+        let args = request.get("arguments").cloned().unwrap_or(Value::Null);
+
+        if self.session.is_none()
+            && !matches!(command.as_str(), "initialize" | "launch" | "disconnect")
+        {
+            self.fail(request, "No program has been launched")?;
+            return Ok(true);
+        }
+
+        match command.as_str() {
+            "initialize" => self.respond(
+                request,
+                json_object! {
+                    "supportsConfigurationDoneRequest" => true,
+                    "supportsSetVariable" => true,
+                    "supportsTerminateRequest" => true,
+                },
+            )?,
+            "launch" => match launch(&args) {
+                Ok(session) => {
+                    self.session = Some(session);
+                    self.respond(request, Value::Null)?;
+                    self.event("initialized", Value::Null)?;
+                }
+                Err(message) => self.fail(request, &message)?,
+            },
+            "setBreakpoints" => {
+                let body = self.set_breakpoints(&args);
+                self.respond(request, body)?;
+            }
+            "configurationDone" => {
+                self.respond(request, Value::Null)?;
+                if self.session().stop_on_entry {
+                    self.stopped("entry", None)?;
+                } else {
+                    self.resume(Resume::Continue)?;
+                }
+            }
+            "threads" => self.respond(
+                request,
+                json_object! {
+                    "threads" => vec![json_object! { "id" => THREAD_ID, "name" => "LC-3" }],
+                },
+            )?,
+            "stackTrace" => {
+                let body = self.stack_trace();
+                self.respond(request, body)?;
+            }
+            "scopes" => self.respond(
+                request,
+                json_object! {
+                    "scopes" => vec![
+                        json_object! {
+                            "name" => "Registers",
+                            "variablesReference" => REGISTERS_REF,
+                            "expensive" => false,
+                        },
+                        json_object! {
+                            "name" => "Memory",
+                            "variablesReference" => MEMORY_REF,
+                            "expensive" => true,
+                        },
+                    ],
+                },
+            )?,
+            "variables" => {
+                let reference = args
+                    .get("variablesReference")
+                    .and_then(Value::as_i64)
+                    .unwrap_or(0) as u32;
+                let body = json_object! { "variables" => self.variables(reference) };
+                self.respond(request, body)?;
+            }
+            "setVariable" => match self.set_variable(&args) {
+                Some(value) => self.respond(request, json_object! { "value" => value })?,
+                None => self.fail(request, "Cannot set that variable")?,
+            },
+            "evaluate" => match self.evaluate(&args) {
+                Ok(result) => self.respond(
+                    request,
+                    json_object! { "result" => result, "variablesReference" => 0u32 },
+                )?,
+                Err(message) => self.fail(request, &message)?,
+            },
+            "continue" => {
+                self.respond(request, json_object! { "allThreadsContinued" => true })?;
+                self.resume(Resume::Continue)?;
+            }
+            "next" => {
+                self.respond(request, Value::Null)?;
+                self.resume(Resume::Next)?;
+            }
+            "stepIn" => {
+                self.respond(request, Value::Null)?;
+                self.resume(Resume::Step)?;
+            }
+            "stepOut" => {
+                self.respond(request, Value::Null)?;
+                self.resume(Resume::StepOut)?;
+            }
+            "pause" => {
+                self.respond(request, Value::Null)?;
+                if self.running() {
+                    self.stop("pause", None)?;
+                }
+            }
+            "disconnect" | "terminate" => {
+                self.respond(request, Value::Null)?;
+                return Ok(false);
+            }
+            _ => self.fail(request, &format!("Unsupported request: {}", command))?,
+        }
+        Ok(true)
+    }
+
+    fn session(&mut self) -> &mut Session {
+        self.session.as_mut().expect("launch creates the session")
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Value {
+        let session = self.session();
+        let source = args.get("source").cloned().unwrap_or(Value::Null);
+        let lines: Vec<usize> = args
+            .get("breakpoints")
+            .and_then(Value::as_array)
+            .map(|bps| {
+                bps.iter()
+                    .filter_map(|bp| bp.get("line").and_then(Value::as_i64))
+                    .map(|line| line as usize)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // There is only one source file, so every request replaces the whole set.
+        session.breakpoints.clear();
+        let breakpoints: Vec<Value> = lines
+            .iter()
+            .map(|line| match session.debug.address_of_line(*line) {
+                Some((addr, actual)) => {
+                    session.breakpoints.insert(addr);
+                    json_object! {
+                        "verified" => true,
+                        "line" => actual,
+                        "source" => source.clone(),
+                        "instructionReference" => format!("x{:04X}", addr),
+                    }
+                }
+                None => json_object! {
+                    "verified" => false,
+                    "line" => *line,
+                    "message" => "No instruction at or after this line",
+                },
+            })
+            .collect();
+        json_object! { "breakpoints" => breakpoints }
+    }
+
+    fn stack_trace(&mut self) -> Value {
+        let session = self.session();
+        let pc = session.vm.read_register(Register::PC);
+        let source = session.debug.source.as_ref().map(|path| {
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            json_object! { "name" => name, "path" => path.to_string_lossy().into_owned() }
+        });
+
+        // Innermost first: the current PC, then each call site out to the entry point.
+        let frames = session.calls.frames();
+        let locations = std::iter::once(pc).chain(frames.iter().rev().map(|f| f.call_site));
+        let stack_frames: Vec<Value> = locations
+            .enumerate()
+            .map(|(id, addr)| {
+                let name = match frames.len().checked_sub(id + 1) {
+                    Some(i) => session.debug.describe(frames[i].target),
+                    None => "<entry>".to_string(),
+                };
+                let mut frame = json_object! {
+                    "id" => id as u32,
+                    "name" => format!("{} @ {}", name, session.debug.describe(addr)),
+                    "line" => session.debug.line_of(addr).unwrap_or(0),
+                    "column" => 1u32,
+                    "instructionPointerReference" => format!("x{:04X}", addr),
+                };
+                if let (Some(source), Value::Object(map)) = (&source, &mut frame) {
+                    map.insert("source".to_string(), source.clone());
+                }
+                frame
+            })
+            .collect();
+        let total = stack_frames.len() as u32;
+        json_object! { "stackFrames" => stack_frames, "totalFrames" => total }
+    }
+
+    fn variables(&mut self, reference: u32) -> Vec<Value> {
+        let vm = &self.session().vm;
+        match reference {
+            REGISTERS_REF => REGISTERS
+                .iter()
+                .map(|(name, reg)| {
+                    json_object! {
+                        "name" => *name,
+                        "value" => format_word(vm.read_register(*reg)),
+                        "variablesReference" => 0u32,
+                    }
+                })
+                .collect(),
+            MEMORY_REF => (0..256u32)
+                .map(|page| {
+                    json_object! {
+                        "name" => format!("x{:04X}-x{:04X}", page << 8, (page << 8) | 0xFF),
+                        "value" => "",
+                        "variablesReference" => MEMORY_PAGE_REF + page,
+                        "indexedVariables" => 256u32,
+                    }
+                })
+                .collect(),
+            r if (MEMORY_PAGE_REF..MEMORY_PAGE_REF + 256).contains(&r) => {
+                let base = ((r - MEMORY_PAGE_REF) << 8) as u16;
+                (0..256u16)
+                    .map(|i| {
+                        let addr = base + i;
+                        json_object! {
+                            "name" => format!("x{:04X}", addr),
+                            "value" => format_word(vm.peek_memory(addr)),
+                            "variablesReference" => 0u32,
+                            "memoryReference" => format!("x{:04X}", addr),
+                        }
+                    })
+                    .collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn set_variable(&mut self, args: &Value) -> Option<String> {
+        let reference = args.get("variablesReference")?.as_i64()? as u32;
+        let name = args.get("name")?.as_str()?;
+        let value = parse_word(args.get("value")?.as_str()?)?;
+        let vm = &mut self.session().vm;
+        if reference == REGISTERS_REF {
+            let (_, reg) = REGISTERS.iter().find(|(n, _)| *n == name)?;
+            vm.write_register(*reg, value);
+        } else if (MEMORY_PAGE_REF..MEMORY_PAGE_REF + 256).contains(&reference) {
+            vm.write_memory(parse_word(name)?, value);
+        } else {
+            return None;
+        }
+        Some(format_word(value))
+    }
+
+    /// Evaluates a register name, an address or label (memory contents), or
+    /// `input <text>` to queue keys for the program.
+    fn evaluate(&mut self, args: &Value) -> Result<String, String> {
+        let expression = args
+            .get("expression")
+            .and_then(Value::as_str)
+            .unwrap_or("")
+            .trim();
+        let session = self.session();
+
+        if let Some(text) = expression.strip_prefix("input ") {
+            let keys = text.replace("\\n", "\n");
+            session.console.push_input(keys.as_bytes());
+            return Ok(format!("{} key(s) queued", keys.len()));
+        }
+        if let Some((_, reg)) = REGISTERS
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(expression))
+        {
+            return Ok(format_word(session.vm.read_register(*reg)));
+        }
+        let addr = session
+            .debug
+            .address_of(expression)
+            .or_else(|| parse_word(expression))
+            .ok_or_else(|| format!("Cannot evaluate {}", expression))?;
+        Ok(format_word(session.vm.peek_memory(addr)))
+    }
+
+    fn flush_output(&mut self) -> io::Result<()> {
+        let bytes = self.session().console.take_output();
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        let text: String = bytes.iter().map(|b| *b as char).collect();
+        self.output("stdout", &text)
+    }
+
+    fn running(&self) -> bool {
+        self.session.as_ref().is_some_and(|s| s.running.is_some())
+    }
+
+    /// Starts the program running; `run_slice` then runs it until it stops.
+    fn resume(&mut self, mode: Resume) -> io::Result<()> {
+        if self.session().terminated {
+            return self.event("terminated", Value::Null);
+        }
+        let start_depth = self.session().calls.depth();
+        self.session().running = Some(Running { mode, start_depth });
+        Ok(())
+    }
+
+    fn stop(&mut self, reason: &str, text: Option<&str>) -> io::Result<()> {
+        self.session().running = None;
+        self.flush_output()?;
+        self.stopped(reason, text)
+    }
+
+    fn wait_for_input(&mut self) -> io::Result<()> {
+        self.session().running = None;
+        self.flush_output()?;
+        self.output(
+            "console",
+            "Program is waiting for a key; type `input <text>` to provide it.\n",
+        )?;
+        self.stopped("pause", Some("Waiting for input"))
+    }
+
+    /// Runs up to `SLICE` instructions of a running program.
+    fn run_slice(&mut self) -> io::Result<()> {
+        let Some(Running { mode, start_depth }) = self.session().running else {
+            return Ok(());
+        };
+        for _ in 0..SLICE {
+            let session = self.session();
+            let empty_polls = session.vm.empty_polls();
+            let result = session.vm.step();
+            if let Ok(step) = &result {
+                session.calls.observe(step, &session.vm);
+            }
+
+            match result {
+                Ok(step) if step.halted => {
+                    session.terminated = true;
+                    session.running = None;
+                    self.flush_output()?;
+                    self.event("exited", json_object! { "exitCode" => 0u32 })?;
+                    return self.event("terminated", Value::Null);
+                }
+                Ok(_) => {
+                    let pc = session.vm.read_register(Register::PC);
+                    let depth = session.calls.depth();
+                    let stepped = match mode {
+                        Resume::Continue => false,
+                        Resume::Step => true,
+                        Resume::Next => depth <= start_depth,
+                        Resume::StepOut => depth < start_depth,
+                    };
+                    if session.breakpoints.contains(&pc) {
+                        return self.stop("breakpoint", None);
+                    } else if stepped {
+                        return self.stop("step", None);
+                    } else if session.vm.empty_polls() > empty_polls {
+                        // A poll of KBSR found no key: the program would spin until one is typed.
+                        return self.wait_for_input();
+                    }
+                }
+                Err(VmError::WaitingForInput) => return self.wait_for_input(),
+                Err(e) => {
+                    let message = e.to_string();
+                    self.session().running = None;
+                    self.flush_output()?;
+                    self.output("stderr", &format!("{}\n", message))?;
+                    return self.stopped("exception", Some(&message));
+                }
+            }
+        }
+        self.flush_output()
+    }
+}
+
+fn launch(args: &Value) -> Result<Session, String> {
+    let program = args.get("program").and_then(Value::as_str);
+    let source = args.get("source").and_then(Value::as_str);
+    let symbols = args.get("symbols").and_then(Value::as_str);
+
+    let console = SharedConsole::new();
+    let mut vm = Vm::with_console(console.clone());
+    let mut debug = DebugInfo::default();
+
+    if let Some(source) = source {
+        let text = fs::read_to_string(source).map_err(|e| format!("{}: {}", source, e))?;
+        let assembly = asm::assemble(&text).map_err(|e| format!("{}: {}", source, e))?;
+        debug = DebugInfo::from_assembly(&assembly, Some(Path::new(source)));
+        if program.is_none() {
+            vm.load_program(assembly.origin, &assembly.words);
+        }
+    }
+    if let Some(program) = program {
+        vm.read_image_file(program)
+            .map_err(|e| format!("{}: {}", program, e))?;
+    } else if source.is_none() {
+        return Err("launch needs a `program` (.obj) or `source` (.asm)".to_string());
+    }
+    if let Some(symbols) = symbols {
+        let sym = DebugInfo::read_sym_file(symbols).map_err(|e| format!("{}: {}", symbols, e))?;
+        debug.symbols.extend(sym.symbols);
+    }
+
+    Ok(Session {
+        vm,
+        console,
+        debug,
+        breakpoints: HashSet::new(),
+        calls: CallStack::new(),
+        stop_on_entry: args
+            .get("stopOnEntry")
+            .and_then(Value::as_bool)
+            .unwrap_or(false),
+        terminated: false,
+        running: None,
+    })
+}
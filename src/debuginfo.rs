@@ -0,0 +1,112 @@
+//! Symbols and source lines for a loaded image.
+
+use crate::asm::{self, Assembly};
+use crate::vm::read_obj_file;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DebugInfo {
+    pub symbols: BTreeMap<String, u16>,
+    /// Assembly source the line table refers to, if known.
+    pub source: Option<PathBuf>,
+    /// 1-based source line of every instruction, keyed by address.
+    pub lines: BTreeMap<u16, usize>,
+}
+
+impl DebugInfo {
+    pub fn from_assembly(assembly: &Assembly, source: Option<&Path>) -> Self {
+        Self {
+            symbols: assembly.symbols.clone(),
+            source: source.map(Path::to_path_buf),
+            lines: assembly.lines.clone(),
+        }
+    }
+
+    /// Reads a symbol table in the `.sym` format written by `lc3as`:
+    ///
+    /// ```text
+    /// // Symbol table
+    /// // Scope level 0:
+    /// //  Symbol Name       Page Address
+    /// //  ----------------  ------------
+    /// //  hello             3003
+    /// ```
+    pub fn read_sym_file(path: &str) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        Ok(Self {
+            symbols: parse_sym(&text),
+            ..Self::default()
+        })
+    }
+
+    pub fn address_of(&self, label: &str) -> Option<u16> {
+        self.symbols.get(label).copied()
+    }
+
+    pub fn label_at(&self, addr: u16) -> Option<&str> {
+        self.symbols
+            .iter()
+            .find(|(_, a)| **a == addr)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Formats `addr` as `LABEL` or `LABEL+n` using the closest label at or below it.
+    pub fn describe(&self, addr: u16) -> String {
+        let closest = self
+            .symbols
+            .iter()
+            .filter(|(_, a)| **a <= addr)
+            .max_by_key(|(_, a)| **a);
+        match closest {
+            Some((name, a)) if *a == addr => name.clone(),
+            Some((name, a)) => format!("{}+{}", name, addr - a),
+            None => format!("x{:04X}", addr),
+        }
+    }
+
+    pub fn line_of(&self, addr: u16) -> Option<usize> {
+        self.lines.get(&addr).copied()
+    }
+
+    /// Resolves a source line to the first instruction at or after it, returning the
+    /// address and the line it actually lives on.
+    pub fn address_of_line(&self, line: usize) -> Option<(u16, usize)> {
+        self.lines
+            .iter()
+            .filter(|(_, l)| **l >= line)
+            .min_by_key(|(_, l)| **l)
+            .map(|(addr, l)| (*addr, *l))
+    }
+}
+
+fn parse_sym(text: &str) -> BTreeMap<String, u16> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.trim_start_matches('/').split_whitespace();
+            let name = fields.next()?;
+            let addr = u16::from_str_radix(fields.next()?, 16).ok()?;
+            if fields.next().is_some() {
+                return None;
+            }
+            Some((name.to_string(), addr))
+        })
+        .collect()
+}
+
+/// Reads a program given as `.obj` or `.asm`, with the symbols and lines of an assembled
+/// source.
+pub fn load_program_file(path: &Path) -> Result<(u16, Vec<u16>, DebugInfo), String> {
+    if path.extension().is_some_and(|ext| ext == "asm") {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let program = asm::assemble(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let debug = DebugInfo::from_assembly(&program, Some(path));
+        return Ok((program.origin, program.words, debug));
+    }
+    let (origin, words) = read_obj_file(&path.to_string_lossy())
+        .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    Ok((origin, words, DebugInfo::default()))
+}
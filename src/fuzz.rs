@@ -0,0 +1,516 @@
+//! Differential fuzzing: random instruction sequences and machine states run on one of the
+//! emulator's engines and on `reference::Reference`, with any difference in registers,
+//! condition codes, memory, output or how the run ended shrunk to a small counterexample.
+//!
+//! Every case comes from its own seed, so `--seed S --cases 1` reruns the case that
+//! seed `S` generated.
+
+use crate::bench::{Engine, Runner};
+use crate::console::SharedConsole;
+use crate::instruction::Instruction;
+use crate::reference::{self, Fault, Reference};
+use crate::vm::{MEMORY_MAX, Register, Vm, VmError};
+use std::fmt;
+
+/// Instructions a generated case may run.
+pub const DEFAULT_MAX_INSTRUCTIONS: u32 = 64;
+const MAX_PROGRAM_LEN: usize = 16;
+const MAX_DATA_WORDS: usize = 8;
+// Differences listed per counterexample before the rest are summarized.
+const MAX_DIFFERENCES: usize = 8;
+
+const REGISTERS: [Register; 8] = [
+    Register::R0,
+    Register::R1,
+    Register::R2,
+    Register::R3,
+    Register::R4,
+    Register::R5,
+    Register::R6,
+    Register::R7,
+];
+
+/// A starting state and program.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Case {
+    pub registers: [u16; 8],
+    /// One of `reference::N`, `Z` and `P`.
+    pub cc: u16,
+    pub origin: u16,
+    pub program: Vec<u16>,
+    /// Words stored before the run, for loads and indirect addresses to find.
+    pub data: Vec<(u16, u16)>,
+    pub max_instructions: u32,
+}
+
+impl Case {
+    /// A random case. Registers lean towards values at the edges of their ranges and
+    /// towards addresses near the program, which is where loads and jumps get interesting.
+    pub fn generate(seed: u64, max_instructions: u32) -> Case {
+        let mut rng = Rng::new(seed);
+        let origin = if rng.below(4) == 0 {
+            rng.next() as u16
+        } else {
+            0x3000
+        };
+        let near = |rng: &mut Rng| origin.wrapping_add(rng.below(64) as u16).wrapping_sub(16);
+        let mut registers = [0; 8];
+        for register in &mut registers {
+            *register = match rng.below(4) {
+                0 => [0, 1, 0x7FFF, 0x8000, 0xFFFF][rng.below(5) as usize],
+                1 => near(&mut rng),
+                _ => rng.next() as u16,
+            };
+        }
+        let cc = [reference::N, reference::Z, reference::P][rng.below(3) as usize];
+        let len = 1 + rng.below(MAX_PROGRAM_LEN as u64) as usize;
+        let program = (0..len).map(|_| random_instruction(&mut rng)).collect();
+        let data = (0..rng.below(MAX_DATA_WORDS as u64 + 1))
+            .map(|_| {
+                let value = if rng.below(2) == 0 {
+                    near(&mut rng)
+                } else {
+                    rng.next() as u16
+                };
+                (near(&mut rng), value)
+            })
+            .collect();
+        Case {
+            registers,
+            cc,
+            origin,
+            program,
+            data,
+            max_instructions,
+        }
+    }
+
+    fn vm(&self) -> (Vm, SharedConsole) {
+        let console = SharedConsole::new();
+        let mut vm = Vm::with_console(console.clone());
+        for &(addr, value) in &self.data {
+            vm.write_memory(addr, value);
+        }
+        vm.load_program(self.origin, &self.program);
+        for (register, value) in REGISTERS.iter().zip(self.registers) {
+            vm.write_register(*register, value);
+        }
+        vm.write_register(Register::Cond, self.cc);
+        (vm, console)
+    }
+
+    fn reference(&self) -> Reference {
+        let mut reference = Reference::new();
+        for &(addr, value) in &self.data {
+            reference.memory[addr as usize] = value;
+        }
+        for (i, word) in self.program.iter().enumerate() {
+            reference.memory[self.origin.wrapping_add(i as u16) as usize] = *word;
+        }
+        reference.registers = self.registers;
+        reference.pc = self.origin;
+        reference.cc = self.cc;
+        reference
+    }
+}
+
+impl fmt::Display for Case {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let registers: Vec<String> = self
+            .registers
+            .iter()
+            .enumerate()
+            .map(|(i, value)| format!("R{}=x{:04X}", i, value))
+            .collect();
+        writeln!(f, "  registers  {}", registers.join(" "))?;
+        writeln!(f, "  cc         {}", cc_name(self.cc))?;
+        for (addr, value) in &self.data {
+            writeln!(f, "  data       x{:04X}: x{:04X}", addr, value)?;
+        }
+        for (i, word) in self.program.iter().enumerate() {
+            let addr = self.origin.wrapping_add(i as u16);
+            writeln!(
+                f,
+                "  x{:04X}      x{:04X}  {}",
+                addr,
+                word,
+                Instruction::decode(*word).disassemble(addr, None)
+            )?;
+        }
+        let plural = if self.max_instructions == 1 { "" } else { "s" };
+        write!(
+            f,
+            "  runs for   {} instruction{}",
+            self.max_instructions, plural
+        )
+    }
+}
+
+fn cc_name(cc: u16) -> String {
+    [
+        (reference::N, 'n'),
+        (reference::Z, 'z'),
+        (reference::P, 'p'),
+    ]
+    .iter()
+    .filter(|(bit, _)| cc & bit != 0)
+    .map(|(_, name)| *name)
+    .collect()
+}
+
+// Any instruction except GETC and IN, which would wait for a key. Traps are mostly the
+// output routines and HALT; RTI and the reserved opcode are rare.
+fn random_instruction(rng: &mut Rng) -> u16 {
+    let word = rng.next() as u16;
+    match word >> 12 {
+        0b1000 | 0b1101 if rng.below(4) != 0 => random_instruction(rng),
+        0b1111 => {
+            let vector = match rng.below(8) {
+                0 => rng.next() as u16 & 0xFF,
+                n => [0x21, 0x22, 0x24, 0x25][n as usize % 4],
+            };
+            match vector {
+                0x20 | 0x23 => 0xF025,
+                _ => 0xF000 | vector,
+            }
+        }
+        _ => word,
+    }
+}
+
+/// How a run ended.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Ending {
+    /// It used up its instructions.
+    Running,
+    Halted,
+    Fault(Fault),
+}
+
+impl fmt::Display for Ending {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ending::Running => write!(f, "still running"),
+            Ending::Halted => write!(f, "halted"),
+            Ending::Fault(Fault::Reserved(opcode)) => write!(f, "reserved opcode {:04b}", opcode),
+            Ending::Fault(Fault::UnknownTrap(vector)) => write!(f, "unknown trap x{:02X}", vector),
+            Ending::Fault(Fault::Input) => write!(f, "waiting for input"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Agree,
+    /// What differed, one line each.
+    Diverge(Vec<String>),
+    /// The case read the keyboard, which the reference does not model.
+    Inconclusive,
+}
+
+/// Runs `case` on `engine` and on the reference, then compares the two machines. The
+/// reference runs exactly as many instructions as the engine did.
+pub fn compare(case: &Case, engine: Engine) -> Verdict {
+    let (mut vm, console) = case.vm();
+    let mut runner = Runner::new(engine, &mut vm);
+    let ending = match runner.run(&mut vm, case.max_instructions as u64) {
+        Ok(true) => Ending::Halted,
+        Ok(false) => Ending::Running,
+        Err(VmError::ReservedOpcode(opcode)) => Ending::Fault(Fault::Reserved(opcode as u16)),
+        Err(VmError::UnknownTrap(vector)) => Ending::Fault(Fault::UnknownTrap(vector)),
+        Err(VmError::WaitingForInput) => Ending::Fault(Fault::Input),
+        Err(e) => return Verdict::Diverge(vec![format!("{} failed: {}", engine.name(), e)]),
+    };
+
+    let mut reference = case.reference();
+    let mut reference_ending = Ending::Running;
+    let mut executed = 0;
+    while executed < vm.instructions() {
+        match reference.step() {
+            Ok(false) => executed += 1,
+            Ok(true) => {
+                executed += 1;
+                reference_ending = Ending::Halted;
+                break;
+            }
+            Err(fault) => {
+                reference_ending = Ending::Fault(fault);
+                break;
+            }
+        }
+    }
+    if let Ending::Fault(_) = ending
+        && reference_ending == Ending::Running
+        && let Err(fault) = reference.step()
+    {
+        reference_ending = Ending::Fault(fault);
+    }
+    if reference_ending == Ending::Fault(Fault::Input) {
+        return Verdict::Inconclusive;
+    }
+
+    let name = engine.name();
+    let mut differences = Vec::new();
+    if (ending, executed) != (reference_ending, vm.instructions()) {
+        differences.push(format!(
+            "{} {} after {} instructions, reference {} after {}",
+            name,
+            ending,
+            vm.instructions(),
+            reference_ending,
+            executed
+        ));
+    }
+    for (i, register) in REGISTERS.iter().enumerate() {
+        let value = vm.read_register(*register);
+        if value != reference.registers[i] {
+            differences.push(format!(
+                "R{}: {} x{:04X}, reference x{:04X}",
+                i, name, value, reference.registers[i]
+            ));
+        }
+    }
+    if vm.read_register(Register::PC) != reference.pc {
+        differences.push(format!(
+            "PC: {} x{:04X}, reference x{:04X}",
+            name,
+            vm.read_register(Register::PC),
+            reference.pc
+        ));
+    }
+    if vm.read_register(Register::Cond) != reference.cc {
+        differences.push(format!(
+            "cc: {} {}, reference {}",
+            name,
+            cc_name(vm.read_register(Register::Cond)),
+            cc_name(reference.cc)
+        ));
+    }
+    for addr in 0..MEMORY_MAX {
+        let value = vm.peek_memory(addr as u16);
+        if value != reference.memory[addr] {
+            differences.push(format!(
+                "memory x{:04X}: {} x{:04X}, reference x{:04X}",
+                addr, name, value, reference.memory[addr]
+            ));
+        }
+    }
+    let output = console.take_output();
+    if output != reference.output {
+        differences.push(format!(
+            "output: {} {:?}, reference {:?}",
+            name,
+            String::from_utf8_lossy(&output),
+            String::from_utf8_lossy(&reference.output)
+        ));
+    }
+
+    if differences.is_empty() {
+        Verdict::Agree
+    } else {
+        if differences.len() > MAX_DIFFERENCES {
+            let more = differences.len() - MAX_DIFFERENCES;
+            differences.truncate(MAX_DIFFERENCES);
+            differences.push(format!("... and {} more", more));
+        }
+        Verdict::Diverge(differences)
+    }
+}
+
+/// Shrinks `case` while `diverges` still holds for it: fewer instructions to run, fewer
+/// program and data words, a data word that was executed as the whole program, and
+/// registers and words with fewer bits set. Each change is kept only
+/// if the case still diverges, so the result is a local minimum.
+pub fn minimize(case: &Case, mut diverges: impl FnMut(&Case) -> bool) -> Case {
+    let mut best = case.clone();
+    loop {
+        let before = best.clone();
+
+        // The shortest run that still goes wrong.
+        let (mut low, mut high) = (0, best.max_instructions);
+        while low + 1 < high {
+            let mid = low + (high - low) / 2;
+            let candidate = Case {
+                max_instructions: mid,
+                ..best.clone()
+            };
+            if diverges(&candidate) {
+                high = mid;
+            } else {
+                low = mid;
+            }
+        }
+        best.max_instructions = high;
+
+        let mut i = best.program.len();
+        while i > 0 && best.program.len() > 1 {
+            i -= 1;
+            let mut candidate = best.clone();
+            candidate.program.remove(i);
+            if diverges(&candidate) {
+                best = candidate;
+            }
+        }
+        let mut i = best.data.len();
+        while i > 0 {
+            i -= 1;
+            let mut candidate = best.clone();
+            candidate.data.remove(i);
+            if diverges(&candidate) {
+                best = candidate;
+            }
+        }
+        // A data word the program ran into may be all the program needs.
+        let mut i = best.data.len();
+        while i > 0 {
+            i -= 1;
+            let mut candidate = best.clone();
+            let (_, word) = candidate.data.remove(i);
+            candidate.program = vec![word];
+            if diverges(&candidate) {
+                best = candidate;
+            }
+        }
+        for i in 0..8 {
+            simplify(&mut best, Word::Register(i), &mut diverges);
+        }
+        for i in 0..best.program.len() {
+            simplify(&mut best, Word::Program(i), &mut diverges);
+        }
+        for i in 0..best.data.len() {
+            simplify(&mut best, Word::Data(i), &mut diverges);
+        }
+        if best.cc != reference::Z {
+            let candidate = Case {
+                cc: reference::Z,
+                ..best.clone()
+            };
+            if diverges(&candidate) {
+                best = candidate;
+            }
+        }
+        if best.origin != 0x3000 {
+            let candidate = Case {
+                origin: 0x3000,
+                ..best.clone()
+            };
+            if diverges(&candidate) {
+                best = candidate;
+            }
+        }
+
+        if best == before {
+            return best;
+        }
+    }
+}
+
+// A word of a case that `minimize` can simplify.
+#[derive(Clone, Copy)]
+enum Word {
+    Register(usize),
+    Program(usize),
+    Data(usize),
+}
+
+impl Word {
+    fn get(self, case: &mut Case) -> &mut u16 {
+        match self {
+            Word::Register(i) => &mut case.registers[i],
+            Word::Program(i) => &mut case.program[i],
+            Word::Data(i) => &mut case.data[i].1,
+        }
+    }
+}
+
+// Tries `word` as zero, then with one bit cleared at a time. Clearing bits also drops the
+// fields an instruction ignores, so encodings of the same bug minimize alike.
+fn simplify(best: &mut Case, word: Word, diverges: &mut impl FnMut(&Case) -> bool) {
+    for mask in std::iter::once(0).chain((0..16).rev().map(|bit| !(1 << bit))) {
+        let mut candidate = best.clone();
+        let value = word.get(&mut candidate);
+        if *value & mask == *value {
+            continue;
+        }
+        *value &= mask;
+        if diverges(&candidate) {
+            *best = candidate;
+        }
+    }
+}
+
+pub struct Counterexample {
+    /// The seed the case was generated from.
+    pub seed: u64,
+    /// The case after minimizing.
+    pub case: Case,
+    pub differences: Vec<String>,
+}
+
+pub struct Report {
+    pub cases: u64,
+    pub inconclusive: u64,
+    /// One per distinct minimized case, in the order they were found.
+    pub counterexamples: Vec<Counterexample>,
+    /// Cases that diverged, including those that minimized to a case already reported.
+    pub divergent: u64,
+}
+
+/// Generates and compares `cases` cases from seeds `seed`, `seed + 1` and so on.
+pub fn fuzz(seed: u64, cases: u64, engine: Engine, max_instructions: u32) -> Report {
+    let mut report = Report {
+        cases,
+        inconclusive: 0,
+        counterexamples: Vec::new(),
+        divergent: 0,
+    };
+    for case_seed in seed..seed.saturating_add(cases) {
+        let case = Case::generate(case_seed, max_instructions);
+        match compare(&case, engine) {
+            Verdict::Agree => {}
+            Verdict::Inconclusive => report.inconclusive += 1,
+            Verdict::Diverge(_) => {
+                report.divergent += 1;
+                let minimized = minimize(&case, |candidate| {
+                    matches!(compare(candidate, engine), Verdict::Diverge(_))
+                });
+                if report.counterexamples.iter().all(|c| c.case != minimized) {
+                    let Verdict::Diverge(differences) = compare(&minimized, engine) else {
+                        unreachable!("minimized cases still diverge");
+                    };
+                    report.counterexamples.push(Counterexample {
+                        seed: case_seed,
+                        case: minimized,
+                        differences,
+                    });
+                }
+            }
+        }
+    }
+    report
+}
+
+// xorshift64*, which is plenty for generating test cases.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        // Spread out nearby seeds, and avoid the all-zero state.
+        let mut state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ 0xD1B5_4A32_D192_ED03;
+        if state == 0 {
+            state = 1;
+        }
+        Rng(state)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
@@ -0,0 +1,450 @@
+//! GDB remote serial protocol stub.
+//!
+//! GDB only understands byte-addressed targets, so LC-3 word `w` is exposed as the two
+//! bytes at `2w` (low) and `2w + 1` (high). The `pc` register follows the same
+//! convention and therefore holds a byte address.
+
+use crate::vm::{Register, Vm, VmError};
+use std::collections::HashSet;
+use std::io::{self, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.lc3.core">
+    <flags id="lc3_cond" size="2">
+      <field name="P" start="0" end="0"/>
+      <field name="Z" start="1" end="1"/>
+      <field name="N" start="2" end="2"/>
+    </flags>
+    <reg name="r0" bitsize="16" type="int16" regnum="0"/>
+    <reg name="r1" bitsize="16" type="int16"/>
+    <reg name="r2" bitsize="16" type="int16"/>
+    <reg name="r3" bitsize="16" type="int16"/>
+    <reg name="r4" bitsize="16" type="int16"/>
+    <reg name="r5" bitsize="16" type="int16"/>
+    <reg name="r6" bitsize="16" type="data_ptr"/>
+    <reg name="r7" bitsize="16" type="int16"/>
+    <reg name="pc" bitsize="32" type="code_ptr"/>
+    <reg name="cond" bitsize="16" type="lc3_cond"/>
+  </feature>
+</target>
+"#;
+
+// Signal numbers used in stop replies.
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGINT: u8 = 2;
+
+// How many instructions to run between checks for an interrupt from the client.
+const INTERRUPT_POLL_INTERVAL: u32 = 1024;
+/// The largest packet GDB may send or ask for, as advertised in `qSupported`.
+const PACKET_SIZE: u32 = 0x1000;
+// Memory ends at word xFFFF, which is byte x1FFFF.
+const MEMORY_BYTES: u32 = 0x20000;
+
+const REGISTERS: [Register; 10] = [
+    Register::R0,
+    Register::R1,
+    Register::R2,
+    Register::R3,
+    Register::R4,
+    Register::R5,
+    Register::R6,
+    Register::R7,
+    Register::PC,
+    Register::Cond,
+];
+
+enum Stop {
+    Signal(u8),
+    Exited,
+}
+
+/// Accepts a single debugger connection and serves it until it detaches or the program halts.
+pub fn serve(listener: &TcpListener, vm: &mut Vm) -> io::Result<()> {
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    GdbStub::new(stream, vm)?.run()
+}
+
+struct GdbStub<'a> {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    vm: &'a mut Vm,
+    breakpoints: HashSet<u16>,
+    no_ack: bool,
+}
+
+impl<'a> GdbStub<'a> {
+    fn new(stream: TcpStream, vm: &'a mut Vm) -> io::Result<Self> {
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            vm,
+            breakpoints: HashSet::new(),
+            no_ack: false,
+        })
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            let (reply, done) = self.handle(&packet);
+            if let Some(reply) = reply {
+                self.send(&reply)?;
+            }
+            if done {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0u8; 1];
+        match self.reader.read_exact(&mut byte) {
+            Ok(()) => Ok(Some(byte[0])),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Reads the next `$data#cs` packet, skipping acknowledgements and stray interrupts.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => {}
+                Some(_) => continue,
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(b) => data.push(b),
+                }
+            }
+            let mut checksum = [0u8; 2];
+            self.reader.read_exact(&mut checksum)?;
+
+            let expected = u8::from_str_radix(&String::from_utf8_lossy(&checksum), 16).ok();
+            let actual = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+            if expected != Some(actual) {
+                if !self.no_ack {
+                    self.writer.write_all(b"-")?;
+                }
+                continue;
+            }
+            if !self.no_ack {
+                self.writer.write_all(b"+")?;
+            }
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(self.writer, "${}#{:02x}", data, checksum)?;
+        self.writer.flush()?;
+        if !self.no_ack {
+            // GDB answers every packet with '+', or '-' to request a retransmission.
+            while let Some(ack) = self.read_byte()? {
+                match ack {
+                    b'+' => break,
+                    b'-' => {
+                        write!(self.writer, "${}#{:02x}", data, checksum)?;
+                        self.writer.flush()?;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the reply to send (if any) and whether the session is over.
+    fn handle(&mut self, packet: &str) -> (Option<String>, bool) {
+        let reply = match packet.as_bytes().first() {
+            Some(b'?') => stop_reply(&Stop::Signal(SIGTRAP)),
+            Some(b'g') => self.read_registers(),
+            Some(b'G') => self.write_registers(&packet[1..]),
+            Some(b'p') => self.read_register(&packet[1..]),
+            Some(b'P') => self.write_register(&packet[1..]),
+            Some(b'm') => self.read_memory(&packet[1..]),
+            Some(b'M') => self.write_memory(&packet[1..]),
+            Some(b'Z') => self.breakpoint(&packet[1..], true),
+            Some(b'z') => self.breakpoint(&packet[1..], false),
+            Some(b's') => {
+                let stop = self.resume(true);
+                let done = matches!(stop, Stop::Exited);
+                return (Some(stop_reply(&stop)), done);
+            }
+            Some(b'c') => {
+                let stop = self.resume(false);
+                let done = matches!(stop, Stop::Exited);
+                return (Some(stop_reply(&stop)), done);
+            }
+            Some(b'H') => "OK".to_string(),
+            Some(b'k') => return (None, true),
+            Some(b'D') => return (Some("OK".to_string()), true),
+            Some(b'q') | Some(b'Q') => self.query(packet),
+            _ => String::new(),
+        };
+        (Some(reply), false)
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            format!(
+                "PacketSize={:x};qXfer:features:read+;swbreak+;QStartNoAckMode+",
+                PACKET_SIZE
+            )
+        } else if packet == "QStartNoAckMode" {
+            self.no_ack = true;
+            "OK".to_string()
+        } else if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            xfer(TARGET_XML, args).unwrap_or_else(|| "E01".to_string())
+        } else if packet == "qAttached" {
+            "1".to_string()
+        } else if packet == "qC" {
+            "QC1".to_string()
+        } else if packet == "qfThreadInfo" {
+            "m1".to_string()
+        } else if packet == "qsThreadInfo" {
+            "l".to_string()
+        } else {
+            String::new()
+        }
+    }
+
+    fn register_value(&self, reg: Register) -> u32 {
+        let value = self.vm.read_register(reg);
+        if reg == Register::PC {
+            (value as u32) << 1
+        } else {
+            value as u32
+        }
+    }
+
+    fn set_register_value(&mut self, reg: Register, value: u32) {
+        if reg == Register::PC {
+            self.vm.write_register(reg, (value >> 1) as u16);
+        } else {
+            self.vm.write_register(reg, value as u16);
+        }
+    }
+
+    fn read_registers(&self) -> String {
+        REGISTERS
+            .iter()
+            .map(|reg| encode_le(self.register_value(*reg), register_size(*reg)))
+            .collect()
+    }
+
+    fn write_registers(&mut self, hex: &str) -> String {
+        let mut offset = 0;
+        for reg in REGISTERS {
+            let len = register_size(reg) * 2;
+            let Some(value) = hex.get(offset..offset + len).and_then(decode_le) else {
+                return "E01".to_string();
+            };
+            self.set_register_value(reg, value);
+            offset += len;
+        }
+        "OK".to_string()
+    }
+
+    fn read_register(&self, args: &str) -> String {
+        match register_by_number(args) {
+            Some(reg) => encode_le(self.register_value(reg), register_size(reg)),
+            None => "E01".to_string(),
+        }
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let Some((number, value)) = args.split_once('=') else {
+            return "E01".to_string();
+        };
+        match (register_by_number(number), decode_le(value)) {
+            (Some(reg), Some(value)) => {
+                self.set_register_value(reg, value);
+                "OK".to_string()
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn read_memory(&self, args: &str) -> String {
+        let Some((addr, len)) = parse_addr_len(args) else {
+            return "E01".to_string();
+        };
+        if addr >= MEMORY_BYTES {
+            return "E01".to_string();
+        }
+        // A shorter reply than asked for is allowed; GDB asks again for the rest.
+        let len = len.min(PACKET_SIZE / 2).min(MEMORY_BYTES - addr);
+        (0..len)
+            .map(|i| {
+                let byte_addr = addr + i;
+                let word = self.vm.peek_memory((byte_addr >> 1) as u16);
+                let byte = if byte_addr & 1 == 0 {
+                    word & 0xFF
+                } else {
+                    word >> 8
+                };
+                format!("{:02x}", byte)
+            })
+            .collect()
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let Some((range, data)) = args.split_once(':') else {
+            return "E01".to_string();
+        };
+        let Some((addr, len)) = parse_addr_len(range) else {
+            return "E01".to_string();
+        };
+        // Nothing is written unless all of it fits in memory.
+        if addr.checked_add(len).is_none_or(|end| end > MEMORY_BYTES) {
+            return "E01".to_string();
+        }
+        let bytes = decode_hex(data);
+        if bytes.len() != len as usize {
+            return "E01".to_string();
+        }
+        for (i, byte) in bytes.into_iter().enumerate() {
+            let byte_addr = addr + i as u32;
+            let word_addr = (byte_addr >> 1) as u16;
+            let word = self.vm.peek_memory(word_addr);
+            let word = if byte_addr & 1 == 0 {
+                (word & 0xFF00) | byte as u16
+            } else {
+                (word & 0x00FF) | ((byte as u16) << 8)
+            };
+            self.vm.write_memory(word_addr, word);
+        }
+        "OK".to_string()
+    }
+
+    fn breakpoint(&mut self, args: &str, insert: bool) -> String {
+        let mut parts = args.split(',');
+        let kind = parts.next();
+        let addr = parts.next().and_then(|a| u32::from_str_radix(a, 16).ok());
+        match (kind, addr) {
+            (Some("0") | Some("1"), Some(addr)) => {
+                let word_addr = (addr >> 1) as u16;
+                if insert {
+                    self.breakpoints.insert(word_addr);
+                } else {
+                    self.breakpoints.remove(&word_addr);
+                }
+                "OK".to_string()
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn resume(&mut self, single_step: bool) -> Stop {
+        let mut executed = 0u32;
+        loop {
+            match self.vm.step() {
+                Ok(step) if step.halted => return Stop::Exited,
+                Ok(_) => {}
+                Err(VmError::Io(_) | VmError::WaitingForInput) => return Stop::Signal(SIGINT),
+                Err(_) => return Stop::Signal(SIGILL),
+            }
+            if single_step {
+                return Stop::Signal(SIGTRAP);
+            }
+            if self
+                .breakpoints
+                .contains(&self.vm.read_register(Register::PC))
+            {
+                return Stop::Signal(SIGTRAP);
+            }
+            executed += 1;
+            if executed.is_multiple_of(INTERRUPT_POLL_INTERVAL) && self.interrupted() {
+                return Stop::Signal(SIGINT);
+            }
+        }
+    }
+
+    /// Checks, without blocking, whether the client sent a break (0x03) while running.
+    fn interrupted(&mut self) -> bool {
+        let mut byte = [0u8; 1];
+        if !self.reader.buffer().is_empty() {
+            return self.reader.read_exact(&mut byte).is_ok() && byte[0] == 0x03;
+        }
+        if self.writer.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let interrupted = matches!(self.writer.peek(&mut byte), Ok(1) if byte[0] == 0x03);
+        if interrupted {
+            let _ = self.reader.read_exact(&mut byte);
+        }
+        let _ = self.writer.set_nonblocking(false);
+        interrupted
+    }
+}
+
+fn stop_reply(stop: &Stop) -> String {
+    match stop {
+        Stop::Signal(signal) => format!("S{:02x}", signal),
+        Stop::Exited => "W00".to_string(),
+    }
+}
+
+fn register_size(reg: Register) -> usize {
+    if reg == Register::PC { 4 } else { 2 }
+}
+
+fn register_by_number(hex: &str) -> Option<Register> {
+    let number = usize::from_str_radix(hex, 16).ok()?;
+    REGISTERS.get(number).copied()
+}
+
+fn encode_le(value: u32, size: usize) -> String {
+    value.to_le_bytes()[..size]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn decode_le(hex: &str) -> Option<u32> {
+    let bytes = decode_hex(hex);
+    if bytes.is_empty() || bytes.len() > 4 || bytes.len() * 2 != hex.len() {
+        return None;
+    }
+    Some(
+        bytes
+            .iter()
+            .rev()
+            .fold(0u32, |value, b| (value << 8) | *b as u32),
+    )
+}
+
+fn decode_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len() / 2)
+        .filter_map(|i| u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_addr_len(args: &str) -> Option<(u32, u32)> {
+    let (addr, len) = args.split_once(',')?;
+    Some((
+        u32::from_str_radix(addr, 16).ok()?,
+        u32::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+/// Serves an `offset,length` window of a qXfer object.
+fn xfer(document: &str, args: &str) -> Option<String> {
+    let (offset, len) = parse_addr_len(args)?;
+    let offset = (offset as usize).min(document.len());
+    let end = (offset + len as usize).min(document.len());
+    let marker = if end == document.len() { 'l' } else { 'm' };
+    Some(format!("{}{}", marker, &document[offset..end]))
+}
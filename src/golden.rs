@@ -0,0 +1,186 @@
+//! Golden-output tests: programs run headless on scripted keys, and what they print is
+//! compared with a recorded copy.
+//!
+//! A test directory holds cases by file name. `NAME.out` is the expected console output of
+//! `NAME.asm` (assembled first) or `NAME.obj`, and `NAME.in`, if present, holds the keys the
+//! program reads. A program that asks for a key after its input has run out halts, as with
+//! `--on-input-end halt`.
+
+use crate::bench::{Engine, Runner};
+use crate::console::{OnExhausted, SharedConsole};
+use crate::debuginfo::load_program_file;
+use crate::vm::Vm;
+use std::fmt::Write;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Instructions a case may run before it counts as hung.
+pub const DEFAULT_MAX_INSTRUCTIONS: u64 = 10_000_000;
+
+// Differing lines shown for a failed case, from each side.
+const DIFF_LINES: usize = 10;
+
+/// A program and the files that go with it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Case {
+    pub name: String,
+    /// `None` when only the expected output was found.
+    pub program: Option<PathBuf>,
+    pub expected: PathBuf,
+    pub input: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Passed,
+    /// The program halted, but its output was not the expected one.
+    Mismatch {
+        diff: String,
+    },
+    /// The program could not be loaded, faulted, or did not halt within the limit.
+    Error(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct CaseResult {
+    pub name: String,
+    pub outcome: Outcome,
+    pub output: Vec<u8>,
+    pub instructions: u64,
+}
+
+/// Finds the cases in `dir`, sorted by name. Subdirectories are not searched.
+pub fn discover(dir: &Path) -> io::Result<Vec<Case>> {
+    let mut cases = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "out") {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        let existing =
+            |ext: &str| Some(dir.join(format!("{}.{}", name, ext))).filter(|p| p.is_file());
+        cases.push(Case {
+            name: name.to_string(),
+            program: existing("asm").or_else(|| existing("obj")),
+            expected: path.clone(),
+            input: existing("in"),
+        });
+    }
+    cases.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(cases)
+}
+
+/// Runs `case` on `engine` for at most `max_instructions` and compares its output.
+pub fn run_case(case: &Case, engine: Engine, max_instructions: u64) -> CaseResult {
+    let mut result = CaseResult {
+        name: case.name.clone(),
+        outcome: Outcome::Passed,
+        output: Vec::new(),
+        instructions: 0,
+    };
+    let files = match load(case) {
+        Ok(files) => files,
+        Err(message) => {
+            result.outcome = Outcome::Error(message);
+            return result;
+        }
+    };
+    let console = SharedConsole::new();
+    console.set_on_exhausted(OnExhausted::Halt);
+    let mut vm = Vm::with_console(console.clone());
+    vm.load_program(files.origin, &files.words);
+    vm.push_input(&files.input);
+
+    let mut runner = Runner::new(engine, &mut vm);
+    let halted = runner.run(&mut vm, max_instructions);
+    result.instructions = runner.instructions();
+    result.output = console.take_output();
+    result.outcome = match halted {
+        Ok(true) if result.output == files.expected => Outcome::Passed,
+        Ok(true) => Outcome::Mismatch {
+            diff: diff(&files.expected, &result.output),
+        },
+        Ok(false) => Outcome::Error(format!(
+            "did not halt within {} instructions",
+            max_instructions
+        )),
+        Err(e) => Outcome::Error(e.to_string()),
+    };
+    result
+}
+
+// A case's program, input and expected output, read in.
+struct Files {
+    origin: u16,
+    words: Vec<u16>,
+    input: Vec<u8>,
+    expected: Vec<u8>,
+}
+
+fn load(case: &Case) -> Result<Files, String> {
+    let read = |path: &Path| {
+        std::fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))
+    };
+    let path = case
+        .program
+        .as_deref()
+        .ok_or_else(|| format!("no {0}.asm or {0}.obj next to {0}.out", case.name))?;
+    let (origin, words, _) = load_program_file(path)?;
+    let input = match &case.input {
+        Some(path) => read(path)?,
+        None => Vec::new(),
+    };
+    Ok(Files {
+        origin,
+        words,
+        input,
+        expected: read(&case.expected)?,
+    })
+}
+
+/// Describes where `actual` departs from `expected`: the first differing line, then the
+/// differing lines of each side (`-` expected, `+` actual) once the common start and end
+/// are left out. Lines are quoted, so control characters and trailing spaces show.
+pub fn diff(expected: &[u8], actual: &[u8]) -> String {
+    let expected = lines(expected);
+    let actual = lines(actual);
+    let prefix = expected
+        .iter()
+        .zip(&actual)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = expected[prefix..]
+        .iter()
+        .rev()
+        .zip(actual[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut text = format!("first difference at line {}\n", prefix + 1);
+    for (sign, side) in [('-', &expected), ('+', &actual)] {
+        let changed = &side[prefix..side.len() - suffix];
+        for line in changed.iter().take(DIFF_LINES) {
+            let _ = writeln!(text, "{} {:?}", sign, line);
+        }
+        if changed.len() > DIFF_LINES {
+            let _ = writeln!(
+                text,
+                "{} ... {} more line(s)",
+                sign,
+                changed.len() - DIFF_LINES
+            );
+        }
+    }
+    text
+}
+
+// Splits output into lines that keep their newline, so a missing final newline differs.
+fn lines(bytes: &[u8]) -> Vec<String> {
+    bytes
+        .split_inclusive(|&b| b == b'\n')
+        .map(|line| String::from_utf8_lossy(line).into_owned())
+        .collect()
+}
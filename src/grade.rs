@@ -0,0 +1,195 @@
+//! Autograding: a rubric of weighted cases run against a submitted program, with a score
+//! report in JSON.
+//!
+//! A rubric is TOML. Each `[[case]]` takes the keys of a `unit` test (`call`, `registers`,
+//! `memory`, `input`, `max_instructions` and `expect.*`), plus a `weight` in points (1 by
+//! default) and an optional `hint` shown to the student when the case fails. Cases without
+//! a `call` run the whole program until it halts.
+//!
+//! ```toml
+//! title = "Lab 3: string length"
+//! max_instructions = 100000
+//!
+//! [[case]]
+//! name = "prints the length"
+//! weight = 2
+//! input = "hello\n"
+//! expect.output = "5\nHALT\n"
+//! hint = "Count characters up to the newline, not including it."
+//! ```
+
+use crate::debuginfo::DebugInfo;
+use crate::json::Value as Json;
+use crate::json_object;
+use crate::toml;
+use crate::unit::{self, Image, UnitTest};
+use std::path::Path;
+
+/// Instructions a case may run unless the rubric says otherwise.
+pub const DEFAULT_MAX_INSTRUCTIONS: u64 = 1_000_000;
+
+pub struct Case {
+    pub test: UnitTest,
+    pub weight: u32,
+    pub hint: Option<String>,
+}
+
+pub struct Rubric {
+    pub title: String,
+    pub cases: Vec<Case>,
+}
+
+impl Rubric {
+    pub fn load(path: &Path) -> Result<Rubric, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        Rubric::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str) -> Result<Rubric, String> {
+        let rubric = toml::parse(text).map_err(|e| e.to_string())?;
+        let title = unit::string(&rubric, "title")?.unwrap_or("").to_string();
+        let max_instructions = unit::max_instructions(&rubric, DEFAULT_MAX_INSTRUCTIONS)?;
+        let mut cases = Vec::new();
+        for (i, case) in unit::tables(&rubric, "case")?.iter().enumerate() {
+            let test = UnitTest::from_toml(case, &format!("case {}", i + 1), max_instructions)?;
+            let weight = match case.get("weight") {
+                Some(value) => value
+                    .as_integer()
+                    .and_then(|n| u32::try_from(n).ok())
+                    .ok_or_else(|| format!("{}: weight must be a whole number", test.name))?,
+                None => 1,
+            };
+            let hint = unit::string(case, "hint")
+                .map_err(|e| format!("{}: {}", test.name, e))?
+                .map(String::from);
+            cases.push(Case { test, weight, hint });
+        }
+        if cases.is_empty() {
+            return Err("the rubric has no [[case]] tables".to_string());
+        }
+        Ok(Rubric { title, cases })
+    }
+
+    /// Grades the `.obj` or `.asm` at `submission`, with labels from a `.sym` file next to
+    /// an `.obj`. Every case runs on a machine of its own; one that cannot be loaded or
+    /// faults scores nothing.
+    pub fn grade(&self, submission: &Path) -> Report {
+        let image = Image::load(submission).and_then(|mut image| {
+            let sym = submission.with_extension("sym");
+            if submission.extension().is_some_and(|ext| ext == "obj") && sym.is_file() {
+                let symbols = DebugInfo::read_sym_file(&sym.to_string_lossy())
+                    .map_err(|e| format!("Could not read {}: {}", sym.display(), e))?;
+                image.debug.symbols.extend(symbols.symbols);
+            }
+            Ok(image)
+        });
+        let cases = self
+            .cases
+            .iter()
+            .map(|case| {
+                let (passed, instructions, mut feedback) = match &image {
+                    Ok(image) => {
+                        let result = image.run(&case.test);
+                        (result.passed(), result.instructions, result.failures)
+                    }
+                    Err(e) => (
+                        false,
+                        0,
+                        vec![format!("the submission could not be loaded: {}", e)],
+                    ),
+                };
+                if !passed
+                    && image.is_ok()
+                    && let Some(hint) = &case.hint
+                {
+                    feedback.push(hint.clone());
+                }
+                CaseReport {
+                    name: case.test.name.clone(),
+                    weight: case.weight,
+                    passed,
+                    instructions,
+                    feedback,
+                }
+            })
+            .collect();
+        Report {
+            title: self.title.clone(),
+            submission: submission.display().to_string(),
+            error: image.err(),
+            cases,
+        }
+    }
+}
+
+pub struct CaseReport {
+    pub name: String,
+    pub weight: u32,
+    pub passed: bool,
+    pub instructions: u64,
+    /// Why the case failed, then its hint.
+    pub feedback: Vec<String>,
+}
+
+pub struct Report {
+    pub title: String,
+    pub submission: String,
+    /// Set when the submission could not be read or assembled.
+    pub error: Option<String>,
+    pub cases: Vec<CaseReport>,
+}
+
+impl Report {
+    // Summed as u64, so no number of u32 weights can overflow.
+    pub fn score(&self) -> u64 {
+        self.cases
+            .iter()
+            .filter(|c| c.passed)
+            .map(|c| c.weight as u64)
+            .sum()
+    }
+
+    pub fn max_score(&self) -> u64 {
+        self.cases.iter().map(|c| c.weight as u64).sum()
+    }
+
+    /// The score as a percentage of the maximum, or 0 when every weight is 0.
+    pub fn percentage(&self) -> f64 {
+        match self.max_score() {
+            0 => 0.0,
+            max => 100.0 * self.score() as f64 / max as f64,
+        }
+    }
+
+    pub fn to_json(&self) -> Json {
+        let cases: Vec<Json> = self
+            .cases
+            .iter()
+            .map(|case| {
+                let feedback: Vec<Json> = case
+                    .feedback
+                    .iter()
+                    .map(|f| Json::from(f.as_str()))
+                    .collect();
+                json_object! {
+                    "name" => case.name.as_str(),
+                    "weight" => case.weight,
+                    "score" => if case.passed { case.weight } else { 0 },
+                    "passed" => case.passed,
+                    "instructions" => case.instructions,
+                    "feedback" => feedback,
+                }
+            })
+            .collect();
+        json_object! {
+            "title" => self.title.as_str(),
+            "submission" => self.submission.as_str(),
+            "error" => self.error.as_deref().map_or(Json::Null, Json::from),
+            "score" => self.score(),
+            "max_score" => self.max_score(),
+            "percentage" => self.percentage(),
+            "tests" => cases,
+        }
+    }
+}
@@ -1,7 +1,9 @@
 use libc::{
-    ECHO, FD_SET, FD_ZERO, ICANON, STDIN_FILENO, TCSANOW, VMIN, VTIME, fd_set, select, tcgetattr,
-    tcsetattr, termios, timeval,
+    ECHO, FD_SET, FD_ZERO, ICANON, STDIN_FILENO, STDOUT_FILENO, TCSANOW, TIOCGWINSZ, VMIN, VTIME,
+    c_int, fd_set, ioctl, select, sigaction, sigemptyset, tcgetattr, tcsetattr, termios, timeval,
+    winsize,
 };
+use std::sync::atomic::{AtomicI32, Ordering};
 
 #[allow(dead_code)]
 static mut ORIGINAL_TERMINAL_SETTINGS: Option<termios> = None;
@@ -51,3 +53,43 @@ pub fn check_key() -> bool {
         ) > 0
     }
 }
+
+/// Returns the terminal's (columns, rows), if stdout is a terminal.
+pub fn terminal_size() -> Option<(u16, u16)> {
+    unsafe {
+        let mut size: winsize = std::mem::zeroed();
+        if ioctl(STDOUT_FILENO, TIOCGWINSZ, &mut size) == 0 && size.ws_col > 0 {
+            Some((size.ws_col, size.ws_row))
+        } else {
+            None
+        }
+    }
+}
+
+static PENDING_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn record_signal(signal: c_int) {
+    PENDING_SIGNAL.store(signal, Ordering::SeqCst);
+}
+
+/// Records `signals` instead of letting them terminate the process; see `take_signal`.
+/// Blocking reads are interrupted rather than restarted so the signal is seen promptly.
+pub fn catch_signals(signals: &[c_int]) {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = record_signal as *const () as usize;
+        action.sa_flags = 0;
+        sigemptyset(&mut action.sa_mask);
+        for signal in signals {
+            sigaction(*signal, &action, std::ptr::null_mut());
+        }
+    }
+}
+
+/// Returns the last caught signal, if one arrived since the previous call.
+pub fn take_signal() -> Option<c_int> {
+    match PENDING_SIGNAL.swap(0, Ordering::SeqCst) {
+        0 => None,
+        signal => Some(signal),
+    }
+}
@@ -0,0 +1,250 @@
+//! Decoded form of an LC-3 instruction word, and its assembly syntax.
+
+use crate::debuginfo::DebugInfo;
+use crate::vm::{Opcode, Register};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction {
+    /// `nzp` holds the condition bits in their encoded positions (N=4, Z=2, P=1).
+    Br {
+        nzp: u16,
+        offset: u16,
+    },
+    AddReg {
+        dst: Register,
+        src1: Register,
+        src2: Register,
+    },
+    AddImm {
+        dst: Register,
+        src1: Register,
+        imm: u16,
+    },
+    Ld {
+        dst: Register,
+        offset: u16,
+    },
+    St {
+        src: Register,
+        offset: u16,
+    },
+    Jsr {
+        offset: u16,
+    },
+    Jsrr {
+        base: Register,
+    },
+    AndReg {
+        dst: Register,
+        src1: Register,
+        src2: Register,
+    },
+    AndImm {
+        dst: Register,
+        src1: Register,
+        imm: u16,
+    },
+    Ldr {
+        dst: Register,
+        base: Register,
+        offset: u16,
+    },
+    Str {
+        src: Register,
+        base: Register,
+        offset: u16,
+    },
+    Rti,
+    Not {
+        dst: Register,
+        src: Register,
+    },
+    Ldi {
+        dst: Register,
+        offset: u16,
+    },
+    Sti {
+        src: Register,
+        offset: u16,
+    },
+    Jmp {
+        base: Register,
+    },
+    Res,
+    Lea {
+        dst: Register,
+        offset: u16,
+    },
+    Trap {
+        vector: u16,
+    },
+}
+
+fn reg(bits: u16) -> Register {
+    Register::from_u16(bits & 0x7).expect("Register fields are 3 bits wide")
+}
+
+fn sign_ext(x: u16, bit_count: usize) -> u16 {
+    (((x as i16) << (16 - bit_count)) >> (16 - bit_count)) as u16
+}
+
+impl Instruction {
+    /// Decodes a word; offsets and immediates come back already sign-extended.
+    pub fn decode(instr: u16) -> Instruction {
+        let dst = reg(instr >> 9);
+        let src1 = reg(instr >> 6);
+        let opcode = Opcode::from_u16(instr >> 12).expect("Opcode is only 4 bits wide");
+        match opcode {
+            Opcode::Br => Instruction::Br {
+                nzp: (instr >> 9) & 0x7,
+                offset: sign_ext(instr & 0x1FF, 9),
+            },
+            Opcode::Add | Opcode::And => {
+                let imm = (instr >> 5) & 0x1 == 1;
+                match (opcode, imm) {
+                    (Opcode::Add, true) => Instruction::AddImm {
+                        dst,
+                        src1,
+                        imm: sign_ext(instr & 0x1F, 5),
+                    },
+                    (Opcode::Add, false) => Instruction::AddReg {
+                        dst,
+                        src1,
+                        src2: reg(instr),
+                    },
+                    (_, true) => Instruction::AndImm {
+                        dst,
+                        src1,
+                        imm: sign_ext(instr & 0x1F, 5),
+                    },
+                    (_, false) => Instruction::AndReg {
+                        dst,
+                        src1,
+                        src2: reg(instr),
+                    },
+                }
+            }
+            Opcode::Ld => Instruction::Ld {
+                dst,
+                offset: sign_ext(instr & 0x1FF, 9),
+            },
+            Opcode::St => Instruction::St {
+                src: dst,
+                offset: sign_ext(instr & 0x1FF, 9),
+            },
+            Opcode::Jsr => {
+                if (instr >> 11) & 0x1 == 1 {
+                    Instruction::Jsr {
+                        offset: sign_ext(instr & 0x7FF, 11),
+                    }
+                } else {
+                    Instruction::Jsrr { base: src1 }
+                }
+            }
+            Opcode::Ldr => Instruction::Ldr {
+                dst,
+                base: src1,
+                offset: sign_ext(instr & 0x3F, 6),
+            },
+            Opcode::Str => Instruction::Str {
+                src: dst,
+                base: src1,
+                offset: sign_ext(instr & 0x3F, 6),
+            },
+            Opcode::Rti => Instruction::Rti,
+            Opcode::Not => Instruction::Not { dst, src: src1 },
+            Opcode::Ldi => Instruction::Ldi {
+                dst,
+                offset: sign_ext(instr & 0x1FF, 9),
+            },
+            Opcode::Sti => Instruction::Sti {
+                src: dst,
+                offset: sign_ext(instr & 0x1FF, 9),
+            },
+            Opcode::Jmp => Instruction::Jmp { base: src1 },
+            Opcode::Res => Instruction::Res,
+            Opcode::Lea => Instruction::Lea {
+                dst,
+                offset: sign_ext(instr & 0x1FF, 9),
+            },
+            Opcode::Trap => Instruction::Trap {
+                vector: instr & 0xFF,
+            },
+        }
+    }
+
+    /// Formats the instruction located at `addr`, resolving PC-relative targets to
+    /// absolute addresses (or labels, when `debug` knows them).
+    pub fn disassemble(&self, addr: u16, debug: Option<&DebugInfo>) -> String {
+        let target = |offset: u16| {
+            let target = addr.wrapping_add(1).wrapping_add(offset);
+            debug
+                .and_then(|d| d.label_at(target))
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("x{:04X}", target))
+        };
+        let imm = |value: u16| format!("#{}", value as i16);
+
+        match *self {
+            Instruction::Br { nzp, offset } => {
+                let mut name = String::from("BR");
+                for (bit, flag) in [(4, 'n'), (2, 'z'), (1, 'p')] {
+                    if nzp & bit != 0 {
+                        name.push(flag);
+                    }
+                }
+                if nzp == 0 {
+                    return "NOP".to_string();
+                }
+                format!("{} {}", name, target(offset))
+            }
+            Instruction::AddReg { dst, src1, src2 } => {
+                format!("ADD {:?}, {:?}, {:?}", dst, src1, src2)
+            }
+            Instruction::AddImm {
+                dst,
+                src1,
+                imm: value,
+            } => {
+                format!("ADD {:?}, {:?}, {}", dst, src1, imm(value))
+            }
+            Instruction::AndReg { dst, src1, src2 } => {
+                format!("AND {:?}, {:?}, {:?}", dst, src1, src2)
+            }
+            Instruction::AndImm {
+                dst,
+                src1,
+                imm: value,
+            } => {
+                format!("AND {:?}, {:?}, {}", dst, src1, imm(value))
+            }
+            Instruction::Ld { dst, offset } => format!("LD {:?}, {}", dst, target(offset)),
+            Instruction::St { src, offset } => format!("ST {:?}, {}", src, target(offset)),
+            Instruction::Ldi { dst, offset } => format!("LDI {:?}, {}", dst, target(offset)),
+            Instruction::Sti { src, offset } => format!("STI {:?}, {}", src, target(offset)),
+            Instruction::Lea { dst, offset } => format!("LEA {:?}, {}", dst, target(offset)),
+            Instruction::Ldr { dst, base, offset } => {
+                format!("LDR {:?}, {:?}, {}", dst, base, imm(offset))
+            }
+            Instruction::Str { src, base, offset } => {
+                format!("STR {:?}, {:?}, {}", src, base, imm(offset))
+            }
+            Instruction::Jsr { offset } => format!("JSR {}", target(offset)),
+            Instruction::Jsrr { base } => format!("JSRR {:?}", base),
+            Instruction::Jmp { base: Register::R7 } => "RET".to_string(),
+            Instruction::Jmp { base } => format!("JMP {:?}", base),
+            Instruction::Not { dst, src } => format!("NOT {:?}, {:?}", dst, src),
+            Instruction::Trap { vector } => match vector {
+                0x20 => "GETC".to_string(),
+                0x21 => "OUT".to_string(),
+                0x22 => "PUTS".to_string(),
+                0x23 => "IN".to_string(),
+                0x24 => "PUTSP".to_string(),
+                0x25 => "HALT".to_string(),
+                _ => format!("TRAP x{:02X}", vector),
+            },
+            Instruction::Rti => "RTI".to_string(),
+            Instruction::Res => "RES".to_string(),
+        }
+    }
+}
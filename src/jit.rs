@@ -0,0 +1,411 @@
+//! Native x86-64 code for hot basic blocks.
+//!
+//! A compiled block is a System V function `(registers, memory, vm) -> u32` that keeps the
+//! LC-3 registers and memory where the `Vm` has them: `rbx` points at the registers, `r12`
+//! at memory and `r13` at the `Vm` itself. Stores call back into `Vm::write_memory` so the
+//! decode cache and translated-code tracking stay right. Like interpreted blocks, compiled
+//! code stops in front of any access to the device page and leaves it to the interpreter.
+//!
+//! The function sets PC before returning. The result is the number of instructions that
+//! ran, with bit 16 set when a store overwrote translated code.
+
+#[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+compile_error!("the `jit` feature needs x86-64 Linux");
+
+use crate::blocks::{Block, Exit, Op};
+use crate::vm::{ConditionalFlag, DEVICE_PAGE, Register, Vm};
+use std::ptr;
+
+const CHUNK_SIZE: usize = 256 * 1024;
+const MODIFIED: u32 = 1 << 16;
+
+// Displacements from `rbx`.
+const PC: u8 = Register::PC as u8 * 2;
+const COND: u8 = Register::Cond as u8 * 2;
+const R7: u8 = Register::R7 as u8 * 2;
+
+type Entry = unsafe extern "sysv64" fn(*mut u16, *mut u16, *mut Vm) -> u32;
+
+unsafe extern "sysv64" fn store(vm: *mut Vm, addr: u32, value: u32) -> u32 {
+    // SAFETY: compiled code passes on the `Vm` pointer it was called with.
+    let vm = unsafe { &mut *vm };
+    vm.write_memory(addr as u16, value as u16);
+    if vm.take_code_modified() { MODIFIED } else { 0 }
+}
+
+pub(crate) struct NativeBlock {
+    entry: Entry,
+}
+
+impl NativeBlock {
+    /// Runs the block on `vm`; `len` is how many ops it was compiled from.
+    pub(crate) fn call(&self, vm: &mut Vm, len: usize) -> Exit {
+        let vm_ptr: *mut Vm = vm;
+        // SAFETY: `entry` points at code generated for this layout, in a chunk that stays
+        // mapped until the engine drops every block compiled into it.
+        let result = unsafe {
+            let (registers, memory) = (*vm_ptr).raw_parts();
+            (self.entry)(registers, memory, vm_ptr)
+        };
+        let executed = (result & 0xFFFF) as usize;
+        if result & MODIFIED != 0 {
+            Exit::Modified(executed)
+        } else if executed < len {
+            Exit::Bail(executed)
+        } else {
+            Exit::Done
+        }
+    }
+}
+
+struct Chunk {
+    base: *mut u8,
+    used: usize,
+}
+
+impl Chunk {
+    fn new() -> Option<Chunk> {
+        // SAFETY: a fresh anonymous private mapping.
+        let base = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                CHUNK_SIZE,
+                libc::PROT_READ | libc::PROT_EXEC,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        (base != libc::MAP_FAILED).then(|| Chunk {
+            base: base.cast(),
+            used: 0,
+        })
+    }
+
+    /// Copies `code` in, keeping the chunk writable only while it does.
+    fn append(&mut self, code: &[u8]) -> Option<*const u8> {
+        if self.used + code.len() > CHUNK_SIZE {
+            return None;
+        }
+        // SAFETY: the range is inside the mapping, and no compiled code runs while the
+        // chunk is not executable.
+        unsafe {
+            let base = self.base.cast();
+            if libc::mprotect(base, CHUNK_SIZE, libc::PROT_READ | libc::PROT_WRITE) != 0 {
+                return None;
+            }
+            let at = self.base.add(self.used);
+            ptr::copy_nonoverlapping(code.as_ptr(), at, code.len());
+            if libc::mprotect(base, CHUNK_SIZE, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                return None;
+            }
+            self.used += code.len();
+            Some(at)
+        }
+    }
+}
+
+impl Drop for Chunk {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was created by `Chunk::new` with this size.
+        unsafe {
+            libc::munmap(self.base.cast(), CHUNK_SIZE);
+        }
+    }
+}
+
+/// Executable memory holding compiled blocks.
+pub(crate) struct CodeCache {
+    chunks: Vec<Chunk>,
+}
+
+impl CodeCache {
+    pub(crate) fn new() -> Self {
+        Self { chunks: Vec::new() }
+    }
+
+    /// Unmaps all compiled code. Every `NativeBlock` from this cache must be gone.
+    pub(crate) fn clear(&mut self) {
+        self.chunks.clear();
+    }
+
+    /// `None` when executable memory is not available; the block stays interpreted.
+    pub(crate) fn compile(&mut self, block: &Block) -> Option<NativeBlock> {
+        let code = generate(block);
+        let at = match self.chunks.last_mut().and_then(|chunk| chunk.append(&code)) {
+            Some(at) => at,
+            None => {
+                self.chunks.push(Chunk::new()?);
+                self.chunks.last_mut()?.append(&code)?
+            }
+        };
+        // SAFETY: `at` is the start of code generated with the `Entry` ABI.
+        let entry = unsafe { std::mem::transmute::<*const u8, Entry>(at) };
+        Some(NativeBlock { entry })
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Stub {
+    /// Leave op `n` to the interpreter.
+    Bail(usize),
+    /// Op `n` overwrote translated code.
+    Modified(usize),
+}
+
+#[derive(Default)]
+struct Asm {
+    code: Vec<u8>,
+    // Positions of rel32 jumps to patch with the address of a stub.
+    fixups: Vec<(usize, Stub)>,
+}
+
+fn reg(r: Register) -> u8 {
+    r as u8 * 2
+}
+
+fn cond_of(value: u16) -> u16 {
+    if value == 0 {
+        ConditionalFlag::Zro as u16
+    } else if value >> 15 == 1 {
+        ConditionalFlag::Neg as u16
+    } else {
+        ConditionalFlag::Pos as u16
+    }
+}
+
+impl Asm {
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn imm16(&mut self, value: u16) {
+        self.emit(&value.to_le_bytes());
+    }
+
+    fn imm32(&mut self, value: u32) {
+        self.emit(&value.to_le_bytes());
+    }
+
+    /// mov ax, [rbx + disp]
+    fn load_ax(&mut self, disp: u8) {
+        self.emit(&[0x66, 0x8B, 0x43, disp]);
+    }
+
+    /// mov cx, [rbx + disp]
+    fn load_cx(&mut self, disp: u8) {
+        self.emit(&[0x66, 0x8B, 0x4B, disp]);
+    }
+
+    /// mov [rbx + disp], ax
+    fn store_ax(&mut self, disp: u8) {
+        self.emit(&[0x66, 0x89, 0x43, disp]);
+    }
+
+    /// mov word [rbx + disp], value
+    fn store_imm(&mut self, disp: u8, value: u16) {
+        self.emit(&[0x66, 0xC7, 0x43, disp]);
+        self.imm16(value);
+    }
+
+    /// movzx eax, word [r12 + addr * 2]
+    fn load_memory(&mut self, addr: u16) {
+        self.emit(&[0x41, 0x0F, 0xB7, 0x84, 0x24]);
+        self.imm32(addr as u32 * 2);
+    }
+
+    /// movzx eax, word [r12 + rax * 2]; rax holds a zero-extended address.
+    fn load_memory_at_ax(&mut self) {
+        self.emit(&[0x41, 0x0F, 0xB7, 0x04, 0x44]);
+    }
+
+    /// Stores ax in `dst` and sets Cond from it, like `Vm::update_flags`.
+    fn set_with_flags(&mut self, dst: Register) {
+        self.store_ax(reg(dst));
+        // test ax, ax; mov ecx, Z; jz done; mov ecx, N; js done; mov ecx, P
+        self.emit(&[0x66, 0x85, 0xC0]);
+        self.emit(&[0xB9]);
+        self.imm32(ConditionalFlag::Zro as u32);
+        self.emit(&[0x74, 0x0C, 0xB9]);
+        self.imm32(ConditionalFlag::Neg as u32);
+        self.emit(&[0x78, 0x05, 0xB9]);
+        self.imm32(ConditionalFlag::Pos as u32);
+        // done: mov [rbx + COND], cx
+        self.emit(&[0x66, 0x89, 0x4B, COND]);
+    }
+
+    fn jump_to_stub(&mut self, opcode: &[u8], stub: Stub) {
+        self.emit(opcode);
+        self.fixups.push((self.code.len(), stub));
+        self.imm32(0);
+    }
+
+    /// Bails out to the interpreter at op `i` if ax is in the device page.
+    fn check_device(&mut self, i: usize) {
+        // cmp ax, DEVICE_PAGE; jae bail
+        self.emit(&[0x66, 0x3D]);
+        self.imm16(DEVICE_PAGE);
+        self.jump_to_stub(&[0x0F, 0x83], Stub::Bail(i));
+    }
+
+    /// Writes `src` to the address in esi through `store`.
+    fn call_store(&mut self, src: Register, i: usize) {
+        // movzx edx, word [rbx + src]; mov rdi, r13; mov rax, store; call rax
+        self.emit(&[0x0F, 0xB7, 0x53, reg(src)]);
+        self.emit(&[0x4C, 0x89, 0xEF]);
+        self.emit(&[0x48, 0xB8]);
+        self.emit(&(store as *const () as u64).to_le_bytes());
+        self.emit(&[0xFF, 0xD0]);
+        // test eax, eax; jnz modified
+        self.emit(&[0x85, 0xC0]);
+        self.jump_to_stub(&[0x0F, 0x85], Stub::Modified(i));
+    }
+
+    /// mov eax, result; pop r13; pop r12; pop rbx; ret
+    fn ret(&mut self, result: u32) {
+        self.emit(&[0xB8]);
+        self.imm32(result);
+        self.emit(&[0x41, 0x5D, 0x41, 0x5C, 0x5B, 0xC3]);
+    }
+}
+
+fn generate(block: &Block) -> Vec<u8> {
+    let mut asm = Asm::default();
+    // push rbx; push r12; push r13 (leaves the stack 16-byte aligned for calls)
+    asm.emit(&[0x53, 0x41, 0x54, 0x41, 0x55]);
+    // mov rbx, rdi; mov r12, rsi; mov r13, rdx
+    asm.emit(&[0x48, 0x89, 0xFB, 0x49, 0x89, 0xF4, 0x49, 0x89, 0xD5]);
+
+    let mut transfers = false;
+    for (i, op) in block.ops.iter().enumerate() {
+        match *op {
+            Op::AddReg(dst, src1, src2) => {
+                asm.load_ax(reg(src1));
+                asm.load_cx(reg(src2));
+                asm.emit(&[0x66, 0x01, 0xC8]);
+                asm.set_with_flags(dst);
+            }
+            Op::AddImm(dst, src1, imm) => {
+                asm.load_ax(reg(src1));
+                asm.emit(&[0x66, 0x05]);
+                asm.imm16(imm);
+                asm.set_with_flags(dst);
+            }
+            Op::AndReg(dst, src1, src2) => {
+                asm.load_ax(reg(src1));
+                asm.load_cx(reg(src2));
+                asm.emit(&[0x66, 0x21, 0xC8]);
+                asm.set_with_flags(dst);
+            }
+            Op::AndImm(dst, src1, imm) => {
+                asm.load_ax(reg(src1));
+                asm.emit(&[0x66, 0x25]);
+                asm.imm16(imm);
+                asm.set_with_flags(dst);
+            }
+            Op::Not(dst, src) => {
+                asm.load_ax(reg(src));
+                asm.emit(&[0x66, 0xF7, 0xD0]);
+                asm.set_with_flags(dst);
+            }
+            Op::Lea(dst, addr) => {
+                asm.store_imm(reg(dst), addr);
+                asm.store_imm(COND, cond_of(addr));
+            }
+            Op::Ld(dst, addr) => {
+                asm.load_memory(addr);
+                asm.set_with_flags(dst);
+            }
+            Op::Ldr(dst, base, offset) => {
+                asm.load_ax(reg(base));
+                asm.emit(&[0x66, 0x05]);
+                asm.imm16(offset);
+                asm.check_device(i);
+                // movzx eax, ax
+                asm.emit(&[0x0F, 0xB7, 0xC0]);
+                asm.load_memory_at_ax();
+                asm.set_with_flags(dst);
+            }
+            Op::Ldi(dst, pointer) => {
+                asm.load_memory(pointer);
+                asm.check_device(i);
+                asm.load_memory_at_ax();
+                asm.set_with_flags(dst);
+            }
+            Op::St(src, addr) => {
+                // mov esi, addr
+                asm.emit(&[0xBE]);
+                asm.imm32(addr as u32);
+                asm.call_store(src, i);
+            }
+            Op::Str(src, base, offset) => {
+                asm.load_ax(reg(base));
+                asm.emit(&[0x66, 0x05]);
+                asm.imm16(offset);
+                asm.check_device(i);
+                // movzx esi, ax
+                asm.emit(&[0x0F, 0xB7, 0xF0]);
+                asm.call_store(src, i);
+            }
+            Op::Sti(src, pointer) => {
+                asm.load_memory(pointer);
+                asm.check_device(i);
+                asm.emit(&[0x0F, 0xB7, 0xF0]);
+                asm.call_store(src, i);
+            }
+            Op::Nop => {}
+            Op::Br { nzp, target } => {
+                // test word [rbx + COND], nzp; PC = end; jz taken_done; PC = target
+                asm.emit(&[0x66, 0xF7, 0x43, COND]);
+                asm.imm16(nzp);
+                asm.store_imm(PC, block.end);
+                asm.emit(&[0x74, 0x06]);
+                asm.store_imm(PC, target);
+                transfers = true;
+            }
+            Op::Jump(target) => {
+                asm.store_imm(PC, target);
+                transfers = true;
+            }
+            Op::Jmp(base) => {
+                asm.load_ax(reg(base));
+                asm.store_ax(PC);
+                transfers = true;
+            }
+            Op::Jsr(target) => {
+                asm.store_imm(R7, block.end);
+                asm.store_imm(PC, target);
+                transfers = true;
+            }
+            Op::Jsrr(base) => {
+                // The base is read before R7 is written, as in the interpreter.
+                asm.load_ax(reg(base));
+                asm.store_ax(PC);
+                asm.store_imm(R7, block.end);
+                transfers = true;
+            }
+        }
+    }
+    if !transfers {
+        asm.store_imm(PC, block.end);
+    }
+    asm.ret(block.ops.len() as u32);
+
+    let fixups = std::mem::take(&mut asm.fixups);
+    for (at, stub) in fixups {
+        let target = asm.code.len();
+        let rel = (target - (at + 4)) as u32;
+        asm.code[at..at + 4].copy_from_slice(&rel.to_le_bytes());
+        match stub {
+            Stub::Bail(i) => {
+                asm.store_imm(PC, block.start.wrapping_add(i as u16));
+                asm.ret(i as u32);
+            }
+            Stub::Modified(i) => {
+                asm.store_imm(PC, block.start.wrapping_add(i as u16 + 1));
+                asm.ret((i as u32 + 1) | MODIFIED);
+            }
+        }
+    }
+    asm.code
+}
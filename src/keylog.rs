@@ -0,0 +1,78 @@
+//! Keystroke logs: every key a program read, with the instruction count it was read at, so
+//! an interactive session can be replayed exactly.
+//!
+//! A log is text. The first line is `HEADER`, then one key per line: the number of
+//! instructions completed before the one that read the key, and the key in hex. Anything
+//! after `#` is a comment; written logs show printable keys there.
+//!
+//! ```text
+//! lc3-keys 1
+//! 18734 x79  # y
+//! 240112 x77  # w
+//! ```
+
+use std::io::{self, Write};
+use std::path::Path;
+
+pub const HEADER: &str = "lc3-keys 1";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyEvent {
+    /// Instructions completed before the instruction that read the key.
+    pub instructions: u64,
+    pub key: u8,
+}
+
+impl KeyEvent {
+    pub fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        write!(out, "{} x{:02X}", self.instructions, self.key)?;
+        if self.key.is_ascii_graphic() {
+            write!(out, "  # {}", self.key as char)?;
+        }
+        writeln!(out)
+    }
+}
+
+/// Parses a log, checking that its counts never go backwards.
+pub fn parse(text: &str) -> Result<Vec<KeyEvent>, String> {
+    let mut lines = text.lines().enumerate();
+    if lines.next().map(|(_, line)| line.trim()) != Some(HEADER) {
+        return Err(format!("not a key log (expected `{}` first)", HEADER));
+    }
+    let mut events: Vec<KeyEvent> = Vec::new();
+    for (i, line) in lines {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let event =
+            parse_event(line).ok_or_else(|| format!("line {}: invalid key: {}", i + 1, line))?;
+        if events
+            .last()
+            .is_some_and(|last| last.instructions > event.instructions)
+        {
+            return Err(format!(
+                "line {}: instruction counts must not decrease",
+                i + 1
+            ));
+        }
+        events.push(event);
+    }
+    Ok(events)
+}
+
+fn parse_event(line: &str) -> Option<KeyEvent> {
+    let mut fields = line.split_whitespace();
+    let instructions = fields.next()?.parse().ok()?;
+    let key = u8::from_str_radix(fields.next()?.strip_prefix('x')?, 16).ok()?;
+    fields
+        .next()
+        .is_none()
+        .then_some(KeyEvent { instructions, key })
+}
+
+pub fn read_file(path: &Path) -> Result<Vec<KeyEvent>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
@@ -1,2 +1,33 @@
+pub mod aot;
+pub mod asm;
+pub mod bench;
+pub mod blocks;
+pub mod callstack;
+pub mod cfg;
+pub mod conformance;
+pub mod console;
+pub mod coverage;
+pub mod dap;
+pub mod debuginfo;
+pub mod fuzz;
+pub mod gdb;
+pub mod golden;
+pub mod grade;
 pub mod hardware;
+pub mod instruction;
+#[cfg(feature = "jit")]
+mod jit;
+pub mod json;
+pub mod keylog;
+pub mod lint;
+pub mod micro;
+pub mod playthrough;
+pub mod profile;
+pub mod reference;
+pub mod snapshot;
+pub mod terminal;
+pub mod timing;
+pub mod toml;
+pub mod tui;
+pub mod unit;
 pub mod vm;
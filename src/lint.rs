@@ -0,0 +1,408 @@
+//! Static checks for common LC-3 programming mistakes, run over the control-flow graph.
+//!
+//! With the program's assembly (`DebugInfo::lines`), words the assembler emitted as data
+//! are known: unreachable code can be told apart from data, and code that runs into data is
+//! reported instead of being analysed as instructions. Without it every reachable word is
+//! treated as an instruction and unreachable code is not reported.
+
+use crate::cfg::{Block, Cfg, EdgeKind, Exit};
+use crate::debuginfo::DebugInfo;
+use crate::instruction::Instruction;
+use crate::vm::Register;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Check {
+    /// A register is read before any path from the entry point writes it.
+    UninitializedRegister,
+    /// A subroutine returns through R7 after a call, trap or other write replaced it.
+    ClobberedReturnAddress,
+    /// Instructions no path from the entry point reaches.
+    UnreachableCode,
+    /// `LD`, `LDI`, `ST` or `STI` whose PC-relative address holds an instruction.
+    OffsetIntoCode,
+    /// Execution runs into data or past the end of the image instead of halting.
+    MissingHalt,
+}
+
+impl Check {
+    pub fn name(self) -> &'static str {
+        match self {
+            Check::UninitializedRegister => "uninitialized-register",
+            Check::ClobberedReturnAddress => "clobbered-return-address",
+            Check::UnreachableCode => "unreachable-code",
+            Check::OffsetIntoCode => "offset-into-code",
+            Check::MissingHalt => "missing-halt",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub addr: u16,
+    pub check: Check,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// `file:line: message [check]` when `debug` knows the source line, otherwise the
+    /// address and closest label.
+    pub fn format(&self, debug: &DebugInfo) -> String {
+        let location = match (&debug.source, debug.line_of(self.addr)) {
+            (Some(source), Some(line)) => format!("{}:{}", source.display(), line),
+            _ => describe(self.addr, debug),
+        };
+        format!("{}: {} [{}]", location, self.message, self.check.name())
+    }
+}
+
+/// `addr` and the label it is at or after, if there is one.
+fn describe(addr: u16, debug: &DebugInfo) -> String {
+    let hex = format!("x{:04X}", addr);
+    match debug.describe(addr) {
+        name if name == hex => hex,
+        name => format!("{} ({})", hex, name),
+    }
+}
+
+// Registers as bits of a mask.
+#[derive(Clone, Copy, Default, PartialEq)]
+struct Registers(u8);
+
+impl Registers {
+    fn with(self, reg: Register) -> Self {
+        Registers(self.0 | 1 << reg as u8)
+    }
+
+    fn contains(self, reg: Register) -> bool {
+        self.0 & 1 << reg as u8 != 0
+    }
+
+    fn union(self, other: Self) -> Self {
+        Registers(self.0 | other.0)
+    }
+}
+
+fn reads(instr: Instruction) -> Vec<Register> {
+    match instr {
+        Instruction::AddReg { src1, src2, .. } | Instruction::AndReg { src1, src2, .. } => {
+            vec![src1, src2]
+        }
+        // `AND R, R, #0` clears R whatever it held.
+        Instruction::AndImm { imm: 0, .. } => vec![],
+        Instruction::AddImm { src1, .. } | Instruction::AndImm { src1, .. } => vec![src1],
+        Instruction::Not { src, .. }
+        | Instruction::St { src, .. }
+        | Instruction::Sti { src, .. } => {
+            vec![src]
+        }
+        Instruction::Str { src, base, .. } => vec![src, base],
+        Instruction::Ldr { base, .. } | Instruction::Jmp { base } | Instruction::Jsrr { base } => {
+            vec![base]
+        }
+        // OUT, PUTS and PUTSP take R0.
+        Instruction::Trap {
+            vector: 0x21 | 0x22 | 0x24,
+        } => vec![Register::R0],
+        _ => vec![],
+    }
+}
+
+fn writes(instr: Instruction) -> Registers {
+    let none = Registers::default();
+    match instr {
+        Instruction::AddReg { dst, .. }
+        | Instruction::AddImm { dst, .. }
+        | Instruction::AndReg { dst, .. }
+        | Instruction::AndImm { dst, .. }
+        | Instruction::Not { dst, .. }
+        | Instruction::Ld { dst, .. }
+        | Instruction::Ldi { dst, .. }
+        | Instruction::Ldr { dst, .. }
+        | Instruction::Lea { dst, .. } => none.with(dst),
+        Instruction::Jsr { .. } | Instruction::Jsrr { .. } => none.with(Register::R7),
+        // GETC and IN return the key in R0.
+        Instruction::Trap {
+            vector: 0x20 | 0x23,
+        } => none.with(Register::R0).with(Register::R7),
+        Instruction::Trap { .. } => none.with(Register::R7),
+        _ => none,
+    }
+}
+
+struct Linter<'a> {
+    cfg: &'a Cfg,
+    debug: &'a DebugInfo,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Runs every check, returning the findings in address order.
+pub fn lint(cfg: &Cfg, debug: &DebugInfo) -> Vec<Diagnostic> {
+    let mut linter = Linter {
+        cfg,
+        debug,
+        diagnostics: Vec::new(),
+    };
+    linter.uninitialized_registers();
+    linter.clobbered_return_addresses();
+    linter.unreachable_code();
+    linter.offsets_into_code();
+    linter.missing_halts();
+    linter
+        .diagnostics
+        .sort_by_key(|d| (d.addr, d.check, d.message.clone()));
+    linter.diagnostics
+}
+
+impl Linter<'_> {
+    fn report(&mut self, addr: u16, check: Check, message: String) {
+        self.diagnostics.push(Diagnostic {
+            addr,
+            check,
+            message,
+        });
+    }
+
+    /// Whether the assembler emitted `addr` as an instruction, when that is known.
+    fn is_instruction(&self, addr: u16) -> bool {
+        self.debug.lines.is_empty() || self.debug.lines.contains_key(&addr)
+    }
+
+    fn instructions<'b>(
+        &'b self,
+        block: &'b Block,
+    ) -> impl Iterator<Item = (u16, Instruction)> + 'b {
+        block
+            .addresses()
+            .take_while(|&addr| self.is_instruction(addr))
+            .filter_map(|addr| Some((addr, self.cfg.instruction(addr)?)))
+    }
+
+    fn describe(&self, addr: u16) -> String {
+        describe(addr, self.debug)
+    }
+
+    /// Registers possibly written by everything reachable from `start`, calls included.
+    fn reachable_writes(&self, start: u16) -> Registers {
+        let mut written = Registers::default();
+        let mut seen = BTreeSet::new();
+        let mut pending = vec![start];
+        while let Some(start) = pending.pop() {
+            let Some(block) = self.cfg.blocks.get(&start) else {
+                continue;
+            };
+            if !seen.insert(start) {
+                continue;
+            }
+            for (_, instr) in self.instructions(block) {
+                written = written.union(writes(instr));
+            }
+            pending.extend(block.edges.iter().map(|e| e.target));
+        }
+        written
+    }
+
+    // Forward may-analysis of the registers some path has written. A call passes the
+    // caller's registers to the callee, and its return point gets whatever the callee
+    // can write on top of them.
+    fn uninitialized_registers(&mut self) {
+        let mut callee_writes = BTreeMap::new();
+        let mut entering: BTreeMap<u16, Registers> = BTreeMap::new();
+        let mut pending = Vec::new();
+        // An entry point outside the image has no code to analyse.
+        if self.cfg.blocks.contains_key(&self.cfg.entry) {
+            entering.insert(self.cfg.entry, Registers::default());
+            pending.push(self.cfg.entry);
+        }
+        while let Some(start) = pending.pop() {
+            let Some(block) = self.cfg.blocks.get(&start) else {
+                continue;
+            };
+            let mut written = entering[&start];
+            for (_, instr) in self.instructions(block) {
+                written = written.union(writes(instr));
+            }
+            let mut after_call = written;
+            for edge in block.edges.iter().filter(|e| e.kind == EdgeKind::Call) {
+                let callee = *callee_writes
+                    .entry(edge.target)
+                    .or_insert_with(|| self.reachable_writes(edge.target));
+                after_call = after_call.union(callee);
+            }
+            for edge in &block.edges {
+                let out = match edge.kind {
+                    EdgeKind::Next if after_call != written => after_call,
+                    _ => written,
+                };
+                let before = entering.get(&edge.target).copied();
+                let merged = before.unwrap_or_default().union(out);
+                if before != Some(merged) && self.cfg.blocks.contains_key(&edge.target) {
+                    entering.insert(edge.target, merged);
+                    pending.push(edge.target);
+                }
+            }
+        }
+
+        for (start, entry_state) in entering {
+            let block = &self.cfg.blocks[&start];
+            let mut written = entry_state;
+            let mut found = Vec::new();
+            for (addr, instr) in self.instructions(block) {
+                for reg in reads(instr) {
+                    if !written.contains(reg) {
+                        found.push((addr, instr, reg));
+                    }
+                }
+                written = written.union(writes(instr));
+            }
+            for (addr, instr, reg) in found {
+                let message = format!(
+                    "{} reads {:?}, which no path from the entry point writes",
+                    instr.disassemble(addr, Some(self.debug)),
+                    reg
+                );
+                self.report(addr, Check::UninitializedRegister, message);
+            }
+        }
+    }
+
+    // For each subroutine, tracks where R7 was last overwritten by anything but a load
+    // (restoring a saved return address) and reports RETs reached after that.
+    fn clobbered_return_addresses(&mut self) {
+        let entries: Vec<u16> = self
+            .cfg
+            .subroutines
+            .iter()
+            .map(|s| s.entry)
+            .filter(|&e| e != self.cfg.entry)
+            .collect();
+        let mut found = BTreeMap::new();
+        for entry in entries {
+            let mut entering: BTreeMap<u16, Option<u16>> = BTreeMap::new();
+            entering.insert(entry, None);
+            let mut pending = vec![entry];
+            while let Some(start) = pending.pop() {
+                let Some(block) = self.cfg.blocks.get(&start) else {
+                    continue;
+                };
+                let mut clobbered = entering[&start];
+                for (addr, instr) in self.instructions(block) {
+                    match instr {
+                        Instruction::Jmp { base: Register::R7 } => {
+                            if let Some(at) = clobbered {
+                                found.entry(addr).or_insert(at);
+                            }
+                        }
+                        Instruction::Ld {
+                            dst: Register::R7, ..
+                        }
+                        | Instruction::Ldi {
+                            dst: Register::R7, ..
+                        }
+                        | Instruction::Ldr {
+                            dst: Register::R7, ..
+                        } => clobbered = None,
+                        _ if writes(instr).contains(Register::R7) => clobbered = Some(addr),
+                        _ => {}
+                    }
+                }
+                for target in block.local_successors() {
+                    let before = entering.get(&target).copied();
+                    let merged = match (before.flatten(), clobbered) {
+                        (Some(a), Some(b)) => Some(a.min(b)),
+                        (a, b) => a.or(b),
+                    };
+                    if before != Some(merged) && self.cfg.blocks.contains_key(&target) {
+                        entering.insert(target, merged);
+                        pending.push(target);
+                    }
+                }
+            }
+        }
+        for (ret, at) in found {
+            let instr = self.cfg.instruction(at).expect("Clobbers are instructions");
+            let message = format!(
+                "RET uses R7 after {} at {} overwrote the return address",
+                instr.disassemble(at, Some(self.debug)),
+                self.describe(at)
+            );
+            self.report(ret, Check::ClobberedReturnAddress, message);
+        }
+    }
+
+    fn unreachable_code(&mut self) {
+        let mut runs: Vec<(u16, u16)> = Vec::new();
+        for &addr in self.debug.lines.keys() {
+            if self.cfg.word(addr).is_none() || self.cfg.is_code(addr) {
+                continue;
+            }
+            match runs.last_mut() {
+                Some((_, end)) if end.wrapping_add(1) == addr => *end = addr,
+                _ => runs.push((addr, addr)),
+            }
+        }
+        for (start, end) in runs {
+            let count = end - start + 1;
+            let message = match count {
+                1 => "unreachable instruction".to_string(),
+                _ => format!("{} unreachable instructions", count),
+            };
+            self.report(start, Check::UnreachableCode, message);
+        }
+    }
+
+    fn offsets_into_code(&mut self) {
+        let mut found = Vec::new();
+        for block in self.cfg.blocks.values() {
+            for (addr, instr) in self.instructions(block) {
+                let (verb, offset) = match instr {
+                    Instruction::Ld { offset, .. } | Instruction::Ldi { offset, .. } => {
+                        ("reads", offset)
+                    }
+                    Instruction::St { offset, .. } | Instruction::Sti { offset, .. } => {
+                        ("overwrites", offset)
+                    }
+                    _ => continue,
+                };
+                let target = addr.wrapping_add(1).wrapping_add(offset);
+                let code = self.cfg.is_code(target) && self.is_instruction(target)
+                    || self.debug.lines.contains_key(&target);
+                if code {
+                    let message = format!(
+                        "{} {} the instruction at {}; data was likely intended",
+                        instr.disassemble(addr, Some(self.debug)),
+                        verb,
+                        self.describe(target)
+                    );
+                    found.push((addr, message));
+                }
+            }
+        }
+        for (addr, message) in found {
+            self.report(addr, Check::OffsetIntoCode, message);
+        }
+    }
+
+    fn missing_halts(&mut self) {
+        let mut found = Vec::new();
+        for block in self.cfg.blocks.values() {
+            let data = block.addresses().find(|&addr| !self.is_instruction(addr));
+            match data {
+                // Only where code runs into the data, not at every block inside it.
+                Some(addr) if self.is_instruction(addr.wrapping_sub(1)) => {
+                    let message = format!("execution runs into data at {}", self.describe(addr));
+                    found.push((addr.wrapping_sub(1), message));
+                }
+                Some(_) => {}
+                None if block.exit == Exit::OffImage => {
+                    let message = "execution runs past the end of the program".to_string();
+                    found.push((block.end, message));
+                }
+                None => {}
+            }
+        }
+        for (addr, message) in found {
+            self.report(addr, Check::MissingHalt, message);
+        }
+    }
+}
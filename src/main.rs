@@ -1,295 +1,1044 @@
-mod hardware;
-mod vm;
-use hardware::{disable_input_buffering, restore_input_buffering};
-use std::io::{Read, Write};
-use vm::*;
+use lc3_vm_rust::asm::Assembly;
+use lc3_vm_rust::bench::{Engine, WORKLOADS, Workload};
+use lc3_vm_rust::blocks::BlockEngine;
+use lc3_vm_rust::callstack::CallStack;
+use lc3_vm_rust::cfg::Cfg;
+use lc3_vm_rust::console::{OnExhausted, ScriptedConsole, SharedConsole, StdConsole};
+use lc3_vm_rust::coverage::Coverage;
+use lc3_vm_rust::debuginfo::{DebugInfo, load_program_file};
+use lc3_vm_rust::golden::{self, Outcome};
+use lc3_vm_rust::grade::Rubric;
+use lc3_vm_rust::hardware::{
+    catch_signals, disable_input_buffering, restore_input_buffering, take_signal,
+};
+use lc3_vm_rust::keylog::{self, KeyEvent};
+use lc3_vm_rust::micro::Microarchitecture;
+use lc3_vm_rust::profile::Profiler;
+use lc3_vm_rust::snapshot::Snapshot;
+use lc3_vm_rust::terminal::{CapturingConsole, Terminal};
+use lc3_vm_rust::timing::CycleModel;
+use lc3_vm_rust::tui::Tui;
+use lc3_vm_rust::unit::Suite;
+use lc3_vm_rust::vm::{Register, Step, Vm, VmError, read_obj_file};
+use lc3_vm_rust::{aot, asm, dap, fuzz, gdb, lint};
+use std::fs::File;
+use std::io::BufWriter;
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-fn main() {
-    disable_input_buffering();
+const USAGE: &str = "Please use: cargo run -- [--gdb <port> | --tui] [--sym file.sym] \
+[--source file.asm] [--save-state file] [--restore file] [--profile report.txt] \
+[--folded stacks.folded] [--coverage hits.txt] [--lcov lcov.info] [--blocks | --jit] \
+[--cycles] [--memory-latency N] [--micro] [--micro-trace trace.txt] [--input file] \
+[--input-string keys] [--on-input-end halt|error|block] [--record keys.log] \
+[--replay keys.log] [--screenshot screen.txt] path/file_name.obj | --dap | translate file.obj [-o out.rs] | bench [--instructions N] \
+[--engine name] [--workload name] [--json] | cfg file.obj|file.asm [--entry addr] [--sym file.sym] [-o out.dot] | lint file.asm|file.obj [--entry addr] [--sym file.sym] | test dir \
+[--max-instructions N] [--engine name] | unit spec.toml | grade rubric.toml \
+submission.obj|submission.asm [-o report.json] | fuzz [--cases N] [--seed S] [--engine name] \
+[--max-instructions N]";
 
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 {
-        println!("Please use: cargo run -- path/file_name.obj");
-        return;
+const BENCH_INSTRUCTIONS: u64 = 20_000_000;
+const FUZZ_CASES: u64 = 10_000;
+
+/// The one of `choices` called `name`, or an error listing them all.
+fn parse_choice<T: Copy>(
+    what: &str,
+    name: &str,
+    choices: &[T],
+    name_of: fn(T) -> &'static str,
+) -> Result<T, String> {
+    choices
+        .iter()
+        .copied()
+        .find(|&choice| name_of(choice) == name)
+        .ok_or_else(|| {
+            let names: Vec<&str> = choices.iter().map(|&choice| name_of(choice)).collect();
+            format!(
+                "Unknown {} {}; expected one of {}",
+                what,
+                name,
+                names.join(", ")
+            )
+        })
+}
+
+fn parse_engine(name: &str) -> Result<Engine, String> {
+    parse_choice("engine", name, Engine::ALL, Engine::name)
+}
+
+/// `bench`: runs the synthetic workloads headless and reports MIPS for each, as a table or
+/// as one JSON object per line.
+fn bench_command(args: &[String]) -> Result<(), String> {
+    let mut instructions = BENCH_INSTRUCTIONS;
+    let mut engine = Engine::Cache;
+    let mut workloads: Vec<&Workload> = Vec::new();
+    let mut json = false;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--instructions" => {
+                let count = iter.next().ok_or("--instructions expects a count")?;
+                instructions = count
+                    .parse()
+                    .map_err(|_| format!("Invalid instruction count: {}", count))?;
+            }
+            "--engine" => {
+                let name = iter.next().ok_or("--engine expects a name")?;
+                engine = parse_engine(name)?;
+            }
+            "--workload" => {
+                let name = iter.next().ok_or("--workload expects a name")?;
+                workloads.push(Workload::find(name).ok_or_else(|| {
+                    let names: Vec<&str> = WORKLOADS.iter().map(|w| w.name).collect();
+                    format!(
+                        "Unknown workload {}; expected one of {}",
+                        name,
+                        names.join(", ")
+                    )
+                })?);
+            }
+            "--json" => json = true,
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+    if workloads.is_empty() {
+        workloads = WORKLOADS.iter().collect();
     }
 
-    let mut lc3 = Vm::new();
-    lc3.read_image_file(&args[1])
-        .expect("Error while loading .obj file");
-
-    let mut running = true;
-    while running {
-        //fetch
-        let pc_idx = lc3.read_register(Register::PC);
-        let instr = lc3.read_memory(pc_idx);
-
-        lc3.write_register(Register::PC, pc_idx.wrapping_add(1));
-
-        //decode
-        let opcode_bits = instr >> 12;
-
-        //execute
-        if let Some(opcode) = Opcode::from_u16(opcode_bits) {
-            match opcode {
-                Opcode::Br => {
-                    let pc_offset = lc3.sign_ext(instr & 0x1FF, 9);
-                    let instr_cond = (instr >> 9) & 0x7;
-                    let current_cond = lc3.read_register(Register::Cond);
-
-                    if (current_cond & instr_cond) != 0 {
-                        let current_pc_value = lc3.read_register(Register::PC);
-                        lc3.write_register(Register::PC, current_pc_value.wrapping_add(pc_offset));
-                    }
-                }
+    if !json {
+        println!(
+            "Engine: {}, {} instructions per workload",
+            engine.name(),
+            instructions
+        );
+    }
+    for workload in workloads {
+        let measurement = workload
+            .measure(engine, instructions)
+            .map_err(|e| format!("{}: {}", workload.name, e))?;
+        if json {
+            println!("{}", measurement.to_json());
+        } else {
+            println!(
+                "  {:<8} {:>8.1} MIPS  {:>7.3} s  {}",
+                workload.name,
+                measurement.mips(),
+                measurement.elapsed.as_secs_f64(),
+                workload.description
+            );
+        }
+    }
+    Ok(())
+}
 
-                Opcode::Add => {
-                    let dst = lc3.reg((instr >> 9) & 0x7);
-                    let src1 = lc3.reg((instr >> 6) & 0x7);
-                    let imm_flag = (instr >> 5) & 0x1;
-
-                    if imm_flag == 1 {
-                        let imm5 = lc3.sign_ext(instr & 0x1F, 5);
-                        let val1 = lc3.read_register(src1);
-                        let res = val1.wrapping_add(imm5);
-                        lc3.write_register(dst, res);
-                        lc3.update_flags(res);
-                    } else {
-                        let src2 = lc3.reg(instr & 0x7);
-                        let val1 = lc3.read_register(src1);
-                        let val2 = lc3.read_register(src2);
-                        let res = val1.wrapping_add(val2);
-                        lc3.write_register(dst, res);
-                        lc3.update_flags(res);
-                    }
-                }
+/// Handler for the words after a subcommand's name.
+type Subcommand = fn(&[String]) -> Result<(), String>;
 
-                Opcode::Ld => {
-                    let dst = lc3.reg((instr >> 9) & 0x7);
-                    let pc_offset = lc3.sign_ext(instr & 0x1FF, 9);
-                    let pc_val = lc3.read_register(Register::PC);
-                    let addr = pc_val.wrapping_add(pc_offset);
+/// Parses `x3000`, `0x3000` or a label from `debug`.
+fn parse_address(text: &str, debug: &DebugInfo) -> Option<u16> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix('x')) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => debug.address_of(text),
+    }
+}
 
-                    let val = lc3.read_memory(addr);
-                    lc3.write_register(dst, val);
-                    lc3.update_flags(val);
-                }
+/// Loads a program for `cfg` and `lint` and builds its graph from `entry`, the origin by
+/// default.
+fn build_cfg(
+    image: &str,
+    entry: Option<&String>,
+    sym: Option<&String>,
+) -> Result<(Cfg, DebugInfo), String> {
+    let (origin, words, mut debug) = load_program_file(Path::new(image))?;
+    if let Some(path) = sym {
+        let symbols = DebugInfo::read_sym_file(path)
+            .map_err(|e| format!("Could not read {}: {}", path, e))?;
+        debug.symbols.extend(symbols.symbols);
+    }
+    let entry = match entry {
+        Some(text) => {
+            parse_address(text, &debug).ok_or_else(|| format!("Unknown entry point: {}", text))?
+        }
+        None => origin,
+    };
+    if entry.wrapping_sub(origin) as usize >= words.len() {
+        return Err(format!("Entry point x{:04X} is outside the image", entry));
+    }
+    Ok((Cfg::build(origin, &words, entry), debug))
+}
 
-                Opcode::St => {
-                    let src = lc3.reg((instr >> 9) & 0x7);
-                    let pc_offset = lc3.sign_ext(instr & 0x1FF, 9);
-                    let pc_val = lc3.read_register(Register::PC);
-                    let addr = pc_val.wrapping_add(pc_offset);
+/// `cfg file.obj|file.asm [--entry addr] [--sym file.sym] [-o out.dot]`: writes the
+/// program's control-flow graph as Graphviz source, to stdout by default.
+fn cfg_command(args: &[String]) -> Result<(), String> {
+    let mut image = None;
+    let mut entry = None;
+    let mut sym = None;
+    let mut output = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--entry" => entry = Some(iter.next().ok_or("--entry expects an address")?),
+            "--sym" => sym = Some(iter.next().ok_or("--sym expects a file")?),
+            "-o" => output = Some(iter.next().ok_or("-o expects a file")?),
+            _ if image.is_none() => image = Some(arg),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+    let image = image.ok_or(
+        "Please use: cargo run -- cfg file.obj|file.asm [--entry addr] [--sym file.sym] [-o out.dot]",
+    )?;
+    let (cfg, debug) = build_cfg(image, entry, sym)?;
+    let dot = cfg.to_dot(&debug);
+    match output {
+        Some(path) => {
+            std::fs::write(path, &dot).map_err(|e| format!("Could not write {}: {}", path, e))?;
+            eprintln!(
+                "Wrote {} ({} block(s) in {} subroutine(s)); render it with `dot -Tsvg {}`",
+                path,
+                cfg.blocks.len(),
+                cfg.subroutines.len(),
+                path
+            );
+        }
+        None => print!("{}", dot),
+    }
+    Ok(())
+}
 
-                    let val = lc3.read_register(src);
-                    lc3.write_memory(addr, val);
-                }
+/// `lint file.asm|file.obj [--entry addr] [--sym file.sym]`: prints what the static checks
+/// found, one line each, and exits with status 1 if there was anything.
+fn lint_command(args: &[String]) -> Result<(), String> {
+    let mut image = None;
+    let mut entry = None;
+    let mut sym = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--entry" => entry = Some(iter.next().ok_or("--entry expects an address")?),
+            "--sym" => sym = Some(iter.next().ok_or("--sym expects a file")?),
+            _ if image.is_none() => image = Some(arg),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+    let image = image
+        .ok_or("Please use: cargo run -- lint file.asm|file.obj [--entry addr] [--sym file.sym]")?;
+    let (cfg, debug) = build_cfg(image, entry, sym)?;
+    let diagnostics = lint::lint(&cfg, &debug);
+    for diagnostic in &diagnostics {
+        println!("{}", diagnostic.format(&debug));
+    }
+    if !diagnostics.is_empty() {
+        eprintln!("{} warning(s)", diagnostics.len());
+        std::process::exit(1);
+    }
+    Ok(())
+}
 
-                Opcode::Jsr => {
-                    let current_pc = lc3.read_register(Register::PC);
-                    lc3.write_register(Register::R7, current_pc);
-
-                    let bit_11 = (instr >> 11) & 0x1;
-                    if bit_11 == 0 {
-                        let base_r = lc3.reg((instr >> 6) & 0x7);
-                        let val = lc3.read_register(base_r);
-                        lc3.write_register(Register::PC, val);
-                    } else {
-                        let pc_offset = lc3.sign_ext(instr & 0x7FF, 11);
-                        let val = current_pc.wrapping_add(pc_offset);
-                        lc3.write_register(Register::PC, val);
-                    }
-                }
+/// `test dir [--max-instructions N] [--engine name]`: runs every golden-output case in the
+/// directory, reports each, and exits with status 1 if any failed.
+fn test_command(args: &[String]) -> Result<(), String> {
+    let mut dir = None;
+    let mut max_instructions = golden::DEFAULT_MAX_INSTRUCTIONS;
+    let mut engine = Engine::Cache;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--max-instructions" => {
+                let count = iter.next().ok_or("--max-instructions expects a count")?;
+                max_instructions = count
+                    .parse()
+                    .map_err(|_| format!("Invalid instruction count: {}", count))?;
+            }
+            "--engine" => {
+                let name = iter.next().ok_or("--engine expects a name")?;
+                engine = parse_engine(name)?;
+            }
+            _ if dir.is_none() => dir = Some(arg),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+    let dir =
+        dir.ok_or("Please use: cargo run -- test dir [--max-instructions N] [--engine name]")?;
+    let cases =
+        golden::discover(Path::new(dir)).map_err(|e| format!("Could not read {}: {}", dir, e))?;
+    if cases.is_empty() {
+        return Err(format!("No test cases (NAME.out files) in {}", dir));
+    }
 
-                Opcode::And => {
-                    let dst = lc3.reg((instr >> 9) & 0x7);
-                    let src1 = lc3.reg((instr >> 6) & 0x7);
-                    let imm_flag = (instr >> 5) & 0x1;
-
-                    if imm_flag == 1 {
-                        let imm5 = lc3.sign_ext(instr & 0x1F, 5);
-                        let val1 = lc3.read_register(src1);
-                        let res = val1 & imm5;
-                        lc3.write_register(dst, res);
-                        lc3.update_flags(res);
-                    } else {
-                        let src2 = lc3.reg(instr & 0x7);
-                        let val1 = lc3.read_register(src1);
-                        let val2 = lc3.read_register(src2);
-                        let res = val1 & val2;
-                        lc3.write_register(dst, res);
-                        lc3.update_flags(res);
-                    }
-                }
+    let mut failed = 0;
+    for case in &cases {
+        let result = golden::run_case(case, engine, max_instructions);
+        match &result.outcome {
+            Outcome::Passed => println!(
+                "PASS {} ({} instructions)",
+                result.name, result.instructions
+            ),
+            Outcome::Mismatch { diff } => {
+                println!("FAIL {}: output differs, {}", result.name, diff.trim_end());
+            }
+            Outcome::Error(message) => println!("FAIL {}: {}", result.name, message),
+        }
+        if result.outcome != Outcome::Passed {
+            failed += 1;
+        }
+    }
+    println!("{} passed, {} failed", cases.len() - failed, failed);
+    if failed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
 
-                Opcode::Ldr => {
-                    let dst = lc3.reg((instr >> 9) & 0x7);
-                    let src = lc3.reg((instr >> 6) & 0x7);
-                    let offset = lc3.sign_ext(instr & 0x3F, 6);
+/// `unit spec.toml`: calls the subroutines a spec lists, reports each test, and exits with
+/// status 1 if any failed.
+fn unit_command(args: &[String]) -> Result<(), String> {
+    let [spec] = args else {
+        return Err("Please use: cargo run -- unit spec.toml".to_string());
+    };
+    let suite = Suite::load(Path::new(spec))?;
+    if suite.tests.is_empty() {
+        return Err(format!("No [[test]] tables in {}", spec));
+    }
 
-                    let val_base = lc3.read_register(src);
-                    let addr = val_base.wrapping_add(offset);
+    let results = suite.run_all();
+    let failed = results.iter().filter(|r| !r.passed()).count();
+    for result in &results {
+        if result.passed() {
+            println!(
+                "PASS {} ({} instructions)",
+                result.name, result.instructions
+            );
+        } else {
+            println!("FAIL {}", result.name);
+            for failure in &result.failures {
+                println!("  {}", failure);
+            }
+        }
+    }
+    println!("{} passed, {} failed", results.len() - failed, failed);
+    if failed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
 
-                    let val = lc3.read_memory(addr);
-                    lc3.write_register(dst, val);
-                    lc3.update_flags(val);
-                }
+/// `grade rubric.toml submission.obj|submission.asm [-o report.json]`: scores the submission
+/// and writes the report as JSON, to stdout by default. Failed cases are part of the report,
+/// not an error.
+fn grade_command(args: &[String]) -> Result<(), String> {
+    let mut files = Vec::new();
+    let mut output = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-o" => output = Some(iter.next().ok_or("-o expects a file")?),
+            _ => files.push(arg),
+        }
+    }
+    let [rubric, submission] = files[..] else {
+        return Err(
+            "Please use: cargo run -- grade rubric.toml submission.obj|submission.asm [-o report.json]"
+                .to_string(),
+        );
+    };
+    let rubric = Rubric::load(Path::new(rubric))?;
+    let report = rubric.grade(Path::new(submission));
+    let json = format!("{}\n", report.to_json());
+    match output {
+        Some(path) => {
+            std::fs::write(path, json).map_err(|e| format!("Could not write {}: {}", path, e))?;
+            eprintln!(
+                "Score: {}/{}; wrote {}",
+                report.score(),
+                report.max_score(),
+                path
+            );
+        }
+        None => print!("{}", json),
+    }
+    Ok(())
+}
 
-                Opcode::Str => {
-                    let src = lc3.reg((instr >> 9) & 0x7);
-                    let base_r = lc3.reg((instr >> 6) & 0x7);
-                    let offset6 = lc3.sign_ext(instr & 0x3F, 6);
+/// `fuzz [--cases N] [--seed S] [--engine name] [--max-instructions N]`: compares an engine
+/// with the reference model on random programs, prints each distinct minimized
+/// counterexample, and exits with status 1 if there were any.
+fn fuzz_command(args: &[String]) -> Result<(), String> {
+    let mut cases = FUZZ_CASES;
+    let mut seed = None;
+    let mut engine = Engine::Cache;
+    let mut max_instructions = fuzz::DEFAULT_MAX_INSTRUCTIONS;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--cases" => {
+                let count = iter.next().ok_or("--cases expects a count")?;
+                cases = count
+                    .parse()
+                    .map_err(|_| format!("Invalid case count: {}", count))?;
+            }
+            "--seed" => {
+                let text = iter.next().ok_or("--seed expects a number")?;
+                seed = Some(
+                    text.parse()
+                        .map_err(|_| format!("Invalid seed: {}", text))?,
+                );
+            }
+            "--engine" => {
+                let name = iter.next().ok_or("--engine expects a name")?;
+                engine = parse_engine(name)?;
+            }
+            "--max-instructions" => {
+                let count = iter.next().ok_or("--max-instructions expects a count")?;
+                max_instructions = count
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| format!("Invalid instruction count: {}", count))?;
+            }
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+    // A new seed every run unless one is given; it is printed so any run can be repeated.
+    let seed = seed.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64)
+    });
+    println!(
+        "Fuzzing {} against the reference model: {} cases from seed {}",
+        engine.name(),
+        cases,
+        seed
+    );
 
-                    let base_val = lc3.read_register(base_r);
-                    let addr = base_val.wrapping_add(offset6);
+    let report = fuzz::fuzz(seed, cases, engine, max_instructions);
+    for counterexample in &report.counterexamples {
+        println!(
+            "\nDIVERGENCE (seed {}), minimized to:\n{}",
+            counterexample.seed, counterexample.case
+        );
+        for difference in &counterexample.differences {
+            println!("  - {}", difference);
+        }
+    }
+    println!(
+        "\n{} cases: {} agreed, {} diverged ({} distinct), {} read the keyboard and were skipped",
+        report.cases,
+        report.cases - report.divergent - report.inconclusive,
+        report.divergent,
+        report.counterexamples.len(),
+        report.inconclusive
+    );
+    if !report.counterexamples.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
 
-                    let val_to_store = lc3.read_register(src);
-                    lc3.write_memory(addr, val_to_store);
-                }
+/// `translate file.obj [-o out.rs]`: writes the program as Rust source, to stdout by default.
+fn translate_command(args: &[String]) -> Result<(), String> {
+    let mut image = None;
+    let mut output = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-o" => output = Some(iter.next().ok_or("-o expects a file")?.clone()),
+            _ if image.is_none() => image = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+    let image = image.ok_or("Please use: cargo run -- translate file.obj [-o out.rs]")?;
+    let (origin, words) =
+        read_obj_file(&image).map_err(|e| format!("Could not read {}: {}", image, e))?;
+    let translation = aot::translate(origin, &words, &image);
+    match output {
+        Some(path) => {
+            std::fs::write(&path, &translation.source)
+                .map_err(|e| format!("Could not write {}: {}", path, e))?;
+            eprintln!(
+                "Wrote {} ({} block(s)); build it with `rustc -O {}`",
+                path,
+                translation.blocks.len(),
+                path
+            );
+        }
+        None => print!("{}", translation.source),
+    }
+    Ok(())
+}
 
-                Opcode::Rti | Opcode::Res => {
-                    println!("Opcode {:?} is unused or reserved", opcode);
-                    running = false;
-                }
+struct Options {
+    image: Option<String>,
+    gdb_port: Option<u16>,
+    dap: bool,
+    tui: bool,
+    sym: Option<String>,
+    source: Option<String>,
+    save_state: Option<String>,
+    restore: Option<String>,
+    profile: Option<String>,
+    folded: Option<String>,
+    coverage: Option<String>,
+    lcov: Option<String>,
+    blocks: bool,
+    jit: bool,
+    cycles: Option<CycleModel>,
+    micro: bool,
+    micro_trace: Option<String>,
+    /// Keys from `--input` and `--input-string`, in order.
+    input: Vec<u8>,
+    on_input_end: Option<OnExhausted>,
+    record: Option<String>,
+    replay: Vec<KeyEvent>,
+    screenshot: Option<String>,
+}
 
-                Opcode::Not => {
-                    let dst = lc3.reg((instr >> 9) & 0x7);
-                    let src = lc3.reg((instr >> 6) & 0x7);
-                    let val = !lc3.read_register(src);
-                    lc3.write_register(dst, val);
-                    lc3.update_flags(val);
-                }
+impl Options {
+    /// Whether keys come from the command line instead of the terminal.
+    fn scripted(&self) -> bool {
+        !self.input.is_empty() || self.on_input_end.is_some()
+    }
+}
 
-                Opcode::Ldi => {
-                    let dst = lc3.reg((instr >> 9) & 0x7);
-                    let pc_offset = lc3.sign_ext(instr & 0x1FF, 9);
-                    let pc_val = lc3.read_register(Register::PC);
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut image = None;
+    let mut gdb_port = None;
+    let mut dap = false;
+    let mut tui = false;
+    let mut sym = None;
+    let mut source = None;
+    let mut save_state = None;
+    let mut restore = None;
+    let mut profile = None;
+    let mut folded = None;
+    let mut coverage = None;
+    let mut lcov = None;
+    let mut blocks = false;
+    let mut jit = false;
+    let mut cycles = None;
+    let mut micro = false;
+    let mut micro_trace = None;
+    let mut input = Vec::new();
+    let mut on_input_end = None;
+    let mut record = None;
+    let mut replay = Vec::new();
+    let mut screenshot = None;
 
-                    let intermediate_addr = pc_val.wrapping_add(pc_offset);
-                    let final_addr = lc3.read_memory(intermediate_addr);
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--gdb" => {
+                let port = iter.next().ok_or("--gdb expects a port")?;
+                gdb_port = Some(
+                    port.parse()
+                        .map_err(|_| format!("Invalid port: {}", port))?,
+                );
+            }
+            "--dap" => dap = true,
+            "--tui" => tui = true,
+            "--sym" => sym = Some(iter.next().ok_or("--sym expects a file")?.clone()),
+            "--source" => source = Some(iter.next().ok_or("--source expects a file")?.clone()),
+            "--save-state" => {
+                save_state = Some(iter.next().ok_or("--save-state expects a file")?.clone());
+            }
+            "--restore" => restore = Some(iter.next().ok_or("--restore expects a file")?.clone()),
+            "--profile" => profile = Some(iter.next().ok_or("--profile expects a file")?.clone()),
+            "--folded" => folded = Some(iter.next().ok_or("--folded expects a file")?.clone()),
+            "--coverage" => {
+                coverage = Some(iter.next().ok_or("--coverage expects a file")?.clone());
+            }
+            "--blocks" => blocks = true,
+            "--jit" if cfg!(feature = "jit") => jit = true,
+            "--jit" => return Err("--jit needs a build with `--features jit`".to_string()),
+            "--cycles" => {
+                cycles.get_or_insert_with(CycleModel::default);
+            }
+            "--memory-latency" => {
+                let latency = iter
+                    .next()
+                    .ok_or("--memory-latency expects a cycle count")?;
+                let memory_latency = latency
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| format!("Invalid memory latency: {}", latency))?;
+                cycles = Some(CycleModel::new(memory_latency));
+            }
+            "--micro" => micro = true,
+            "--micro-trace" => {
+                micro_trace = Some(iter.next().ok_or("--micro-trace expects a file")?.clone());
+                micro = true;
+            }
+            "--input" => {
+                let path = iter.next().ok_or("--input expects a file")?;
+                let bytes =
+                    std::fs::read(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+                input.extend(bytes);
+            }
+            "--input-string" => {
+                let text = iter.next().ok_or("--input-string expects keys")?;
+                input.extend(unescape(text)?);
+            }
+            "--on-input-end" => {
+                let name = iter.next().ok_or("--on-input-end expects a policy")?;
+                on_input_end = Some(parse_choice(
+                    "input policy",
+                    name,
+                    OnExhausted::ALL,
+                    OnExhausted::name,
+                )?);
+            }
+            "--record" => record = Some(iter.next().ok_or("--record expects a file")?.clone()),
+            "--replay" => {
+                let path = iter.next().ok_or("--replay expects a file")?;
+                replay = keylog::read_file(Path::new(path))?;
+            }
+            "--screenshot" => {
+                screenshot = Some(iter.next().ok_or("--screenshot expects a file")?.clone());
+            }
+            "--lcov" => lcov = Some(iter.next().ok_or("--lcov expects a file")?.clone()),
+            _ if image.is_none() => image = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
 
-                    let val = lc3.read_memory(final_addr);
-                    lc3.write_register(dst, val);
-                    lc3.update_flags(val);
-                }
+    if image.is_none() && source.is_none() && restore.is_none() && !dap {
+        return Err(USAGE.to_string());
+    }
+    if lcov.is_some() && source.is_none() {
+        return Err("--lcov needs the program's --source".to_string());
+    }
+    if (blocks || jit)
+        && [&profile, &folded, &coverage, &lcov]
+            .iter()
+            .any(|o| o.is_some())
+    {
+        return Err("--blocks and --jit cannot be combined with profiling or coverage".to_string());
+    }
+    // The GDB stub runs the program itself, with none of the other modes or collectors.
+    if gdb_port.is_some()
+        && let Some((flag, _)) = [
+            ("--tui", tui),
+            ("--save-state", save_state.is_some()),
+            ("--profile", profile.is_some()),
+            ("--folded", folded.is_some()),
+            ("--coverage", coverage.is_some()),
+            ("--lcov", lcov.is_some()),
+            ("--blocks", blocks),
+            ("--jit", jit),
+            ("--micro-trace", micro_trace.is_some()),
+            ("--micro", micro),
+        ]
+        .into_iter()
+        .find(|&(_, given)| given)
+    {
+        return Err(format!("--gdb cannot be combined with {}", flag));
+    }
+    if screenshot.is_some() && tui {
+        return Err("--screenshot cannot be combined with --tui".to_string());
+    }
+    if micro && (blocks || jit) {
+        return Err("--micro cannot be combined with --blocks or --jit".to_string());
+    }
+    if cycles.is_some() && (blocks || jit) {
+        return Err(
+            "--cycles and --memory-latency cannot be combined with --blocks or --jit".to_string(),
+        );
+    }
+    Ok(Options {
+        image,
+        gdb_port,
+        dap,
+        tui,
+        sym,
+        source,
+        save_state,
+        restore,
+        profile,
+        folded,
+        coverage,
+        lcov,
+        blocks,
+        jit,
+        cycles,
+        micro,
+        micro_trace,
+        input,
+        on_input_end,
+        record,
+        replay,
+        screenshot,
+    })
+}
 
-                Opcode::Sti => {
-                    let src = lc3.reg((instr >> 9) & 0x7);
-                    let pc_offset = lc3.sign_ext(instr & 0x1FF, 9);
-                    let pc_val = lc3.read_register(Register::PC);
+/// Decodes `\n`, `\r`, `\t`, `\e` (escape), `\\` and `\xNN` in `--input-string` keys.
+fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('r') => bytes.push(b'\r'),
+            Some('t') => bytes.push(b'\t'),
+            Some('e') => bytes.push(0x1B),
+            Some('\\') => bytes.push(b'\\'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&hex, 16)
+                    .map_err(|_| format!("Invalid escape in --input-string: \\x{}", hex))?;
+                bytes.push(byte);
+            }
+            other => {
+                let escape = other.map(String::from).unwrap_or_default();
+                return Err(format!("Invalid escape in --input-string: \\{}", escape));
+            }
+        }
+    }
+    Ok(bytes)
+}
 
-                    let intermediate_addr = pc_val.wrapping_add(pc_offset);
-                    let final_addr = lc3.read_memory(intermediate_addr);
+fn save_state(lc3: &mut Vm, path: &str) {
+    match lc3.snapshot().save(path) {
+        Ok(()) => eprintln!("Saved state to {}", path),
+        Err(e) => eprintln!("Could not save state to {}: {}", path, e),
+    }
+}
 
-                    let val_to_store = lc3.read_register(src);
-                    lc3.write_memory(final_addr, val_to_store);
-                }
+const BACKTRACE_LIMIT: usize = 32;
 
-                Opcode::Jmp => {
-                    let src_reg = lc3.reg((instr >> 6) & 0x7);
-                    let val = lc3.read_register(src_reg);
-                    lc3.write_register(Register::PC, val);
-                }
+/// Prints the call stack with `pc`, the instruction that faulted or is about to run, as
+/// the innermost frame.
+fn print_backtrace(pc: u16, calls: &CallStack, debug: &DebugInfo) {
+    let lines = calls.backtrace(pc, debug);
+    eprintln!("Backtrace:");
+    for line in lines.iter().take(BACKTRACE_LIMIT) {
+        eprintln!("  {}", line);
+    }
+    if lines.len() > BACKTRACE_LIMIT {
+        eprintln!("  ... {} more frame(s)", lines.len() - BACKTRACE_LIMIT);
+    }
+}
 
-                Opcode::Lea => {
-                    let dst = lc3.reg((instr >> 9) & 0x7);
-                    let pc_offset = lc3.sign_ext(instr & 0x1FF, 9);
-                    let val = lc3.read_register(Register::PC).wrapping_add(pc_offset);
-                    lc3.write_register(dst, val);
-                    lc3.update_flags(val);
-                }
+// Instructions run by the block engine between checks for signals.
+const BLOCK_SLICE: u64 = 100_000;
+// Pause before retrying a trap that is waiting for input.
+const INPUT_WAIT: Duration = Duration::from_millis(10);
+
+/// Optional per-instruction bookkeeping, written out when the program stops.
+#[derive(Default)]
+struct Collectors {
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+}
+
+impl Collectors {
+    fn observe(&mut self, step: &Step, lc3: &Vm) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.observe(step, lc3);
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.observe(step, lc3);
+        }
+    }
 
-                Opcode::Trap => {
-                    /* TRAP instructions in the LC-3 architecture are service calls.
-                    According to the spec, the current PC must be saved in R7
-                    to allow the service routine to return (via RET/JMP R7).
-                    Even though our VM handles traps directly in Rust (keeping the control
-                    flow within this loop), we update R7 to maintain architectural
-                    fidelity and compatibility with programs that might inspect it. */
-                    let current_pc = lc3.read_register(Register::PC);
-                    lc3.write_register(Register::R7, current_pc);
-
-                    let trap_vector = instr & 0xFF;
-
-                    match trap_vector {
-                        0x20 => {
-                            // GETC
-                            let mut buffer = [0u8; 1];
-                            std::io::stdin().read_exact(&mut buffer).unwrap();
-                            lc3.write_register(Register::R0, buffer[0] as u16);
-                        }
-
-                        0x21 => {
-                            // OUT
-                            let char = (lc3.read_register(Register::R0) & 0xFF) as u8 as char;
-                            print!("{}", char);
-                            std::io::stdout().flush().expect("Failed to flush");
-                        }
-
-                        0x22 => {
-                            // PUTS
-                            let mut addr = lc3.read_register(Register::R0);
-                            loop {
-                                let char = lc3.read_memory(addr);
-                                if char == 0x0000 {
-                                    break;
-                                }
-                                print!("{}", (char as u8) as char);
-                                addr = addr.wrapping_add(1);
-                            }
-                            std::io::stdout().flush().expect("Failed to flush");
-                        }
-
-                        0x23 => {
-                            // IN
-                            print!("Enter a character: ");
-                            std::io::stdout().flush().expect("Failed to flush");
-                            let mut buffer = [0u8; 1];
-                            std::io::stdin().read_exact(&mut buffer).unwrap();
-                            let char = buffer[0] as char;
-                            print!("{}", char);
-                            std::io::stdout().flush().expect("Failed to flush");
-
-                            lc3.write_register(Register::R0, buffer[0] as u16);
-                        }
-
-                        0x24 => {
-                            // PUTSP
-                            let mut addr = lc3.read_register(Register::R0);
-                            loop {
-                                let word = lc3.read_memory(addr);
-                                if word == 0x0000 {
-                                    break;
-                                }
-
-                                let char_l = (word & 0xFF) as u8;
-                                print!("{}", char_l as char);
-
-                                let char_h = (word >> 8) as u8;
-                                if char_h != 0 {
-                                    print!("{}", char_h as char);
-                                }
-
-                                addr = addr.wrapping_add(1);
-                            }
-                            std::io::stdout().flush().expect("Failed to flush");
-                        }
-
-                        0x25 => {
-                            // HALT
-                            println!("HALT");
-                            std::io::stdout().flush().expect("Failed to flush");
-                            running = false;
-                        }
-
-                        _ => {
-                            println!("trap not implemented: 0x{:02X}", trap_vector);
-                            running = false;
-                        }
-                    }
+    fn write(&self, options: &Options, lc3: &Vm, debug: &DebugInfo) {
+        let report = |what: &str, path: &str, result: std::io::Result<()>| match result {
+            Ok(()) => eprintln!("Wrote {} to {}", what, path),
+            Err(e) => eprintln!("Could not write {} to {}: {}", what, path, e),
+        };
+        if let (Some(profiler), Some(path)) = (&self.profiler, &options.profile) {
+            report("profile", path, profiler.write_report(path, lc3, debug));
+        }
+        if let (Some(profiler), Some(path)) = (&self.profiler, &options.folded) {
+            report("folded stacks", path, profiler.write_folded(path, debug));
+        }
+        if let (Some(coverage), Some(path)) = (&self.coverage, &options.coverage) {
+            let result =
+                File::create(path).and_then(|f| coverage.write_hits(&mut BufWriter::new(f)));
+            report("coverage", path, result);
+        }
+        if let (Some(coverage), Some(path)) = (&self.coverage, &options.lcov) {
+            let name = options
+                .image
+                .as_deref()
+                .or(options.source.as_deref())
+                .unwrap_or("");
+            let test_name = Path::new(name)
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let result = std::fs::write(path, coverage.lcov(lc3, debug, &test_name));
+            report("lcov report", path, result);
+        }
+    }
+}
+
+/// Runs until the program halts or SIGINT/SIGTERM stops it. With a save path, those signals
+/// save first, SIGUSR1 saves and keeps going, and the final state is saved on exit. Faults
+/// and stops print a backtrace; returns that do not match their call are reported as they happen. With a
+/// block engine, neither calls nor collectors are tracked. With a microarchitecture, every
+/// instruction runs through its states. Returns false if the program faulted.
+fn run_program(
+    lc3: &mut Vm,
+    save_path: Option<&str>,
+    debug: &DebugInfo,
+    collectors: &mut Collectors,
+    mut engine: Option<&mut BlockEngine>,
+    mut micro: Option<&mut Microarchitecture>,
+) -> bool {
+    catch_signals(&[libc::SIGINT, libc::SIGTERM]);
+    if save_path.is_some() {
+        catch_signals(&[libc::SIGUSR1]);
+    }
+
+    let mut calls = CallStack::new();
+    let mut ok = true;
+    loop {
+        if let Some(signal) = take_signal() {
+            if let Some(path) = save_path {
+                save_state(lc3, path);
+            }
+            if signal != libc::SIGUSR1 {
+                print_backtrace(lc3.read_register(Register::PC), &calls, debug);
+                return true;
+            }
+        }
+        if let Some(engine) = engine.as_deref_mut() {
+            match engine.run(lc3, BLOCK_SLICE) {
+                Ok(run) if run.halted => break,
+                Ok(_) => {}
+                Err(VmError::WaitingForInput) => std::thread::sleep(INPUT_WAIT),
+                Err(e) => {
+                    println!("{}", e);
+                    // The failing `Vm::step` had already moved PC past the instruction.
+                    let pc = lc3.read_register(Register::PC).wrapping_sub(1);
+                    print_backtrace(pc, &calls, debug);
+                    ok = false;
+                    break;
+                }
+            }
+            continue;
+        }
+        let pc = lc3.read_register(Register::PC);
+        let result = match micro.as_deref_mut() {
+            Some(micro) => micro.step(lc3),
+            None => lc3.step(),
+        };
+        if let Ok(step) = &result {
+            collectors.observe(step, lc3);
+        }
+        match result {
+            Ok(step) if step.halted => break,
+            Ok(step) => {
+                let event = calls.observe(&step, lc3);
+                if let Some(warning) = event.and_then(|e| e.describe(debug)) {
+                    eprintln!("warning: {}", warning);
                 }
             }
+            // Interrupted by a signal while waiting for a key, or blocked on scripted
+            // input that ran out; the trap is retried.
+            Err(VmError::WaitingForInput) => std::thread::sleep(INPUT_WAIT),
+            Err(e) => {
+                println!("{}", e);
+                print_backtrace(pc, &calls, debug);
+                ok = false;
+                break;
+            }
+        }
+    }
+
+    if let Some(path) = save_path {
+        save_state(lc3, path);
+    }
+    ok
+}
+
+/// Assembles `--source`, if given, and reads the labels from it and from `--sym`.
+fn load_debug_info(options: &Options) -> Result<(DebugInfo, Option<Assembly>), String> {
+    let mut debug = DebugInfo::default();
+    let mut assembly = None;
+    if let Some(path) = &options.source {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        let program = asm::assemble(&text).map_err(|e| format!("{}: {}", path, e))?;
+        debug = DebugInfo::from_assembly(&program, Some(Path::new(path)));
+        assembly = Some(program);
+    }
+    if let Some(path) = &options.sym {
+        let sym = DebugInfo::read_sym_file(path)
+            .map_err(|e| format!("Could not read {}: {}", path, e))?;
+        debug.symbols.extend(sym.symbols);
+    }
+    Ok((debug, assembly))
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command: Option<Subcommand> = match args.first().map(String::as_str) {
+        Some("translate") => Some(translate_command),
+        Some("bench") => Some(bench_command),
+        Some("cfg") => Some(cfg_command),
+        Some("lint") => Some(lint_command),
+        Some("test") => Some(test_command),
+        Some("unit") => Some(unit_command),
+        Some("grade") => Some(grade_command),
+        Some("fuzz") => Some(fuzz_command),
+        _ => None,
+    };
+    // Usage errors and failures to run at all exit with 2, apart from the 1 that
+    // subcommands use for failing checks.
+    if let Some(command) = command {
+        if let Err(message) = command(&args[1..]) {
+            eprintln!("{}", message);
+            std::process::exit(2);
+        }
+        return;
+    }
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(2);
+        }
+    };
+
+    if options.dap {
+        // The program is chosen by the client's launch request; stdio carries the protocol.
+        if let Err(e) = dap::serve(std::io::BufReader::new(std::io::stdin()), std::io::stdout()) {
+            eprintln!("DAP connection error: {}", e);
+        }
+        return;
+    }
+
+    let (debug, assembly) = match load_debug_info(&options) {
+        Ok(loaded) => loaded,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(2);
+        }
+    };
+
+    // Scripted runs never read stdin, which need not be a terminal.
+    if !options.scripted() || options.tui {
+        disable_input_buffering();
+    }
+
+    let mut lc3 = Vm::new();
+    lc3.set_decode_cache(true);
+    lc3.set_cycle_model(options.cycles);
+    let console = SharedConsole::new();
+    // What the program's output leaves on the screen, for `--screenshot`.
+    let screen = options
+        .screenshot
+        .as_ref()
+        .map(|_| Arc::new(Mutex::new(Terminal::default())));
+    if options.tui {
+        console.push_input(&options.input);
+        lc3.set_console(console.clone());
+    } else if options.scripted() {
+        let on_input_end = options.on_input_end.unwrap_or(OnExhausted::Halt);
+        let scripted = ScriptedConsole::new(&options.input, on_input_end);
+        match &screen {
+            Some(screen) => lc3.set_console(CapturingConsole::new(scripted, screen.clone())),
+            None => lc3.set_console(scripted),
+        }
+    } else if let Some(screen) = &screen {
+        lc3.set_console(CapturingConsole::new(StdConsole::default(), screen.clone()));
+    }
+    if let Some(image) = &options.image {
+        lc3.read_image_file(image)
+            .expect("Error while loading .obj file");
+    } else if let Some(program) = &assembly {
+        lc3.load_program(program.origin, &program.words);
+    }
+    if let Some(path) = &options.restore {
+        let snapshot = Snapshot::load(path).expect("Error while loading snapshot");
+        lc3.restore(&snapshot);
+    }
+    lc3.replay_keys(&options.replay);
+    if let Some(path) = &options.record {
+        let file = File::create(path).expect("Error while creating key log");
+        lc3.record_keys(BufWriter::new(file))
+            .expect("Error while writing key log");
+    }
+    let mut micro = match &options.micro_trace {
+        Some(path) => {
+            let file = File::create(path).expect("Error while creating micro trace file");
+            Some(Microarchitecture::with_trace(BufWriter::new(file)))
+        }
+        None => options.micro.then(Microarchitecture::new),
+    };
+
+    let mut ok = true;
+    if options.tui {
+        let mut tui = Tui::new(lc3, console, debug);
+        if let Some(micro) = micro {
+            tui = tui.with_micro(micro);
+        }
+        if let Err(e) = tui.run() {
+            println!("Terminal error: {}", e);
+        }
+    } else if let Some(port) = options.gdb_port {
+        let listener =
+            TcpListener::bind(("127.0.0.1", port)).expect("Error while opening GDB port");
+        println!("Waiting for GDB on 127.0.0.1:{}", port);
+        if let Err(e) = gdb::serve(&listener, &mut lc3) {
+            println!("GDB connection error: {}", e);
+        }
+    } else {
+        let mut collectors = Collectors::default();
+        if options.profile.is_some() || options.folded.is_some() {
+            collectors.profiler = Some(Profiler::new(lc3.read_register(Register::PC)));
+        }
+        if options.coverage.is_some() || options.lcov.is_some() {
+            collectors.coverage = Some(Coverage::new());
+        }
+        #[cfg(feature = "jit")]
+        let mut engine = if options.jit {
+            Some(BlockEngine::with_jit())
+        } else {
+            options.blocks.then(BlockEngine::new)
+        };
+        #[cfg(not(feature = "jit"))]
+        let mut engine = (options.blocks || options.jit).then(BlockEngine::new);
+        ok = run_program(
+            &mut lc3,
+            options.save_state.as_deref(),
+            &debug,
+            &mut collectors,
+            engine.as_mut(),
+            micro.as_mut(),
+        );
+        if let (Some(micro), Some(path)) = (&mut micro, &options.micro_trace) {
+            match micro.flush() {
+                Ok(()) => eprintln!("Wrote microstep trace to {}", path),
+                Err(e) => eprintln!("Could not write microstep trace to {}: {}", path, e),
+            }
+        }
+        collectors.write(&options, &lc3, &debug);
+        if options.cycles.is_some() {
+            eprintln!("Cycles: {}", lc3.cycles());
         }
     }
 
     restore_input_buffering();
+    if let Some(path) = &options.record {
+        eprintln!("Wrote key log to {}", path);
+    }
+    if let (Some(path), Some(screen)) = (&options.screenshot, &screen) {
+        match std::fs::write(path, screen.lock().unwrap().text()) {
+            Ok(()) => eprintln!("Wrote screenshot to {}", path),
+            Err(e) => eprintln!("Could not write screenshot to {}: {}", path, e),
+        }
+    }
+    if !ok {
+        std::process::exit(1);
+    }
 }
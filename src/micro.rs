@@ -0,0 +1,326 @@
+//! Microarchitecture-level execution: every instruction runs as the sequence of control
+//! store states from Patt & Patel's appendix C (the same sequences `timing` counts), with
+//! MAR, MDR, IR and BEN latched between states. Registers, condition codes and memory are
+//! the `Vm`'s own, so each completed instruction leaves the machine exactly as `Vm::step`
+//! would.
+//!
+//! Traps are serviced by the emulator: state 30 runs the service routine and returns to
+//! the instruction after the TRAP instead of loading PC from the vector table.
+
+use crate::instruction::Instruction;
+use crate::timing::{FETCH_STATES, branch_enable, execute_states};
+use crate::vm::{Opcode, Register, Step, Vm, VmError};
+use std::fmt;
+use std::io::{self, ErrorKind, Write};
+
+/// The datapath latches an instruction's states communicate through.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Datapath {
+    pub mar: u16,
+    pub mdr: u16,
+    pub ir: u16,
+    pub ben: bool,
+}
+
+/// One state executed by `Microarchitecture::microstep`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MicroStep {
+    pub state: u8,
+    /// Cycles spent in the state, memory latency included.
+    pub cycles: u32,
+    /// Latches and PC after the state.
+    pub datapath: Datapath,
+    pub pc: u16,
+    /// Set when the state completed an instruction.
+    pub completed: Option<Step>,
+}
+
+impl fmt::Display for MicroStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "state {:>2}  {:<44} MAR=x{:04X} MDR=x{:04X} IR=x{:04X} BEN={} PC=x{:04X}",
+            self.state,
+            describe_state(self.state),
+            self.datapath.mar,
+            self.datapath.mdr,
+            self.datapath.ir,
+            self.datapath.ben as u8,
+            self.pc
+        )
+    }
+}
+
+/// Register transfers of a state, in the textbook's notation.
+pub fn describe_state(state: u8) -> &'static str {
+    match state {
+        18 => "MAR<-PC, PC<-PC+1",
+        33 | 24 | 25 | 29 => "MDR<-M[MAR]",
+        35 => "IR<-MDR",
+        32 => "BEN<-IR[11]&N+IR[10]&Z+IR[9]&P, [IR[15:12]]",
+        1 => "DR<-SR1+OP2, set CC",
+        5 => "DR<-SR1&OP2, set CC",
+        9 => "DR<-NOT(SR), set CC",
+        14 => "DR<-PC+off9, set CC",
+        2 | 3 | 10 | 11 => "MAR<-PC+off9",
+        6 | 7 => "MAR<-B+off6",
+        26 | 31 => "MAR<-MDR",
+        27 => "DR<-MDR, set CC",
+        23 => "MDR<-SR",
+        16 => "M[MAR]<-MDR",
+        0 => "[BEN]",
+        22 => "PC<-PC+off9",
+        12 => "PC<-BaseR",
+        4 => "[IR[11]]",
+        21 => "R7<-PC, PC<-PC+off11",
+        20 => "R7<-PC, PC<-BaseR",
+        15 => "MAR<-ZEXT[IR[7:0]]",
+        28 => "MDR<-M[MAR], R7<-PC",
+        30 => "trap service routine (emulated), return",
+        8 => "RTI",
+        13 => "reserved opcode",
+        _ => "?",
+    }
+}
+
+/// Runs a `Vm` one control store state at a time.
+pub struct Microarchitecture {
+    datapath: Datapath,
+    // States of the current phase (fetch, then the instruction's own) and the next one.
+    states: &'static [u8],
+    next: usize,
+    instr: Instruction,
+    // Where the instruction started, to rewind a trap that has to wait for input.
+    fetch_pc: u16,
+    saved_r7: u16,
+    // Cycles of the instruction in progress, charged when it completes.
+    pending_cycles: u64,
+    cycles: u64,
+    trace: Option<Box<dyn Write>>,
+}
+
+impl Default for Microarchitecture {
+    fn default() -> Self {
+        Self {
+            datapath: Datapath::default(),
+            states: FETCH_STATES,
+            next: 0,
+            instr: Instruction::decode(0),
+            fetch_pc: 0,
+            saved_r7: 0,
+            pending_cycles: 0,
+            cycles: 0,
+            trace: None,
+        }
+    }
+}
+
+impl Microarchitecture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes every executed state to `trace`, one line each, prefixed with the cycle it
+    /// started on.
+    pub fn with_trace(trace: impl Write + 'static) -> Self {
+        Self {
+            trace: Some(Box::new(trace)),
+            ..Self::default()
+        }
+    }
+
+    pub fn datapath(&self) -> &Datapath {
+        &self.datapath
+    }
+
+    /// The state the next microstep executes.
+    pub fn state(&self) -> u8 {
+        self.states[self.next]
+    }
+
+    /// Whether the machine is between instructions.
+    pub fn at_instruction_boundary(&self) -> bool {
+        self.next == 0 && self.states == FETCH_STATES
+    }
+
+    /// Cycles of every completed instruction, under `vm`'s cycle model or the default one.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Executes the current state. A trap waiting for input rewinds PC and R7 to the start
+    /// of the TRAP, like `Vm::step`, and the instruction is fetched again on the next call.
+    pub fn microstep(&mut self, vm: &mut Vm) -> Result<MicroStep, VmError> {
+        let state = self.state();
+        let halted = match self.execute(vm, state) {
+            Ok(halted) => halted,
+            Err(VmError::Io(e))
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) =>
+            {
+                vm.write_register(Register::PC, self.fetch_pc);
+                vm.write_register(Register::R7, self.saved_r7);
+                self.states = FETCH_STATES;
+                self.next = 0;
+                self.pending_cycles = 0;
+                return Err(VmError::WaitingForInput);
+            }
+            Err(e) => return Err(e),
+        };
+
+        let cycles = vm.cycle_model().unwrap_or_default().state_cycles(state);
+        if let Some(trace) = &mut self.trace {
+            let line = MicroStep {
+                state,
+                cycles,
+                datapath: self.datapath,
+                pc: vm.read_register(Register::PC),
+                completed: None,
+            };
+            let _ = writeln!(trace, "{:>10}  {}", self.cycles + self.pending_cycles, line);
+        }
+        self.pending_cycles += cycles as u64;
+
+        // State 32 picked the instruction's states; the last of them ends the instruction.
+        self.next += 1;
+        let mut completed = None;
+        if state == 32 {
+            self.states = execute_states(self.instr, self.datapath.ben);
+            self.next = 0;
+        } else if self.next == self.states.len() {
+            self.states = FETCH_STATES;
+            self.next = 0;
+            self.cycles += self.pending_cycles;
+            vm.add_cycles(std::mem::take(&mut self.pending_cycles));
+            let halted = vm.check_input_exhausted(halted)?;
+            vm.add_instructions(1);
+            vm.check_replay()?;
+            completed = Some(Step {
+                pc: self.fetch_pc,
+                instr: self.datapath.ir,
+                halted,
+            });
+        }
+        Ok(MicroStep {
+            state,
+            cycles,
+            datapath: self.datapath,
+            pc: vm.read_register(Register::PC),
+            completed,
+        })
+    }
+
+    /// Runs states until the current instruction completes.
+    pub fn step(&mut self, vm: &mut Vm) -> Result<Step, VmError> {
+        loop {
+            if let Some(step) = self.microstep(vm)?.completed {
+                return Ok(step);
+            }
+        }
+    }
+
+    /// Flushes the trace, if any.
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.trace {
+            Some(trace) => trace.flush(),
+            None => Ok(()),
+        }
+    }
+
+    // Performs the register transfers of `state`. Returns whether a trap halted.
+    fn execute(&mut self, vm: &mut Vm, state: u8) -> Result<bool, VmError> {
+        let d = &mut self.datapath;
+        let pc = vm.read_register(Register::PC);
+        let set_cc = |vm: &mut Vm, dst: Register, value: u16| {
+            vm.write_register(dst, value);
+            vm.update_flags(value);
+        };
+        match (state, self.instr) {
+            (18, _) => {
+                self.fetch_pc = pc;
+                self.saved_r7 = vm.read_register(Register::R7);
+                d.mar = pc;
+                vm.write_register(Register::PC, pc.wrapping_add(1));
+            }
+            (33 | 24 | 25 | 29, _) => d.mdr = vm.read_memory(d.mar),
+            (35, _) => d.ir = d.mdr,
+            (32, _) => {
+                self.instr = Instruction::decode(d.ir);
+                // Set for every opcode from IR[11:9]; only BR looks at it.
+                let nzp = Instruction::Br {
+                    nzp: (d.ir >> 9) & 0x7,
+                    offset: 0,
+                };
+                d.ben = branch_enable(nzp, vm.read_register(Register::Cond));
+            }
+            (1, Instruction::AddReg { dst, src1, src2 }) => {
+                let value = vm.read_register(src1).wrapping_add(vm.read_register(src2));
+                set_cc(vm, dst, value);
+            }
+            (1, Instruction::AddImm { dst, src1, imm }) => {
+                set_cc(vm, dst, vm.read_register(src1).wrapping_add(imm));
+            }
+            (5, Instruction::AndReg { dst, src1, src2 }) => {
+                set_cc(vm, dst, vm.read_register(src1) & vm.read_register(src2));
+            }
+            (5, Instruction::AndImm { dst, src1, imm }) => {
+                set_cc(vm, dst, vm.read_register(src1) & imm);
+            }
+            (9, Instruction::Not { dst, src }) => set_cc(vm, dst, !vm.read_register(src)),
+            (14, Instruction::Lea { dst, offset }) => set_cc(vm, dst, pc.wrapping_add(offset)),
+            (
+                2 | 3 | 10 | 11,
+                Instruction::Ld { offset, .. }
+                | Instruction::St { offset, .. }
+                | Instruction::Ldi { offset, .. }
+                | Instruction::Sti { offset, .. },
+            ) => d.mar = pc.wrapping_add(offset),
+            (
+                6 | 7,
+                Instruction::Ldr { base, offset, .. } | Instruction::Str { base, offset, .. },
+            ) => d.mar = vm.read_register(base).wrapping_add(offset),
+            (26 | 31, _) => d.mar = d.mdr,
+            (
+                27,
+                Instruction::Ld { dst, .. }
+                | Instruction::Ldr { dst, .. }
+                | Instruction::Ldi { dst, .. },
+            ) => set_cc(vm, dst, d.mdr),
+            (
+                23,
+                Instruction::St { src, .. }
+                | Instruction::Str { src, .. }
+                | Instruction::Sti { src, .. },
+            ) => d.mdr = vm.read_register(src),
+            (16, _) => vm.write_memory(d.mar, d.mdr),
+            (0 | 4, _) => {}
+            (22, Instruction::Br { offset, .. }) => {
+                vm.write_register(Register::PC, pc.wrapping_add(offset));
+            }
+            (12, Instruction::Jmp { base }) => {
+                let target = vm.read_register(base);
+                vm.write_register(Register::PC, target);
+            }
+            (21, Instruction::Jsr { offset }) => {
+                vm.write_register(Register::R7, pc);
+                vm.write_register(Register::PC, pc.wrapping_add(offset));
+            }
+            // The register file is read before it is written in the same cycle, so the
+            // base is the old R7 for `JSRR R7`.
+            (20, Instruction::Jsrr { base }) => {
+                let target = vm.read_register(base);
+                vm.write_register(Register::R7, pc);
+                vm.write_register(Register::PC, target);
+            }
+            (15, Instruction::Trap { vector }) => d.mar = vector,
+            (28, _) => {
+                d.mdr = vm.read_memory(d.mar);
+                vm.write_register(Register::R7, pc);
+            }
+            (30, Instruction::Trap { vector }) => return vm.trap(vector),
+            (8, _) => return Err(VmError::ReservedOpcode(Opcode::Rti)),
+            (13, _) => return Err(VmError::ReservedOpcode(Opcode::Res)),
+            (state, instr) => unreachable!("state {} is not part of {:?}", state, instr),
+        }
+        Ok(false)
+    }
+}
@@ -0,0 +1,126 @@
+//! Scripted playthroughs of interactive programs, such as the bundled games: keys are typed
+//! one batch at a time, and after each batch the program runs until it has read them all
+//! and waits for another, so the screen it drew can be checked.
+//!
+//! A program waits for a key either in GETC/IN or by polling KBSR. Both count: the run
+//! stops once the typed keys are gone and a trap blocks or a poll finds nothing (checked
+//! every `SLICE` instructions, so a polling loop may go round a few more times). The output
+//! is shown on a `Terminal`, so the screen reads as it would on a real one.
+
+use crate::bench::{Engine, Runner};
+use crate::console::SharedConsole;
+use crate::debuginfo::load_program_file;
+use crate::terminal::Terminal;
+use crate::vm::{Vm, VmError};
+use std::path::Path;
+
+/// Instructions a batch of keys may take before the program counts as hung.
+pub const DEFAULT_MAX_INSTRUCTIONS: u64 = 10_000_000;
+
+// Instructions run between checks for a poll that found no key. Every engine stops at the
+// same instruction, so where a batch ends does not depend on the engine.
+const SLICE: u64 = 1024;
+
+pub struct Playthrough {
+    vm: Vm,
+    console: SharedConsole,
+    runner: Runner,
+    output: Vec<u8>,
+    terminal: Terminal,
+    halted: bool,
+    max_instructions: u64,
+}
+
+impl Playthrough {
+    /// Loads an `.asm` or `.obj` file and runs it up to the first time it waits for a key.
+    pub fn load(path: &Path, engine: Engine) -> Result<Playthrough, String> {
+        let (origin, words, _) = load_program_file(path)?;
+        let mut playthrough = Playthrough::new(origin, &words, engine);
+        playthrough.press(b"")?;
+        Ok(playthrough)
+    }
+
+    /// A machine with the program loaded that has not run yet.
+    pub fn new(origin: u16, words: &[u16], engine: Engine) -> Playthrough {
+        let console = SharedConsole::new();
+        let mut vm = Vm::with_console(console.clone());
+        vm.load_program(origin, words);
+        let runner = Runner::new(engine, &mut vm);
+        Playthrough {
+            vm,
+            console,
+            runner,
+            output: Vec::new(),
+            terminal: Terminal::default(),
+            halted: false,
+            max_instructions: DEFAULT_MAX_INSTRUCTIONS,
+        }
+    }
+
+    /// Limits how long the program may run for each batch of keys.
+    pub fn set_max_instructions(&mut self, max_instructions: u64) {
+        self.max_instructions = max_instructions;
+    }
+
+    /// Types `keys` and runs until the program has read them and waits for another key, or
+    /// halts. Fails if it faults, keeps running past the limit, or halts with keys unread.
+    pub fn press(&mut self, keys: &[u8]) -> Result<(), String> {
+        if self.halted {
+            return Err("the program has halted".to_string());
+        }
+        self.console.push_input(keys);
+        let empty_polls = self.vm.empty_polls();
+        let end = self.runner.instructions() + self.max_instructions;
+        let result = loop {
+            if self.runner.instructions() >= end {
+                break Err(format!(
+                    "still running after {} instructions",
+                    self.max_instructions
+                ));
+            }
+            let budget = SLICE.min(end - self.runner.instructions());
+            match self.runner.run(&mut self.vm, budget) {
+                Ok(true) => {
+                    self.halted = true;
+                    break Ok(());
+                }
+                Ok(false) if self.vm.empty_polls() > empty_polls => break Ok(()),
+                Ok(false) => {}
+                Err(VmError::WaitingForInput) => break Ok(()),
+                Err(e) => break Err(e.to_string()),
+            }
+        };
+        let output = self.console.take_output();
+        self.terminal.feed(&output);
+        self.output.extend(output);
+        result?;
+        match self.console.pending_input() {
+            0 => Ok(()),
+            unread => Err(format!("the program halted with {} key(s) unread", unread)),
+        }
+    }
+
+    /// Everything the program printed so far, escape sequences included.
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// What the screen shows, as `Terminal::text`.
+    pub fn screen(&self) -> String {
+        self.terminal.text()
+    }
+
+    /// The screen, for its cursor or individual lines.
+    pub fn terminal(&self) -> &Terminal {
+        &self.terminal
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    /// The machine, to look at the program's state in memory.
+    pub fn vm(&self) -> &Vm {
+        &self.vm
+    }
+}
@@ -0,0 +1,263 @@
+//! Instruction-level profiler: execution counts per address and per opcode, attributed to
+//! the enclosing subroutine through the shadow call stack.
+
+use crate::callstack::{CallEvent, CallStack};
+use crate::debuginfo::DebugInfo;
+use crate::instruction::Instruction;
+use crate::vm::{MEMORY_MAX, Opcode, Step, Vm};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+const HOT_SPOTS: usize = 20;
+
+// One node per distinct chain of subroutines seen, rooted at the entry point.
+struct Node {
+    parent: usize,
+    target: u16,
+    count: u64,
+}
+
+pub struct Profiler {
+    entry: u16,
+    total: u64,
+    pc_counts: Vec<u64>,
+    opcode_counts: [u64; 16],
+    calls: CallStack,
+    nodes: Vec<Node>,
+    children: HashMap<(usize, u16), usize>,
+    // Node of every frame on `calls`, innermost last; the root is not included.
+    path: Vec<usize>,
+}
+
+/// Execution counts of one subroutine: `self_count` for its own instructions, `total`
+/// including everything it called.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SubroutineCost {
+    pub entry: u16,
+    pub self_count: u64,
+    pub total: u64,
+}
+
+impl Profiler {
+    /// `entry` names the code running before any call, normally the program's origin.
+    pub fn new(entry: u16) -> Self {
+        Self {
+            entry,
+            total: 0,
+            pc_counts: vec![0; MEMORY_MAX],
+            opcode_counts: [0; 16],
+            calls: CallStack::new(),
+            nodes: vec![Node {
+                parent: 0,
+                target: entry,
+                count: 0,
+            }],
+            children: HashMap::new(),
+            path: Vec::new(),
+        }
+    }
+
+    /// Records `step`, which has just executed on `vm`. An instruction is charged to the
+    /// subroutine it belongs to, so a JSR counts for the caller and its RET for the callee.
+    pub fn observe(&mut self, step: &Step, vm: &Vm) {
+        self.total += 1;
+        self.pc_counts[step.pc as usize] += 1;
+        self.opcode_counts[(step.instr >> 12) as usize] += 1;
+        let current = self.current();
+        self.nodes[current].count += 1;
+
+        match self.calls.observe(step, vm) {
+            Some(CallEvent::Call(frame)) => {
+                // Recursion, or a JSR used as a loop jump (rogue's `JSR LOOP`), goes back to
+                // the node already on the stack so the stacks don't grow without bound.
+                let node = self
+                    .path
+                    .iter()
+                    .rev()
+                    .find(|&&node| self.nodes[node].target == frame.target)
+                    .copied()
+                    .unwrap_or_else(|| self.child(current, frame.target));
+                self.path.push(node);
+                // The call stack drops its oldest frame past its depth limit.
+                if self.path.len() > self.calls.depth() {
+                    self.path.remove(0);
+                }
+            }
+            Some(CallEvent::Return(_)) | Some(CallEvent::MismatchedReturn { .. }) => {
+                self.path.truncate(self.calls.depth());
+            }
+            None => {}
+        }
+    }
+
+    fn current(&self) -> usize {
+        self.path.last().copied().unwrap_or(0)
+    }
+
+    fn child(&mut self, parent: usize, target: u16) -> usize {
+        if let Some(&node) = self.children.get(&(parent, target)) {
+            return node;
+        }
+        self.nodes.push(Node {
+            parent,
+            target,
+            count: 0,
+        });
+        let node = self.nodes.len() - 1;
+        self.children.insert((parent, target), node);
+        node
+    }
+
+    // Subroutine entry points from the root down to `node`.
+    fn stack_of(&self, mut node: usize) -> Vec<u16> {
+        let mut stack = vec![self.nodes[node].target];
+        while node != 0 {
+            node = self.nodes[node].parent;
+            stack.push(self.nodes[node].target);
+        }
+        stack.reverse();
+        stack
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn count_at(&self, addr: u16) -> u64 {
+        self.pc_counts[addr as usize]
+    }
+
+    pub fn opcode_count(&self, opcode: Opcode) -> u64 {
+        self.opcode_counts[opcode as usize]
+    }
+
+    /// Executed addresses, most executed first.
+    pub fn hot_spots(&self) -> Vec<(u16, u64)> {
+        let mut spots: Vec<(u16, u64)> = (0..MEMORY_MAX)
+            .filter(|&addr| self.pc_counts[addr] > 0)
+            .map(|addr| (addr as u16, self.pc_counts[addr]))
+            .collect();
+        spots.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        spots
+    }
+
+    /// Per-subroutine costs, highest total first. The code outside any call is listed
+    /// under the entry point.
+    pub fn subroutines(&self) -> Vec<SubroutineCost> {
+        let mut costs: BTreeMap<u16, (u64, u64)> = BTreeMap::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            costs.entry(node.target).or_default().0 += node.count;
+            // Charge each subroutine on the stack once, however often it recurses.
+            let mut stack = self.stack_of(index);
+            stack.sort_unstable();
+            stack.dedup();
+            for target in stack {
+                costs.entry(target).or_default().1 += node.count;
+            }
+        }
+        let mut costs: Vec<SubroutineCost> = costs
+            .into_iter()
+            .filter(|(_, (_, total))| *total > 0)
+            .map(|(entry, (self_count, total))| SubroutineCost {
+                entry,
+                self_count,
+                total,
+            })
+            .collect();
+        costs.sort_by(|a, b| b.total.cmp(&a.total).then(a.entry.cmp(&b.entry)));
+        costs
+    }
+
+    /// Folded stacks (`outer;inner count` per line), the input format of `flamegraph.pl`
+    /// and compatible tools.
+    pub fn folded(&self, debug: &DebugInfo) -> String {
+        let mut lines: BTreeMap<String, u64> = BTreeMap::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            if node.count == 0 {
+                continue;
+            }
+            let names: Vec<String> = self
+                .stack_of(index)
+                .into_iter()
+                .map(|addr| debug.describe(addr))
+                .collect();
+            *lines.entry(names.join(";")).or_default() += node.count;
+        }
+        lines
+            .into_iter()
+            .map(|(stack, count)| format!("{} {}\n", stack, count))
+            .collect()
+    }
+
+    /// Human-readable report: hottest addresses, opcode mix and subroutine costs.
+    pub fn report(&self, vm: &Vm, debug: &DebugInfo) -> String {
+        let percent = |count: u64| {
+            if self.total == 0 {
+                0.0
+            } else {
+                count as f64 * 100.0 / self.total as f64
+            }
+        };
+        let mut out = String::new();
+        let _ = writeln!(out, "Instructions executed: {}", self.total);
+        let _ = writeln!(out, "Entry point: {}", debug.describe(self.entry));
+
+        let _ = writeln!(out, "\nHot spots:");
+        let _ = writeln!(
+            out,
+            "{:>12} {:>7}  {:<5} {:<16} instruction",
+            "count", "%", "addr", "location"
+        );
+        for (addr, count) in self.hot_spots().into_iter().take(HOT_SPOTS) {
+            let text = Instruction::decode(vm.peek_memory(addr)).disassemble(addr, Some(debug));
+            let _ = writeln!(
+                out,
+                "{:>12} {:>6.2}%  x{:04X} {:<16} {}",
+                count,
+                percent(count),
+                addr,
+                debug.describe(addr),
+                text
+            );
+        }
+
+        let _ = writeln!(out, "\nOpcodes:");
+        let mut opcodes: Vec<(u16, u64)> = (0..16u16)
+            .map(|op| (op, self.opcode_counts[op as usize]))
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for (op, count) in opcodes {
+            let opcode = Opcode::from_u16(op).expect("Opcode is only 4 bits wide");
+            let _ = writeln!(out, "{:>12} {:>6.2}%  {:?}", count, percent(count), opcode);
+        }
+
+        let _ = writeln!(out, "\nSubroutines:");
+        let _ = writeln!(
+            out,
+            "{:>12} {:>7} {:>12} {:>7}  subroutine",
+            "self", "%", "total", "%"
+        );
+        for cost in self.subroutines() {
+            let _ = writeln!(
+                out,
+                "{:>12} {:>6.2}% {:>12} {:>6.2}%  {}",
+                cost.self_count,
+                percent(cost.self_count),
+                cost.total,
+                percent(cost.total),
+                debug.describe(cost.entry)
+            );
+        }
+        out
+    }
+
+    pub fn write_report(&self, path: &str, vm: &Vm, debug: &DebugInfo) -> io::Result<()> {
+        std::fs::File::create(path)?.write_all(self.report(vm, debug).as_bytes())
+    }
+
+    pub fn write_folded(&self, path: &str, debug: &DebugInfo) -> io::Result<()> {
+        std::fs::File::create(path)?.write_all(self.folded(debug).as_bytes())
+    }
+}
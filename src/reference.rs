@@ -0,0 +1,221 @@
+//! A reference model of the LC-3, written straight from the ISA tables and independent of
+//! `Vm`, `Instruction` and the other engines, for the differential fuzzer to check them
+//! against. It favours being obviously right over being fast: every instruction is decoded
+//! from its bit fields on every step.
+//!
+//! Where the ISA leaves room, it does what the emulator documents: LEA sets the condition
+//! codes, traps run the emulator's built-in routines (with `HALT` printing `HALT`) instead
+//! of code at the trap vector table, and the keyboard never has a key.
+
+/// Why the model stopped without halting.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    /// RTI or the reserved opcode, with its opcode number.
+    Reserved(u16),
+    /// A trap vector the emulator has no routine for.
+    UnknownTrap(u16),
+    /// GETC or IN, which the model cannot answer.
+    Input,
+}
+
+pub const N: u16 = 0b100;
+pub const Z: u16 = 0b010;
+pub const P: u16 = 0b001;
+
+const KBSR: u16 = 0xFE00;
+
+pub struct Reference {
+    pub registers: [u16; 8],
+    pub pc: u16,
+    /// One of `N`, `Z` and `P`.
+    pub cc: u16,
+    pub memory: Vec<u16>,
+    /// Everything the trap routines printed.
+    pub output: Vec<u8>,
+}
+
+impl Default for Reference {
+    fn default() -> Self {
+        Reference {
+            registers: [0; 8],
+            pc: 0x3000,
+            cc: Z,
+            memory: vec![0; 1 << 16],
+            output: Vec::new(),
+        }
+    }
+}
+
+// Bits `hi..=lo` of `word`.
+fn bits(word: u16, hi: u32, lo: u32) -> u16 {
+    (word >> lo) & ((1 << (hi - lo + 1)) - 1)
+}
+
+// Sign-extends the low `width` bits of `value`.
+fn sext(value: u16, width: u32) -> u16 {
+    if value & (1 << (width - 1)) != 0 {
+        value | !((1 << width) - 1)
+    } else {
+        value
+    }
+}
+
+impl Reference {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn read(&mut self, addr: u16) -> u16 {
+        if addr == KBSR {
+            // No key is ever ready.
+            self.memory[KBSR as usize] = 0;
+        }
+        self.memory[addr as usize]
+    }
+
+    fn set_cc(&mut self, value: u16) {
+        self.cc = if value == 0 {
+            Z
+        } else if value & 0x8000 != 0 {
+            N
+        } else {
+            P
+        };
+    }
+
+    // Writes a result register and sets the condition codes from it.
+    fn load(&mut self, dr: u16, value: u16) {
+        self.registers[dr as usize] = value;
+        self.set_cc(value);
+    }
+
+    /// Executes one instruction. Returns whether it halted the machine.
+    pub fn step(&mut self) -> Result<bool, Fault> {
+        let ir = self.read(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+
+        let dr = bits(ir, 11, 9);
+        let sr1 = bits(ir, 8, 6);
+        let pc_offset9 = self.pc.wrapping_add(sext(bits(ir, 8, 0), 9));
+        let base_offset6 = self.registers[sr1 as usize].wrapping_add(sext(bits(ir, 5, 0), 6));
+        // The second ALU operand: imm5 when bit 5 is set, otherwise SR2.
+        let operand = if bits(ir, 5, 5) == 1 {
+            sext(bits(ir, 4, 0), 5)
+        } else {
+            self.registers[bits(ir, 2, 0) as usize]
+        };
+
+        match bits(ir, 15, 12) {
+            // BR
+            0b0000 => {
+                if bits(ir, 11, 9) & self.cc != 0 {
+                    self.pc = pc_offset9;
+                }
+            }
+            // ADD
+            0b0001 => {
+                let value = self.registers[sr1 as usize].wrapping_add(operand);
+                self.load(dr, value);
+            }
+            // LD
+            0b0010 => {
+                let value = self.read(pc_offset9);
+                self.load(dr, value);
+            }
+            // ST
+            0b0011 => self.memory[pc_offset9 as usize] = self.registers[dr as usize],
+            // JSR, JSRR
+            0b0100 => {
+                let target = if bits(ir, 11, 11) == 1 {
+                    self.pc.wrapping_add(sext(bits(ir, 10, 0), 11))
+                } else {
+                    self.registers[sr1 as usize]
+                };
+                self.registers[7] = self.pc;
+                self.pc = target;
+            }
+            // AND
+            0b0101 => {
+                let value = self.registers[sr1 as usize] & operand;
+                self.load(dr, value);
+            }
+            // LDR
+            0b0110 => {
+                let value = self.read(base_offset6);
+                self.load(dr, value);
+            }
+            // STR
+            0b0111 => self.memory[base_offset6 as usize] = self.registers[dr as usize],
+            // NOT
+            0b1001 => {
+                let value = !self.registers[sr1 as usize];
+                self.load(dr, value);
+            }
+            // LDI
+            0b1010 => {
+                let addr = self.read(pc_offset9);
+                let value = self.read(addr);
+                self.load(dr, value);
+            }
+            // STI
+            0b1011 => {
+                let addr = self.read(pc_offset9);
+                self.memory[addr as usize] = self.registers[dr as usize];
+            }
+            // JMP, RET
+            0b1100 => self.pc = self.registers[sr1 as usize],
+            // LEA
+            0b1110 => self.load(dr, pc_offset9),
+            // TRAP
+            0b1111 => {
+                self.registers[7] = self.pc;
+                return self.trap(bits(ir, 7, 0));
+            }
+            // RTI and the reserved opcode
+            opcode => return Err(Fault::Reserved(opcode)),
+        }
+        Ok(false)
+    }
+
+    fn trap(&mut self, vector: u16) -> Result<bool, Fault> {
+        match vector {
+            0x20 | 0x23 => return Err(Fault::Input),
+            // OUT
+            0x21 => self.output.push(self.registers[0] as u8),
+            // PUTS: one character per word.
+            0x22 => {
+                let mut addr = self.registers[0];
+                loop {
+                    let word = self.read(addr);
+                    if word == 0 {
+                        break;
+                    }
+                    self.output.push(word as u8);
+                    addr = addr.wrapping_add(1);
+                }
+            }
+            // PUTSP: two characters per word, low byte first.
+            0x24 => {
+                let mut addr = self.registers[0];
+                loop {
+                    let word = self.read(addr);
+                    if word == 0 {
+                        break;
+                    }
+                    self.output.push(word as u8);
+                    if word >> 8 != 0 {
+                        self.output.push((word >> 8) as u8);
+                    }
+                    addr = addr.wrapping_add(1);
+                }
+            }
+            // HALT
+            0x25 => {
+                self.output.extend_from_slice(b"HALT\n");
+                return Ok(true);
+            }
+            _ => return Err(Fault::UnknownTrap(vector)),
+        }
+        Ok(false)
+    }
+}
@@ -0,0 +1,135 @@
+//! Save states: the whole machine in a versioned binary file.
+//!
+//! Layout (big-endian, like `.obj` images):
+//!
+//! | field         | size                                      |
+//! |---------------|-------------------------------------------|
+//! | magic `LC3S`  | 4 bytes                                   |
+//! | version       | u16                                       |
+//! | registers     | 10 × u16 (R0-R7, PC, Cond)                |
+//! | memory        | 65536 × u16 (device registers included)   |
+//! | flags         | u16 (bit 0: IN prompt already printed)    |
+//! | instructions  | u64                                       |
+//! | cycles        | u64                                       |
+//! | pending input | u32 length, then that many bytes          |
+//!
+//! The counters carry on after a restore, so a key log recorded from a restored machine
+//! replays against the same snapshot.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+use crate::vm::MEMORY_MAX;
+
+const MAGIC: &[u8; 4] = b"LC3S";
+pub const VERSION: u16 = 2;
+pub const REGISTER_COUNT: usize = 10;
+
+const FLAG_IN_PROMPT_SHOWN: u16 = 1 << 0;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    pub registers: [u16; REGISTER_COUNT],
+    pub memory: Box<[u16; MEMORY_MAX]>,
+    pub in_prompt_shown: bool,
+    /// `Vm::instructions` and `Vm::cycles`.
+    pub instructions: u64,
+    pub cycles: u64,
+    pub pending_input: Vec<u8>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_u16(reader: &mut impl Read) -> io::Result<u16> {
+    let mut bytes = [0u8; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_be_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+impl Snapshot {
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_be_bytes())?;
+        for value in self.registers.iter().chain(self.memory.iter()) {
+            writer.write_all(&value.to_be_bytes())?;
+        }
+        let flags = if self.in_prompt_shown {
+            FLAG_IN_PROMPT_SHOWN
+        } else {
+            0
+        };
+        writer.write_all(&flags.to_be_bytes())?;
+        writer.write_all(&self.instructions.to_be_bytes())?;
+        writer.write_all(&self.cycles.to_be_bytes())?;
+        writer.write_all(&(self.pending_input.len() as u32).to_be_bytes())?;
+        writer.write_all(&self.pending_input)
+    }
+
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("Not an LC-3 snapshot"));
+        }
+        let version = read_u16(reader)?;
+        if version != VERSION {
+            return Err(invalid(&format!(
+                "Unsupported snapshot version {} (expected {})",
+                version, VERSION
+            )));
+        }
+
+        let mut registers = [0u16; REGISTER_COUNT];
+        for register in registers.iter_mut() {
+            *register = read_u16(reader)?;
+        }
+        let mut memory = Box::new([0u16; MEMORY_MAX]);
+        for word in memory.iter_mut() {
+            *word = read_u16(reader)?;
+        }
+        let flags = read_u16(reader)?;
+        let instructions = read_u64(reader)?;
+        let cycles = read_u64(reader)?;
+
+        // The length comes from the file, so only the bytes actually there are allocated.
+        let mut len = [0u8; 4];
+        reader.read_exact(&mut len)?;
+        let len = u32::from_be_bytes(len) as u64;
+        let mut pending_input = Vec::new();
+        reader.take(len).read_to_end(&mut pending_input)?;
+        if (pending_input.len() as u64) < len {
+            return Err(invalid(&format!(
+                "Snapshot is truncated: {} of {} bytes of pending input",
+                pending_input.len(),
+                len
+            )));
+        }
+
+        Ok(Self {
+            registers,
+            memory,
+            in_prompt_shown: flags & FLAG_IN_PROMPT_SHOWN != 0,
+            instructions,
+            cycles,
+            pending_input,
+        })
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn load(path: &str) -> io::Result<Self> {
+        Self::read_from(&mut BufReader::new(File::open(path)?))
+    }
+}
@@ -0,0 +1,274 @@
+//! An in-process virtual terminal: console output goes in, and the screen a real terminal
+//! would show comes out as text, for tests, snapshots and headless screenshots.
+//!
+//! It understands what the bundled programs and most LC-3 games use. That covers printable
+//! characters, `\n` (as a newline, the way a terminal in its usual mode shows it), `\r`,
+//! backspace and tab, and the CSI sequences that move the cursor (`A` `B` `C` `D` `E` `F`
+//! `G` `H` `f` `d`), clear the screen or a line (`J`, `K`), scroll (`S`, `T`) and save or
+//! restore the cursor (`s`, `u`, `ESC 7`, `ESC 8`). Colours and modes are accepted and
+//! ignored. Writing past the last column wraps, and a newline on the last row scrolls.
+
+use crate::console::{Console, OnExhausted};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+pub const DEFAULT_WIDTH: usize = 80;
+pub const DEFAULT_HEIGHT: usize = 24;
+
+const ESC: u8 = 0x1b;
+
+pub struct Terminal {
+    width: usize,
+    height: usize,
+    rows: Vec<Vec<char>>,
+    row: usize,
+    column: usize,
+    saved: (usize, usize),
+    // Set after a character lands in the last column: the next one starts a new line.
+    wrap_pending: bool,
+    // The bytes of an escape sequence after ESC, which may arrive over several writes.
+    escape: Option<Vec<u8>>,
+}
+
+impl Default for Terminal {
+    fn default() -> Self {
+        Terminal::new(DEFAULT_WIDTH, DEFAULT_HEIGHT)
+    }
+}
+
+impl Terminal {
+    pub fn new(width: usize, height: usize) -> Self {
+        assert!(
+            width > 0 && height > 0,
+            "a terminal needs at least one cell"
+        );
+        Terminal {
+            width,
+            height,
+            rows: vec![vec![' '; width]; height],
+            row: 0,
+            column: 0,
+            saved: (0, 0),
+            wrap_pending: false,
+            escape: None,
+        }
+    }
+
+    /// Columns and rows.
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// The cursor's row and column, from 0.
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.row, self.column)
+    }
+
+    /// Every row, without trailing spaces.
+    pub fn lines(&self) -> Vec<String> {
+        self.rows
+            .iter()
+            .map(|row| row.iter().collect::<String>().trim_end().to_string())
+            .collect()
+    }
+
+    /// The screen as text: each row on its own line, without trailing spaces, and without
+    /// the blank rows below the last one with something on it.
+    pub fn text(&self) -> String {
+        let lines = self.lines();
+        let used = lines
+            .iter()
+            .rposition(|line| !line.is_empty())
+            .map_or(0, |i| i + 1);
+        lines[..used]
+            .iter()
+            .map(|line| format!("{}\n", line))
+            .collect()
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            match &mut self.escape {
+                Some(sequence) => {
+                    sequence.push(byte);
+                    // CSI sequences end with a byte in 0x40..=0x7E; the others are one byte.
+                    let done = match sequence.as_slice() {
+                        [b'['] => false,
+                        [b'[', .., last] => (0x40..=0x7E).contains(last),
+                        _ => true,
+                    };
+                    if done {
+                        let sequence = self.escape.take().expect("an escape sequence");
+                        self.escape(&sequence);
+                    }
+                }
+                None => self.byte(byte),
+            }
+        }
+    }
+
+    fn byte(&mut self, byte: u8) {
+        match byte {
+            ESC => self.escape = Some(Vec::new()),
+            b'\n' => {
+                self.column = 0;
+                self.line_feed();
+            }
+            b'\r' => self.move_to(self.row, 0),
+            0x08 => self.move_to(self.row, self.column.saturating_sub(1)),
+            b'\t' => self.move_to(self.row, (self.column / 8 + 1) * 8),
+            byte if byte >= 0x20 && byte != 0x7F => self.put(byte as char),
+            _ => {}
+        }
+    }
+
+    fn put(&mut self, c: char) {
+        if self.wrap_pending {
+            self.column = 0;
+            self.line_feed();
+        }
+        self.rows[self.row][self.column] = c;
+        if self.column + 1 < self.width {
+            self.column += 1;
+        } else {
+            self.wrap_pending = true;
+        }
+    }
+
+    fn line_feed(&mut self) {
+        self.wrap_pending = false;
+        if self.row + 1 < self.height {
+            self.row += 1;
+        } else {
+            self.scroll_up(1);
+        }
+    }
+
+    // Clamps to the screen.
+    fn move_to(&mut self, row: usize, column: usize) {
+        self.row = row.min(self.height - 1);
+        self.column = column.min(self.width - 1);
+        self.wrap_pending = false;
+    }
+
+    fn scroll_up(&mut self, count: usize) {
+        for _ in 0..count.min(self.height) {
+            self.rows.remove(0);
+            self.rows.push(vec![' '; self.width]);
+        }
+    }
+
+    fn scroll_down(&mut self, count: usize) {
+        for _ in 0..count.min(self.height) {
+            self.rows.pop();
+            self.rows.insert(0, vec![' '; self.width]);
+        }
+    }
+
+    // Blanks the cells from (row, column) up to, not including, (end_row, end_column).
+    fn erase(&mut self, (row, column): (usize, usize), (end_row, end_column): (usize, usize)) {
+        let start = row * self.width + column;
+        let end = end_row * self.width + end_column;
+        for cell in start..end {
+            self.rows[cell / self.width][cell % self.width] = ' ';
+        }
+    }
+
+    // `sequence` is what followed ESC.
+    fn escape(&mut self, sequence: &[u8]) {
+        match sequence {
+            [b'7'] => self.saved = (self.row, self.column),
+            [b'8'] => self.move_to(self.saved.0, self.saved.1),
+            [b'c'] => *self = Terminal::new(self.width, self.height),
+            [b'[', b'?', ..] => {}
+            [b'[', params @ .., command] => self.control(params, *command),
+            _ => {}
+        }
+    }
+
+    fn control(&mut self, params: &[u8], command: u8) {
+        let params: Vec<usize> = String::from_utf8_lossy(params)
+            .split(';')
+            .map(|p| p.parse().unwrap_or(0))
+            .collect();
+        let param = |i: usize| params.get(i).copied().unwrap_or(0);
+        // A count or a position, where 0 and nothing mean 1.
+        let count = |i: usize| param(i).max(1);
+        let (row, column) = (self.row, self.column);
+        let (width, height) = (self.width, self.height);
+        match command {
+            b'A' => self.move_to(row.saturating_sub(count(0)), column),
+            b'B' => self.move_to(row.saturating_add(count(0)), column),
+            b'C' => self.move_to(row, column.saturating_add(count(0))),
+            b'D' => self.move_to(row, column.saturating_sub(count(0))),
+            b'E' => self.move_to(row.saturating_add(count(0)), 0),
+            b'F' => self.move_to(row.saturating_sub(count(0)), 0),
+            b'G' => self.move_to(row, count(0) - 1),
+            b'H' | b'f' => self.move_to(count(0) - 1, count(1) - 1),
+            b'd' => self.move_to(count(0) - 1, column),
+            b'J' => match param(0) {
+                0 => self.erase((row, column), (height, 0)),
+                1 => self.erase((0, 0), (row, column + 1)),
+                2 => self.erase((0, 0), (height, 0)),
+                // 3 clears the scrollback, which this terminal does not keep.
+                _ => {}
+            },
+            b'K' => match param(0) {
+                0 => self.erase((row, column), (row, width)),
+                1 => self.erase((row, 0), (row, column + 1)),
+                2 => self.erase((row, 0), (row, width)),
+                _ => {}
+            },
+            b'S' => self.scroll_up(count(0)),
+            b'T' => self.scroll_down(count(0)),
+            b's' => self.saved = (row, column),
+            b'u' => self.move_to(self.saved.0, self.saved.1),
+            // Colours, modes, and anything else.
+            _ => {}
+        }
+    }
+}
+
+/// Passes everything through to another console and also shows the output on a
+/// `Terminal`, which the caller keeps a handle to.
+pub struct CapturingConsole<C> {
+    inner: C,
+    terminal: Arc<Mutex<Terminal>>,
+}
+
+impl<C: Console> CapturingConsole<C> {
+    pub fn new(inner: C, terminal: Arc<Mutex<Terminal>>) -> Self {
+        CapturingConsole { inner, terminal }
+    }
+}
+
+impl<C: Console> Console for CapturingConsole<C> {
+    fn poll_key(&mut self) -> Option<u8> {
+        self.inner.poll_key()
+    }
+
+    fn read_key(&mut self) -> io::Result<u8> {
+        self.inner.read_key()
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.terminal.lock().unwrap().feed(bytes);
+        self.inner.write(bytes)
+    }
+
+    fn pending_input(&mut self) -> Vec<u8> {
+        self.inner.pending_input()
+    }
+
+    fn queue_input(&mut self, bytes: &[u8]) {
+        self.inner.queue_input(bytes);
+    }
+
+    fn push_input(&mut self, bytes: &[u8]) {
+        self.inner.push_input(bytes);
+    }
+
+    fn exhausted(&self) -> Option<OnExhausted> {
+        self.inner.exhausted()
+    }
+}
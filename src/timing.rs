@@ -0,0 +1,88 @@
+//! Cycle counts from the LC-3 state machine (Patt & Patel, appendix C).
+//!
+//! Every instruction goes through the fetch states 18, 33, 35 and 32, then the states of
+//! its opcode. Each state takes one cycle, except the states that access memory, which
+//! wait `memory_latency` cycles for it to become ready. Traps are serviced by the emulator
+//! itself, so a TRAP costs only its own states (vector lookup and the jump), not the
+//! service routine's.
+
+use crate::instruction::Instruction;
+
+/// States every instruction starts with: MAR <- PC, MDR <- M[MAR], IR <- MDR, decode.
+pub const FETCH_STATES: &[u8] = &[18, 33, 35, 32];
+
+/// States that read or write memory.
+const MEMORY_STATES: &[u8] = &[16, 24, 25, 28, 29, 33];
+
+pub const DEFAULT_MEMORY_LATENCY: u32 = 5;
+
+/// States of `instr` after decode. `ben` is the branch enable bit set in state 32, i.e.
+/// whether a BR is taken.
+pub fn execute_states(instr: Instruction, ben: bool) -> &'static [u8] {
+    match instr {
+        Instruction::Br { .. } if ben => &[0, 22],
+        Instruction::Br { .. } => &[0],
+        Instruction::AddReg { .. } | Instruction::AddImm { .. } => &[1],
+        Instruction::AndReg { .. } | Instruction::AndImm { .. } => &[5],
+        Instruction::Not { .. } => &[9],
+        Instruction::Lea { .. } => &[14],
+        Instruction::Ld { .. } => &[2, 25, 27],
+        Instruction::Ldr { .. } => &[6, 25, 27],
+        Instruction::Ldi { .. } => &[10, 24, 26, 25, 27],
+        Instruction::St { .. } => &[3, 23, 16],
+        Instruction::Str { .. } => &[7, 23, 16],
+        Instruction::Sti { .. } => &[11, 29, 31, 23, 16],
+        Instruction::Jmp { .. } => &[12],
+        Instruction::Jsr { .. } => &[4, 21],
+        Instruction::Jsrr { .. } => &[4, 20],
+        Instruction::Trap { .. } => &[15, 28, 30],
+        Instruction::Rti => &[8],
+        Instruction::Res => &[13],
+    }
+}
+
+pub fn is_memory_state(state: u8) -> bool {
+    MEMORY_STATES.contains(&state)
+}
+
+/// Whether `instr`, decoded with condition codes `cond`, sets BEN.
+pub fn branch_enable(instr: Instruction, cond: u16) -> bool {
+    matches!(instr, Instruction::Br { nzp, .. } if nzp & cond != 0)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CycleModel {
+    /// Cycles a memory state waits for memory, 1 for memory as fast as the datapath.
+    pub memory_latency: u32,
+}
+
+impl Default for CycleModel {
+    fn default() -> Self {
+        Self {
+            memory_latency: DEFAULT_MEMORY_LATENCY,
+        }
+    }
+}
+
+impl CycleModel {
+    pub fn new(memory_latency: u32) -> Self {
+        Self { memory_latency }
+    }
+
+    pub fn state_cycles(&self, state: u8) -> u32 {
+        if is_memory_state(state) {
+            self.memory_latency
+        } else {
+            1
+        }
+    }
+
+    /// Cycles from fetch to completion of `instr`, executed with condition codes `cond`.
+    pub fn instruction_cycles(&self, instr: Instruction, cond: u16) -> u32 {
+        FETCH_STATES
+            .iter()
+            .chain(execute_states(instr, branch_enable(instr, cond)))
+            .map(|&state| self.state_cycles(state))
+            .sum()
+    }
+}
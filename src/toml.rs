@@ -0,0 +1,410 @@
+//! Minimal TOML reader for test specs: tables, arrays of tables, dotted keys, strings,
+//! integers, booleans, arrays and inline tables. Floats, dates and multi-line strings are
+//! not supported.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Bool(bool),
+    Array(Vec<Value>),
+    Table(BTreeMap<String, Value>),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Table(map) => map.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Value>> {
+        match self {
+            Value::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_table(&self) -> Option<&BTreeMap<String, Value>> {
+        match self {
+            Value::Table(map) => Some(map),
+            _ => None,
+        }
+    }
+
+    /// What kind of value this is, for error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Integer(_) => "integer",
+            Value::Bool(_) => "boolean",
+            Value::Array(_) => "array",
+            Value::Table(_) => "table",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    /// 1-based.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parses a document into its root table.
+pub fn parse(text: &str) -> Result<Value, ParseError> {
+    let mut parser = Parser {
+        bytes: text.as_bytes(),
+        pos: 0,
+    };
+    let mut root = BTreeMap::new();
+    // Path of the table that `key = value` lines go into.
+    let mut current: Vec<String> = Vec::new();
+    loop {
+        parser.skip_trivia();
+        match parser.peek() {
+            None => return Ok(Value::Table(root)),
+            Some(b'[') => {
+                parser.pos += 1;
+                let array = parser.peek() == Some(b'[');
+                if array {
+                    parser.pos += 1;
+                }
+                let path = parser.key()?;
+                let close: &[u8] = if array { b"]]" } else { b"]" };
+                parser.skip_spaces();
+                if !parser.bytes[parser.pos..].starts_with(close) {
+                    return Err(parser.error("Expected ']' after table name"));
+                }
+                parser.pos += close.len();
+                let (last, parents) = path.split_last().expect("keys have a part");
+                let parent = parser.table_at(&mut root, parents)?;
+                if array {
+                    let tables = parent
+                        .entry(last.clone())
+                        .or_insert_with(|| Value::Array(Vec::new()));
+                    let Value::Array(tables) = tables else {
+                        return Err(parser.error(format!("{} is not an array of tables", last)));
+                    };
+                    tables.push(Value::Table(BTreeMap::new()));
+                } else if parent.contains_key(last) {
+                    return Err(parser.error(format!("Table {} is defined twice", last)));
+                } else {
+                    parent.insert(last.clone(), Value::Table(BTreeMap::new()));
+                }
+                current = path;
+            }
+            Some(_) => {
+                let table = parser.table_at(&mut root, &current)?;
+                parser.key_value(table)?;
+            }
+        }
+        parser.end_of_line()?;
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        let line = 1 + self.bytes[..self.pos]
+            .iter()
+            .filter(|&&b| b == b'\n')
+            .count();
+        ParseError {
+            line,
+            message: message.into(),
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t')) {
+            self.pos += 1;
+        }
+    }
+
+    // Skips whitespace, newlines and comments.
+    fn skip_trivia(&mut self) {
+        loop {
+            match self.peek() {
+                Some(b' ' | b'\t' | b'\r' | b'\n') => self.pos += 1,
+                Some(b'#') => {
+                    while !matches!(self.peek(), Some(b'\n') | None) {
+                        self.pos += 1;
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<(), ParseError> {
+        self.skip_spaces();
+        match self.peek() {
+            Some(b'#') | Some(b'\n') | None => Ok(()),
+            Some(b'\r') if self.bytes.get(self.pos + 1) == Some(&b'\n') => Ok(()),
+            _ => Err(self.error("Expected the end of the line")),
+        }
+    }
+
+    // The table at `path` below `root`, going into the last table of any array of tables
+    // on the way and creating missing tables.
+    fn table_at<'t>(
+        &self,
+        root: &'t mut BTreeMap<String, Value>,
+        path: &[String],
+    ) -> Result<&'t mut BTreeMap<String, Value>, ParseError> {
+        let mut table = root;
+        for part in path {
+            let value = table
+                .entry(part.clone())
+                .or_insert_with(|| Value::Table(BTreeMap::new()));
+            table = match value {
+                Value::Table(map) => map,
+                Value::Array(items) => match items.last_mut() {
+                    Some(Value::Table(map)) => map,
+                    _ => return Err(self.error(format!("{} is not a table", part))),
+                },
+                _ => return Err(self.error(format!("{} is not a table", part))),
+            };
+        }
+        Ok(table)
+    }
+
+    // `a.b = value`, inserted into `table`.
+    fn key_value(&mut self, table: &mut BTreeMap<String, Value>) -> Result<(), ParseError> {
+        let path = self.key()?;
+        self.skip_spaces();
+        if self.peek() != Some(b'=') {
+            return Err(self.error("Expected '='"));
+        }
+        self.pos += 1;
+        let value = self.value()?;
+        let (last, parents) = path.split_last().expect("keys have a part");
+        let table = self.table_at(table, parents)?;
+        if table.contains_key(last) {
+            return Err(self.error(format!("Key {} is defined twice", last)));
+        }
+        table.insert(last.clone(), value);
+        Ok(())
+    }
+
+    // A dotted key, as its parts.
+    fn key(&mut self) -> Result<Vec<String>, ParseError> {
+        let mut parts = Vec::new();
+        loop {
+            self.skip_spaces();
+            let part = match self.peek() {
+                Some(b'"') => self.basic_string()?,
+                Some(b'\'') => self.literal_string()?,
+                _ => {
+                    let start = self.pos;
+                    while matches!(
+                        self.peek(),
+                        Some(b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'_' | b'-')
+                    ) {
+                        self.pos += 1;
+                    }
+                    if start == self.pos {
+                        return Err(self.error("Expected a key"));
+                    }
+                    String::from_utf8_lossy(&self.bytes[start..self.pos]).into_owned()
+                }
+            };
+            parts.push(part);
+            self.skip_spaces();
+            if self.peek() != Some(b'.') {
+                return Ok(parts);
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        self.skip_spaces();
+        match self.peek() {
+            Some(b'"') => self.basic_string().map(Value::String),
+            Some(b'\'') => self.literal_string().map(Value::String),
+            Some(b'[') => self.array(),
+            Some(b'{') => self.inline_table(),
+            Some(b't') if self.bytes[self.pos..].starts_with(b"true") => {
+                self.pos += 4;
+                Ok(Value::Bool(true))
+            }
+            Some(b'f') if self.bytes[self.pos..].starts_with(b"false") => {
+                self.pos += 5;
+                Ok(Value::Bool(false))
+            }
+            Some(b'+' | b'-' | b'0'..=b'9') => self.integer(),
+            _ => Err(self.error("Expected a value")),
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, ParseError> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        loop {
+            self.skip_trivia();
+            if self.peek() == Some(b']') {
+                self.pos += 1;
+                return Ok(Value::Array(items));
+            }
+            items.push(self.value()?);
+            self.skip_trivia();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {}
+                _ => return Err(self.error("Expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn inline_table(&mut self) -> Result<Value, ParseError> {
+        self.pos += 1;
+        let mut map = BTreeMap::new();
+        self.skip_spaces();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Value::Table(map));
+        }
+        loop {
+            self.key_value(&mut map)?;
+            self.skip_spaces();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Value::Table(map));
+                }
+                _ => return Err(self.error("Expected ',' or '}'")),
+            }
+        }
+    }
+
+    // Decimal with an optional sign, or 0x, 0o and 0b; `_` may separate digits.
+    fn integer(&mut self) -> Result<Value, ParseError> {
+        let start = self.pos;
+        while matches!(
+            self.peek(),
+            Some(b'+' | b'-' | b'_' | b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z')
+        ) {
+            self.pos += 1;
+        }
+        let text: String = String::from_utf8_lossy(&self.bytes[start..self.pos])
+            .chars()
+            .filter(|&c| c != '_')
+            .collect();
+        let (radix, digits) = match text.get(..2) {
+            Some("0x") => (16, &text[2..]),
+            Some("0o") => (8, &text[2..]),
+            Some("0b") => (2, &text[2..]),
+            _ => (10, text.as_str()),
+        };
+        i64::from_str_radix(digits, radix)
+            .map(Value::Integer)
+            .map_err(|_| self.error(format!("Invalid integer {}", text)))
+    }
+
+    fn literal_string(&mut self) -> Result<String, ParseError> {
+        self.pos += 1;
+        let start = self.pos;
+        while !matches!(self.peek(), Some(b'\'' | b'\n') | None) {
+            self.pos += 1;
+        }
+        if self.peek() != Some(b'\'') {
+            return Err(self.error("Unterminated string"));
+        }
+        self.pos += 1;
+        Ok(String::from_utf8_lossy(&self.bytes[start..self.pos - 1]).into_owned())
+    }
+
+    fn basic_string(&mut self) -> Result<String, ParseError> {
+        self.pos += 1;
+        let mut out = Vec::new();
+        loop {
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return String::from_utf8(out).map_err(|_| self.error("Invalid UTF-8"));
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escape = self
+                        .peek()
+                        .ok_or_else(|| self.error("Unterminated string"))?;
+                    self.pos += 1;
+                    let c = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'b' => '\u{8}',
+                        b'e' => '\u{1b}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' | b'U' => {
+                            let len = if escape == b'u' { 4 } else { 8 };
+                            let code = self
+                                .bytes
+                                .get(self.pos..self.pos + len)
+                                .and_then(|d| std::str::from_utf8(d).ok())
+                                .and_then(|d| u32::from_str_radix(d, 16).ok())
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| self.error("Invalid unicode escape"))?;
+                            self.pos += len;
+                            code
+                        }
+                        _ => return Err(self.error("Invalid escape")),
+                    };
+                    let mut buffer = [0; 4];
+                    out.extend(c.encode_utf8(&mut buffer).as_bytes());
+                }
+                Some(b'\n') | None => return Err(self.error("Unterminated string")),
+                Some(b) => {
+                    out.push(b);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,501 @@
+//! Full-screen terminal debugger.
+//!
+//! The program's console is captured instead of written to the terminal, so its output
+//! lives in its own pane and the debugger owns the screen. While the program runs, every
+//! key goes to it; Tab pauses it and hands the keyboard back to the debugger.
+
+use crate::callstack::CallStack;
+use crate::console::SharedConsole;
+use crate::debuginfo::DebugInfo;
+use crate::hardware::{check_key, terminal_size};
+use crate::instruction::Instruction;
+use crate::micro::{Microarchitecture, describe_state};
+use crate::terminal::Terminal;
+use crate::vm::{ConditionalFlag, Register, Step, Vm, VmError};
+use std::collections::BTreeSet;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+const MIN_WIDTH: usize = 80;
+const MIN_HEIGHT: usize = 24;
+const MEMORY_WORDS_PER_ROW: u16 = 8;
+// Instructions executed between keyboard polls while running.
+const RUN_SLICE: u32 = 20_000;
+const REDRAW_INTERVAL: Duration = Duration::from_millis(33);
+
+const KEY_TAB: u8 = b'\t';
+const KEY_ESC: u8 = 0x1B;
+
+const HELP: &str = "s step  m microstep  c continue  Tab pause  b breakpoint  j/k move  . to PC  [ ] memory  i send key  q quit";
+
+/// What to show, with plain-text lines so layout can be tested without a terminal.
+pub struct Frame {
+    pub lines: Vec<String>,
+    /// Row of the disassembly cursor, drawn in reverse video.
+    pub cursor_row: Option<usize>,
+}
+
+pub struct Tui {
+    vm: Vm,
+    console: SharedConsole,
+    debug: DebugInfo,
+    // What the program's output would show on a terminal of its own.
+    screen: Terminal,
+    breakpoints: BTreeSet<u16>,
+    calls: CallStack,
+    cursor: u16,
+    memory_addr: u16,
+    running: bool,
+    halted: bool,
+    waiting_for_input: bool,
+    status: String,
+    micro: Option<Microarchitecture>,
+}
+
+impl Tui {
+    /// `console` must be the console installed in `vm`.
+    pub fn new(vm: Vm, console: SharedConsole, debug: DebugInfo) -> Self {
+        let pc = vm.read_register(Register::PC);
+        Self {
+            vm,
+            console,
+            debug,
+            screen: Terminal::default(),
+            breakpoints: BTreeSet::new(),
+            calls: CallStack::new(),
+            cursor: pc,
+            memory_addr: pc & !(MEMORY_WORDS_PER_ROW - 1),
+            running: false,
+            halted: false,
+            waiting_for_input: false,
+            status: "Paused".to_string(),
+            micro: None,
+        }
+    }
+
+    /// Executes through `micro`, which adds the datapath pane and microstepping.
+    pub fn with_micro(mut self, micro: Microarchitecture) -> Self {
+        self.micro = Some(micro);
+        self
+    }
+
+    pub fn vm(&self) -> &Vm {
+        &self.vm
+    }
+
+    pub fn toggle_breakpoint(&mut self, addr: u16) {
+        if !self.breakpoints.remove(&addr) {
+            self.breakpoints.insert(addr);
+        }
+    }
+
+    /// Executes one instruction, returning false when the machine stopped.
+    fn step(&mut self) -> bool {
+        if self.halted {
+            self.status = "Program halted".to_string();
+            return false;
+        }
+        let result = match &mut self.micro {
+            Some(micro) => micro.step(&mut self.vm),
+            None => self.vm.step(),
+        };
+        self.finish(result)
+    }
+
+    /// Executes one state of the microarchitecture, returning false when the machine
+    /// stopped. Without one, steps a whole instruction.
+    pub fn microstep(&mut self) -> bool {
+        let Some(micro) = &mut self.micro else {
+            return self.step();
+        };
+        if self.halted {
+            self.status = "Program halted".to_string();
+            return false;
+        }
+        let result = micro.microstep(&mut self.vm);
+        let completed = match result {
+            Ok(micro_step) => match micro_step.completed {
+                Some(step) => Ok(step),
+                None => {
+                    self.status = format!("Microstep: state {}", micro_step.state);
+                    self.cursor = self.vm.read_register(Register::PC);
+                    return true;
+                }
+            },
+            Err(e) => Err(e),
+        };
+        let running = self.finish(completed);
+        if running {
+            self.status = "Microstep: instruction complete".to_string();
+        }
+        self.cursor = self.vm.read_register(Register::PC);
+        running
+    }
+
+    // Records the outcome of an instruction and whether the machine can go on.
+    fn finish(&mut self, result: Result<Step, VmError>) -> bool {
+        if let Ok(step) = &result {
+            self.calls.observe(step, &self.vm);
+        }
+        self.screen.feed(&self.console.take_output());
+        self.waiting_for_input = matches!(result, Err(VmError::WaitingForInput));
+        match result {
+            Ok(step) if step.halted => {
+                self.halted = true;
+                self.status = "Program halted".to_string();
+                false
+            }
+            Ok(_) => true,
+            Err(VmError::WaitingForInput) => {
+                self.status =
+                    "Waiting for input (c to run and type, or i to send a key)".to_string();
+                false
+            }
+            Err(e) => {
+                self.halted = true;
+                self.status = e.to_string();
+                false
+            }
+        }
+    }
+
+    pub fn single_step(&mut self) {
+        if self.step() {
+            self.status = "Paused".to_string();
+        }
+        self.cursor = self.vm.read_register(Register::PC);
+    }
+
+    /// Runs up to `RUN_SLICE` instructions, stopping at breakpoints, and reports whether
+    /// the program can keep running.
+    pub fn run_slice(&mut self) -> bool {
+        self.status = "Running (Tab to pause)".to_string();
+        for _ in 0..RUN_SLICE {
+            if !self.step() {
+                // A program waiting for a key keeps running so typed keys reach it.
+                return self.waiting_for_input;
+            }
+            let pc = self.vm.read_register(Register::PC);
+            if self.breakpoints.contains(&pc) {
+                self.status = format!("Breakpoint at {}", self.debug.describe(pc));
+                self.cursor = pc;
+                return false;
+            }
+        }
+        true
+    }
+
+    fn handle_key(&mut self, key: u8, input: &mut impl Read) -> bool {
+        match key {
+            b'q' => return false,
+            b's' | b'n' => self.single_step(),
+            b'm' => {
+                self.microstep();
+            }
+            b'c' if !self.halted => {
+                self.running = true;
+                self.status = "Running (Tab to pause)".to_string();
+            }
+            b'b' => self.toggle_breakpoint(self.cursor),
+            b'j' => self.cursor = self.cursor.wrapping_add(1),
+            b'k' => self.cursor = self.cursor.wrapping_sub(1),
+            b'.' => self.cursor = self.vm.read_register(Register::PC),
+            b'[' => {
+                self.memory_addr = self.memory_addr.wrapping_sub(MEMORY_WORDS_PER_ROW * 4);
+            }
+            b']' => {
+                self.memory_addr = self.memory_addr.wrapping_add(MEMORY_WORDS_PER_ROW * 4);
+            }
+            b'i' => {
+                self.status = "Press the key to send to the program".to_string();
+                self.draw();
+                let mut key = [0u8; 1];
+                if input.read_exact(&mut key).is_ok() {
+                    self.console.push_input(&key);
+                    self.status = format!("Queued key 0x{:02X}", key[0]);
+                }
+            }
+            KEY_ESC => {
+                // Arrow keys arrive as ESC [ A/B.
+                let mut sequence = [0u8; 2];
+                if check_key() && input.read_exact(&mut sequence).is_ok() {
+                    match sequence {
+                        [b'[', b'A'] => self.cursor = self.cursor.wrapping_sub(1),
+                        [b'[', b'B'] => self.cursor = self.cursor.wrapping_add(1),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+
+    pub fn render(&self, width: usize, height: usize) -> Frame {
+        let width = width.max(MIN_WIDTH);
+        let height = height.max(MIN_HEIGHT);
+        let left_width = width / 2;
+        let right_width = width - left_width - 1;
+        let body = height - 2;
+
+        let disasm_rows = body * 3 / 5;
+        let memory_rows = body - disasm_rows;
+        let (disasm, cursor) = self.disassembly_pane(disasm_rows);
+        let mut left = disasm;
+        left.extend(self.memory_pane(memory_rows));
+
+        let mut right = self.registers_pane();
+        right.extend(self.datapath_pane());
+        let breakpoint_rows = 5.min(body.saturating_sub(right.len() + 4));
+        right.extend(self.breakpoints_pane(breakpoint_rows));
+        let call_stack_rows = 5.min(body.saturating_sub(right.len() + 4));
+        right.extend(self.call_stack_pane(call_stack_rows));
+        let console_rows = body.saturating_sub(right.len());
+        right.extend(self.console_pane(console_rows));
+
+        let mut lines = vec![fit(&format!(" LC-3 debugger | {}", self.status), width)];
+        for row in 0..body {
+            let l = left.get(row).map(String::as_str).unwrap_or("");
+            let r = right.get(row).map(String::as_str).unwrap_or("");
+            lines.push(format!("{}|{}", fit(l, left_width), fit(r, right_width)));
+        }
+        lines.push(fit(&format!(" {}", HELP), width));
+
+        Frame {
+            lines,
+            cursor_row: cursor.map(|row| row + 1),
+        }
+    }
+
+    fn disassembly_pane(&self, rows: usize) -> (Vec<String>, Option<usize>) {
+        let mut lines = vec![title("Disassembly")];
+        let count = rows.saturating_sub(1) as u16;
+        let start = self.cursor.wrapping_sub(count / 2);
+        let pc = self.vm.read_register(Register::PC);
+        let mut cursor_row = None;
+
+        for i in 0..count {
+            let addr = start.wrapping_add(i);
+            let word = self.vm.peek_memory(addr);
+            let marker = match (self.breakpoints.contains(&addr), addr == pc) {
+                (true, true) => "*>",
+                (true, false) => "* ",
+                (false, true) => " >",
+                (false, false) => "  ",
+            };
+            let label = self.debug.label_at(addr).unwrap_or("");
+            let text = Instruction::decode(word).disassemble(addr, Some(&self.debug));
+            if addr == self.cursor {
+                cursor_row = Some(lines.len());
+            }
+            lines.push(format!(
+                "{}x{:04X} {:04X} {:<10.10} {}",
+                marker, addr, word, label, text
+            ));
+        }
+        (lines, cursor_row)
+    }
+
+    fn memory_pane(&self, rows: usize) -> Vec<String> {
+        let mut lines = vec![title("Memory")];
+        for row in 0..rows.saturating_sub(1) as u16 {
+            let base = self
+                .memory_addr
+                .wrapping_add(row.wrapping_mul(MEMORY_WORDS_PER_ROW));
+            let words: Vec<String> = (0..MEMORY_WORDS_PER_ROW)
+                .map(|i| format!("{:04X}", self.vm.peek_memory(base.wrapping_add(i))))
+                .collect();
+            lines.push(format!(" x{:04X}: {}", base, words.join(" ")));
+        }
+        lines
+    }
+
+    fn registers_pane(&self) -> Vec<String> {
+        let r = |reg: Register| self.vm.read_register(reg);
+        let cond = r(Register::Cond);
+        let flag = |f: ConditionalFlag, name: char| {
+            if cond & f as u16 != 0 { name } else { '-' }
+        };
+        let cycles = match self.vm.cycle_model() {
+            Some(_) => format!("  Cycles {}", self.vm.cycles()),
+            None => String::new(),
+        };
+        vec![
+            title("Registers"),
+            format!(
+                " R0 x{:04X}  R1 x{:04X}  R2 x{:04X}  R3 x{:04X}",
+                r(Register::R0),
+                r(Register::R1),
+                r(Register::R2),
+                r(Register::R3)
+            ),
+            format!(
+                " R4 x{:04X}  R5 x{:04X}  R6 x{:04X}  R7 x{:04X}",
+                r(Register::R4),
+                r(Register::R5),
+                r(Register::R6),
+                r(Register::R7)
+            ),
+            format!(
+                " PC x{:04X}  Cond {}{}{}{}",
+                r(Register::PC),
+                flag(ConditionalFlag::Neg, 'N'),
+                flag(ConditionalFlag::Zro, 'Z'),
+                flag(ConditionalFlag::Pos, 'P'),
+                cycles
+            ),
+        ]
+    }
+
+    fn datapath_pane(&self) -> Vec<String> {
+        let Some(micro) = &self.micro else {
+            return Vec::new();
+        };
+        let d = micro.datapath();
+        vec![
+            title("Datapath"),
+            format!(
+                " MAR x{:04X}  MDR x{:04X}  IR x{:04X}  BEN {}",
+                d.mar, d.mdr, d.ir, d.ben as u8
+            ),
+            format!(
+                " Next state {}: {}",
+                micro.state(),
+                describe_state(micro.state())
+            ),
+        ]
+    }
+
+    fn breakpoints_pane(&self, rows: usize) -> Vec<String> {
+        if rows == 0 {
+            return Vec::new();
+        }
+        let mut lines = vec![title("Breakpoints")];
+        lines.extend(
+            self.breakpoints
+                .iter()
+                .map(|addr| format!(" x{:04X} {}", addr, self.debug.describe(*addr)))
+                .take(rows - 1),
+        );
+        lines.resize(rows, String::new());
+        lines
+    }
+
+    fn call_stack_pane(&self, rows: usize) -> Vec<String> {
+        if rows == 0 {
+            return Vec::new();
+        }
+        let mut lines = vec![title("Call stack")];
+        lines.extend(
+            self.calls
+                .backtrace(self.vm.read_register(Register::PC), &self.debug)
+                .into_iter()
+                .map(|l| format!(" {}", l))
+                .take(rows - 1),
+        );
+        lines.resize(rows, String::new());
+        lines
+    }
+
+    // The program's screen, or as many of its rows as fit, ending with the cursor's.
+    fn console_pane(&self, rows: usize) -> Vec<String> {
+        let screen = self.screen.lines();
+        let count = rows.saturating_sub(1);
+        let end = (self.screen.cursor().0 + 1).max(count).min(screen.len());
+        let mut lines = vec![title("Console")];
+        lines.extend(
+            screen[end.saturating_sub(count)..end]
+                .iter()
+                .map(|l| format!(" {}", l)),
+        );
+        lines
+    }
+
+    fn draw(&self) {
+        let (width, height) = terminal_size()
+            .map(|(w, h)| (w as usize, h as usize))
+            .unwrap_or((MIN_WIDTH, MIN_HEIGHT));
+        let frame = self.render(width, height);
+
+        let mut out = String::from("\x1b[H");
+        for (row, line) in frame.lines.iter().enumerate() {
+            if row > 0 {
+                out.push_str("\r\n");
+            }
+            if Some(row) == frame.cursor_row {
+                out.push_str(&format!("\x1b[7m{}\x1b[0m", line));
+            } else {
+                out.push_str(line);
+            }
+        }
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(out.as_bytes());
+        let _ = stdout.flush();
+    }
+
+    /// Takes over the terminal until the user quits.
+    pub fn run(&mut self) -> io::Result<()> {
+        let mut stdout = io::stdout();
+        // Alternate screen, hidden cursor, cleared.
+        stdout.write_all(b"\x1b[?1049h\x1b[?25l\x1b[2J")?;
+        stdout.flush()?;
+
+        let result = self.event_loop();
+
+        stdout.write_all(b"\x1b[?25h\x1b[?1049l")?;
+        stdout.flush()?;
+        result
+    }
+
+    fn event_loop(&mut self) -> io::Result<()> {
+        let mut stdin = io::stdin();
+        let mut last_draw = Instant::now();
+        self.draw();
+
+        loop {
+            if self.running {
+                let keep_running = self.run_slice();
+                while check_key() {
+                    let mut key = [0u8; 1];
+                    stdin.read_exact(&mut key)?;
+                    if key[0] == KEY_TAB {
+                        self.running = false;
+                        self.status = "Paused".to_string();
+                        self.cursor = self.vm.read_register(Register::PC);
+                        break;
+                    }
+                    self.console.push_input(&key);
+                }
+                if !keep_running {
+                    self.running = false;
+                }
+                if !self.running || last_draw.elapsed() >= REDRAW_INTERVAL {
+                    self.draw();
+                    last_draw = Instant::now();
+                }
+                if self.running && self.waiting_for_input {
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+            } else {
+                let mut key = [0u8; 1];
+                stdin.read_exact(&mut key)?;
+                if !self.handle_key(key[0], &mut stdin) {
+                    return Ok(());
+                }
+                self.draw();
+            }
+        }
+    }
+}
+
+fn title(name: &str) -> String {
+    format!("-- {} ", name)
+}
+
+/// Truncates or pads `text` to exactly `width` characters.
+fn fit(text: &str, width: usize) -> String {
+    let mut line: String = text.chars().take(width).collect();
+    let len = line.chars().count();
+    line.extend(std::iter::repeat_n(' ', width - len));
+    line
+}
@@ -0,0 +1,493 @@
+//! Unit tests for single subroutines, declared in TOML.
+//!
+//! A spec names the program and lists tests. Each test loads a fresh copy of the image,
+//! sets registers and memory, and calls a label as `JSR` would, with R7 holding
+//! `RETURN_ADDRESS`. When the subroutine returns there, the expectations are checked:
+//!
+//! ```toml
+//! program = "strlen.asm"   # relative to the spec; an .obj can take labels from `sym`
+//!
+//! [[test]]
+//! name = "counts up to the terminator"
+//! call = "STRLEN"                        # without it, the program runs from its origin to HALT
+//! registers = { R0 = "BUFFER" }          # a label, an address, or an integer
+//! memory = { BUFFER = "hi" }             # a string is stored one character per word, then 0
+//! input = ""                             # keys for GETC/IN; reading past them is an error
+//! expect.registers = { R0 = 2 }
+//! expect.memory = { x4000 = [104, 105] }
+//! expect.output = ""
+//! expect.cc = "p"
+//! expect.preserved = ["R1", "R2"]        # unchanged from before the call
+//! ```
+//!
+//! Labels are looked up when a test runs, so a label the program lacks fails that test
+//! rather than the whole spec.
+
+use crate::console::{OnExhausted, SharedConsole};
+use crate::debuginfo::{DebugInfo, load_program_file};
+use crate::toml::{self, Value};
+use crate::vm::{ConditionalFlag, Register, Vm};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Where a called subroutine returns to. The test ends when PC reaches it.
+pub const RETURN_ADDRESS: u16 = 0xFDFF;
+
+/// Instructions a test may run before it counts as hung.
+pub const DEFAULT_MAX_INSTRUCTIONS: u64 = 1_000_000;
+
+/// Console output a test may produce before it is stopped.
+pub const MAX_OUTPUT: usize = 64 * 1024;
+
+/// A word given in a spec, as a number or as a label of the program under test.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Word {
+    Literal(u16),
+    Label(String),
+}
+
+impl Word {
+    /// `x3000`, `0x3000` or a label.
+    pub(crate) fn parse(text: &str) -> Result<Word, String> {
+        match text.strip_prefix("0x").or_else(|| text.strip_prefix('x')) {
+            Some(hex) => u16::from_str_radix(hex, 16)
+                .map(Word::Literal)
+                .map_err(|_| format!("Invalid address: {}", text)),
+            None => Ok(Word::Label(text.to_string())),
+        }
+    }
+
+    pub fn resolve(&self, debug: &DebugInfo) -> Result<u16, String> {
+        match self {
+            Word::Literal(value) => Ok(*value),
+            Word::Label(label) => debug
+                .address_of(label)
+                .ok_or_else(|| format!("the program has no label {}", label)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnitTest {
+    pub name: String,
+    /// The subroutine to call, or `None` to run the program from its origin until it halts.
+    pub call: Option<Word>,
+    pub registers: Vec<(Register, Word)>,
+    pub memory: Vec<(Word, Vec<u16>)>,
+    pub input: Vec<u8>,
+    pub max_instructions: u64,
+    pub expect: Expectations,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Expectations {
+    pub registers: Vec<(Register, Word)>,
+    pub memory: Vec<(Word, Vec<u16>)>,
+    pub output: Option<Vec<u8>>,
+    /// `ConditionalFlag` bits.
+    pub cc: Option<u16>,
+    pub preserved: Vec<Register>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestResult {
+    pub name: String,
+    /// One line for each expectation that did not hold, or for the error that stopped the
+    /// test. Empty when it passed.
+    pub failures: Vec<String>,
+    pub instructions: u64,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// A program to test, with its labels.
+pub struct Image {
+    pub origin: u16,
+    pub words: Vec<u16>,
+    pub debug: DebugInfo,
+}
+
+impl Image {
+    /// Reads an `.asm` or `.obj` file.
+    pub fn load(path: &Path) -> Result<Image, String> {
+        let (origin, words, debug) = load_program_file(path)?;
+        Ok(Image {
+            origin,
+            words,
+            debug,
+        })
+    }
+
+    /// Runs `test` on a fresh machine and checks what it left behind.
+    pub fn run(&self, test: &UnitTest) -> TestResult {
+        let mut result = TestResult {
+            name: test.name.clone(),
+            failures: Vec::new(),
+            instructions: 0,
+        };
+        if let Err(failure) = self.run_into(test, &mut result) {
+            result.failures.push(failure);
+        }
+        result
+    }
+
+    // Records failed expectations in `result`; an error that stops the test is returned.
+    fn run_into(&self, test: &UnitTest, result: &mut TestResult) -> Result<(), String> {
+        let debug = &self.debug;
+        let console = SharedConsole::new();
+        console.set_on_exhausted(OnExhausted::Error);
+        let mut vm = Vm::with_console(console.clone());
+        vm.load_program(self.origin, &self.words);
+        for (addr, words) in &test.memory {
+            vm.load_program(addr.resolve(debug)?, words);
+        }
+        for (reg, value) in &test.registers {
+            vm.write_register(*reg, value.resolve(debug)?);
+        }
+        let call = test.call.as_ref().map(|w| w.resolve(debug)).transpose()?;
+        let (start, end) = match call {
+            Some(call) => {
+                vm.write_register(Register::R7, RETURN_ADDRESS);
+                (call, Some(RETURN_ADDRESS))
+            }
+            None => (self.origin, None),
+        };
+        vm.write_register(Register::PC, start);
+        vm.push_input(&test.input);
+        let before: Vec<u16> = test
+            .expect
+            .preserved
+            .iter()
+            .map(|&reg| vm.read_register(reg))
+            .collect();
+
+        let mut output = Vec::new();
+        while Some(vm.read_register(Register::PC)) != end {
+            if result.instructions == test.max_instructions {
+                let what = if end.is_some() { "return" } else { "halt" };
+                return Err(format!(
+                    "did not {} within {} instructions",
+                    what, test.max_instructions
+                ));
+            }
+            let pc = vm.read_register(Register::PC);
+            let step = vm
+                .step()
+                .map_err(|e| format!("{} at {}", e, debug.describe(pc)))?;
+            result.instructions += 1;
+            output.extend(console.take_output());
+            if output.len() > MAX_OUTPUT {
+                return Err(format!("printed more than {} bytes", MAX_OUTPUT));
+            }
+            if step.halted {
+                if end.is_some() {
+                    return Err(format!(
+                        "halted at {} instead of returning",
+                        debug.describe(pc)
+                    ));
+                }
+                break;
+            }
+        }
+
+        let expect = &test.expect;
+        for (reg, value) in &expect.registers {
+            let (actual, value) = (vm.read_register(*reg), value.resolve(debug)?);
+            if actual != value {
+                result.failures.push(format!(
+                    "{:?} is {}, expected {}",
+                    reg,
+                    word(actual),
+                    word(value)
+                ));
+            }
+        }
+        for (&reg, &value) in expect.preserved.iter().zip(&before) {
+            let actual = vm.read_register(reg);
+            if actual != value {
+                result.failures.push(format!(
+                    "{:?} was not preserved: {} became x{:04X}",
+                    reg,
+                    word(value),
+                    actual
+                ));
+            }
+        }
+        for (start, words) in &expect.memory {
+            let start = start.resolve(debug)?;
+            for (i, &value) in words.iter().enumerate() {
+                // Like `load_program`, a range that runs past xFFFF wraps to x0000.
+                let addr = start.wrapping_add(i as u16);
+                let actual = vm.read_memory(addr);
+                if actual != value {
+                    result.failures.push(format!(
+                        "memory at {} is {}, expected {}",
+                        debug.describe(addr),
+                        word(actual),
+                        word(value)
+                    ));
+                }
+            }
+        }
+        if let Some(cc) = expect.cc {
+            let actual = vm.read_register(Register::Cond);
+            if actual != cc {
+                result.failures.push(format!(
+                    "condition codes are {}, expected {}",
+                    cc_name(actual),
+                    cc_name(cc)
+                ));
+            }
+        }
+        if let Some(expected) = &expect.output
+            && output != *expected
+        {
+            result.failures.push(format!(
+                "output is {:?}, expected {:?}",
+                String::from_utf8_lossy(&output),
+                String::from_utf8_lossy(expected)
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A program and the tests of its subroutines.
+pub struct Suite {
+    pub image: Image,
+    pub tests: Vec<UnitTest>,
+}
+
+impl Suite {
+    /// Reads a spec and the program it names.
+    pub fn load(path: &Path) -> Result<Suite, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        Suite::from_spec(&text, dir).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Parses a spec whose file names are relative to `dir`.
+    pub fn from_spec(text: &str, dir: &Path) -> Result<Suite, String> {
+        let spec = toml::parse(text).map_err(|e| e.to_string())?;
+        let program = string(&spec, "program")?.ok_or("program is missing")?;
+        let mut image = Image::load(&dir.join(program))?;
+        if let Some(sym) = string(&spec, "sym")? {
+            let path = dir.join(sym);
+            let symbols = DebugInfo::read_sym_file(&path.to_string_lossy())
+                .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+            image.debug.symbols.extend(symbols.symbols);
+        }
+        let max_instructions = max_instructions(&spec, DEFAULT_MAX_INSTRUCTIONS)?;
+        let tests = tables(&spec, "test")?
+            .iter()
+            .enumerate()
+            .map(|(i, test)| {
+                UnitTest::from_toml(test, &format!("test {}", i + 1), max_instructions)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Suite { image, tests })
+    }
+
+    pub fn run(&self, test: &UnitTest) -> TestResult {
+        self.image.run(test)
+    }
+
+    /// Runs every test, in order.
+    pub fn run_all(&self) -> Vec<TestResult> {
+        self.tests.iter().map(|test| self.run(test)).collect()
+    }
+}
+
+impl UnitTest {
+    /// Reads a `[[test]]` table, named `default_name` unless it has a `name`. Errors are
+    /// prefixed with the test's name.
+    pub fn from_toml(
+        test: &Value,
+        default_name: &str,
+        max_instructions: u64,
+    ) -> Result<UnitTest, String> {
+        let name = string(test, "name")?.unwrap_or(default_name).to_string();
+        parse_test(test, name.clone(), max_instructions).map_err(|e| format!("{}: {}", name, e))
+    }
+}
+
+/// The `[[key]]` tables of `spec`, if any.
+pub fn tables<'v>(spec: &'v Value, key: &str) -> Result<&'v [Value], String> {
+    match spec.get(key) {
+        Some(value) => value
+            .as_array()
+            .map(Vec::as_slice)
+            .ok_or_else(|| format!("{} must be an array of tables", key)),
+        None => Ok(&[]),
+    }
+}
+
+/// `max_instructions` of `table`, or `default`.
+pub fn max_instructions(table: &Value, default: u64) -> Result<u64, String> {
+    match table.get("max_instructions") {
+        Some(value) => value
+            .as_integer()
+            .and_then(|n| u64::try_from(n).ok())
+            .ok_or_else(|| "max_instructions must be a positive integer".to_string()),
+        None => Ok(default),
+    }
+}
+
+/// The string at `key` of `table`, if any.
+pub fn string<'v>(table: &'v Value, key: &str) -> Result<Option<&'v str>, String> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s)),
+        Some(other) => Err(format!(
+            "{} must be a string, not {}",
+            key,
+            other.type_name()
+        )),
+    }
+}
+
+// A word in hex, with its signed value.
+pub(crate) fn word(value: u16) -> String {
+    format!("x{:04X} ({})", value, value as i16)
+}
+
+pub(crate) fn cc_name(cc: u16) -> &'static str {
+    match cc {
+        c if c == ConditionalFlag::Neg as u16 => "n",
+        c if c == ConditionalFlag::Zro as u16 => "z",
+        c if c == ConditionalFlag::Pos as u16 => "p",
+        _ => "?",
+    }
+}
+
+fn parse_test(test: &Value, name: String, default_max: u64) -> Result<UnitTest, String> {
+    let call = string(test, "call")?.map(Word::parse).transpose()?;
+    let input = string(test, "input")?
+        .unwrap_or_default()
+        .as_bytes()
+        .to_vec();
+    let expect = test.get("expect");
+    let output = match expect {
+        Some(expect) => string(expect, "output")?.map(|s| s.as_bytes().to_vec()),
+        None => None,
+    };
+    let cc = match expect.map(|e| string(e, "cc")).transpose()?.flatten() {
+        None => None,
+        Some(name) => Some(
+            cc_from_name(name)
+                .ok_or_else(|| format!("expect.cc must be n, z or p, not {}", name))?,
+        ),
+    };
+    let preserved = match expect.and_then(|e| e.get("preserved")) {
+        Some(value) => value
+            .as_array()
+            .ok_or("expect.preserved must be an array of register names")?
+            .iter()
+            .map(|name| register(name.as_str().unwrap_or("")))
+            .collect::<Result<_, _>>()?,
+        None => Vec::new(),
+    };
+    Ok(UnitTest {
+        name,
+        call,
+        registers: registers(test.get("registers"))?,
+        memory: memory(test.get("memory"))?,
+        input,
+        max_instructions: max_instructions(test, default_max)?,
+        expect: Expectations {
+            registers: registers(expect.and_then(|e| e.get("registers")))?,
+            memory: memory(expect.and_then(|e| e.get("memory")))?,
+            output,
+            cc,
+            preserved,
+        },
+    })
+}
+
+/// `ConditionalFlag` bits for `n`, `z` or `p`.
+pub(crate) fn cc_from_name(name: &str) -> Option<u16> {
+    match name {
+        "n" => Some(ConditionalFlag::Neg as u16),
+        "z" => Some(ConditionalFlag::Zro as u16),
+        "p" => Some(ConditionalFlag::Pos as u16),
+        _ => None,
+    }
+}
+
+fn register(name: &str) -> Result<Register, String> {
+    name.strip_prefix(['R', 'r'])
+        .and_then(|n| n.parse::<u16>().ok())
+        .filter(|&n| n < 8)
+        .and_then(Register::from_u16)
+        .ok_or_else(|| format!("Unknown register {}; expected R0 to R7", name))
+}
+
+// An integer that fits a word, signed or not.
+pub(crate) fn integer(value: i64) -> Result<u16, String> {
+    if (-0x8000..=0xFFFF).contains(&value) {
+        Ok(value as u16)
+    } else {
+        Err(format!("{} does not fit in a word", value))
+    }
+}
+
+pub(crate) fn registers(table: Option<&Value>) -> Result<Vec<(Register, Word)>, String> {
+    let Some(table) = table else {
+        return Ok(Vec::new());
+    };
+    let table = table.as_table().ok_or("registers must be a table")?;
+    table
+        .iter()
+        .map(|(name, value)| {
+            let value = match value {
+                Value::Integer(n) => Word::Literal(integer(*n)?),
+                Value::String(s) => Word::parse(s)?,
+                other => {
+                    return Err(format!(
+                        "{} must be an integer or a label, not {}",
+                        name,
+                        other.type_name()
+                    ));
+                }
+            };
+            Ok((register(name)?, value))
+        })
+        .collect()
+}
+
+pub(crate) fn memory(table: Option<&Value>) -> Result<Vec<(Word, Vec<u16>)>, String> {
+    let Some(table) = table else {
+        return Ok(Vec::new());
+    };
+    let table: &BTreeMap<String, Value> = table.as_table().ok_or("memory must be a table")?;
+    table
+        .iter()
+        .map(|(key, value)| {
+            let words = match value {
+                Value::Integer(n) => vec![integer(*n)?],
+                Value::String(s) => s.chars().map(|c| c as u16).chain([0]).collect(),
+                Value::Array(items) => items
+                    .iter()
+                    .map(|item| {
+                        item.as_integer()
+                            .ok_or_else(|| format!("{} must hold integers", key))
+                            .and_then(integer)
+                    })
+                    .collect::<Result<_, _>>()?,
+                other => {
+                    return Err(format!(
+                        "{} must be an integer, an array or a string, not {}",
+                        key,
+                        other.type_name()
+                    ));
+                }
+            };
+            Ok((Word::parse(key)?, words))
+        })
+        .collect()
+}
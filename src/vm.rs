@@ -1,5 +1,12 @@
+use crate::console::{Console, OnExhausted, StdConsole};
+use crate::instruction::Instruction;
+use crate::keylog::{self, KeyEvent};
+use crate::snapshot::Snapshot;
+use crate::timing::CycleModel;
+use std::collections::VecDeque;
+use std::fmt;
 use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::{BufReader, ErrorKind, Read, Write};
 
 pub const MEMORY_MAX: usize = 1 << 16;
 pub const PC_START: u16 = 0x3000;
@@ -43,6 +50,8 @@ impl Register {
 // Memory-Mapped I/O Registers
 const MR_KBSR: usize = 0xFE00; // Keyboard Status Register
 const MR_KBDR: usize = 0xFE02; // Keyboard Data Register
+/// First address of the memory-mapped device registers.
+pub const DEVICE_PAGE: u16 = 0xFE00;
 
 #[repr(u16)]
 pub enum ConditionalFlag {
@@ -96,9 +105,94 @@ impl Opcode {
     }
 }
 
+#[derive(Debug)]
+pub enum VmError {
+    ReservedOpcode(Opcode),
+    UnknownTrap(u16),
+    /// GETC/IN found no key on a console that cannot block, or was interrupted by a signal
+    /// while waiting. The trap is rewound so the next `step` retries it.
+    WaitingForInput,
+    /// The program wanted a key after its scripted input ran out (`OnExhausted::Error`).
+    InputExhausted,
+    /// A replayed key was not read at the instruction count it was recorded at.
+    ReplayDiverged(u64),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmError::ReservedOpcode(opcode) => {
+                write!(f, "Opcode {:?} is unused or reserved", opcode)
+            }
+            VmError::UnknownTrap(vector) => write!(f, "trap not implemented: 0x{:02X}", vector),
+            VmError::WaitingForInput => write!(f, "waiting for keyboard input"),
+            VmError::InputExhausted => write!(f, "the program read past the end of its input"),
+            VmError::ReplayDiverged(instructions) => write!(
+                f,
+                "replay diverged: the key recorded at instruction {} was not read there",
+                instructions
+            ),
+            VmError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
+
+impl From<std::io::Error> for VmError {
+    fn from(e: std::io::Error) -> Self {
+        VmError::Io(e)
+    }
+}
+
+/// Reads a big-endian `.obj` image: its origin, then the words to load there.
+pub fn read_obj_file(path: &str) -> std::io::Result<(u16, Vec<u16>)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut buffer_bytes = [0u8; 2];
+
+    reader.read_exact(&mut buffer_bytes)?;
+    let origin = u16::from_be_bytes(buffer_bytes);
+
+    let mut words = Vec::new();
+    loop {
+        match reader.read_exact(&mut buffer_bytes) {
+            Ok(_) => words.push(u16::from_be_bytes(buffer_bytes)),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok((origin, words))
+}
+
+/// Outcome of a single fetch/decode/execute cycle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Step {
+    pub pc: u16,
+    pub instr: u16,
+    pub halted: bool,
+}
+
 pub struct Vm {
     memory: [u16; MEMORY_MAX],
     registers: [u16; REG_COUNT],
+    console: Box<dyn Console>,
+    // Set when IN already printed its prompt but had to rewind waiting for a key.
+    in_prompt_shown: bool,
+    // Set when KBSR was polled after the console's scripted input ran out.
+    polled_past_input: bool,
+    // Instructions decoded on first execution, dropped when their word is written.
+    decoded: Option<Box<[Option<Instruction>]>>,
+    // Addresses translated by a `BlockEngine`, and whether one of them was written since.
+    code_watch: Vec<bool>,
+    code_modified: bool,
+    cycle_model: Option<CycleModel>,
+    cycles: u64,
+    instructions: u64,
+    empty_polls: u64,
+    // Where every key read is logged, and the keys still to replay.
+    key_recorder: Option<Box<dyn Write + Send>>,
+    replay: VecDeque<KeyEvent>,
 }
 
 impl Default for Vm {
@@ -110,6 +204,18 @@ impl Default for Vm {
         Self {
             memory: [0; MEMORY_MAX],
             registers,
+            console: Box::new(StdConsole::default()),
+            in_prompt_shown: false,
+            polled_past_input: false,
+            decoded: None,
+            code_watch: Vec::new(),
+            code_modified: false,
+            cycle_model: None,
+            cycles: 0,
+            instructions: 0,
+            empty_polls: 0,
+            key_recorder: None,
+            replay: VecDeque::new(),
         }
     }
 }
@@ -118,35 +224,209 @@ impl Vm {
     pub fn new() -> Self {
         Self::default()
     }
+
+    pub fn with_console(console: impl Console + 'static) -> Self {
+        let mut vm = Self::default();
+        vm.set_console(console);
+        vm
+    }
+
+    pub fn set_console(&mut self, console: impl Console + 'static) {
+        self.console = Box::new(console);
+    }
+
+    /// Keeps decoded instructions between executions instead of decoding every fetch.
+    /// Writes through `write_memory` (including the program's own stores) invalidate the
+    /// written address, so self-modifying code still sees its new instructions.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decoded = enabled.then(|| vec![None; MEMORY_MAX].into_boxed_slice());
+    }
+
+    /// Counts clock cycles for every instruction executed from now on, following `model`.
+    /// `None` stops counting; the count so far is kept.
+    pub fn set_cycle_model(&mut self, model: Option<CycleModel>) {
+        self.cycle_model = model;
+    }
+
+    pub fn cycle_model(&self) -> Option<CycleModel> {
+        self.cycle_model
+    }
+
+    /// Cycles counted so far: the machine's clock for anything timed in cycles rather than
+    /// instructions.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Instructions completed since the machine was created, by any engine.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// KBSR reads that found no key ready, by any engine. It grows while a program waits
+    /// for a key in a polling loop.
+    pub fn empty_polls(&self) -> u64 {
+        self.empty_polls
+    }
+
+    /// Counts instructions executed outside `step`.
+    pub(crate) fn add_instructions(&mut self, instructions: u64) {
+        self.instructions += instructions;
+    }
+
+    /// Logs every key the program reads from now on to `out`, in the `keylog` format. Each
+    /// key is flushed as it is read, so the log survives the process being killed.
+    pub fn record_keys(&mut self, mut out: impl Write + Send + 'static) -> std::io::Result<()> {
+        writeln!(out, "{}", keylog::HEADER)?;
+        out.flush()?;
+        self.key_recorder = Some(Box::new(out));
+        Ok(())
+    }
+
+    /// Feeds `events` to the program at the instruction counts they were recorded at,
+    /// instead of keys from the console. Once they run out, keys come from the console
+    /// again.
+    pub fn replay_keys(&mut self, events: &[KeyEvent]) {
+        self.replay = events.iter().copied().collect();
+    }
+
+    /// Whether replayed keys are still to be read.
+    pub fn replaying(&self) -> bool {
+        !self.replay.is_empty()
+    }
+
+    /// Charges cycles spent outside `step`, when a model is set.
+    pub(crate) fn add_cycles(&mut self, cycles: u64) {
+        if self.cycle_model.is_some() {
+            self.cycles += cycles;
+        }
+    }
+
+    /// Queues keys for the program after any already waiting. Together with a console
+    /// whose input runs out (`SharedConsole::set_on_exhausted`, `ScriptedConsole`), this
+    /// scripts a run.
+    pub fn push_input(&mut self, bytes: &[u8]) {
+        self.console.push_input(bytes);
+    }
+
+    /// Applies the console's policy if the instruction just executed polled KBSR after
+    /// the scripted input ran out. Returns whether the machine halted.
+    pub(crate) fn check_input_exhausted(&mut self, halted: bool) -> Result<bool, VmError> {
+        if !std::mem::take(&mut self.polled_past_input) {
+            return Ok(halted);
+        }
+        match self.console.exhausted() {
+            Some(OnExhausted::Halt) => Ok(true),
+            Some(OnExhausted::Error) => Err(VmError::InputExhausted),
+            _ => Ok(halted),
+        }
+    }
+
+    /// Fails if the program got past the instruction the next replayed key was recorded
+    /// at without reading it.
+    pub(crate) fn check_replay(&self) -> Result<(), VmError> {
+        match self.replay.front() {
+            Some(event) if event.instructions < self.instructions => {
+                Err(VmError::ReplayDiverged(event.instructions))
+            }
+            _ => Ok(()),
+        }
+    }
+
     pub fn read_memory(&mut self, addr: u16) -> u16 {
         if addr == MR_KBSR as u16 {
-            if crate::hardware::check_key() {
+            if let Some(key) = self.poll_key() {
                 self.memory[MR_KBSR] = 1 << 15;
-                let mut buffer = [0; 1];
-                if std::io::stdin().read_exact(&mut buffer).is_ok() {
-                    self.memory[MR_KBDR] = buffer[0] as u16;
-                } else {
-                    self.memory[MR_KBSR] = 0;
-                }
+                self.memory[MR_KBDR] = key as u16;
             } else {
                 self.memory[MR_KBSR] = 0;
+                self.empty_polls += 1;
+                self.polled_past_input =
+                    self.replay.is_empty() && self.console.exhausted().is_some();
             }
         }
         self.memory[addr as usize]
     }
 
+    // A key for a KBSR poll: the next replayed key if it was recorded at this instruction,
+    // or whatever the console has once the replay is over.
+    fn poll_key(&mut self) -> Option<u8> {
+        let key = match self.replay.front() {
+            Some(event) if event.instructions == self.instructions => {
+                self.replay.pop_front().map(|event| event.key)
+            }
+            Some(_) => None,
+            None => self.console.poll_key(),
+        };
+        if let Some(key) = key {
+            self.record_key(key);
+        }
+        key
+    }
+
+    fn record_key(&mut self, key: u8) {
+        if let Some(out) = &mut self.key_recorder {
+            let event = KeyEvent {
+                instructions: self.instructions,
+                key,
+            };
+            // A failing log must not stop the program.
+            let _ = event.write(out).and_then(|()| out.flush());
+        }
+    }
+
+    /// Reads memory without triggering memory-mapped device side effects.
+    #[inline]
+    pub fn peek_memory(&self, addr: u16) -> u16 {
+        self.memory[addr as usize]
+    }
+
+    #[inline]
     pub fn write_memory(&mut self, addr: u16, value: u16) {
         self.memory[addr as usize] = value;
+        if let Some(cache) = &mut self.decoded {
+            cache[addr as usize] = None;
+        }
+        if self.code_watch.get(addr as usize) == Some(&true) {
+            self.code_modified = true;
+        }
+    }
+
+    /// Registers and memory for native code, which reads and writes them directly.
+    #[cfg(feature = "jit")]
+    pub(crate) fn raw_parts(&mut self) -> (*mut u16, *mut u16) {
+        (self.registers.as_mut_ptr(), self.memory.as_mut_ptr())
+    }
+
+    /// Flags later writes to `addr` through `take_code_modified`.
+    pub(crate) fn watch_code(&mut self, addr: u16) {
+        if self.code_watch.is_empty() {
+            self.code_watch = vec![false; MEMORY_MAX];
+        }
+        self.code_watch[addr as usize] = true;
     }
 
+    /// Whether watched code was overwritten since the last call. Watches are cleared.
+    #[inline]
+    pub(crate) fn take_code_modified(&mut self) -> bool {
+        let modified = std::mem::take(&mut self.code_modified);
+        if modified {
+            self.code_watch.fill(false);
+        }
+        modified
+    }
+
+    #[inline]
     pub fn read_register(&self, reg: Register) -> u16 {
         self.registers[reg as usize]
     }
 
+    #[inline]
     pub fn write_register(&mut self, reg: Register, value: u16) {
         self.registers[reg as usize] = value;
     }
 
+    #[inline]
     pub fn update_flags(&mut self, value: u16) {
         if value == 0 {
             self.registers[Register::Cond as usize] = ConditionalFlag::Zro as u16;
@@ -166,29 +446,339 @@ impl Vm {
     }
 
     pub fn read_image_file(&mut self, path: &str) -> std::io::Result<()> {
-        let mut file = File::open(path)?;
-        let mut reader = BufReader::new(&mut file);
-        let mut buffer_bytes = [0u8; 2];
-
-        reader.read_exact(&mut buffer_bytes)?;
-        let origin_addr = u16::from_be_bytes(buffer_bytes);
-        self.write_register(Register::PC, origin_addr);
-
-        let mut addr = origin_addr;
-        loop {
-            match reader.read_exact(&mut buffer_bytes) {
-                Ok(_) => {
-                    let instruction = u16::from_be_bytes(buffer_bytes);
-                    self.write_memory(addr, instruction);
+        let (origin, words) = read_obj_file(path)?;
+        self.load_program(origin, &words);
+        Ok(())
+    }
+
+    /// Copies an image into memory at `origin` and points PC at it, like `read_image_file`.
+    pub fn load_program(&mut self, origin: u16, words: &[u16]) {
+        self.write_register(Register::PC, origin);
+        let mut addr = origin;
+        for word in words {
+            self.write_memory(addr, *word);
+            addr = addr.wrapping_add(1);
+        }
+    }
+
+    /// Captures memory, registers, device state, the instruction and cycle counters and
+    /// queued keys.
+    pub fn snapshot(&mut self) -> Snapshot {
+        Snapshot {
+            registers: self.registers,
+            memory: Box::new(self.memory),
+            in_prompt_shown: self.in_prompt_shown,
+            instructions: self.instructions,
+            cycles: self.cycles,
+            pending_input: self.console.pending_input(),
+        }
+    }
+
+    /// Puts the machine back in the state captured by `snapshot`. Its pending input is
+    /// queued on the current console ahead of anything typed later.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.registers = snapshot.registers;
+        self.memory = *snapshot.memory;
+        if let Some(cache) = &mut self.decoded {
+            cache.fill(None);
+        }
+        self.code_modified = true;
+        self.in_prompt_shown = snapshot.in_prompt_shown;
+        self.instructions = snapshot.instructions;
+        self.cycles = snapshot.cycles;
+        self.console.queue_input(&snapshot.pending_input);
+    }
+
+    pub fn step(&mut self) -> Result<Step, VmError> {
+        //fetch
+        let pc = self.read_register(Register::PC);
+        let instr = self.read_memory(pc);
+
+        self.write_register(Register::PC, pc.wrapping_add(1));
+
+        let decoded = self.decode(pc, instr);
+        let r7 = self.read_register(Register::R7);
+        let cond = self.read_register(Register::Cond);
+        match self.execute(decoded) {
+            Ok(halted) => {
+                if let Some(model) = &self.cycle_model {
+                    self.cycles += model.instruction_cycles(decoded, cond) as u64;
+                }
+                let halted = self.check_input_exhausted(halted)?;
+                self.instructions += 1;
+                self.check_replay()?;
+                Ok(Step { pc, instr, halted })
+            }
+            Err(VmError::Io(e))
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) =>
+            {
+                self.write_register(Register::PC, pc);
+                self.write_register(Register::R7, r7);
+                Err(VmError::WaitingForInput)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn decode(&mut self, pc: u16, instr: u16) -> Instruction {
+        match &mut self.decoded {
+            // Device registers change under the program, so they are never cached.
+            Some(cache) if pc < DEVICE_PAGE => {
+                *cache[pc as usize].get_or_insert_with(|| Instruction::decode(instr))
+            }
+            _ => Instruction::decode(instr),
+        }
+    }
+
+    /// Runs until the program halts or faults.
+    pub fn run(&mut self) -> Result<(), VmError> {
+        while !self.step()?.halted {}
+        Ok(())
+    }
+
+    /// Executes an already fetched and decoded instruction, returning whether it halted the
+    /// machine.
+    fn execute(&mut self, instr: Instruction) -> Result<bool, VmError> {
+        match instr {
+            Instruction::Br { nzp, offset } => {
+                let current_cond = self.read_register(Register::Cond);
+                if (current_cond & nzp) != 0 {
+                    let current_pc_value = self.read_register(Register::PC);
+                    self.write_register(Register::PC, current_pc_value.wrapping_add(offset));
+                }
+            }
+
+            Instruction::AddImm { dst, src1, imm } => {
+                let res = self.read_register(src1).wrapping_add(imm);
+                self.write_register(dst, res);
+                self.update_flags(res);
+            }
+
+            Instruction::AddReg { dst, src1, src2 } => {
+                let res = self
+                    .read_register(src1)
+                    .wrapping_add(self.read_register(src2));
+                self.write_register(dst, res);
+                self.update_flags(res);
+            }
+
+            Instruction::Ld { dst, offset } => {
+                let addr = self.read_register(Register::PC).wrapping_add(offset);
+                let val = self.read_memory(addr);
+                self.write_register(dst, val);
+                self.update_flags(val);
+            }
+
+            Instruction::St { src, offset } => {
+                let addr = self.read_register(Register::PC).wrapping_add(offset);
+                let val = self.read_register(src);
+                self.write_memory(addr, val);
+            }
+
+            Instruction::Jsr { offset } => {
+                let current_pc = self.read_register(Register::PC);
+                self.write_register(Register::R7, current_pc);
+                self.write_register(Register::PC, current_pc.wrapping_add(offset));
+            }
+
+            Instruction::Jsrr { base } => {
+                // The base is read before R7 is written, so `JSRR R7` jumps to the old R7.
+                let current_pc = self.read_register(Register::PC);
+                let val = self.read_register(base);
+                self.write_register(Register::R7, current_pc);
+                self.write_register(Register::PC, val);
+            }
+
+            Instruction::AndImm { dst, src1, imm } => {
+                let res = self.read_register(src1) & imm;
+                self.write_register(dst, res);
+                self.update_flags(res);
+            }
+
+            Instruction::AndReg { dst, src1, src2 } => {
+                let res = self.read_register(src1) & self.read_register(src2);
+                self.write_register(dst, res);
+                self.update_flags(res);
+            }
+
+            Instruction::Ldr { dst, base, offset } => {
+                let addr = self.read_register(base).wrapping_add(offset);
+                let val = self.read_memory(addr);
+                self.write_register(dst, val);
+                self.update_flags(val);
+            }
+
+            Instruction::Str { src, base, offset } => {
+                let addr = self.read_register(base).wrapping_add(offset);
+                let val_to_store = self.read_register(src);
+                self.write_memory(addr, val_to_store);
+            }
+
+            Instruction::Rti => return Err(VmError::ReservedOpcode(Opcode::Rti)),
+            Instruction::Res => return Err(VmError::ReservedOpcode(Opcode::Res)),
+
+            Instruction::Not { dst, src } => {
+                let val = !self.read_register(src);
+                self.write_register(dst, val);
+                self.update_flags(val);
+            }
+
+            Instruction::Ldi { dst, offset } => {
+                let intermediate_addr = self.read_register(Register::PC).wrapping_add(offset);
+                let final_addr = self.read_memory(intermediate_addr);
+
+                let val = self.read_memory(final_addr);
+                self.write_register(dst, val);
+                self.update_flags(val);
+            }
+
+            Instruction::Sti { src, offset } => {
+                let intermediate_addr = self.read_register(Register::PC).wrapping_add(offset);
+                let final_addr = self.read_memory(intermediate_addr);
+
+                let val_to_store = self.read_register(src);
+                self.write_memory(final_addr, val_to_store);
+            }
+
+            Instruction::Jmp { base } => {
+                let val = self.read_register(base);
+                self.write_register(Register::PC, val);
+            }
+
+            Instruction::Lea { dst, offset } => {
+                let val = self.read_register(Register::PC).wrapping_add(offset);
+                self.write_register(dst, val);
+                self.update_flags(val);
+            }
+
+            Instruction::Trap { vector } => {
+                /* TRAP instructions in the LC-3 architecture are service calls.
+                According to the spec, the current PC must be saved in R7
+                to allow the service routine to return (via RET/JMP R7).
+                Even though our VM handles traps directly in Rust (keeping the control
+                flow within this loop), we update R7 to maintain architectural
+                fidelity and compatibility with programs that might inspect it. */
+                let current_pc = self.read_register(Register::PC);
+                self.write_register(Register::R7, current_pc);
+
+                return self.trap(vector);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Reads a key for GETC and IN. `None` means the scripted input ran out and the
+    /// machine halts.
+    fn read_key(&mut self) -> Result<Option<u8>, VmError> {
+        if let Some(event) = self.replay.pop_front() {
+            if event.instructions != self.instructions {
+                return Err(VmError::ReplayDiverged(event.instructions));
+            }
+            self.record_key(event.key);
+            return Ok(Some(event.key));
+        }
+        match self.console.read_key() {
+            Ok(key) => {
+                self.record_key(key);
+                Ok(Some(key))
+            }
+            Err(e) => match self.console.exhausted() {
+                Some(OnExhausted::Halt) => Ok(None),
+                Some(OnExhausted::Error) => Err(VmError::InputExhausted),
+                _ => Err(e.into()),
+            },
+        }
+    }
+
+    /// Services a trap as the emulator does, without touching PC or R7. Returns whether
+    /// the machine halted.
+    pub(crate) fn trap(&mut self, trap_vector: u16) -> Result<bool, VmError> {
+        match trap_vector {
+            0x20 => {
+                // GETC
+                let Some(key) = self.read_key()? else {
+                    return Ok(true);
+                };
+                self.write_register(Register::R0, key as u16);
+            }
+
+            0x21 => {
+                // OUT
+                let char = (self.read_register(Register::R0) & 0xFF) as u8;
+                self.console.write(&[char])?;
+            }
+
+            0x22 => {
+                // PUTS
+                let mut addr = self.read_register(Register::R0);
+                let mut out = Vec::new();
+                loop {
+                    let char = self.read_memory(addr);
+                    if char == 0x0000 {
+                        break;
+                    }
+                    out.push(char as u8);
                     addr = addr.wrapping_add(1);
                 }
-                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
-                    break;
+                self.console.write(&out)?;
+            }
+
+            0x23 => {
+                // IN
+                if !self.in_prompt_shown {
+                    self.console.write(b"Enter a character: ")?;
                 }
-                Err(e) => return Err(e),
+                let key = match self.read_key() {
+                    Err(VmError::Io(e))
+                        if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) =>
+                    {
+                        self.in_prompt_shown = true;
+                        return Err(e.into());
+                    }
+                    key => key?,
+                };
+                self.in_prompt_shown = false;
+                let Some(key) = key else {
+                    return Ok(true);
+                };
+                self.console.write(&[key])?;
+
+                self.write_register(Register::R0, key as u16);
             }
+
+            0x24 => {
+                // PUTSP
+                let mut addr = self.read_register(Register::R0);
+                let mut out = Vec::new();
+                loop {
+                    let word = self.read_memory(addr);
+                    if word == 0x0000 {
+                        break;
+                    }
+
+                    let char_l = (word & 0xFF) as u8;
+                    out.push(char_l);
+
+                    let char_h = (word >> 8) as u8;
+                    if char_h != 0 {
+                        out.push(char_h);
+                    }
+
+                    addr = addr.wrapping_add(1);
+                }
+                self.console.write(&out)?;
+            }
+
+            0x25 => {
+                // HALT
+                self.console.write(b"HALT\n")?;
+                return Ok(true);
+            }
+
+            _ => return Err(VmError::UnknownTrap(trap_vector)),
         }
 
-        Ok(())
+        Ok(false)
     }
 }
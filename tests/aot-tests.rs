@@ -0,0 +1,147 @@
+use assert_cmd::cargo::cargo_bin_cmd;
+use lc3_vm_rust::aot;
+use lc3_vm_rust::asm;
+use lc3_vm_rust::console::SharedConsole;
+use lc3_vm_rust::vm::{Vm, VmError};
+use predicates::prelude::*;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+mod common;
+
+// Patches an instruction later in its own block, calls a subroutine only reachable
+// through JSRR, and has that subroutine patch a translated block.
+const PROGRAM: &str = "
+        .ORIG x3000
+        GETC
+        LD R1, PATCH
+        ST R1, HERE
+HERE    ADD R0, R0, #0
+        AND R3, R3, #0
+        ADD R3, R3, #3
+AGAIN   OUT
+        JSR SLOT
+        LEA R2, BUMP
+        JSRR R2
+        ADD R3, R3, #-1
+        BRp AGAIN
+        HALT
+SLOT    ADD R0, R0, #0
+        RET
+BUMP    LD R1, PATCH
+        ST R1, SLOT
+        RET
+PATCH   ADD R0, R0, #1
+        .END
+";
+
+/// Builds `source` with plain `rustc -O` and runs it on `input`, returning its stdout.
+fn build_and_run(source: &str, dir: &Path, input: &[u8]) -> Vec<u8> {
+    let file = dir.join("program.rs");
+    let exe = dir.join("program");
+    std::fs::write(&file, source).unwrap();
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let status = Command::new(rustc)
+        .arg("-O")
+        .arg("-o")
+        .arg(&exe)
+        .arg(&file)
+        .status()
+        .unwrap();
+    assert!(status.success(), "generated program does not compile");
+
+    let mut child = Command::new(&exe)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap().stdout
+}
+
+fn emulate(origin: u16, words: &[u16], input: &[u8]) -> Vec<u8> {
+    let console = SharedConsole::new();
+    let mut lc3 = Vm::with_console(console.clone());
+    lc3.load_program(origin, words);
+    console.push_input(input);
+    loop {
+        match lc3.step() {
+            Ok(step) if step.halted => return console.take_output(),
+            Ok(_) => {}
+            // Where the shared console waits, the standalone program finds a closed pipe.
+            Err(VmError::WaitingForInput) => {
+                let mut output = console.take_output();
+                output.extend_from_slice(b"I/O error: unexpected end of file\n");
+                return output;
+            }
+            Err(e) => panic!("{}", e),
+        }
+    }
+}
+
+#[test]
+fn test_translate_finds_blocks_from_the_origin() {
+    let assembly = asm::assemble(PROGRAM).unwrap();
+    let translation = aot::translate(assembly.origin, &assembly.words, "program");
+
+    // BUMP is only reached through JSRR, and PATCH is data.
+    assert_eq!(
+        translation.blocks,
+        vec![
+            0x3000, 0x3001, 0x3006, 0x3007, 0x3008, 0x300A, 0x300C, 0x300D
+        ]
+    );
+    assert!(
+        translation
+            .source
+            .contains("fn block_x300d(m: &mut Machine) -> bool {")
+    );
+    assert!(translation.source.contains("    // x3006  OUT\n"));
+    assert!(!translation.source.contains("fn block_x300f"));
+}
+
+#[test]
+fn test_translated_program_matches_emulator() {
+    let assembly = asm::assemble(PROGRAM).unwrap();
+    let translation = aot::translate(assembly.origin, &assembly.words, "program");
+    let dir = common::temp_dir("aot-program");
+
+    let output = build_and_run(&translation.source, &dir, b"a");
+    assert_eq!(output, b"bbcHALT\n");
+    assert_eq!(output, emulate(assembly.origin, &assembly.words, b"a"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_translate_command_builds_rogue() {
+    let dir = common::temp_dir("aot-rogue");
+    let source = dir.join("rogue.rs");
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args(["translate", "assets/rogue.obj", "-o"])
+        .arg(&source)
+        .assert()
+        .stderr(predicate::str::contains("block(s)"));
+
+    let keys = b"ddddssssddddwwwwaaaassss";
+    let output = build_and_run(&std::fs::read_to_string(&source).unwrap(), &dir, keys);
+    let (origin, words) = lc3_vm_rust::vm::read_obj_file("assets/rogue.obj").unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&output),
+        String::from_utf8_lossy(&emulate(origin, &words, keys))
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_translate_command_prints_source() {
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args(["translate", "assets/hello.obj"])
+        .assert()
+        .stdout(predicate::str::contains(
+            "fn block_x3000(m: &mut Machine) -> bool {",
+        ))
+        .stdout(predicate::str::contains("static IMAGE: [u16; 17] = ["));
+}
@@ -0,0 +1,47 @@
+use lc3_vm_rust::asm::assemble;
+use lc3_vm_rust::debuginfo::DebugInfo;
+
+#[test]
+fn test_assembles_hello_identically_to_bundled_obj() {
+    let source = std::fs::read_to_string("assets/hello.asm").unwrap();
+    let assembly = assemble(&source).unwrap();
+    assert_eq!(
+        assembly.to_obj_bytes(),
+        std::fs::read("assets/hello.obj").unwrap()
+    );
+    assert_eq!(assembly.symbols.get("hello"), Some(&0x3003));
+    assert_eq!(assembly.lines.get(&0x3002), Some(&7));
+}
+
+#[test]
+fn test_assembles_rogue_identically_to_bundled_obj() {
+    let source = std::fs::read_to_string("assets/rogue.asm").unwrap();
+    let assembly = assemble(&source).unwrap();
+    assert_eq!(
+        assembly.to_obj_bytes(),
+        std::fs::read("assets/rogue.obj").unwrap()
+    );
+}
+
+#[test]
+fn test_assembler_reports_errors_with_line_numbers() {
+    let error = assemble(".ORIG x3000\nADD R0, R1\n.END").unwrap_err();
+    assert_eq!(error.line, 2);
+
+    let error = assemble(".ORIG x3000\nBRz NOWHERE\n.END").unwrap_err();
+    assert_eq!(error.to_string(), "line 2: Undefined label NOWHERE");
+}
+
+#[test]
+fn test_reads_lc3as_symbol_table() {
+    let path = std::env::temp_dir().join("lc3-vm-rust-hello.sym");
+    std::fs::write(
+        &path,
+        "// Symbol table\n// Scope level 0:\n//\tSymbol Name       Page Address\n//\t----------------  ------------\n//\thello             3003\n",
+    )
+    .unwrap();
+
+    let debug = DebugInfo::read_sym_file(path.to_str().unwrap()).unwrap();
+    assert_eq!(debug.address_of("hello"), Some(0x3003));
+    assert_eq!(debug.describe(0x3005), "hello+2");
+}
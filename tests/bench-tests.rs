@@ -0,0 +1,84 @@
+use assert_cmd::cargo::cargo_bin_cmd;
+use lc3_vm_rust::bench::{Engine, Runner, WORKLOADS, Workload};
+use lc3_vm_rust::json;
+use predicates::prelude::*;
+
+#[test]
+fn test_workloads_run_the_same_on_every_engine() {
+    for workload in WORKLOADS {
+        for &engine in Engine::ALL {
+            let mut lc3 = workload.load();
+            let mut runner = Runner::new(engine, &mut lc3);
+            assert!(
+                !runner.run(&mut lc3, 100_000).unwrap(),
+                "{} halted",
+                workload.name
+            );
+            assert_eq!(runner.instructions(), 100_000);
+
+            // The interpreter, stopped at the same count, is in the same state.
+            let mut expected = workload.load();
+            Runner::new(Engine::Decode, &mut expected)
+                .run(&mut expected, 100_000)
+                .unwrap();
+            assert!(
+                lc3.snapshot() == expected.snapshot(),
+                "{} differs on {}",
+                workload.name,
+                engine.name()
+            );
+        }
+    }
+}
+
+#[test]
+fn test_measure_counts_instructions() {
+    let measurement = Workload::find("calls")
+        .unwrap()
+        .measure(Engine::Cache, 50_000)
+        .unwrap();
+    assert_eq!(measurement.instructions, 50_000);
+    assert!(measurement.mips() > 0.0);
+    assert_eq!(
+        measurement.to_json().get("workload").unwrap().as_str(),
+        Some("calls")
+    );
+}
+
+#[test]
+fn test_bench_command_prints_json_lines() {
+    let output = cargo_bin_cmd!("lc3-vm-rust")
+        .args(["bench", "--instructions", "10000", "--json"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let results: Vec<json::Value> = stdout.lines().map(|l| json::parse(l).unwrap()).collect();
+
+    let names: Vec<&str> = results
+        .iter()
+        .map(|r| r.get("workload").unwrap().as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["arith", "memcpy", "calls"]);
+    for result in &results {
+        assert_eq!(result.get("engine").unwrap().as_str(), Some("cache"));
+        assert_eq!(result.get("instructions").unwrap().as_i64(), Some(10_000));
+        assert!(result.get("mips").unwrap().as_f64().unwrap() > 0.0);
+    }
+}
+
+#[test]
+fn test_bench_command_selects_engine_and_workload() {
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args(["bench", "--instructions", "10000"])
+        .args(["--engine", "blocks", "--workload", "memcpy"])
+        .assert()
+        .stdout(predicate::str::contains("Engine: blocks"))
+        .stdout(predicate::str::contains("memcpy"))
+        .stdout(predicate::str::contains("arith").not());
+
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args(["bench", "--engine", "fast"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("Unknown engine fast"));
+}
@@ -0,0 +1,156 @@
+use lc3_vm_rust::asm;
+use lc3_vm_rust::blocks::BlockEngine;
+use lc3_vm_rust::console::SharedConsole;
+use lc3_vm_rust::snapshot::Snapshot;
+use lc3_vm_rust::vm::{Register, Vm, VmError};
+
+mod common;
+
+struct Outcome {
+    snapshot: Snapshot,
+    output: Vec<u8>,
+    instructions: u64,
+    result: String,
+}
+
+fn finish(mut lc3: Vm, console: SharedConsole, instructions: u64, result: String) -> Outcome {
+    Outcome {
+        snapshot: lc3.snapshot(),
+        output: console.take_output(),
+        instructions,
+        result,
+    }
+}
+
+fn interpret(program: &dyn Fn(&mut Vm), keys: &[u8]) -> Outcome {
+    let (mut lc3, console) = common::machine(program, keys);
+    let mut instructions = 0;
+    let result = loop {
+        match lc3.step() {
+            Ok(step) if step.halted => break "halted".to_string(),
+            Ok(_) => instructions += 1,
+            Err(e) => break e.to_string(),
+        }
+    };
+    finish(
+        lc3,
+        console,
+        instructions + (result == "halted") as u64,
+        result,
+    )
+}
+
+fn translate(program: &dyn Fn(&mut Vm), keys: &[u8]) -> Outcome {
+    let (mut lc3, console) = common::machine(program, keys);
+    let mut engine = BlockEngine::new();
+    let result = loop {
+        match engine.run(&mut lc3, 1000) {
+            Ok(run) if run.halted => break "halted".to_string(),
+            Ok(_) => {}
+            Err(e) => break e.to_string(),
+        }
+    };
+    finish(lc3, console, engine.instructions(), result)
+}
+
+fn assert_same(program: &dyn Fn(&mut Vm), keys: &[u8]) {
+    let expected = interpret(program, keys);
+    let actual = translate(program, keys);
+    assert_eq!(actual.result, expected.result);
+    assert!(
+        actual.snapshot == expected.snapshot,
+        "machine state differs"
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&actual.output),
+        String::from_utf8_lossy(&expected.output)
+    );
+    assert_eq!(actual.instructions, expected.instructions);
+}
+
+fn assembled(source: &'static str) -> impl Fn(&mut Vm) {
+    move |lc3: &mut Vm| {
+        let assembly = asm::assemble(source).unwrap();
+        lc3.load_program(assembly.origin, &assembly.words);
+    }
+}
+
+#[test]
+fn test_blocks_match_interpreter_on_rogue() {
+    assert_same(
+        &|lc3: &mut Vm| lc3.read_image_file("assets/rogue.obj").unwrap(),
+        b"ddddssssddddwwwwaaaassss",
+    );
+}
+
+#[test]
+fn test_blocks_handle_self_modifying_code() {
+    // Patches the ADD at PATCH from +1 to +2 from inside the same block.
+    let program = assembled(
+        "
+        .ORIG x3000
+        AND R1, R1, #0
+        AND R2, R2, #0
+        ADD R2, R2, #3
+LOOP    LD R0, NEW
+        ST R0, PATCH
+PATCH   ADD R1, R1, #1
+        ADD R2, R2, #-1
+        BRp LOOP
+        HALT
+NEW     ADD R1, R1, #2
+        .END
+",
+    );
+    assert_same(&program, b"");
+    let outcome = translate(&program, b"");
+    assert_eq!(outcome.snapshot.registers[Register::R1 as usize], 6);
+}
+
+#[test]
+fn test_blocks_leave_device_registers_to_the_interpreter() {
+    // Polls KBSR through LDI and LDR, then echoes the key read from KBDR.
+    let program = assembled(
+        "
+        .ORIG x3000
+POLL    LDI R1, KBSR
+        BRzp POLL
+        LD R2, KBDR
+        LDR R0, R2, #0
+        OUT
+        LEA R3, TEXT
+        STR R0, R3, #0
+        HALT
+KBSR    .FILL xFE00
+KBDR    .FILL xFE02
+TEXT    .BLKW 1
+        .END
+",
+    );
+    assert_same(&program, b"k");
+    assert_eq!(translate(&program, b"k").output, b"kHALT\n");
+}
+
+#[test]
+fn test_blocks_stop_on_reserved_opcodes_and_waiting_for_input() {
+    let rti = |lc3: &mut Vm| lc3.load_program(0x3000, &[0x1021, 0x1021, 0x8000]);
+    assert_same(&rti, b"");
+    let outcome = translate(&rti, b"");
+    assert_eq!(outcome.result, "Opcode Rti is unused or reserved");
+    assert_eq!(outcome.snapshot.registers[Register::PC as usize], 0x3003);
+
+    let getc = |lc3: &mut Vm| lc3.load_program(0x3000, &[0x1021, 0xF020, 0xF020, 0xF025]);
+    assert_same(&getc, b"a");
+    assert_eq!(
+        translate(&getc, b"a").result,
+        VmError::WaitingForInput.to_string()
+    );
+}
+
+#[test]
+fn test_blocks_flag_runs_program() {
+    assert_cmd::cargo::cargo_bin_cmd!("lc3-vm-rust")
+        .args(["--blocks", "assets/hello.obj"])
+        .assert()
+        .stdout("Hello World!\nHALT\n");
+}
@@ -0,0 +1,161 @@
+use assert_cmd::cargo::cargo_bin_cmd;
+use lc3_vm_rust::asm;
+use lc3_vm_rust::callstack::{CallEvent, CallStack};
+use lc3_vm_rust::debuginfo::DebugInfo;
+use lc3_vm_rust::vm::{Register, Vm};
+use predicates::prelude::*;
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+
+mod common;
+
+const NESTED: &str = "
+        .ORIG x3000
+MAIN    JSR OUTER
+        HALT
+OUTER   ST R7, SAVE
+        JSR INNER
+        LD R7, SAVE
+        RET
+INNER   ADD R0, R0, #1
+        RET
+SAVE    .BLKW 1
+        .END
+";
+
+fn load(source: &str) -> (Vm, DebugInfo) {
+    let (lc3, _) = common::load(source, b"");
+    let assembly = asm::assemble(source).unwrap();
+    (lc3, DebugInfo::from_assembly(&assembly, None))
+}
+
+#[test]
+fn test_backtrace_names_nested_subroutines() {
+    let (mut lc3, debug) = load(NESTED);
+    let inner = debug.address_of("INNER").unwrap();
+    let mut calls = CallStack::new();
+    while lc3.read_register(Register::PC) != inner {
+        let step = lc3.step().unwrap();
+        calls.observe(&step, &lc3);
+    }
+
+    assert_eq!(calls.depth(), 2);
+    assert_eq!(
+        calls.backtrace(inner, &debug),
+        [
+            "#0  x3006 INNER in INNER",
+            "#1  x3003 OUTER+1 in OUTER",
+            "#2  x3000 MAIN in <entry>",
+        ]
+    );
+
+    loop {
+        let step = lc3.step().unwrap();
+        assert!(!matches!(
+            calls.observe(&step, &lc3),
+            Some(CallEvent::MismatchedReturn { .. })
+        ));
+        if step.halted {
+            break;
+        }
+    }
+    assert_eq!(calls.depth(), 0);
+}
+
+#[test]
+fn test_clobbered_r7_is_reported() {
+    // OUTER forgets to save R7 before calling INNER, so its RET loops back into itself.
+    let (mut lc3, debug) = load(
+        &NESTED
+            .replace("ST R7, SAVE", "ADD R0, R0, #0")
+            .replace("LD R7, SAVE", "ADD R0, R0, #0"),
+    );
+    let mut calls = CallStack::new();
+    let event = loop {
+        let step = lc3.step().unwrap();
+        if let Some(event @ CallEvent::MismatchedReturn { .. }) = calls.observe(&step, &lc3) {
+            break event;
+        }
+    };
+
+    assert_eq!(
+        event,
+        CallEvent::MismatchedReturn {
+            ret_pc: 0x3005,
+            expected: 0x3001,
+            actual: 0x3004,
+        }
+    );
+    assert!(
+        event
+            .describe(&debug)
+            .unwrap()
+            .contains("R7 may have been clobbered")
+    );
+}
+
+#[test]
+fn test_error_prints_backtrace() {
+    let dir = common::temp_dir("callstack");
+    let image = dir.join("rti.obj");
+    // JSR x3002; HALT; RTI
+    std::fs::write(&image, [0x30, 0x00, 0x48, 0x01, 0xF0, 0x25, 0x80, 0x00]).unwrap();
+
+    // The innermost frame is the RTI itself, on every engine.
+    for engine in [&[][..], &["--blocks"], &["--micro"]] {
+        cargo_bin_cmd!("lc3-vm-rust")
+            .args(engine)
+            .arg(&image)
+            .assert()
+            .code(1)
+            .stdout(predicate::str::contains("Opcode Rti is unused or reserved"))
+            .stderr(predicate::str::contains("Backtrace:\n  #0  x3002 in "));
+    }
+    cargo_bin_cmd!("lc3-vm-rust")
+        .arg(&image)
+        .assert()
+        .stderr(predicate::str::contains(
+            "Backtrace:\n  #0  x3002 in x3002\n  #1  x3000 in <entry>\n",
+        ));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_interrupt_prints_backtrace() {
+    let dir = common::temp_dir("callstack-signal");
+    let image = dir.join("spin.obj");
+    // JSR SUB; SUB LEA R0, MSG; PUTS; BRnzp #-1; MSG .STRINGZ "r\n"
+    let words: [u16; 8] = [
+        0x3000, 0x4800, 0xE002, 0xF022, 0x0FFF, 0x0072, 0x000A, 0x0000,
+    ];
+    let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+    std::fs::write(&image, bytes).unwrap();
+
+    let mut child = Command::new(assert_cmd::cargo::cargo_bin!("lc3-vm-rust"))
+        .arg(&image)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Once the program has printed, the signal handlers are in place and it is spinning.
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    assert_eq!(line, "r\n");
+    unsafe {
+        libc::kill(child.id() as libc::pid_t, libc::SIGINT);
+    }
+    let output = child.wait_with_output().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Backtrace:\n  #0  x3003 in x3001\n  #1  x3000 in <entry>\n"),
+        "{}",
+        stderr
+    );
+}
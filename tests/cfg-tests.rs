@@ -0,0 +1,163 @@
+use assert_cmd::cargo::cargo_bin_cmd;
+use lc3_vm_rust::asm;
+use lc3_vm_rust::cfg::{Cfg, Edge, EdgeKind, Exit};
+use lc3_vm_rust::debuginfo::DebugInfo;
+use predicates::prelude::*;
+
+mod common;
+
+const PROGRAM: &str = "
+        .ORIG x3000
+MAIN    AND R1, R1, #0
+        ADD R1, R1, #3
+AGAIN   JSR SUB
+        ADD R1, R1, #-1
+        BRp AGAIN
+        LEA R2, SUB2
+        JSRR R2
+        LEA R3, DONE
+        JMP R3
+        ADD R0, R0, #1
+DONE    HALT
+SUB     ADD R0, R0, #1
+        RET
+SUB2    JMP R4
+DATA    .FILL x1234
+        .END
+";
+
+fn build(source: &str, entry: Option<u16>) -> (Cfg, DebugInfo) {
+    let program = asm::assemble(source).unwrap();
+    let cfg = Cfg::build(
+        program.origin,
+        &program.words,
+        entry.unwrap_or(program.origin),
+    );
+    (cfg, DebugInfo::from_assembly(&program, None))
+}
+
+fn edge(target: u16, kind: EdgeKind) -> Edge {
+    Edge { target, kind }
+}
+
+#[test]
+fn test_cfg_splits_blocks_and_subroutines() {
+    let (cfg, _) = build(PROGRAM, None);
+    let blocks: Vec<(u16, u16, Exit)> = cfg
+        .blocks
+        .values()
+        .map(|b| (b.start, b.end, b.exit))
+        .collect();
+    assert_eq!(
+        blocks,
+        [
+            (0x3000, 0x3001, Exit::Flow),
+            (0x3002, 0x3002, Exit::Flow),
+            (0x3003, 0x3004, Exit::Flow),
+            (0x3005, 0x3006, Exit::Flow),
+            (0x3007, 0x3008, Exit::Flow),
+            (0x300A, 0x300A, Exit::Halt),
+            (0x300B, 0x300C, Exit::Return),
+            (0x300D, 0x300D, Exit::Unknown),
+        ]
+    );
+    assert_eq!(
+        cfg.blocks[&0x3003].edges,
+        [edge(0x3002, EdgeKind::Branch), edge(0x3005, EdgeKind::Next)]
+    );
+    // JSRR and JMP are followed through the LEA before them.
+    assert_eq!(
+        cfg.blocks[&0x3005].edges,
+        [edge(0x300D, EdgeKind::Call), edge(0x3007, EdgeKind::Next)]
+    );
+    assert_eq!(cfg.blocks[&0x3007].edges, [edge(0x300A, EdgeKind::Jump)]);
+    assert!(!cfg.is_code(0x3009));
+    assert!(!cfg.is_code(0x300E));
+
+    let subroutines: Vec<(u16, Vec<u16>)> = cfg
+        .subroutines
+        .iter()
+        .map(|s| (s.entry, s.blocks.clone()))
+        .collect();
+    assert_eq!(
+        subroutines,
+        [
+            (0x3000, vec![0x3000, 0x3002, 0x3003, 0x3005, 0x3007, 0x300A]),
+            (0x300B, vec![0x300B]),
+            (0x300D, vec![0x300D]),
+        ]
+    );
+}
+
+#[test]
+fn test_cfg_follows_traps_into_the_image_and_stops_at_its_end() {
+    // The OUT vector points at a routine in the image.
+    let (cfg, _) = build(
+        ".ORIG x0021\n.FILL ROUTINE\nOUT\nHALT\nROUTINE RET\n.END",
+        Some(0x0022),
+    );
+    assert_eq!(
+        cfg.blocks[&0x0022].edges,
+        [edge(0x0024, EdgeKind::Call), edge(0x0023, EdgeKind::Next)]
+    );
+    assert_eq!(cfg.subroutines.len(), 2);
+
+    let (cfg, _) = build(".ORIG x3000\nADD R0, R0, #1\nPUTS\n.END", None);
+    assert_eq!(cfg.blocks[&0x3000].end, 0x3001);
+    assert_eq!(cfg.blocks[&0x3000].exit, Exit::OffImage);
+}
+
+#[test]
+fn test_cfg_dot_output() {
+    let (cfg, debug) = build(PROGRAM, None);
+    let dot = cfg.to_dot(&debug);
+    assert!(dot.starts_with("digraph cfg {"));
+    assert!(dot.contains("subgraph cluster_x300B {\n        label=\"SUB\";"));
+    assert!(dot.contains("x300B [label=\"SUB\\lx300B  ADD R0, R0, #1\\lx300C  RET\\l\"];"));
+    assert!(dot.contains("x3002 -> x300B [label=\"call\", style=dashed];"));
+    assert!(dot.contains("x3003 -> x3002 [label=\"taken\"];"));
+    assert!(dot.contains("x3007 -> x300A [label=\"jmp\"];"));
+    assert!(dot.contains("x3002 -> x3003;"));
+    assert!(dot.contains("(unresolved)"));
+    assert!(!dot.contains("x3009"));
+}
+
+#[test]
+fn test_cfg_command() {
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args(["cfg", "assets/rogue.asm"])
+        .assert()
+        .stdout(predicate::str::contains("label=\"GET_CELL_POINTER\";"))
+        .stdout(predicate::str::contains(
+            "x3041 -> x30D5 [label=\"call\", style=dashed];",
+        ));
+
+    let dir = common::temp_dir("cfg");
+    let path = dir.join("rogue.dot");
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args(["cfg", "assets/rogue.obj", "--entry", "x3114", "-o"])
+        .arg(&path)
+        .assert()
+        .stderr(predicate::str::contains("subroutine(s)"));
+    let dot = std::fs::read_to_string(&path).unwrap();
+    assert!(dot.contains("subgraph cluster_x3114"));
+    assert!(!dot.contains("x3000"));
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args(["cfg", "assets/rogue.asm", "--entry", "NOWHERE"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("Unknown entry point: NOWHERE"));
+
+    for command in ["cfg", "lint"] {
+        cargo_bin_cmd!("lc3-vm-rust")
+            .args([command, "assets/hello.obj", "--entry", "x1000"])
+            .assert()
+            .code(2)
+            .stdout("")
+            .stderr(predicate::str::contains(
+                "Entry point x1000 is outside the image",
+            ));
+    }
+}
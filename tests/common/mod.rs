@@ -0,0 +1,35 @@
+//! Fixtures shared by the integration tests. Every test binary compiles this module and
+//! uses only part of it.
+#![allow(dead_code)]
+
+use lc3_vm_rust::asm;
+use lc3_vm_rust::console::SharedConsole;
+use lc3_vm_rust::vm::Vm;
+use std::path::PathBuf;
+
+/// A machine on a `SharedConsole`, set up by `program`, with `keys` already typed.
+pub fn machine(program: impl FnOnce(&mut Vm), keys: &[u8]) -> (Vm, SharedConsole) {
+    let console = SharedConsole::new();
+    let mut lc3 = Vm::with_console(console.clone());
+    program(&mut lc3);
+    console.push_input(keys);
+    (lc3, console)
+}
+
+/// A machine with `source` assembled into it and `keys` already typed.
+pub fn load(source: &str, keys: &[u8]) -> (Vm, SharedConsole) {
+    machine(
+        |lc3| {
+            let assembly = asm::assemble(source).unwrap();
+            lc3.load_program(assembly.origin, &assembly.words);
+        },
+        keys,
+    )
+}
+
+/// A scratch directory for this test process, which the test removes when it is done.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("lc3-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
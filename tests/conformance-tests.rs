@@ -0,0 +1,153 @@
+use lc3_vm_rust::bench::Engine;
+use lc3_vm_rust::conformance::{self, Vector};
+use lc3_vm_rust::micro::Microarchitecture;
+use std::path::Path;
+
+const FILES: &[&str] = &["operate", "branch", "memory", "control"];
+
+fn vectors() -> Vec<Vector> {
+    FILES
+        .iter()
+        .flat_map(|name| {
+            let path = Path::new("tests/conformance").join(format!("{}.toml", name));
+            conformance::load(&path).unwrap()
+        })
+        .collect()
+}
+
+// Checks every vector with `check`, reporting all the failures at once.
+fn check_all(engine: &str, check: impl Fn(&Vector) -> Vec<String>) {
+    let mut failures = Vec::new();
+    for vector in vectors() {
+        for failure in check(&vector) {
+            failures.push(format!("{}: {}", vector.name, failure));
+        }
+    }
+    assert!(failures.is_empty(), "{}:\n{}", engine, failures.join("\n"));
+}
+
+#[test]
+fn test_vectors_cover_every_opcode() {
+    let vectors = vectors();
+    assert!(vectors.len() > 90);
+    let mut opcodes = [false; 16];
+    for vector in &vectors {
+        opcodes[(vector.code[0] >> 12) as usize] = true;
+    }
+    assert_eq!(opcodes, [true; 16]);
+}
+
+#[test]
+fn test_engines_conform() {
+    for &engine in Engine::ALL {
+        check_all(engine.name(), |vector| vector.check_engine(engine));
+    }
+}
+
+#[test]
+fn test_microarchitecture_conforms() {
+    check_all("micro", |vector| {
+        let mut micro = Microarchitecture::new();
+        vector.check(|vm| micro.step(vm).map(|step| step.halted))
+    });
+}
+
+#[test]
+fn test_failures_name_what_differs() {
+    let vectors = conformance::parse(
+        "
+[[vector]]
+name = 'wrong'
+code = 0b0001_001_001_1_00001
+expect.registers = { R1 = 2 }
+expect.pc = 0x3005
+expect.cc = 'n'
+expect.memory = { x3000 = 0 }
+expect.output = 'x'
+
+[[vector]]
+code = 0xF025
+expect.error = 'never'
+",
+    )
+    .unwrap();
+    assert_eq!(vectors[1].name, "vector 2");
+    assert_eq!(
+        vectors[0].check_engine(Engine::Decode),
+        [
+            "R1 is x0001 (1), expected x0002 (2)",
+            "PC is x3001, expected x3005",
+            "condition codes are p, expected n",
+            "memory at x3000 is x1261 (4705), expected x0000 (0)",
+            "output is \"\", expected \"x\"",
+        ]
+    );
+    assert_eq!(
+        vectors[1].check_engine(Engine::Decode),
+        ["expected the error \"never\""]
+    );
+
+    let stuck = conformance::parse("[[vector]]\ncode = 0x8000\nsteps = 2").unwrap();
+    assert_eq!(
+        stuck[0].check_engine(Engine::Decode),
+        ["step 1 failed: Opcode Rti is unused or reserved"]
+    );
+}
+
+#[test]
+fn test_ranges_wrap_past_xffff() {
+    let vectors = conformance::parse(
+        "
+[[vector]]
+code = 0x0000
+memory = { xFFFF = [1, 2] }
+expect.memory = { xFFFF = [1, 2] }
+
+[[vector]]
+code = 0x0000
+expect.memory = { xFFFF = [1, 2] }
+",
+    )
+    .unwrap();
+    assert!(vectors[0].check_engine(Engine::Decode).is_empty());
+    assert_eq!(
+        vectors[1].check_engine(Engine::Decode),
+        [
+            "memory at xFFFF is x0000 (0), expected x0001 (1)",
+            "memory at x0000 is x0000 (0), expected x0002 (2)",
+        ]
+    );
+}
+
+#[test]
+fn test_parse_errors() {
+    for (text, error) in [
+        ("[[vector]]\nname = 'a'", "a: the vector has no code or asm"),
+        (
+            "[[vector]]\nname = 'a'\ncode = 0\nasm = 'HALT'",
+            "a: give either code or asm, not both",
+        ),
+        (
+            "[[vector]]\ncode = 0\ncc = 'q'",
+            "vector 1: cc must be n, z or p, not q",
+        ),
+        (
+            "[[vector]]\ncode = 0\nsteps = 0",
+            "vector 1: steps must be a positive integer",
+        ),
+        (
+            "[[vector]]\ncode = 0\nregisters = { R1 = 'LOOP' }",
+            "vector 1: Invalid address: LOOP",
+        ),
+        (
+            "[[vector]]\ncode = 'x'",
+            "vector 1: code must be a word or an array of words, not string",
+        ),
+    ] {
+        assert_eq!(conformance::parse(text).unwrap_err(), error);
+    }
+
+    let vector =
+        &conformance::parse("[[vector]]\nasm = 'ADD R1, R1, #-16'\npc = 'x4000'").unwrap()[0];
+    assert_eq!((vector.pc, vector.code.as_slice()), (0x4000, &[0x1270][..]));
+}
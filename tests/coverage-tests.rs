@@ -0,0 +1,136 @@
+use assert_cmd::cargo::cargo_bin_cmd;
+use lc3_vm_rust::asm;
+use lc3_vm_rust::coverage::{BranchCounts, Coverage};
+use lc3_vm_rust::debuginfo::DebugInfo;
+use lc3_vm_rust::vm::Vm;
+use predicates::prelude::*;
+use std::path::Path;
+
+mod common;
+
+const PROGRAM: &str = "        .ORIG x3000
+        AND R1, R1, #0
+        ADD R1, R1, #3
+LOOP    ADD R1, R1, #-1
+        BRp LOOP
+        BRn NEVER
+        HALT
+NEVER   ADD R0, R0, #1
+        HALT
+        .END
+";
+
+#[test]
+fn test_coverage_counts_hits_and_branch_directions() {
+    let assembly = asm::assemble(PROGRAM).unwrap();
+    let mut lc3 = Vm::new();
+    lc3.load_program(assembly.origin, &assembly.words);
+    let mut coverage = Coverage::new();
+    loop {
+        let step = lc3.step().unwrap();
+        coverage.observe(&step, &lc3);
+        if step.halted {
+            break;
+        }
+    }
+
+    assert_eq!(coverage.hits(0x3002), 3);
+    assert_eq!(coverage.hits(0x3006), 0);
+    assert_eq!(
+        coverage.branch(0x3003),
+        Some(BranchCounts {
+            taken: 2,
+            not_taken: 1,
+        })
+    );
+    assert_eq!(
+        coverage.branch(0x3004),
+        Some(BranchCounts {
+            taken: 0,
+            not_taken: 1,
+        })
+    );
+
+    let mut hits = Vec::new();
+    coverage.write_hits(&mut hits).unwrap();
+    assert!(
+        String::from_utf8(hits)
+            .unwrap()
+            .starts_with("x3000 1\nx3001 1\nx3002 3\n")
+    );
+
+    let debug = DebugInfo::from_assembly(&assembly, Some(Path::new("loop.asm")));
+    assert_eq!(
+        coverage.lcov(&lc3, &debug, "loop"),
+        "TN:loop
+SF:loop.asm
+BRDA:5,0,0,2
+BRDA:5,0,1,1
+BRDA:6,0,0,0
+BRDA:6,0,1,1
+BRF:4
+BRH:3
+DA:2,1
+DA:3,1
+DA:4,3
+DA:5,3
+DA:6,1
+DA:7,1
+DA:8,0
+DA:9,0
+LF:8
+LH:6
+end_of_record
+"
+    );
+}
+
+#[test]
+fn test_lcov_flag_runs_source_and_writes_report() {
+    let dir = common::temp_dir("coverage");
+    let lcov = dir.join("lcov.info");
+
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args(["--source", "assets/hello.asm", "--lcov"])
+        .arg(&lcov)
+        .assert()
+        .stdout(predicate::str::contains("Hello World!"));
+
+    assert_eq!(
+        std::fs::read_to_string(&lcov).unwrap(),
+        "TN:hello\nSF:assets/hello.asm\nDA:5,1\nDA:6,1\nDA:7,1\nLF:3\nLH:3\nend_of_record\n"
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_source_that_cannot_be_loaded_exits_2() {
+    let dir = common::temp_dir("coverage-bad");
+    let bad = dir.join("bad.asm");
+    std::fs::write(&bad, ".ORIG x3000\nFOO R1\n.END\n").unwrap();
+    let bad = bad.to_str().unwrap();
+
+    for (args, error) in [
+        (
+            &["--source", bad][..],
+            format!("{}: line 2: Unknown instruction R1", bad),
+        ),
+        (
+            &["--source", "/nope.asm"],
+            "Could not read /nope.asm".to_string(),
+        ),
+        (
+            &["--sym", "/nope.sym", "assets/hello.obj"],
+            "Could not read /nope.sym".to_string(),
+        ),
+    ] {
+        cargo_bin_cmd!("lc3-vm-rust")
+            .args(args)
+            .assert()
+            .code(2)
+            .stdout("")
+            .stderr(predicate::str::contains(error));
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
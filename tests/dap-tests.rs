@@ -0,0 +1,263 @@
+use lc3_vm_rust::dap;
+use lc3_vm_rust::json::{self, Value};
+use std::io::Cursor;
+
+mod common;
+
+fn frame(seq: usize, command: &str, arguments: &str) -> String {
+    let body = format!(
+        r#"{{"seq":{},"type":"request","command":"{}","arguments":{}}}"#,
+        seq, command, arguments
+    );
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+fn run_session(requests: &[(&str, &str)]) -> Vec<Value> {
+    let input: String = requests
+        .iter()
+        .enumerate()
+        .map(|(i, (command, args))| frame(i + 1, command, args))
+        .collect();
+    let mut output = Vec::new();
+    dap::serve(Cursor::new(input), &mut output).unwrap();
+
+    let text = String::from_utf8(output).unwrap();
+    text.split("Content-Length: ")
+        .filter(|chunk| !chunk.is_empty())
+        .map(|chunk| json::parse(chunk.split_once("\r\n\r\n").unwrap().1).unwrap())
+        .collect()
+}
+
+fn find<'a>(messages: &'a [Value], kind: &str, name: &str) -> Vec<&'a Value> {
+    let key = if kind == "event" { "event" } else { "command" };
+    messages
+        .iter()
+        .filter(|m| {
+            m.get("type").and_then(Value::as_str) == Some(kind)
+                && m.get(key).and_then(Value::as_str) == Some(name)
+        })
+        .collect()
+}
+
+#[test]
+fn test_dap_breakpoint_by_source_line() {
+    let messages = run_session(&[
+        ("initialize", r#"{"adapterID":"lc3"}"#),
+        ("launch", r#"{"source":"assets/hello.asm"}"#),
+        (
+            "setBreakpoints",
+            r#"{"source":{"path":"assets/hello.asm"},"breakpoints":[{"line":7}]}"#,
+        ),
+        ("configurationDone", "{}"),
+        ("stackTrace", r#"{"threadId":1}"#),
+        ("variables", r#"{"variablesReference":1}"#),
+        ("continue", r#"{"threadId":1}"#),
+        ("disconnect", "{}"),
+    ]);
+
+    let breakpoints = find(&messages, "response", "setBreakpoints");
+    let bp = &breakpoints[0]
+        .get("body")
+        .unwrap()
+        .get("breakpoints")
+        .unwrap();
+    assert_eq!(
+        bp.as_array().unwrap()[0].get("verified"),
+        Some(&Value::Bool(true))
+    );
+
+    let stopped = find(&messages, "event", "stopped");
+    assert_eq!(
+        stopped[0]
+            .get("body")
+            .unwrap()
+            .get("reason")
+            .unwrap()
+            .as_str(),
+        Some("breakpoint")
+    );
+
+    let trace = find(&messages, "response", "stackTrace");
+    let top = &trace[0]
+        .get("body")
+        .unwrap()
+        .get("stackFrames")
+        .unwrap()
+        .as_array()
+        .unwrap()[0];
+    assert_eq!(top.get("line").unwrap().as_i64(), Some(7));
+
+    // R0 points at the string by the time HALT is reached.
+    let vars = find(&messages, "response", "variables");
+    let r0 = &vars[0]
+        .get("body")
+        .unwrap()
+        .get("variables")
+        .unwrap()
+        .as_array()
+        .unwrap()[0];
+    assert_eq!(r0.get("value").unwrap().as_str(), Some("x3003 (12291)"));
+
+    let output: String = find(&messages, "event", "output")
+        .iter()
+        .filter_map(|m| m.get("body")?.get("output")?.as_str())
+        .collect();
+    assert!(output.contains("Hello World!"));
+    assert!(output.contains("HALT"));
+    assert_eq!(find(&messages, "event", "terminated").len(), 1);
+}
+
+#[test]
+fn test_dap_program_waits_for_input_from_debug_console() {
+    let messages = run_session(&[
+        ("initialize", "{}"),
+        (
+            "launch",
+            r#"{"program":"assets/rogue.obj","stopOnEntry":true}"#,
+        ),
+        ("configurationDone", "{}"),
+        ("next", r#"{"threadId":1}"#),
+        ("evaluate", r#"{"expression":"pc","context":"repl"}"#),
+        ("continue", r#"{"threadId":1}"#),
+        ("evaluate", r#"{"expression":"input x","context":"repl"}"#),
+        ("next", r#"{"threadId":1}"#),
+        ("evaluate", r#"{"expression":"R0","context":"repl"}"#),
+        ("disconnect", "{}"),
+    ]);
+
+    let reasons: Vec<&str> = find(&messages, "event", "stopped")
+        .iter()
+        .filter_map(|m| m.get("body")?.get("reason")?.as_str())
+        .collect();
+    assert_eq!(reasons, ["entry", "step", "pause", "step"]);
+
+    let results: Vec<&str> = find(&messages, "response", "evaluate")
+        .iter()
+        .filter_map(|m| m.get("body")?.get("result")?.as_str())
+        .collect();
+    assert_eq!(results, ["x3001 (12289)", "1 key(s) queued", "x0078 (120)"]);
+}
+
+#[test]
+fn test_dap_next_steps_over_calls() {
+    let dir = common::temp_dir("dap-next");
+    let source = dir.join("calls.asm");
+    std::fs::write(
+        &source,
+        ".ORIG x3000\nJSR SUB\nJSR SUB\nHALT\nSUB ADD R0, R0, #5\nRET\n.END\n",
+    )
+    .unwrap();
+    let launch = format!(
+        r#"{{"source":"{}","stopOnEntry":true}}"#,
+        source.to_str().unwrap()
+    );
+    let messages = run_session(&[
+        ("initialize", "{}"),
+        ("launch", &launch),
+        ("configurationDone", "{}"),
+        ("next", r#"{"threadId":1}"#),
+        ("evaluate", r#"{"expression":"pc","context":"repl"}"#),
+        ("evaluate", r#"{"expression":"R0","context":"repl"}"#),
+        ("stepIn", r#"{"threadId":1}"#),
+        ("evaluate", r#"{"expression":"pc","context":"repl"}"#),
+        ("next", r#"{"threadId":1}"#),
+        ("evaluate", r#"{"expression":"pc","context":"repl"}"#),
+        // Stepping over the RET comes back out to the caller.
+        ("next", r#"{"threadId":1}"#),
+        ("evaluate", r#"{"expression":"pc","context":"repl"}"#),
+        ("evaluate", r#"{"expression":"R0","context":"repl"}"#),
+        ("disconnect", "{}"),
+    ]);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let reasons: Vec<&str> = find(&messages, "event", "stopped")
+        .iter()
+        .filter_map(|m| m.get("body")?.get("reason")?.as_str())
+        .collect();
+    assert_eq!(reasons, ["entry", "step", "step", "step", "step"]);
+
+    let results: Vec<&str> = find(&messages, "response", "evaluate")
+        .iter()
+        .filter_map(|m| m.get("body")?.get("result")?.as_str())
+        .collect();
+    assert_eq!(
+        results,
+        [
+            "x3001 (12289)",
+            "x0005 (5)",
+            "x3003 (12291)",
+            "x3004 (12292)",
+            "x3002 (12290)",
+            "x000A (10)",
+        ]
+    );
+}
+
+#[test]
+fn test_dap_pauses_a_program_polling_for_keys() {
+    let dir = common::temp_dir("dap-poll");
+    let source = dir.join("poll.asm");
+    std::fs::write(
+        &source,
+        ".ORIG x3000\nPOLL LDI R1, KBSR\nBRzp POLL\nLDI R0, KBDR\nHALT\n\
+         KBSR .FILL xFE00\nKBDR .FILL xFE02\n.END\n",
+    )
+    .unwrap();
+    let launch = format!(r#"{{"source":"{}"}}"#, source.to_str().unwrap());
+    let messages = run_session(&[
+        ("initialize", "{}"),
+        ("launch", &launch),
+        ("configurationDone", "{}"),
+        ("evaluate", r#"{"expression":"pc","context":"repl"}"#),
+        ("evaluate", r#"{"expression":"input a","context":"repl"}"#),
+        ("continue", r#"{"threadId":1}"#),
+        ("evaluate", r#"{"expression":"R0","context":"repl"}"#),
+        ("disconnect", "{}"),
+    ]);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let stopped = find(&messages, "event", "stopped");
+    assert_eq!(stopped.len(), 1);
+    let body = stopped[0].get("body").unwrap();
+    assert_eq!(body.get("reason").unwrap().as_str(), Some("pause"));
+    assert_eq!(
+        body.get("description").unwrap().as_str(),
+        Some("Waiting for input")
+    );
+
+    let results: Vec<&str> = find(&messages, "response", "evaluate")
+        .iter()
+        .filter_map(|m| m.get("body")?.get("result")?.as_str())
+        .collect();
+    assert_eq!(results, ["x3001 (12289)", "1 key(s) queued", "x0061 (97)"]);
+    assert_eq!(find(&messages, "event", "terminated").len(), 1);
+}
+
+#[test]
+fn test_dap_pause_stops_a_running_program() {
+    let dir = common::temp_dir("dap-pause");
+    let source = dir.join("spin.asm");
+    std::fs::write(&source, ".ORIG x3000\nLOOP BR LOOP\n.END\n").unwrap();
+    let launch = format!(r#"{{"source":"{}"}}"#, source.to_str().unwrap());
+    let messages = run_session(&[
+        ("initialize", "{}"),
+        ("launch", &launch),
+        ("configurationDone", "{}"),
+        ("pause", r#"{"threadId":1}"#),
+        ("evaluate", r#"{"expression":"pc","context":"repl"}"#),
+        ("disconnect", "{}"),
+    ]);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let reasons: Vec<&str> = find(&messages, "event", "stopped")
+        .iter()
+        .filter_map(|m| m.get("body")?.get("reason")?.as_str())
+        .collect();
+    assert_eq!(reasons, ["pause"]);
+    assert_eq!(find(&messages, "response", "pause").len(), 1);
+    let results: Vec<&str> = find(&messages, "response", "evaluate")
+        .iter()
+        .filter_map(|m| m.get("body")?.get("result")?.as_str())
+        .collect();
+    assert_eq!(results, ["x3000 (12288)"]);
+}
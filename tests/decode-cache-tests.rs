@@ -0,0 +1,78 @@
+use lc3_vm_rust::asm;
+use lc3_vm_rust::console::SharedConsole;
+use lc3_vm_rust::vm::{Register, Vm};
+
+// Rewrites the ADD at PATCH from +1 to +2 after its first run, so R1 ends up 1 + 2 + 2.
+const SELF_MODIFYING: &str = "
+        .ORIG x3000
+        AND R1, R1, #0
+        AND R2, R2, #0
+        ADD R2, R2, #3
+LOOP
+PATCH   ADD R1, R1, #1
+        LD R0, NEW
+        ST R0, PATCH
+        ADD R2, R2, #-1
+        BRp LOOP
+        HALT
+NEW     ADD R1, R1, #2
+        .END
+";
+
+fn run(source: &str, cached: bool) -> (Vm, SharedConsole) {
+    let assembly = asm::assemble(source).unwrap();
+    let console = SharedConsole::new();
+    let mut lc3 = Vm::with_console(console.clone());
+    lc3.set_decode_cache(cached);
+    lc3.load_program(assembly.origin, &assembly.words);
+    lc3.run().unwrap();
+    (lc3, console)
+}
+
+#[test]
+fn test_decode_cache_sees_self_modifying_code() {
+    let (plain, _) = run(SELF_MODIFYING, false);
+    let (cached, _) = run(SELF_MODIFYING, true);
+    assert_eq!(plain.read_register(Register::R1), 5);
+    assert_eq!(cached.read_register(Register::R1), 5);
+}
+
+#[test]
+fn test_decode_cache_is_invalidated_by_host_writes() {
+    let console = SharedConsole::new();
+    let mut lc3 = Vm::with_console(console.clone());
+    lc3.set_decode_cache(true);
+    // ADD R0, R0, #1; BRnzp back to it.
+    lc3.load_program(0x3000, &[0x1021, 0x0FFE]);
+    lc3.step().unwrap();
+    lc3.step().unwrap();
+    assert_eq!(lc3.read_register(Register::R0), 1);
+
+    // ADD R0, R0, #5
+    lc3.write_memory(0x3000, 0x1025);
+    lc3.step().unwrap();
+    assert_eq!(lc3.read_register(Register::R0), 6);
+
+    let snapshot = lc3.snapshot();
+    lc3.load_program(0x3000, &[0x1021, 0x0FFE]);
+    lc3.restore(&snapshot);
+    lc3.write_register(Register::PC, 0x3000);
+    lc3.step().unwrap();
+    assert_eq!(lc3.read_register(Register::R0), 11);
+}
+
+#[test]
+fn test_decode_cache_matches_plain_interpreter_on_rogue() {
+    let keys = b"ddddssssddddwwwwaaaassss";
+    let mut results = Vec::new();
+    for cached in [false, true] {
+        let console = SharedConsole::new();
+        let mut lc3 = Vm::with_console(console.clone());
+        lc3.set_decode_cache(cached);
+        lc3.read_image_file("assets/rogue.obj").unwrap();
+        console.push_input(keys);
+        while lc3.step().is_ok() {}
+        results.push((lc3.snapshot(), console.take_output()));
+    }
+    assert!(results[0] == results[1]);
+}
@@ -0,0 +1,89 @@
+use assert_cmd::cargo::cargo_bin_cmd;
+use lc3_vm_rust::bench::Engine;
+use lc3_vm_rust::fuzz::{self, Case, Verdict};
+use lc3_vm_rust::reference::{self, Reference};
+use lc3_vm_rust::vm::read_obj_file;
+use predicates::prelude::*;
+
+#[test]
+fn test_reference_runs_hello() {
+    let (origin, words) = read_obj_file("assets/hello.obj").unwrap();
+    let mut machine = Reference::new();
+    machine.memory[origin as usize..origin as usize + words.len()].copy_from_slice(&words);
+    machine.pc = origin;
+    while !machine.step().unwrap() {}
+    assert_eq!(machine.output, b"Hello World!\nHALT\n");
+}
+
+#[test]
+fn test_generated_cases_are_reproducible() {
+    let case = Case::generate(42, 64);
+    assert_eq!(case, Case::generate(42, 64));
+    assert_ne!(case, Case::generate(43, 64));
+    assert!(!case.program.is_empty());
+}
+
+#[test]
+fn test_minimize_keeps_only_what_the_divergence_needs() {
+    let case = Case {
+        registers: [0x1234, 0xFFFF, 0x8000, 7, 0x3000, 1, 0x7FFF, 0x4000],
+        cc: reference::N,
+        origin: 0x4000,
+        program: vec![0x1261, 0x5A3F, 0x97FF, 0x0E05, 0xF025],
+        data: vec![(0x4010, 0xBEEF), (0x4011, 0x3000)],
+        max_instructions: 64,
+    };
+    // Pretend the engine gets `NOT R3, ...` wrong.
+    let minimized = fuzz::minimize(&case, |c| c.program.iter().any(|w| w & 0xFE00 == 0x9600));
+    assert_eq!(
+        minimized,
+        Case {
+            registers: [0; 8],
+            cc: reference::Z,
+            origin: 0x3000,
+            program: vec![0x9600],
+            data: vec![],
+            max_instructions: 1,
+        }
+    );
+}
+
+#[test]
+fn test_engines_agree_with_the_reference() {
+    // The fuzzer's first find: JSRR R7 used to jump to the return address it had just
+    // written instead of to the old R7.
+    let jsrr_r7 = Case {
+        registers: [0; 8],
+        cc: reference::Z,
+        origin: 0x3000,
+        program: vec![0x41C0],
+        data: vec![],
+        max_instructions: 1,
+    };
+    for &engine in Engine::ALL {
+        assert_eq!(fuzz::compare(&jsrr_r7, engine), Verdict::Agree);
+
+        let report = fuzz::fuzz(1, 300, engine, fuzz::DEFAULT_MAX_INSTRUCTIONS);
+        assert_eq!(report.cases, 300);
+        assert_eq!(report.divergent, 0, "{}", engine.name());
+        assert!(report.counterexamples.is_empty());
+    }
+}
+
+#[test]
+fn test_fuzz_command() {
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args(["fuzz", "--seed", "1", "--cases", "3", "--engine", "blocks"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Fuzzing blocks against the reference model: 3 cases from seed 1",
+        ))
+        .stdout(predicate::str::contains("3 cases: 3 agreed, 0 diverged"));
+
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args(["fuzz", "--engine", "nope"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("Unknown engine nope"));
+}
@@ -0,0 +1,148 @@
+use assert_cmd::cargo::cargo_bin_cmd;
+use lc3_vm_rust::gdb;
+use lc3_vm_rust::vm::Vm;
+use predicates::prelude::*;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Client {
+    fn connect(image: &str) -> (Client, thread::JoinHandle<Vm>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let image = image.to_string();
+        let server = thread::spawn(move || {
+            let mut lc3 = Vm::new();
+            lc3.read_image_file(&image).unwrap();
+            gdb::serve(&listener, &mut lc3).unwrap();
+            lc3
+        });
+
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let client = Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+        };
+        (client, server)
+    }
+
+    fn request(&mut self, data: &str) -> String {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(self.writer, "${}#{:02x}", data, checksum).unwrap();
+
+        let mut ack = [0u8; 1];
+        self.reader.read_exact(&mut ack).unwrap();
+        assert_eq!(ack[0], b'+', "Stub did not acknowledge {}", data);
+
+        let mut reply = Vec::new();
+        self.reader.read_until(b'$', &mut reply).unwrap();
+        reply.clear();
+        self.reader.read_until(b'#', &mut reply).unwrap();
+        reply.pop();
+        let mut checksum = [0u8; 2];
+        self.reader.read_exact(&mut checksum).unwrap();
+        self.writer.write_all(b"+").unwrap();
+
+        String::from_utf8(reply).unwrap()
+    }
+}
+
+#[test]
+fn test_gdb_registers_and_memory() {
+    let (mut client, server) = Client::connect("assets/hello.obj");
+
+    assert_eq!(client.request("?"), "S05");
+
+    // r0..r7, then the 32-bit byte-addressed pc (0x3000 * 2), then cond (Z).
+    let registers = client.request("g");
+    assert_eq!(&registers[32..40], "00600000");
+    assert_eq!(&registers[40..44], "0200");
+
+    // `lea r0, hello` is 0xE002, stored little-endian.
+    assert_eq!(client.request("m6000,2"), "02e0");
+
+    assert_eq!(client.request("P1=3412"), "OK");
+    assert_eq!(client.request("p1"), "3412");
+
+    assert_eq!(client.request("M8000,2:cdab"), "OK");
+    assert_eq!(client.request("m8000,2"), "cdab");
+
+    // Reads stop at the packet size and at the end of memory.
+    assert_eq!(client.request("m0,ffffffff").len(), 0x1000);
+    assert_eq!(client.request("m1fffe,100"), "0000");
+    assert_eq!(client.request("m20000,2"), "E01");
+
+    // Writes past the end of memory are refused whole, rather than wrapping to x0000.
+    let low = client.request("m0,2");
+    assert_eq!(client.request("M20000,2:abcd"), "E01");
+    assert_eq!(client.request("M1fffe,4:11223344"), "E01");
+    assert_eq!(client.request("Mffffffff,2:abcd"), "E01");
+    assert_eq!(client.request("m0,2"), low);
+    assert_eq!(client.request("m1fffe,2"), "0000");
+    assert_eq!(client.request("M1fffe,2:1122"), "OK");
+    assert_eq!(client.request("m1fffe,2"), "1122");
+
+    assert_eq!(client.request("D"), "OK");
+    let lc3 = server.join().unwrap();
+    assert_eq!(lc3.peek_memory(0x4000), 0xABCD);
+}
+
+#[test]
+fn test_gdb_breakpoint_step_and_continue() {
+    let (mut client, server) = Client::connect("assets/hello.obj");
+
+    assert!(
+        client
+            .request("qSupported:swbreak+")
+            .contains("qXfer:features:read+")
+    );
+    assert!(
+        client
+            .request("qXfer:features:read:target.xml:0,1000")
+            .contains(r#"<reg name="cond""#)
+    );
+
+    assert_eq!(client.request("s"), "S05");
+    assert_eq!(client.request("p8"), "02600000");
+
+    // Stop before HALT at 0x3002.
+    assert_eq!(client.request("Z0,6004,2"), "OK");
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.request("p8"), "04600000");
+
+    assert_eq!(client.request("z0,6004,2"), "OK");
+    assert_eq!(client.request("c"), "W00");
+
+    server.join().unwrap();
+}
+
+#[test]
+fn test_gdb_rejects_other_modes() {
+    for flags in [
+        &["--tui"][..],
+        &["--save-state", "s.lc3s"],
+        &["--profile", "p.txt"],
+        &["--folded", "f.txt"],
+        &["--coverage", "c.txt"],
+        &["--lcov", "l.info", "--source", "assets/hello.asm"],
+        &["--blocks"],
+        &["--micro"],
+        &["--micro-trace", "m.txt"],
+    ] {
+        cargo_bin_cmd!("lc3-vm-rust")
+            .args(["--gdb", "1234", "assets/hello.obj"])
+            .args(flags)
+            .assert()
+            .code(2)
+            .stdout("")
+            .stderr(predicate::str::contains(format!(
+                "--gdb cannot be combined with {}",
+                flags[0]
+            )));
+    }
+}
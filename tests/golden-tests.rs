@@ -0,0 +1,164 @@
+use assert_cmd::cargo::cargo_bin_cmd;
+use lc3_vm_rust::bench::Engine;
+use lc3_vm_rust::golden::{self, Case, Outcome};
+use predicates::prelude::*;
+use std::path::{Path, PathBuf};
+
+mod common;
+
+/// A scratch directory holding `files`, removed again by the caller.
+fn scratch(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = common::temp_dir(&format!("golden-{}", name));
+    for (file, text) in files {
+        std::fs::write(dir.join(file), text).unwrap();
+    }
+    dir
+}
+
+#[test]
+fn test_golden_cases_pass() {
+    // Also the output of every engine.
+    for engine in Engine::ALL {
+        let cases = golden::discover(Path::new("tests/golden")).unwrap();
+        assert_eq!(cases.len(), 2);
+        for case in &cases {
+            let result = golden::run_case(case, *engine, golden::DEFAULT_MAX_INSTRUCTIONS);
+            assert_eq!(result.outcome, Outcome::Passed, "{}", case.name);
+        }
+    }
+
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args(["test", "tests/golden"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("PASS echo"))
+        .stdout(predicate::str::contains("PASS hello"))
+        .stdout(predicate::str::contains("2 passed, 0 failed"));
+}
+
+#[test]
+fn test_golden_discovery() {
+    let dir = scratch(
+        "discover",
+        &[
+            ("b.asm", ""),
+            ("b.obj", ""),
+            ("b.out", ""),
+            ("b.in", ""),
+            ("a.obj", ""),
+            ("a.out", ""),
+            ("lonely.out", ""),
+            ("untested.asm", ""),
+        ],
+    );
+    let cases = golden::discover(&dir).unwrap();
+    assert_eq!(
+        cases,
+        [
+            Case {
+                name: "a".to_string(),
+                program: Some(dir.join("a.obj")),
+                expected: dir.join("a.out"),
+                input: None,
+            },
+            Case {
+                name: "b".to_string(),
+                program: Some(dir.join("b.asm")),
+                expected: dir.join("b.out"),
+                input: Some(dir.join("b.in")),
+            },
+            Case {
+                name: "lonely".to_string(),
+                program: None,
+                expected: dir.join("lonely.out"),
+                input: None,
+            },
+        ]
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_golden_failures() {
+    let dir = scratch(
+        "fail",
+        &[
+            (
+                "wrong.asm",
+                ".ORIG x3000\nLEA R0, S\nPUTS\nHALT\nS .STRINGZ \"one\\ntwo\\n\"\n.END",
+            ),
+            ("wrong.out", "one\nthree\nHALT\n"),
+            ("hang.asm", ".ORIG x3000\nLOOP BR LOOP\n.END"),
+            ("hang.out", ""),
+            ("fault.asm", ".ORIG x3000\nRTI\n.END"),
+            ("fault.out", ""),
+            ("missing.out", ""),
+        ],
+    );
+    let cases = golden::discover(&dir).unwrap();
+    let outcomes: Vec<Outcome> = cases
+        .iter()
+        .map(|case| golden::run_case(case, Engine::Cache, 1000).outcome)
+        .collect();
+    assert_eq!(
+        outcomes,
+        [
+            Outcome::Error("Opcode Rti is unused or reserved".to_string()),
+            Outcome::Error("did not halt within 1000 instructions".to_string()),
+            Outcome::Error("no missing.asm or missing.obj next to missing.out".to_string()),
+            Outcome::Mismatch {
+                diff: "first difference at line 2\n- \"three\\n\"\n+ \"two\\n\"\n".to_string(),
+            },
+        ]
+    );
+
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args(["test", "--max-instructions", "1000"])
+        .arg(&dir)
+        .assert()
+        .code(1)
+        .stdout(predicate::str::contains(
+            "FAIL wrong: output differs, first difference at line 2",
+        ))
+        .stdout(predicate::str::contains("0 passed, 4 failed"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_golden_diff_limits_lines() {
+    let expected: String = (0..30).map(|i| format!("{}\n", i)).collect();
+    let diff = golden::diff(expected.as_bytes(), b"0\n1\n");
+    assert!(diff.starts_with("first difference at line 3\n- \"2\\n\""));
+    assert!(diff.ends_with("- ... 18 more line(s)\n"));
+    // A missing final newline is a difference too.
+    assert_eq!(
+        golden::diff(b"a\nb\n", b"a\nb"),
+        "first difference at line 2\n- \"b\\n\"\n+ \"b\"\n"
+    );
+}
+
+#[test]
+fn test_commands_that_cannot_run_exit_2() {
+    let empty = scratch("empty", &[]);
+    let empty = empty.to_str().unwrap();
+    for (args, error) in [
+        (&["test", "/nonexistent"][..], "Could not read /nonexistent"),
+        (&["test", empty], "No test cases (NAME.out files)"),
+        (&["lint", "/nope.asm"], "Could not read /nope.asm"),
+        (&["unit", "/nope.toml"], "Could not read /nope.toml"),
+        (
+            &["grade", "/nope.toml", "x.obj"],
+            "Could not read /nope.toml",
+        ),
+        (&["cfg", "/nope.obj"], "Could not read /nope.obj"),
+        (&["fuzz", "--cases", "x"], "Invalid case count: x"),
+    ] {
+        cargo_bin_cmd!("lc3-vm-rust")
+            .args(args)
+            .assert()
+            .code(2)
+            .stdout("")
+            .stderr(predicate::str::contains(error));
+    }
+    std::fs::remove_dir_all(empty).unwrap();
+}
@@ -0,0 +1,17 @@
+; Echoes each key it reads, in upper case, until it reads a 'q'.
+        .ORIG x3000
+LOOP    GETC
+        LD R1, QUIT
+        ADD R1, R0, R1
+        BRz DONE
+        LD R1, UPPER
+        ADD R0, R0, R1
+        OUT
+        BRnzp LOOP
+DONE    LEA R0, BYE
+        PUTS
+        HALT
+QUIT    .FILL #-113
+UPPER   .FILL #-32
+BYE     .STRINGZ "\nBye.\n"
+        .END
@@ -0,0 +1,180 @@
+use assert_cmd::cargo::cargo_bin_cmd;
+use lc3_vm_rust::asm;
+use lc3_vm_rust::grade::Rubric;
+use lc3_vm_rust::json;
+use predicates::prelude::*;
+use std::path::Path;
+
+mod common;
+
+fn rubric() -> Rubric {
+    Rubric::load(Path::new("tests/grade/rubric.toml")).unwrap()
+}
+
+/// Names and feedback of the cases that failed.
+fn failures(submission: &Path) -> (u64, Vec<(String, Vec<String>)>) {
+    let report = rubric().grade(submission);
+    let score = report.score();
+    let failed = report
+        .cases
+        .into_iter()
+        .filter(|c| !c.passed)
+        .map(|c| (c.name, c.feedback))
+        .collect();
+    (score, failed)
+}
+
+#[test]
+fn test_grade_scores_submissions() {
+    assert_eq!(failures(Path::new("tests/grade/correct.asm")), (7, vec![]));
+
+    let (score, failed) = failures(Path::new("tests/grade/wrong.asm"));
+    assert_eq!(score, 1);
+    assert_eq!(
+        failed[0],
+        (
+            "prints the length of a line".to_string(),
+            vec![
+                "output is \"6\\nHALT\\n\", expected \"5\\nHALT\\n\"".to_string(),
+                "Count the characters before the newline.".to_string(),
+            ]
+        )
+    );
+    assert_eq!(
+        failed[2].1,
+        [
+            "R0 is x0004 (4), expected x0003 (3)",
+            "STRLEN should stop at the terminating zero."
+        ]
+    );
+
+    let report = rubric().grade(Path::new("tests/grade/broken.asm"));
+    assert_eq!(report.score(), 0);
+    assert_eq!(report.max_score(), 7);
+    let error = "tests/grade/broken.asm: line 3: Expected a number, found R9";
+    assert_eq!(report.error.as_deref(), Some(error));
+    // Hints are for programs that ran.
+    assert_eq!(
+        report.cases[0].feedback,
+        [format!("the submission could not be loaded: {}", error)]
+    );
+}
+
+#[test]
+fn test_grade_obj_submissions_and_isolation() {
+    let dir = common::temp_dir("grade");
+    let source = std::fs::read_to_string("tests/grade/correct.asm").unwrap();
+    let program = asm::assemble(&source).unwrap();
+    let obj = dir.join("correct.obj");
+    std::fs::write(&obj, program.to_obj_bytes()).unwrap();
+
+    // Without labels only the whole-program cases can run.
+    let (score, failed) = failures(&obj);
+    assert_eq!(score, 4);
+    assert_eq!(failed[0].1[0], "the program has no label BUFFER");
+
+    let sym: String = program
+        .symbols
+        .iter()
+        .map(|(name, addr)| format!("//  {:<16}  {:04X}\n", name, addr))
+        .collect();
+    std::fs::write(dir.join("correct.sym"), sym).unwrap();
+    assert_eq!(failures(&obj), (7, vec![]));
+
+    // A program that prints forever is stopped without disturbing the other cases, and
+    // memory ranges past xFFFF wrap instead of stopping the grader.
+    let chatty = dir.join("chatty.asm");
+    std::fs::write(
+        &chatty,
+        ".ORIG x3000\nLEA R0, MSG\nLOOP PUTS\nBRnzp LOOP\nSTRLEN AND R0, R0, #0\nADD R0, R0, #3\nRET\nMSG .STRINGZ \"spam\"\n.END",
+    )
+    .unwrap();
+    let rubric = Rubric::parse(
+        r#"
+[[case]]
+name = "prints forever"
+max_instructions = 1000000
+[[case]]
+name = "still graded"
+call = "STRLEN"
+expect.registers = { R0 = 3 }
+[[case]]
+name = "wraps around memory"
+call = "STRLEN"
+memory = { xFFFF = [1, 2] }
+expect.memory = { xFFFF = [1, 2, 0] }
+"#,
+    )
+    .unwrap();
+    let report = rubric.grade(&chatty);
+    assert_eq!(report.cases[0].feedback, ["printed more than 65536 bytes"]);
+    assert!(report.cases[1].passed);
+    assert!(report.cases[2].passed, "{:?}", report.cases[2].feedback);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(
+        Rubric::parse("title = \"empty\"").err().unwrap(),
+        "the rubric has no [[case]] tables"
+    );
+    // Scores add up past the largest weight.
+    let heavy = Rubric::parse("[[case]]\nweight = 4294967295\n[[case]]\nweight = 4294967295")
+        .unwrap()
+        .grade(Path::new("assets/hello.asm"));
+    assert_eq!(heavy.score(), 2 * u32::MAX as u64);
+    assert_eq!(heavy.max_score(), 2 * u32::MAX as u64);
+    assert_eq!(heavy.percentage(), 100.0);
+    assert_eq!(
+        Rubric::parse("[[case]]\nname = \"a\"\nweight = -1")
+            .err()
+            .unwrap(),
+        "a: weight must be a whole number"
+    );
+}
+
+#[test]
+fn test_grade_command_writes_json() {
+    let output = cargo_bin_cmd!("lc3-vm-rust")
+        .args(["grade", "tests/grade/rubric.toml", "tests/grade/wrong.asm"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let report = json::parse(&String::from_utf8(output.stdout).unwrap()).unwrap();
+    assert_eq!(
+        report.get("title").and_then(|v| v.as_str()),
+        Some("Lab 3: string length")
+    );
+    assert_eq!(report.get("score").and_then(|v| v.as_i64()), Some(1));
+    assert_eq!(report.get("max_score").and_then(|v| v.as_i64()), Some(7));
+    let tests = report.get("tests").and_then(|v| v.as_array()).unwrap();
+    assert_eq!(tests.len(), 4);
+    assert_eq!(tests[3].get("passed").and_then(|v| v.as_bool()), Some(true));
+    assert_eq!(tests[3].get("score").and_then(|v| v.as_i64()), Some(1));
+    assert_eq!(
+        tests[0]
+            .get("feedback")
+            .and_then(|v| v.as_array())
+            .map(Vec::len),
+        Some(2)
+    );
+
+    let dir = common::temp_dir("grade-cli");
+    let path = dir.join("report.json");
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args([
+            "grade",
+            "tests/grade/rubric.toml",
+            "tests/grade/correct.asm",
+            "-o",
+        ])
+        .arg(&path)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Score: 7/7"));
+    let report = json::parse(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(
+        report.get("percentage").and_then(|v| v.as_f64()),
+        Some(100.0)
+    );
+    assert_eq!(report.get("error"), Some(&json::Value::Null));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
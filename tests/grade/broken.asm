@@ -0,0 +1,5 @@
+; Does not assemble.
+        .ORIG x3000
+        ADD R0, R0, R9
+        HALT
+        .END
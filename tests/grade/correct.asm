@@ -0,0 +1,41 @@
+; Reads a line and prints its length (0-9).
+        .ORIG x3000
+        LEA R0, BUFFER
+        JSR READLINE
+        LEA R0, BUFFER
+        JSR STRLEN
+        LD R1, ZERO
+        ADD R0, R0, R1
+        OUT
+        AND R0, R0, #0
+        ADD R0, R0, #10
+        OUT
+        HALT
+
+; Reads keys into the buffer at R0 up to a newline, which is not stored.
+READLINE ST R7, SAVE7
+        ADD R1, R0, #0
+RLOOP   GETC
+        ADD R2, R0, #-10
+        BRz RDONE
+        STR R0, R1, #0
+        ADD R1, R1, #1
+        BRnzp RLOOP
+RDONE   STR R2, R1, #0
+        LD R7, SAVE7
+        RET
+
+; R0 = length of the string at R0.
+STRLEN  ADD R1, R0, #0
+        AND R0, R0, #0
+SLOOP   LDR R2, R1, #0
+        BRz SDONE
+        ADD R0, R0, #1
+        ADD R1, R1, #1
+        BRnzp SLOOP
+SDONE   RET
+
+ZERO    .FILL x30
+SAVE7   .BLKW 1
+BUFFER  .BLKW 16
+        .END
@@ -0,0 +1,151 @@
+use assert_cmd::cargo::cargo_bin_cmd;
+use lc3_vm_rust::console::{OnExhausted, ScriptedConsole, SharedConsole};
+use lc3_vm_rust::vm::{Register, Vm, VmError};
+use predicates::prelude::*;
+
+mod common;
+
+// Echoes keys read with GETC until it reads a 'q'.
+const ECHO: &str = "
+        .ORIG x3000
+LOOP    GETC
+        OUT
+        LD R1, QUIT
+        ADD R1, R0, R1
+        BRnp LOOP
+        HALT
+QUIT    .FILL #-113
+        .END
+";
+
+// The same loop, polling the keyboard status register instead of using GETC.
+const POLL: &str = "
+        .ORIG x3000
+WAIT    LDI R1, KBSR
+        BRzp WAIT
+        LDI R0, KBDR
+        OUT
+        BR WAIT
+KBSR    .FILL xFE00
+KBDR    .FILL xFE02
+        .END
+";
+
+fn load(source: &str, keys: &[u8], on_exhausted: OnExhausted) -> (Vm, SharedConsole) {
+    let (lc3, console) = common::load(source, keys);
+    console.set_on_exhausted(on_exhausted);
+    (lc3, console)
+}
+
+/// Steps until a halt or an error, giving up after `limit` instructions.
+fn run(lc3: &mut Vm, limit: usize) -> Result<bool, VmError> {
+    for _ in 0..limit {
+        if lc3.step()?.halted {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[test]
+fn test_scripted_input_halts_when_exhausted() {
+    for source in [ECHO, POLL] {
+        let (mut lc3, console) = load(source, b"abc", OnExhausted::Halt);
+        assert!(run(&mut lc3, 1000).unwrap());
+        assert_eq!(console.take_output(), b"abc");
+    }
+
+    // A 'q' ends the echo loop before the script runs out.
+    let (mut lc3, console) = load(ECHO, b"xq", OnExhausted::Halt);
+    assert!(run(&mut lc3, 1000).unwrap());
+    assert_eq!(console.take_output(), b"xqHALT\n");
+    assert_eq!(lc3.read_register(Register::R1), 0);
+}
+
+#[test]
+fn test_scripted_input_errors_or_blocks_when_exhausted() {
+    for source in [ECHO, POLL] {
+        let (mut lc3, console) = load(source, b"ab", OnExhausted::Error);
+        assert!(matches!(run(&mut lc3, 1000), Err(VmError::InputExhausted)));
+        assert_eq!(console.take_output(), b"ab");
+    }
+
+    let (mut lc3, console) = load(ECHO, b"a", OnExhausted::Block);
+    assert!(matches!(run(&mut lc3, 1000), Err(VmError::WaitingForInput)));
+    // More keys pick up where the script left off.
+    lc3.push_input(b"q");
+    assert!(run(&mut lc3, 1000).unwrap());
+    assert_eq!(console.take_output(), b"aqHALT\n");
+
+    // Polling a blocked keyboard just keeps looping.
+    let (mut lc3, _) = load(POLL, b"", OnExhausted::Block);
+    assert!(!run(&mut lc3, 1000).unwrap());
+}
+
+#[test]
+fn test_scripted_console_plays_rogue() {
+    let mut lc3 = Vm::with_console(ScriptedConsole::new(b"ddss", OnExhausted::Error));
+    lc3.read_image_file("assets/rogue.obj").unwrap();
+    assert!(matches!(
+        run(&mut lc3, 100_000_000),
+        Err(VmError::InputExhausted)
+    ));
+}
+
+#[test]
+fn test_input_flags() {
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args([
+            "--input-string",
+            "n",
+            "--input-string",
+            "\\x0a",
+            "assets/2048.obj",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Are you on an ANSI terminal (y/n)? n",
+        ));
+
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args([
+            "--input-string",
+            "ddss",
+            "--on-input-end",
+            "error",
+            "assets/rogue.obj",
+        ])
+        .assert()
+        .code(1)
+        .stdout(predicate::str::contains(
+            "the program read past the end of its input",
+        ));
+
+    let dir = common::temp_dir("input");
+    let path = dir.join("keys.txt");
+    std::fs::write(&path, "ddddssss").unwrap();
+    cargo_bin_cmd!("lc3-vm-rust")
+        .arg("--input")
+        .arg(&path)
+        .arg("assets/rogue.obj")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("@"));
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args(["--input-string", "\\q", "assets/hello.obj"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains(
+            "Invalid escape in --input-string: \\q",
+        ));
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args(["--on-input-end", "wait", "assets/hello.obj"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains(
+            "expected one of halt, error, block",
+        ));
+}
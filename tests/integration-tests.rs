@@ -1,6 +1,4 @@
-use assert_cmd::cargo::cargo_bin_cmd;
 use lc3_vm_rust::vm::Vm;
-use predicates::prelude::*;
 
 #[test]
 fn test_hello_world_memory_load() {
@@ -15,17 +13,6 @@ fn test_hello_world_memory_load() {
     );
 }
 
-#[test]
-fn test_hello_world_terminal_output() {
-    let mut cmd = cargo_bin_cmd!("lc3-vm-rust");
-
-    cmd.arg("assets/hello.obj")
-        .assert()
-        .success()
-        .stdout(predicate::str::contains("Hello World!"))
-        .stdout(predicate::str::contains("HALT"));
-}
-
 #[test]
 fn test_rogue_memory_load() {
     let mut lc3 = Vm::new();
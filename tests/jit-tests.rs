@@ -0,0 +1,199 @@
+#![cfg(feature = "jit")]
+
+use lc3_vm_rust::blocks::BlockEngine;
+use lc3_vm_rust::console::SharedConsole;
+use lc3_vm_rust::snapshot::Snapshot;
+use lc3_vm_rust::vm::{Register, Vm};
+
+mod common;
+
+// Loops run well past the point where their blocks are compiled.
+const ITERATIONS: usize = 40;
+
+struct Outcome {
+    snapshot: Snapshot,
+    output: Vec<u8>,
+    instructions: u64,
+    result: String,
+}
+
+fn interpret(source: &str, keys: &[u8]) -> Outcome {
+    let (mut lc3, console) = common::load(source, keys);
+    let mut instructions = 0;
+    let result = loop {
+        match lc3.step() {
+            Ok(step) => {
+                instructions += 1;
+                if step.halted {
+                    break "halted".to_string();
+                }
+            }
+            Err(e) => break e.to_string(),
+        }
+    };
+    Outcome {
+        snapshot: lc3.snapshot(),
+        output: console.take_output(),
+        instructions,
+        result,
+    }
+}
+
+fn compile(source: &str, keys: &[u8]) -> Outcome {
+    let (mut lc3, console) = common::load(source, keys);
+    let mut engine = BlockEngine::with_jit();
+    let result = loop {
+        match engine.run(&mut lc3, 1000) {
+            Ok(run) if run.halted => break "halted".to_string(),
+            Ok(_) => {}
+            Err(e) => break e.to_string(),
+        }
+    };
+    Outcome {
+        snapshot: lc3.snapshot(),
+        output: console.take_output(),
+        instructions: engine.instructions(),
+        result,
+    }
+}
+
+fn assert_same(source: &str, keys: &[u8]) -> Outcome {
+    let expected = interpret(source, keys);
+    let actual = compile(source, keys);
+    assert_eq!(actual.result, expected.result);
+    assert_eq!(actual.snapshot.registers, expected.snapshot.registers);
+    assert!(actual.snapshot == expected.snapshot, "memory differs");
+    assert_eq!(actual.output, expected.output);
+    assert_eq!(actual.instructions, expected.instructions);
+    actual
+}
+
+#[test]
+fn test_jit_matches_interpreter_for_every_operation() {
+    // Values with every sign, so each flag comes up.
+    let data: Vec<String> = (0..ITERATIONS)
+        .map(|i| format!("        .FILL #{}", (i as i32 * 2731) % 65536 - 32768))
+        .collect();
+    let source = format!(
+        "
+        .ORIG x3000
+        LD R5, COUNT
+        LEA R6, DATA
+LOOP    LDR R1, R6, #0
+        NOT R2, R1
+        ADD R2, R2, #1
+        AND R3, R1, R2
+        AND R3, R3, #-6
+        ADD R4, R3, R1
+        STR R4, R6, #0
+        LDI R0, PTR
+        ADD R0, R0, R4
+        STI R0, PTR
+        ST R3, LAST
+        LD R3, LAST
+        JSR SUB
+        LEA R3, SUB
+        JSRR R3
+        ADD R6, R6, #1
+        ADD R5, R5, #-1
+        BRnp LOOP
+        LEA R7, DONE
+        JMP R7
+DONE    HALT
+SUB     ADD R1, R1, R1
+        BRzp SKIP
+        NOT R1, R1
+        BR SKIP
+SKIP    RET
+COUNT   .FILL #{}
+PTR     .FILL TOTAL
+TOTAL   .FILL #0
+LAST    .BLKW 1
+DATA
+{}
+        .END
+",
+        ITERATIONS,
+        data.join("\n")
+    );
+    assert_same(&source, b"");
+}
+
+#[test]
+fn test_jit_leaves_device_registers_to_the_interpreter() {
+    // R6 walks from xFDF0 into the device page inside an already compiled loop.
+    let source = "
+        .ORIG x3000
+        LD R6, START
+        LD R5, COUNT
+        AND R2, R2, #0
+LOOP    LDR R0, R6, #0
+        ADD R2, R2, R0
+        ADD R6, R6, #1
+        ADD R5, R5, #-1
+        BRp LOOP
+        HALT
+START   .FILL xFDF0
+COUNT   .FILL #24
+        .END
+";
+    let outcome = assert_same(source, b"k");
+    // KBSR saw the key, so KBDR holds it.
+    assert_eq!(outcome.snapshot.memory[0xFE02], b'k' as u16);
+}
+
+#[test]
+fn test_jit_handles_code_patched_from_compiled_block() {
+    // The store goes to a scratch word until the last pass, which patches the ADD right
+    // after it in the same compiled block.
+    let table: Vec<&str> = std::iter::repeat_n("        .FILL SCRATCH", ITERATIONS - 1)
+        .chain(std::iter::once("        .FILL PATCH"))
+        .collect();
+    let source = format!(
+        "
+        .ORIG x3000
+        AND R1, R1, #0
+        LD R5, COUNT
+        LEA R6, TABLE
+        LD R0, NEW
+LOOP    LDR R4, R6, #0
+        STR R0, R4, #0
+PATCH   ADD R1, R1, #1
+        ADD R6, R6, #1
+        ADD R5, R5, #-1
+        BRp LOOP
+        HALT
+NEW     ADD R1, R1, #2
+COUNT   .FILL #{}
+SCRATCH .BLKW 1
+TABLE
+{}
+        .END
+",
+        ITERATIONS,
+        table.join("\n")
+    );
+    let outcome = assert_same(&source, b"");
+    assert_eq!(
+        outcome.snapshot.registers[Register::R1 as usize],
+        ITERATIONS as u16 + 1
+    );
+}
+
+#[test]
+fn test_jit_matches_interpreter_on_rogue() {
+    let run = |jit: bool| {
+        let console = SharedConsole::new();
+        let mut lc3 = Vm::with_console(console.clone());
+        lc3.read_image_file("assets/rogue.obj").unwrap();
+        console.push_input(b"ddddssssddddwwwwaaaassss");
+        if jit {
+            let mut engine = BlockEngine::with_jit();
+            while engine.run(&mut lc3, 1000).is_ok() {}
+        } else {
+            while lc3.step().is_ok() {}
+        }
+        (lc3.snapshot(), console.take_output())
+    };
+    assert!(run(true) == run(false));
+}
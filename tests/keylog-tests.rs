@@ -0,0 +1,265 @@
+use assert_cmd::cargo::cargo_bin_cmd;
+use lc3_vm_rust::asm;
+use lc3_vm_rust::blocks::BlockEngine;
+use lc3_vm_rust::console::{OnExhausted, SharedConsole};
+use lc3_vm_rust::keylog::{self, KeyEvent};
+use lc3_vm_rust::micro::Microarchitecture;
+use lc3_vm_rust::snapshot::Snapshot;
+use lc3_vm_rust::vm::{Step, Vm, VmError};
+use predicates::prelude::*;
+use std::fs::File;
+
+mod common;
+
+// Echoes keys polled from the keyboard status register, forever.
+const POLL: &str = "
+        .ORIG x3000
+WAIT    LDI R1, KBSR
+        BRzp WAIT
+        LDI R0, KBDR
+        OUT
+        BR WAIT
+KBSR    .FILL xFE00
+KBDR    .FILL xFE02
+        .END
+";
+
+fn load_2048() -> (Vm, SharedConsole) {
+    let console = SharedConsole::new();
+    console.set_on_exhausted(OnExhausted::Block);
+    let mut lc3 = Vm::with_console(console.clone());
+    lc3.read_image_file("assets/2048.obj").unwrap();
+    (lc3, console)
+}
+
+/// Runs `step` until `instructions` have run or a trap waits for a key.
+fn run_until(
+    lc3: &mut Vm,
+    instructions: u64,
+    mut step: impl FnMut(&mut Vm) -> Result<Step, VmError>,
+) {
+    while lc3.instructions() < instructions {
+        match step(lc3) {
+            Ok(step) => assert!(!step.halted),
+            Err(VmError::WaitingForInput) => return,
+            Err(e) => panic!("{}", e),
+        }
+    }
+}
+
+#[test]
+fn test_replay_reproduces_a_session_exactly() {
+    let dir = common::temp_dir("keylog-session");
+    let log = dir.join("keys.log");
+
+    // Keys arrive whenever the "player" pushes them. While the game waits for a move it
+    // polls KBSR, so when a key is seen depends on when it was typed.
+    let (mut lc3, console) = load_2048();
+    lc3.record_keys(File::create(&log).unwrap()).unwrap();
+    for (keys, gap) in [
+        (&b"n\n"[..], 10_000),
+        (b"w", 1_234),
+        (b"a", 987),
+        (b"sd", 777),
+    ] {
+        let until = lc3.instructions() + gap;
+        run_until(&mut lc3, until, Vm::step);
+        console.push_input(keys);
+    }
+    run_until(&mut lc3, u64::MAX, Vm::step);
+    let instructions = lc3.instructions();
+    let output = console.take_output();
+    let snapshot = lc3.snapshot();
+
+    let events = keylog::read_file(&log).unwrap();
+    assert_eq!(
+        events.iter().map(|e| e.key).collect::<Vec<_>>(),
+        b"n\nwasd".to_vec()
+    );
+
+    // Nothing is pushed on replay: every key comes from the log.
+    let (mut replay, replay_console) = load_2048();
+    replay.replay_keys(&events);
+    run_until(&mut replay, u64::MAX, Vm::step);
+    assert!(!replay.replaying());
+    assert_eq!(replay.instructions(), instructions);
+    assert_eq!(replay_console.take_output(), output);
+    assert_eq!(replay.snapshot(), snapshot);
+
+    // Other engines see the keys at the same instructions.
+    let (mut replay, replay_console) = load_2048();
+    replay.replay_keys(&events);
+    let mut micro = Microarchitecture::new();
+    run_until(&mut replay, u64::MAX, |lc3| micro.step(lc3));
+    assert_eq!(replay.instructions(), instructions);
+    assert_eq!(replay_console.take_output(), output);
+    assert_eq!(replay.snapshot(), snapshot);
+
+    let (mut replay, replay_console) = load_2048();
+    replay.replay_keys(&events);
+    let mut engine = BlockEngine::new();
+    loop {
+        match engine.run(&mut replay, 1000) {
+            Ok(run) => assert!(!run.halted),
+            Err(VmError::WaitingForInput) => break,
+            Err(e) => panic!("{}", e),
+        }
+    }
+    assert_eq!(replay.instructions(), instructions);
+    assert_eq!(replay_console.take_output(), output);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_restore_and_replay_reproduce_a_session() {
+    let dir = common::temp_dir("keylog-restore");
+    let log = dir.join("keys.log");
+
+    // Save the game once it is under way, then record the rest of the session.
+    let (mut lc3, console) = load_2048();
+    console.push_input(b"n\n");
+    run_until(&mut lc3, 10_000, Vm::step);
+    let saved = lc3.snapshot();
+    assert!(saved.pending_input.is_empty());
+    assert_eq!(saved.instructions, lc3.instructions());
+    console.take_output();
+    lc3.record_keys(File::create(&log).unwrap()).unwrap();
+    for (keys, gap) in [(&b"w"[..], 1_234), (b"a", 987), (b"sd", 777)] {
+        let until = lc3.instructions() + gap;
+        run_until(&mut lc3, until, Vm::step);
+        console.push_input(keys);
+    }
+    run_until(&mut lc3, u64::MAX, Vm::step);
+
+    // The counts in the log carry on from the snapshot's.
+    let events = keylog::read_file(&log).unwrap();
+    assert_eq!(events.len(), 4);
+    assert!(events[0].instructions >= saved.instructions);
+
+    let (mut replay, replay_console) = load_2048();
+    let mut bytes = Vec::new();
+    saved.write_to(&mut bytes).unwrap();
+    replay.restore(&Snapshot::read_from(&mut bytes.as_slice()).unwrap());
+    assert_eq!(replay.instructions(), saved.instructions);
+    replay.replay_keys(&events);
+    run_until(&mut replay, u64::MAX, Vm::step);
+    assert!(!replay.replaying());
+    assert_eq!(replay_console.take_output(), console.take_output());
+    assert_eq!(replay.snapshot(), lc3.snapshot());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_replay_reports_divergence() {
+    let program = asm::assemble(POLL).unwrap();
+    let load = |events: &[KeyEvent]| {
+        let mut lc3 = Vm::with_console(SharedConsole::new());
+        lc3.load_program(program.origin, &program.words);
+        lc3.replay_keys(events);
+        lc3
+    };
+
+    // KBSR is polled by the LDIs at even counts; the BRzp at 3 never reads a key.
+    let mut lc3 = load(&[KeyEvent {
+        instructions: 4,
+        key: b'x',
+    }]);
+    for _ in 0..10 {
+        lc3.step().unwrap();
+    }
+    assert!(!lc3.replaying());
+
+    let mut lc3 = load(&[KeyEvent {
+        instructions: 3,
+        key: b'x',
+    }]);
+    let error = (0..10).find_map(|_| lc3.step().err()).unwrap();
+    assert!(matches!(error, VmError::ReplayDiverged(3)));
+    assert_eq!(lc3.instructions(), 4);
+    assert_eq!(
+        error.to_string(),
+        "replay diverged: the key recorded at instruction 3 was not read there"
+    );
+}
+
+#[test]
+fn test_key_log_format() {
+    let events = keylog::parse("lc3-keys 1\n12 x6E  # n\n\n# a comment\n40 x0A\n").unwrap();
+    assert_eq!(
+        events,
+        vec![
+            KeyEvent {
+                instructions: 12,
+                key: b'n'
+            },
+            KeyEvent {
+                instructions: 40,
+                key: b'\n'
+            },
+        ]
+    );
+
+    let mut out = Vec::new();
+    for event in &events {
+        event.write(&mut out).unwrap();
+    }
+    assert_eq!(String::from_utf8(out).unwrap(), "12 x6E  # n\n40 x0A\n");
+
+    assert_eq!(
+        keylog::parse("12 x6E\n").unwrap_err(),
+        "not a key log (expected `lc3-keys 1` first)"
+    );
+    assert_eq!(
+        keylog::parse("lc3-keys 1\n12 n\n").unwrap_err(),
+        "line 2: invalid key: 12 n"
+    );
+    assert_eq!(
+        keylog::parse("lc3-keys 1\n12 x6E\n11 x6E\n").unwrap_err(),
+        "line 3: instruction counts must not decrease"
+    );
+}
+
+#[test]
+fn test_record_and_replay_flags() {
+    let dir = common::temp_dir("keylog-flags");
+    let log = dir.join("keys.log");
+
+    let recorded = cargo_bin_cmd!("lc3-vm-rust")
+        .args(["--input-string", "n\\nwasd", "--record"])
+        .arg(&log)
+        .arg("assets/2048.obj")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Wrote key log to"))
+        .get_output()
+        .stdout
+        .clone();
+    let text = std::fs::read_to_string(&log).unwrap();
+    assert!(text.starts_with("lc3-keys 1\n"));
+    assert!(text.contains(" x6E  # n\n"));
+    assert_eq!(keylog::parse(&text).unwrap().len(), 6);
+
+    // Replays stop where the log does, like scripted input that ran out.
+    for engine in ["--blocks", "--micro"] {
+        cargo_bin_cmd!("lc3-vm-rust")
+            .args(["--on-input-end", "halt", engine, "--replay"])
+            .arg(&log)
+            .arg("assets/2048.obj")
+            .assert()
+            .success()
+            .stdout(recorded.clone());
+    }
+
+    std::fs::write(&log, "lc3-keys 1\n3 x6E\n").unwrap();
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args(["--on-input-end", "halt", "--replay"])
+        .arg(&log)
+        .arg("assets/2048.obj")
+        .assert()
+        .code(1)
+        .stdout(predicate::str::contains("replay diverged"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
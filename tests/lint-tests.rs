@@ -0,0 +1,144 @@
+use assert_cmd::cargo::cargo_bin_cmd;
+use lc3_vm_rust::asm;
+use lc3_vm_rust::cfg::Cfg;
+use lc3_vm_rust::debuginfo::DebugInfo;
+use lc3_vm_rust::lint::{Check, lint};
+use predicates::prelude::*;
+
+mod common;
+
+const BUGGY: &str = "
+        .ORIG x3000
+        LD R1, COUNT
+        ADD R2, R2, R1
+        JSR PRINT
+        LD R0, PRINT
+        BRnzp SKIP
+        ADD R1, R1, #1
+        ADD R1, R1, #2
+SKIP    ADD R1, R1, #-1
+        BRp SKIP
+        HALT
+PRINT   LEA R0, MSG
+        PUTS
+        RET
+COUNT   .FILL #3
+MSG     .STRINGZ \"hi\"
+        .END
+";
+
+const CLEAN: &str = "
+        .ORIG x3000
+        LD R6, STACK
+        JSR PRINT
+        HALT
+PRINT   STR R7, R6, #-1
+        ADD R6, R6, #-1
+        AND R0, R0, #0
+        LEA R0, MSG
+        PUTS
+        LDR R7, R6, #0
+        ADD R6, R6, #1
+        RET
+STACK   .FILL xFE00
+MSG     .STRINGZ \"ok\"
+        .END
+";
+
+fn check(source: &str, with_source: bool) -> Vec<(u16, Check, String)> {
+    let program = asm::assemble(source).unwrap();
+    let cfg = Cfg::build(program.origin, &program.words, program.origin);
+    let debug = if with_source {
+        DebugInfo::from_assembly(&program, None)
+    } else {
+        DebugInfo::default()
+    };
+    lint(&cfg, &debug)
+        .into_iter()
+        .map(|d| (d.addr, d.check, d.message))
+        .collect()
+}
+
+#[test]
+fn test_lint_finds_each_mistake() {
+    let found = check(BUGGY, true);
+    let checks: Vec<(u16, Check)> = found.iter().map(|(a, c, _)| (*a, *c)).collect();
+    assert_eq!(
+        checks,
+        [
+            (0x3001, Check::UninitializedRegister),
+            (0x3003, Check::OffsetIntoCode),
+            (0x3005, Check::UnreachableCode),
+            (0x300C, Check::ClobberedReturnAddress),
+        ]
+    );
+    assert_eq!(
+        found[0].2,
+        "ADD R2, R2, R1 reads R2, which no path from the entry point writes"
+    );
+    assert_eq!(
+        found[1].2,
+        "LD R0, PRINT reads the instruction at x300A (PRINT); data was likely intended"
+    );
+    assert_eq!(found[2].2, "2 unreachable instructions");
+    assert_eq!(
+        found[3].2,
+        "RET uses R7 after PUTS at x300B (PRINT+1) overwrote the return address"
+    );
+}
+
+#[test]
+fn test_lint_reports_missing_halt() {
+    let source = ".ORIG x3000\nAND R0, R0, #0\nADD R0, R0, #1\nDATA .FILL #5\n.END";
+    let found = check(source, true);
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].0, 0x3001);
+    assert_eq!(found[0].1, Check::MissingHalt);
+    assert_eq!(found[0].2, "execution runs into data at x3002 (DATA)");
+
+    // Without the source the data looks like code, until the image ends.
+    let found = check(".ORIG x3000\nAND R0, R0, #0\nADD R0, R0, #1\n.END", false);
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].1, Check::MissingHalt);
+    assert_eq!(found[0].2, "execution runs past the end of the program");
+}
+
+#[test]
+fn test_lint_accepts_correct_programs() {
+    assert!(check(CLEAN, true).is_empty());
+    assert!(check(CLEAN, false).is_empty());
+}
+
+#[test]
+fn test_lint_entry_outside_the_image() {
+    let program = asm::assemble(CLEAN).unwrap();
+    let cfg = Cfg::build(program.origin, &program.words, 0x0000);
+    assert!(cfg.blocks.is_empty());
+    // No code is reachable from it, so there is nothing to check.
+    assert!(lint(&cfg, &DebugInfo::default()).is_empty());
+}
+
+#[test]
+fn test_lint_command() {
+    let dir = common::temp_dir("lint");
+    let path = dir.join("buggy.asm");
+    std::fs::write(&path, BUGGY).unwrap();
+    cargo_bin_cmd!("lc3-vm-rust")
+        .arg("lint")
+        .arg(&path)
+        .assert()
+        .code(1)
+        .stdout(predicate::str::contains(format!(
+            "{}:4: ADD R2, R2, R1 reads R2",
+            path.display()
+        )))
+        .stdout(predicate::str::contains("[clobbered-return-address]"))
+        .stderr(predicate::str::contains("4 warning(s)"));
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args(["lint", "assets/hello.asm"])
+        .assert()
+        .success()
+        .stdout("");
+}
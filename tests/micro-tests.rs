@@ -0,0 +1,166 @@
+use assert_cmd::cargo::cargo_bin_cmd;
+use lc3_vm_rust::console::SharedConsole;
+use lc3_vm_rust::debuginfo::DebugInfo;
+use lc3_vm_rust::micro::{Datapath, Microarchitecture};
+use lc3_vm_rust::timing::CycleModel;
+use lc3_vm_rust::tui::Tui;
+use lc3_vm_rust::vm::{Register, Vm};
+
+mod common;
+
+const PROGRAM: &str = "
+        .ORIG x3000
+        LEA R0, MSG
+        PUTS
+        LDI R1, PTR
+        ADD R1, R1, #-1
+        STI R1, PTR
+        LEA R2, SUB
+        JSRR R2
+        BRp DONE
+        NOT R3, R1
+DONE    HALT
+SUB     AND R4, R4, #0
+        RET
+MSG     .STRINGZ \"ok\"
+PTR     .FILL DATA
+DATA    .FILL #1
+        .END
+";
+
+fn load(source: &str, keys: &[u8]) -> (Vm, SharedConsole) {
+    let (mut lc3, console) = common::load(source, keys);
+    lc3.set_cycle_model(Some(CycleModel::new(3)));
+    (lc3, console)
+}
+
+/// Runs to a halt or error with either engine, returning the result and instruction count.
+fn run(lc3: &mut Vm, mut micro: Option<&mut Microarchitecture>) -> (String, u64) {
+    let mut instructions = 0;
+    let result = loop {
+        let result = match micro.as_deref_mut() {
+            Some(micro) => micro.step(lc3),
+            None => lc3.step(),
+        };
+        match result {
+            Ok(step) if step.halted => break "halted".to_string(),
+            Ok(_) => instructions += 1,
+            Err(e) => break e.to_string(),
+        }
+    };
+    (result, instructions)
+}
+
+fn assert_same(load: &dyn Fn() -> (Vm, SharedConsole)) {
+    let (mut expected, expected_console) = load();
+    let expected_result = run(&mut expected, None);
+    let (mut actual, actual_console) = load();
+    let mut micro = Microarchitecture::new();
+    let actual_result = run(&mut actual, Some(&mut micro));
+
+    assert_eq!(actual_result, expected_result);
+    assert!(
+        actual.snapshot() == expected.snapshot(),
+        "machine state differs"
+    );
+    assert_eq!(actual_console.take_output(), expected_console.take_output());
+    assert_eq!(actual.cycles(), expected.cycles());
+    assert_eq!(micro.cycles(), expected.cycles());
+}
+
+#[test]
+fn test_microsteps_expose_datapath() {
+    let (mut lc3, _) = load(
+        ".ORIG x3000\nLDI R1, #1\nHALT\n.FILL x3003\n.FILL #-5\n.END",
+        b"",
+    );
+    let mut micro = Microarchitecture::new();
+    let mut states = Vec::new();
+    let step = loop {
+        let micro_step = micro.microstep(&mut lc3).unwrap();
+        states.push(micro_step.state);
+        if micro_step.state == 26 {
+            // MAR now holds the pointer read from x3002.
+            assert_eq!(micro_step.datapath.mar, 0x3003);
+        }
+        if let Some(step) = micro_step.completed {
+            break step;
+        }
+    };
+    assert_eq!(states, [18, 33, 35, 32, 10, 24, 26, 25, 27]);
+    assert_eq!(step.pc, 0x3000);
+    assert_eq!(
+        *micro.datapath(),
+        Datapath {
+            mar: 0x3003,
+            mdr: 0xFFFB,
+            ir: 0xA201,
+            ben: false,
+        }
+    );
+    assert_eq!(lc3.read_register(Register::R1), 0xFFFB);
+    assert_eq!(micro.state(), 18);
+    assert!(micro.at_instruction_boundary());
+    // Fetch and decode at 3 cycles per memory state, then 10, 24, 26, 25, 27.
+    assert_eq!(lc3.cycles(), 6 + 1 + 3 + 1 + 3 + 1);
+}
+
+#[test]
+fn test_micro_matches_interpreter() {
+    assert_same(&|| load(PROGRAM, b""));
+    assert_same(&|| {
+        let console = SharedConsole::new();
+        let mut lc3 = Vm::with_console(console.clone());
+        lc3.read_image_file("assets/rogue.obj").unwrap();
+        lc3.set_cycle_model(Some(CycleModel::default()));
+        console.push_input(b"ddddssssddddwwwwaaaassss");
+        (lc3, console)
+    });
+}
+
+#[test]
+fn test_micro_trace_flag_writes_states() {
+    let dir = common::temp_dir("micro");
+    let trace = dir.join("trace.txt");
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args(["--cycles", "--micro-trace"])
+        .arg(&trace)
+        .arg("assets/hello.obj")
+        .assert()
+        .stdout(predicates::str::contains("Hello World!"))
+        .stderr(predicates::str::contains("Cycles: 39"));
+
+    let text = std::fs::read_to_string(&trace).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    // LEA, PUTS and HALT.
+    assert_eq!(lines.len(), 5 + 7 + 7);
+    assert!(lines[0].contains("state 18"));
+    assert!(lines[0].contains("MAR=x3000"));
+    assert!(lines[2].contains("IR=xE002"));
+    assert!(lines[11].trim_start().starts_with("23  state 30"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_tui_microsteps_and_shows_datapath() {
+    let console = SharedConsole::new();
+    let mut lc3 = Vm::with_console(console.clone());
+    lc3.read_image_file("assets/hello.obj").unwrap();
+    let mut tui = Tui::new(lc3, console, DebugInfo::default()).with_micro(Microarchitecture::new());
+
+    for _ in 0..3 {
+        assert!(tui.microstep());
+    }
+    let text = tui.render(100, 30).lines.join("\n");
+    assert!(text.contains("Microstep: state 35"));
+    assert!(text.contains("MAR x3000  MDR xE002  IR xE002  BEN 0"));
+    assert!(text.contains("Next state 32"));
+
+    // The rest of the LEA.
+    assert!(tui.microstep());
+    assert!(tui.microstep());
+    let text = tui.render(100, 30).lines.join("\n");
+    assert!(text.contains("instruction complete"));
+    assert_eq!(tui.vm().read_register(Register::PC), 0x3001);
+    assert!(text.contains("Next state 18"));
+}
@@ -0,0 +1,275 @@
+use lc3_vm_rust::bench::Engine;
+use lc3_vm_rust::playthrough::Playthrough;
+use std::collections::VecDeque;
+use std::path::Path;
+
+type Board = [[u16; 4]; 4];
+
+// The 2048 board on screen: a tile row on every other line of the frame, each tile four
+// characters wide with two spaces between.
+fn board(screen: &str) -> Board {
+    let lines: Vec<&str> = screen.lines().collect();
+    assert_eq!(lines[0], "+--------------------------+", "{}", screen);
+    let mut board = [[0; 4]; 4];
+    for (r, row) in board.iter_mut().enumerate() {
+        let line = lines[2 + 2 * r];
+        for (c, tile) in row.iter_mut().enumerate() {
+            let text = line[3 + 6 * c..7 + 6 * c].trim();
+            *tile = if text.is_empty() {
+                0
+            } else {
+                text.parse().unwrap()
+            };
+        }
+    }
+    board
+}
+
+// Where a move takes the board before a new tile appears: every line is packed towards
+// the side pressed, and equal neighbours merge once.
+fn slide(board: &Board, key: u8) -> Board {
+    let cell = |line: usize, i: usize| match key {
+        b'w' => (i, line),
+        b's' => (3 - i, line),
+        b'a' => (line, i),
+        b'd' => (line, 3 - i),
+        _ => unreachable!(),
+    };
+    let mut next = [[0; 4]; 4];
+    for line in 0..4 {
+        let tiles: Vec<u16> = (0..4)
+            .map(|i| cell(line, i))
+            .map(|(r, c)| board[r][c])
+            .filter(|&tile| tile != 0)
+            .collect();
+        let mut packed = Vec::new();
+        let mut i = 0;
+        while i < tiles.len() {
+            if i + 1 < tiles.len() && tiles[i] == tiles[i + 1] {
+                packed.push(tiles[i] * 2);
+                i += 2;
+            } else {
+                packed.push(tiles[i]);
+                i += 1;
+            }
+        }
+        for (i, tile) in packed.into_iter().enumerate() {
+            let (r, c) = cell(line, i);
+            next[r][c] = tile;
+        }
+    }
+    next
+}
+
+// The cells where `after` has a tile that `before` lacks; everything else must match.
+fn new_tiles(before: &Board, after: &Board) -> Vec<u16> {
+    let mut tiles = Vec::new();
+    for r in 0..4 {
+        for c in 0..4 {
+            if before[r][c] == 0 && after[r][c] != 0 {
+                tiles.push(after[r][c]);
+            } else {
+                assert_eq!(before[r][c], after[r][c], "{:?} became {:?}", before, after);
+            }
+        }
+    }
+    tiles
+}
+
+#[test]
+fn test_2048_moves_and_merges_tiles() {
+    for &engine in Engine::ALL {
+        let mut game = Playthrough::load(Path::new("assets/2048.obj"), engine).unwrap();
+        assert!(
+            game.screen()
+                .ends_with("Are you on an ANSI terminal (y/n)?\n")
+        );
+        game.press(b"y").unwrap();
+        let mut current = board(&game.screen());
+        let start = new_tiles(&[[0; 4]; 4], &current);
+        assert_eq!(start.len(), 2, "{:?}", current);
+
+        let mut merged = false;
+        for &key in b"wasdwasdwwaassddwdsa" {
+            game.press(&[key]).unwrap();
+            let next = board(&game.screen());
+            let moved = slide(&current, key);
+            let spawned = new_tiles(&moved, &next);
+            if moved != current {
+                assert!(matches!(spawned[..], [2] | [4]), "{}", engine.name());
+            }
+            merged |= moved.iter().flatten().filter(|&&t| t != 0).count()
+                < current.iter().flatten().filter(|&&t| t != 0).count();
+            current = next;
+        }
+        assert!(merged);
+        assert!(!game.halted());
+    }
+}
+
+const WIDTH: usize = 32;
+const HEIGHT: usize = 16;
+// Where rogue keeps its maze, one word per cell, and the characters it draws them with.
+const MAZE: u16 = 0x3500;
+const TILES: &[u8] = b" #@KD";
+
+// The screen drops trailing spaces, so rows come back padded to the maze's width.
+fn maze(screen: &str) -> Vec<Vec<u8>> {
+    let rows: Vec<Vec<u8>> = screen
+        .lines()
+        .take(HEIGHT)
+        .map(|line| format!("{:<1$}", line, WIDTH).into_bytes())
+        .collect();
+    assert!(rows.iter().all(|row| row.len() == WIDTH), "{}", screen);
+    rows
+}
+
+fn find(maze: &[Vec<u8>], tile: u8) -> (usize, usize) {
+    (0..HEIGHT)
+        .flat_map(|y| (0..WIDTH).map(move |x| (x, y)))
+        .find(|&(x, y)| maze[y][x] == tile)
+        .unwrap()
+}
+
+// The maze the game holds in memory must be the one on screen.
+fn assert_maze_in_memory(game: &Playthrough, maze: &[Vec<u8>]) {
+    for (y, row) in maze.iter().enumerate() {
+        for (x, &tile) in row.iter().enumerate() {
+            let id = game.vm().peek_memory(MAZE + (y * WIDTH + x) as u16);
+            assert_eq!(TILES[id as usize], tile, "at {}, {}", x, y);
+        }
+    }
+}
+
+// The keys for a shortest walk from the player to the door. Moves wrap around the edges.
+fn solve(maze: &[Vec<u8>]) -> Vec<u8> {
+    let start = find(maze, b'@');
+    let mut came_from = vec![vec![None; WIDTH]; HEIGHT];
+    let mut queue = VecDeque::from([start]);
+    while let Some((x, y)) = queue.pop_front() {
+        if maze[y][x] == b'D' {
+            let mut keys = Vec::new();
+            let mut at = (x, y);
+            while at != start {
+                let (key, from) = came_from[at.1][at.0].unwrap();
+                keys.push(key);
+                at = from;
+            }
+            keys.reverse();
+            return keys;
+        }
+        for (key, nx, ny) in [
+            (b'w', x, (y + HEIGHT - 1) % HEIGHT),
+            (b's', x, (y + 1) % HEIGHT),
+            (b'a', (x + WIDTH - 1) % WIDTH, y),
+            (b'd', (x + 1) % WIDTH, y),
+        ] {
+            if matches!(maze[ny][nx], b' ' | b'D') && came_from[ny][nx].is_none() {
+                came_from[ny][nx] = Some((key, (x, y)));
+                queue.push_back((nx, ny));
+            }
+        }
+    }
+    panic!("no way to the door");
+}
+
+#[test]
+fn test_rogue_reaches_the_door() {
+    for &engine in Engine::ALL {
+        let mut game = Playthrough::load(Path::new("assets/rogue.obj"), engine).unwrap();
+        assert!(game.screen().ends_with("Press any key..\n"));
+        game.press(b"x").unwrap();
+        let screen = game.screen();
+        let keys = solve(&maze(&screen));
+        assert!(keys.len() > WIDTH, "{:?}", keys);
+
+        let (last, walk) = keys.split_last().unwrap();
+        for &key in walk {
+            let (x, y) = find(&maze(&game.screen()), b'@');
+            game.press(&[key]).unwrap();
+            let screen = game.screen();
+            let maze = maze(&screen);
+            let expected = match key {
+                b'w' => (x, (y + HEIGHT - 1) % HEIGHT),
+                b's' => (x, (y + 1) % HEIGHT),
+                b'a' => ((x + WIDTH - 1) % WIDTH, y),
+                _ => ((x + 1) % WIDTH, y),
+            };
+            assert_eq!(find(&maze, b'@'), expected, "{}", engine.name());
+            assert_maze_in_memory(&game, &maze);
+        }
+        game.press(&[*last]).unwrap();
+        assert!(
+            game.screen().contains("You survived!\n"),
+            "{}",
+            engine.name()
+        );
+
+        game.press(b"n").unwrap();
+        assert!(game.halted());
+        assert!(game.output().ends_with(b"HALT\n"));
+    }
+}
+
+#[test]
+fn test_rogue_screen_matches_snapshot() {
+    // The maze comes from a fixed seed, so the first screen never changes.
+    let expected = std::fs::read_to_string("tests/screens/rogue.txt").unwrap();
+    for &engine in Engine::ALL {
+        let mut game = Playthrough::load(Path::new("assets/rogue.obj"), engine).unwrap();
+        game.press(b"x").unwrap();
+        assert_eq!(game.screen(), expected, "{}", engine.name());
+        assert_eq!(game.terminal().cursor(), (HEIGHT + 1, 0));
+    }
+}
+
+#[test]
+fn test_rogue_walls_stop_the_player() {
+    let mut game = Playthrough::load(Path::new("assets/rogue.obj"), Engine::Cache).unwrap();
+    game.press(b"x").unwrap();
+    let before = game.screen();
+    let (x, y) = find(&maze(&before), b'@');
+    let wall = [
+        (b'w', x, (y + HEIGHT - 1) % HEIGHT),
+        (b's', x, (y + 1) % HEIGHT),
+        (b'a', (x + WIDTH - 1) % WIDTH, y),
+        (b'd', (x + 1) % WIDTH, y),
+    ]
+    .into_iter()
+    .find(|&(_, x, y)| maze(&before)[y][x] == b'#')
+    .unwrap();
+
+    // Bumping into a wall redraws the same maze; other keys are ignored.
+    let drawn = game.output().len();
+    game.press(&[wall.0]).unwrap();
+    assert!(game.output().len() > drawn);
+    assert_eq!(game.screen(), before);
+    assert_maze_in_memory(&game, &maze(&before));
+    let drawn = game.output().len();
+    game.press(b"q").unwrap();
+    assert_eq!(game.output().len(), drawn);
+}
+
+#[test]
+fn test_press_reports_what_went_wrong() {
+    let mut hello = Playthrough::load(Path::new("assets/hello.obj"), Engine::Decode).unwrap();
+    assert!(hello.halted());
+    assert_eq!(hello.screen(), "Hello World!\nHALT\n");
+    assert_eq!(hello.press(b"x").unwrap_err(), "the program has halted");
+
+    // BRnzp #-1
+    let mut spin = Playthrough::new(0x3000, &[0x0FFF], Engine::Blocks);
+    spin.set_max_instructions(1000);
+    assert_eq!(
+        spin.press(b"").unwrap_err(),
+        "still running after 1000 instructions"
+    );
+
+    // HALT without reading anything.
+    let mut halt = Playthrough::new(0x3000, &[0xF025], Engine::Decode);
+    assert_eq!(
+        halt.press(b"ab").unwrap_err(),
+        "the program halted with 2 key(s) unread"
+    );
+    assert!(halt.halted());
+}
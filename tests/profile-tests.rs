@@ -0,0 +1,107 @@
+use assert_cmd::cargo::cargo_bin_cmd;
+use lc3_vm_rust::asm;
+use lc3_vm_rust::debuginfo::DebugInfo;
+use lc3_vm_rust::profile::{Profiler, SubroutineCost};
+use lc3_vm_rust::vm::{Opcode, Register, Vm};
+use predicates::prelude::*;
+
+mod common;
+
+const PROGRAM: &str = "
+        .ORIG x3000
+MAIN    JSR WORK
+        JSR LEAF
+        HALT
+WORK    ST R7, SAVE
+        AND R1, R1, #0
+        ADD R1, R1, #3
+LOOP    JSR LEAF
+        ADD R1, R1, #-1
+        BRp LOOP
+        LD R7, SAVE
+        RET
+LEAF    ADD R0, R0, #1
+        RET
+SAVE    .BLKW 1
+        .END
+";
+
+fn profile() -> (Profiler, Vm, DebugInfo) {
+    let assembly = asm::assemble(PROGRAM).unwrap();
+    let mut lc3 = Vm::new();
+    lc3.load_program(assembly.origin, &assembly.words);
+    let mut profiler = Profiler::new(lc3.read_register(Register::PC));
+    loop {
+        let step = lc3.step().unwrap();
+        profiler.observe(&step, &lc3);
+        if step.halted {
+            break;
+        }
+    }
+    (profiler, lc3, DebugInfo::from_assembly(&assembly, None))
+}
+
+#[test]
+fn test_profiler_counts_and_attributes_to_subroutines() {
+    let (profiler, lc3, debug) = profile();
+    let leaf = debug.address_of("LEAF").unwrap();
+    let work = debug.address_of("WORK").unwrap();
+
+    // MAIN: 3, WORK: 3 + 3 * 3 + 2, LEAF: 4 calls * 2.
+    assert_eq!(profiler.total(), 25);
+    assert_eq!(profiler.count_at(leaf), 4);
+    assert_eq!(profiler.opcode_count(Opcode::Jsr), 5);
+    assert_eq!(profiler.hot_spots()[0], (leaf, 4));
+    assert_eq!(
+        profiler.subroutines(),
+        [
+            SubroutineCost {
+                entry: 0x3000,
+                self_count: 3,
+                total: 25,
+            },
+            SubroutineCost {
+                entry: work,
+                self_count: 14,
+                total: 20,
+            },
+            SubroutineCost {
+                entry: leaf,
+                self_count: 8,
+                total: 8,
+            },
+        ]
+    );
+    assert_eq!(
+        profiler.folded(&debug),
+        "MAIN 3\nMAIN;LEAF 2\nMAIN;WORK 14\nMAIN;WORK;LEAF 6\n"
+    );
+
+    let report = profiler.report(&lc3, &debug);
+    assert!(report.contains("Instructions executed: 25"));
+    assert!(report.contains("LEAF             ADD R0, R0, #1"));
+}
+
+#[test]
+fn test_profile_flags_write_report_and_folded_stacks() {
+    let dir = common::temp_dir("profile");
+    let report = dir.join("report.txt");
+    let folded = dir.join("stacks.folded");
+
+    cargo_bin_cmd!("lc3-vm-rust")
+        .arg("--profile")
+        .arg(&report)
+        .arg("--folded")
+        .arg(&folded)
+        .arg("assets/hello.obj")
+        .assert()
+        .stdout(predicate::str::contains("Hello World!"))
+        .stderr(predicate::str::contains("Wrote profile to"));
+
+    let text = std::fs::read_to_string(&report).unwrap();
+    assert!(text.contains("Instructions executed: 3"));
+    assert!(text.contains("x3001 x3001            PUTS"));
+    assert_eq!(std::fs::read_to_string(&folded).unwrap(), "x3000 3\n");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
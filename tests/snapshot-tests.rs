@@ -0,0 +1,103 @@
+use assert_cmd::cargo::cargo_bin_cmd;
+use lc3_vm_rust::console::SharedConsole;
+use lc3_vm_rust::snapshot::Snapshot;
+use lc3_vm_rust::vm::{Register, Vm, VmError};
+use predicates::prelude::*;
+
+fn temp_path(name: &str) -> String {
+    std::env::temp_dir()
+        .join(format!("lc3-vm-rust-{}-{}", std::process::id(), name))
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn run_until_input(lc3: &mut Vm) {
+    loop {
+        match lc3.step() {
+            Ok(step) => assert!(!step.halted),
+            Err(VmError::WaitingForInput) => return,
+            Err(e) => panic!("{}", e),
+        }
+    }
+}
+
+#[test]
+fn test_snapshot_round_trip_resumes_identically() {
+    let console = SharedConsole::new();
+    let mut original = Vm::with_console(console.clone());
+    original.read_image_file("assets/rogue.obj").unwrap();
+    run_until_input(&mut original);
+    console.push_input(b" dd");
+
+    let mut bytes = Vec::new();
+    original.snapshot().write_to(&mut bytes).unwrap();
+    let snapshot = Snapshot::read_from(&mut bytes.as_slice()).unwrap();
+    assert_eq!(snapshot.pending_input, b" dd");
+    assert_eq!(snapshot.registers[Register::PC as usize], 0x3002);
+
+    let restored_console = SharedConsole::new();
+    let mut restored = Vm::with_console(restored_console.clone());
+    restored.restore(&snapshot);
+    assert_eq!(restored.snapshot(), original.snapshot());
+
+    console.take_output();
+    run_until_input(&mut original);
+    run_until_input(&mut restored);
+    assert_eq!(restored_console.take_output(), console.take_output());
+    assert_eq!(restored.snapshot(), original.snapshot());
+}
+
+#[test]
+fn test_snapshot_rejects_unknown_version() {
+    let mut lc3 = Vm::new();
+    let mut bytes = Vec::new();
+    lc3.snapshot().write_to(&mut bytes).unwrap();
+    bytes[5] = 99;
+
+    let error = Snapshot::read_from(&mut bytes.as_slice()).unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .contains("Unsupported snapshot version 99")
+    );
+}
+
+#[test]
+fn test_snapshot_rejects_truncated_input() {
+    let mut lc3 = Vm::new();
+    let mut bytes = Vec::new();
+    lc3.snapshot().write_to(&mut bytes).unwrap();
+    // A pending input length far past the end of the file.
+    let len = bytes.len() - 4;
+    bytes[len..].copy_from_slice(&u32::MAX.to_be_bytes());
+    bytes.extend_from_slice(b"abc");
+
+    let error = Snapshot::read_from(&mut bytes.as_slice()).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Snapshot is truncated: 3 of 4294967295 bytes of pending input"
+    );
+}
+
+#[test]
+fn test_cli_restores_and_saves_state() {
+    let mut lc3 = Vm::with_console(SharedConsole::new());
+    lc3.read_image_file("assets/hello.obj").unwrap();
+    lc3.step().unwrap();
+    let before = temp_path("before.lc3s");
+    lc3.snapshot().save(&before).unwrap();
+
+    let after = temp_path("after.lc3s");
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args(["--restore", &before, "--save-state", &after])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Hello World!"));
+
+    let saved = Snapshot::load(&after).unwrap();
+    assert_eq!(saved.registers[Register::PC as usize], 0x3003);
+    assert_eq!(saved.registers[Register::R0 as usize], 0x3003);
+
+    std::fs::remove_file(before).unwrap();
+    std::fs::remove_file(after).unwrap();
+}
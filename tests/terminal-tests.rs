@@ -0,0 +1,137 @@
+use assert_cmd::cargo::cargo_bin_cmd;
+use lc3_vm_rust::console::{Console, OnExhausted, SharedConsole};
+use lc3_vm_rust::terminal::{CapturingConsole, Terminal};
+use predicates::prelude::*;
+use std::sync::{Arc, Mutex};
+
+mod common;
+
+fn screen(width: usize, height: usize, output: &[u8]) -> Terminal {
+    let mut terminal = Terminal::new(width, height);
+    terminal.feed(output);
+    terminal
+}
+
+#[test]
+fn test_text_and_newlines() {
+    let terminal = screen(10, 4, b"one\ntwo  \n\nfour\r4");
+    assert_eq!(terminal.text(), "one\ntwo\n\n4our\n");
+    assert_eq!(terminal.lines(), ["one", "two", "", "4our"]);
+    assert_eq!(terminal.cursor(), (3, 1));
+    assert_eq!(terminal.size(), (10, 4));
+    assert_eq!(Terminal::default().size(), (80, 24));
+    assert_eq!(Terminal::default().text(), "");
+}
+
+#[test]
+fn test_wrapping_and_scrolling() {
+    // The last column fills before the line wraps.
+    let terminal = screen(4, 2, b"abcd");
+    assert_eq!(terminal.text(), "abcd\n");
+    assert_eq!(terminal.cursor(), (0, 3));
+
+    let terminal = screen(4, 2, b"abcdef\nxy\nz");
+    assert_eq!(terminal.lines(), ["xy", "z"]);
+
+    let terminal = screen(4, 3, b"a\nb\nc\x1b[1S");
+    assert_eq!(terminal.lines(), ["b", "c", ""]);
+    let terminal = screen(4, 3, b"a\nb\nc\x1b[2T");
+    assert_eq!(terminal.lines(), ["", "", "a"]);
+}
+
+#[test]
+fn test_cursor_movement() {
+    let terminal = screen(10, 5, b"\x1b[3;4Hx\x1b[Ay\x1b[2Bz\x1b[5Dw\x1b[Cv");
+    assert_eq!(terminal.lines(), ["", "    y", "   x", " w v z", ""]);
+
+    // Positions are clamped to the screen.
+    let terminal = screen(10, 5, b"\x1b[99;99Ha\x1b[Hb\x1b[99Ac\x1b[99Dd");
+    assert_eq!(terminal.lines(), ["dc", "", "", "", "         a"]);
+
+    let terminal = screen(
+        10,
+        5,
+        b"abc\x1b[2Ed\x1b[Fe\x1b[7Gf\x1b[4dg\x1b[2;2fh\tT\x08t",
+    );
+    assert_eq!(terminal.lines(), ["abc", "eh    f t", "d", "       g", ""]);
+
+    let terminal = screen(10, 3, b"ab\x1b[sxx\nyy\x1b[uc\x1b7\x1b[3;1Hz\x1b8d");
+    assert_eq!(terminal.lines(), ["abcd", "yy", "z"]);
+}
+
+#[test]
+fn test_erasing() {
+    let full = b"aaaa\nbbbb\ncccc\x1b[2;3H";
+    let erase = |sequence: &[u8]| {
+        let mut terminal = screen(4, 3, full);
+        terminal.feed(sequence);
+        terminal.lines()
+    };
+    assert_eq!(erase(b"\x1b[J"), ["aaaa", "bb", ""]);
+    assert_eq!(erase(b"\x1b[1J"), ["", "   b", "cccc"]);
+    assert_eq!(erase(b"\x1b[2J"), ["", "", ""]);
+    assert_eq!(erase(b"\x1b[3J"), ["aaaa", "bbbb", "cccc"]);
+    assert_eq!(erase(b"\x1b[K"), ["aaaa", "bb", "cccc"]);
+    assert_eq!(erase(b"\x1b[1K"), ["aaaa", "   b", "cccc"]);
+    assert_eq!(erase(b"\x1b[2K"), ["aaaa", "", "cccc"]);
+    assert_eq!(erase(b"\x1bc"), ["", "", ""]);
+}
+
+#[test]
+fn test_escape_sequences_split_across_writes() {
+    let mut terminal = Terminal::new(20, 3);
+    for part in [
+        &b"old\x1b["[..],
+        b"2",
+        b"J\x1b",
+        b"[H\x1b[3J| \x1b[1;3",
+        b"7m 4 \x1b[0m|",
+    ] {
+        terminal.feed(part);
+    }
+    // Colours and private modes do nothing to the text.
+    terminal.feed(b"\x1b[?25l");
+    assert_eq!(terminal.text(), "|  4 |\n");
+}
+
+#[test]
+fn test_capturing_console_passes_everything_through() {
+    let terminal = Arc::new(Mutex::new(Terminal::new(10, 2)));
+    let inner = SharedConsole::new();
+    inner.push_input(b"k");
+    inner.set_on_exhausted(OnExhausted::Error);
+    let mut console = CapturingConsole::new(inner.clone(), terminal.clone());
+    console.write(b"\x1b[2;3Hhi").unwrap();
+    assert_eq!(console.pending_input(), b"k");
+    assert_eq!(console.read_key().unwrap(), b'k');
+    assert_eq!(console.poll_key(), None);
+    assert_eq!(console.exhausted(), Some(OnExhausted::Error));
+    assert_eq!(inner.take_output(), b"\x1b[2;3Hhi");
+    assert_eq!(terminal.lock().unwrap().lines(), ["", "  hi"]);
+}
+
+#[test]
+fn test_screenshot_flag() {
+    let dir = common::temp_dir("terminal");
+    let path = dir.join("rogue.txt");
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args(["--input-string", "x", "--screenshot"])
+        .arg(&path)
+        .arg("assets/rogue.obj")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Wrote screenshot to"));
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        std::fs::read_to_string("tests/screens/rogue.txt").unwrap()
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args(["--tui", "--screenshot", "x.txt", "assets/rogue.obj"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains(
+            "--screenshot cannot be combined with --tui",
+        ));
+}
@@ -0,0 +1,113 @@
+use assert_cmd::cargo::cargo_bin_cmd;
+use lc3_vm_rust::asm;
+use lc3_vm_rust::blocks::BlockEngine;
+use lc3_vm_rust::console::SharedConsole;
+use lc3_vm_rust::instruction::Instruction;
+use lc3_vm_rust::timing::{CycleModel, FETCH_STATES, execute_states};
+use lc3_vm_rust::vm::{ConditionalFlag, Vm};
+use predicates::prelude::*;
+
+const LOOP: &str = "
+        .ORIG x3000
+        AND R1, R1, #0
+        ADD R1, R1, #3
+        LEA R2, DATA
+AGAIN   LDR R3, R2, #0
+        STI R3, PTR
+        ADD R1, R1, #-1
+        BRp AGAIN
+        HALT
+PTR     .FILL DATA
+DATA    .FILL #7
+        .END
+";
+
+fn word(source: &str) -> u16 {
+    asm::assemble(&format!(".ORIG x3000\n{}\n.END", source))
+        .unwrap()
+        .words[0]
+}
+
+#[test]
+fn test_instruction_cycles_follow_the_state_machine() {
+    let model = CycleModel::new(5);
+    let zero = ConditionalFlag::Zro as u16;
+    let cycles = |source: &str| model.instruction_cycles(Instruction::decode(word(source)), zero);
+
+    // Fetch is 18, 33 (memory), 35, 32.
+    assert_eq!(cycles("ADD R0, R0, #1"), 3 + 5 + 1);
+    assert_eq!(cycles("NOT R0, R0"), 9);
+    assert_eq!(cycles("LD R0, #0"), 8 + 2 + 5);
+    assert_eq!(cycles("LDI R0, #0"), 8 + 3 + 10);
+    assert_eq!(cycles("STR R0, R1, #0"), 8 + 2 + 5);
+    assert_eq!(cycles("STI R0, #0"), 8 + 3 + 10);
+    assert_eq!(cycles("JSR #0"), 10);
+    assert_eq!(cycles("HALT"), 8 + 2 + 5);
+    // BEN decides whether state 22 runs.
+    assert_eq!(cycles("BRz #0"), 10);
+    assert_eq!(cycles("BRp #0"), 9);
+
+    assert_eq!(FETCH_STATES, &[18, 33, 35, 32]);
+    let ldi = Instruction::decode(word("LDI R0, #0"));
+    assert_eq!(execute_states(ldi, false), &[10, 24, 26, 25, 27]);
+    assert_eq!(CycleModel::new(1).instruction_cycles(ldi, zero), 9);
+}
+
+fn run(engine: bool, model: CycleModel) -> u64 {
+    let program = asm::assemble(LOOP).unwrap();
+    let mut lc3 = Vm::with_console(SharedConsole::new());
+    lc3.load_program(program.origin, &program.words);
+    lc3.set_cycle_model(Some(model));
+    if engine {
+        assert!(BlockEngine::new().run(&mut lc3, 1000).unwrap().halted);
+    } else {
+        lc3.run().unwrap();
+    }
+    lc3.cycles()
+}
+
+#[test]
+fn test_vm_counts_cycles_per_program() {
+    // AND, ADD, LEA, then three passes of LDR, STI, ADD, BRp (taken twice), then HALT.
+    let expected = |l: u64| {
+        let fetch = 3 + l;
+        let body = (fetch + 2 + l) + (fetch + 3 + 2 * l) + (fetch + 1);
+        3 * (fetch + 1) + 3 * body + 2 * (fetch + 2) + (fetch + 1) + (fetch + 2 + l)
+    };
+    assert_eq!(run(false, CycleModel::default()), expected(5));
+    assert_eq!(run(false, CycleModel::new(1)), expected(1));
+    // The block engine leaves cycle counting to the interpreter.
+    assert_eq!(run(true, CycleModel::new(3)), expected(3));
+
+    let mut lc3 = Vm::new();
+    lc3.load_program(0x3000, &[word("ADD R0, R0, #1")]);
+    lc3.step().unwrap();
+    assert_eq!(lc3.cycles(), 0);
+}
+
+#[test]
+fn test_cycles_flag_reports_total() {
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args(["--cycles", "assets/hello.obj"])
+        .assert()
+        .stdout(predicate::str::contains("Hello World!"))
+        .stderr(predicate::str::contains("Cycles: 39"));
+
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args(["--memory-latency", "1", "assets/hello.obj"])
+        .assert()
+        .stderr(predicate::str::contains("Cycles: 19"));
+
+    // Block engines skip the cycle model, so they are refused rather than run uncounted.
+    for args in [&["--cycles"][..], &["--memory-latency", "1"]] {
+        cargo_bin_cmd!("lc3-vm-rust")
+            .args(args)
+            .args(["--blocks", "assets/hello.obj"])
+            .assert()
+            .code(2)
+            .stdout("")
+            .stderr(predicate::str::contains(
+                "--cycles and --memory-latency cannot be combined with --blocks or --jit",
+            ));
+    }
+}
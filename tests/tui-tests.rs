@@ -0,0 +1,58 @@
+use lc3_vm_rust::console::{Console, SharedConsole};
+use lc3_vm_rust::debuginfo::DebugInfo;
+use lc3_vm_rust::tui::Tui;
+use lc3_vm_rust::vm::{Register, Vm};
+
+#[test]
+fn test_tui_stops_at_breakpoint_and_renders_panes() {
+    let console = SharedConsole::new();
+    let mut lc3 = Vm::with_console(console.clone());
+    lc3.read_image_file("assets/hello.obj").unwrap();
+
+    let mut tui = Tui::new(lc3, console, DebugInfo::default());
+    tui.toggle_breakpoint(0x3002);
+    assert!(!tui.run_slice());
+    assert_eq!(tui.vm().read_register(Register::PC), 0x3002);
+
+    let frame = tui.render(100, 30);
+    assert_eq!(frame.lines.len(), 30);
+    assert!(frame.lines.iter().all(|l| l.chars().count() == 100));
+
+    let text = frame.lines.join("\n");
+    assert!(text.contains("Breakpoint at x3002"));
+    assert!(text.contains("*>x3002 F025"));
+    assert!(text.contains("HALT"));
+    assert!(text.contains("PC x3002  Cond --P"));
+    // The program's output is in the console pane, not on the terminal.
+    assert!(text.contains("Hello World!"));
+
+    let cursor_row = frame.cursor_row.unwrap();
+    assert!(frame.lines[cursor_row].contains("x3002"));
+}
+
+#[test]
+fn test_console_pane_shows_the_program_screen() {
+    let console = SharedConsole::new();
+    let mut lc3 = Vm::with_console(console.clone());
+    lc3.read_image_file("assets/hello.obj").unwrap();
+    // Drawn before the first step: the pane picks it up along with the program's output.
+    console
+        .clone()
+        .write(b"old screen\n\x1b[2J\x1b[H\x1b[3J###\n#@ D\x1b[1;2HX\x1b[3;1H")
+        .unwrap();
+
+    let mut tui = Tui::new(lc3, console, DebugInfo::default());
+    while tui.run_slice() {}
+    let frame = tui.render(100, 30);
+    let console_row = frame
+        .lines
+        .iter()
+        .position(|l| l.contains("Console"))
+        .unwrap();
+    let pane: Vec<&str> = frame.lines[console_row + 1..console_row + 5]
+        .iter()
+        .map(|l| l.split_once('|').unwrap().1.trim_end())
+        .collect();
+    assert_eq!(pane, [" #X#", " #@ D", " Hello World!", " HALT"]);
+    assert!(!frame.lines.join("\n").contains("old screen"));
+}
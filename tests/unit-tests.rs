@@ -0,0 +1,230 @@
+use assert_cmd::cargo::cargo_bin_cmd;
+use lc3_vm_rust::toml::{self, Value};
+use lc3_vm_rust::unit::{Suite, Word};
+use lc3_vm_rust::vm::Register;
+use predicates::prelude::*;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+fn table(entries: &[(&str, Value)]) -> Value {
+    Value::Table(
+        entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.clone()))
+            .collect::<BTreeMap<_, _>>(),
+    )
+}
+
+#[test]
+fn test_toml_subset() {
+    let doc = toml::parse(
+        "
+# comment
+title = 'literal \\n'
+[[test]]
+name = \"a\\tb\" # trailing comment
+expect.registers = { R0 = 0x10, R1 = -2 }
+list = [
+  1, 0b11, 1_000,
+]
+[test.more]
+flag = true
+[[test]]
+name = \"second\"
+",
+    )
+    .unwrap();
+    assert_eq!(
+        doc,
+        table(&[
+            ("title", Value::String("literal \\n".to_string())),
+            (
+                "test",
+                Value::Array(vec![
+                    table(&[
+                        ("name", Value::String("a\tb".to_string())),
+                        (
+                            "expect",
+                            table(&[(
+                                "registers",
+                                table(&[("R0", Value::Integer(16)), ("R1", Value::Integer(-2))]),
+                            )]),
+                        ),
+                        (
+                            "list",
+                            Value::Array(vec![
+                                Value::Integer(1),
+                                Value::Integer(3),
+                                Value::Integer(1000),
+                            ]),
+                        ),
+                        ("more", table(&[("flag", Value::Bool(true))])),
+                    ]),
+                    table(&[("name", Value::String("second".to_string()))]),
+                ]),
+            ),
+        ])
+    );
+
+    let error = toml::parse("a = 1\nb = 2.5\n").unwrap_err();
+    assert_eq!(error.to_string(), "line 2: Expected the end of the line");
+    let error = toml::parse("a = 1\n\na = 2").unwrap_err();
+    assert_eq!(error.to_string(), "line 3: Key a is defined twice");
+}
+
+#[test]
+fn test_unit_suite_passes() {
+    let suite = Suite::load(Path::new("tests/unit/strings.toml")).unwrap();
+    assert_eq!(suite.tests.len(), 4);
+    assert_eq!(
+        suite.tests[0].registers,
+        [
+            (Register::R0, Word::Label("BUFFER".to_string())),
+            (Register::R1, Word::Literal(0x1234))
+        ]
+    );
+    assert_eq!(suite.tests[0].memory[0].1, [104, 101, 108, 108, 111, 0]);
+    for result in suite.run_all() {
+        assert!(result.passed(), "{}: {:?}", result.name, result.failures);
+    }
+
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args(["unit", "tests/unit/strings.toml"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "PASS strlen of an empty string (9 instructions)",
+        ))
+        .stdout(predicate::str::contains("4 passed, 0 failed"));
+}
+
+#[test]
+fn test_unit_failures() {
+    let spec = r#"
+program = "strings.asm"
+max_instructions = 500
+
+[[test]]
+name = "wrong expectations"
+call = "STRLEN"
+registers = { R0 = "BUFFER", R1 = 7 }
+memory = { BUFFER = "abc" }
+expect.registers = { R0 = -1 }
+expect.memory = { x301F = [0x62, 0x64] }
+expect.cc = "n"
+expect.output = "?"
+expect.preserved = ["R1", "R2"]
+
+[[test]]
+name = "halts"
+call = x3000
+
+[[test]]
+call = "READKEY"
+registers = { R0 = "BUFFER" }
+
+[[test]]
+name = "loops"
+call = "SLOOP"
+registers = { R1 = "BUFFER" }
+memory = { BUFFER = "longer than ten instructions" }
+max_instructions = 10
+"#;
+    // The call needs a string; an address is spelled "x3000".
+    let error = Suite::from_spec(spec, Path::new("tests/unit"))
+        .err()
+        .unwrap();
+    assert_eq!(error, "line 18: Expected a value");
+
+    let spec = spec.replace("call = x3000", "call = \"x3000\"");
+    let suite = Suite::from_spec(&spec, Path::new("tests/unit")).unwrap();
+    let failures: Vec<(String, Vec<String>)> = suite
+        .run_all()
+        .into_iter()
+        .map(|r| (r.name, r.failures))
+        .collect();
+    assert_eq!(
+        failures,
+        [
+            (
+                "wrong expectations".to_string(),
+                vec![
+                    "R0 is x0003 (3), expected xFFFF (-1)".to_string(),
+                    "memory at BUFFER+2 is x0063 (99), expected x0064 (100)".to_string(),
+                    "condition codes are p, expected n".to_string(),
+                    "output is \"\", expected \"?\"".to_string(),
+                ]
+            ),
+            (
+                "halts".to_string(),
+                vec!["halted at x3000 instead of returning".to_string()]
+            ),
+            (
+                "test 3".to_string(),
+                vec!["the program read past the end of its input at READKEY+2".to_string()]
+            ),
+            (
+                "loops".to_string(),
+                vec!["did not return within 10 instructions".to_string()]
+            ),
+        ]
+    );
+
+    // Without a call the program runs from its origin; labels are looked up as tests run.
+    let suite = Suite::from_spec(
+        r#"
+program = "strings.asm"
+[[test]]
+name = "whole program"
+expect.output = "HALT\n"
+[[test]]
+name = "unknown label"
+call = "NOWHERE"
+[[test]]
+name = "println keeps its return address"
+call = "PRINTLN"
+registers = { R0 = "BUFFER", R7 = "x0000" }
+"#,
+        Path::new("tests/unit"),
+    )
+    .unwrap();
+    let results = suite.run_all();
+    assert!(results[0].passed());
+    assert_eq!(results[0].instructions, 1);
+    assert_eq!(results[1].failures, ["the program has no label NOWHERE"]);
+    // R7 is set for the call after the registers, so the return address is kept.
+    assert!(results[2].passed());
+
+    // Memory ranges that run past xFFFF wrap around to x0000.
+    let suite = Suite::from_spec(
+        r#"
+program = "strings.asm"
+[[test]]
+name = "wraps"
+memory = { xFFFF = [1, 2] }
+expect.memory = { xFFFF = [1, 2] }
+[[test]]
+name = "wraps and fails"
+expect.memory = { xFFFF = [1, 2] }
+"#,
+        Path::new("tests/unit"),
+    )
+    .unwrap();
+    let results = suite.run_all();
+    assert!(results[0].passed(), "{:?}", results[0].failures);
+    assert_eq!(
+        results[1].failures,
+        [
+            "memory at BUFFER+53217 is x0000 (0), expected x0001 (1)",
+            "memory at x0000 is x0000 (0), expected x0002 (2)",
+        ]
+    );
+
+    cargo_bin_cmd!("lc3-vm-rust")
+        .args(["unit", "tests/unit/missing.toml"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains(
+            "Could not read tests/unit/missing.toml",
+        ));
+}
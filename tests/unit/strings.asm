@@ -0,0 +1,41 @@
+; String subroutines for the unit test runner's own tests.
+        .ORIG x3000
+        HALT
+
+; R0 = length of the string at R0.
+STRLEN  ST R1, SAVE1
+        ADD R1, R0, #0
+        AND R0, R0, #0
+SLOOP   ST R0, COUNT
+        LDR R0, R1, #0
+        BRz SDONE
+        LD R0, COUNT
+        ADD R0, R0, #1
+        ADD R1, R1, #1
+        BRnzp SLOOP
+SDONE   LD R0, COUNT
+        LD R1, SAVE1
+        RET
+
+; Prints the string at R0 followed by a newline.
+PRINTLN ST R7, SAVE7
+        PUTS
+        AND R0, R0, #0
+        ADD R0, R0, #10
+        OUT
+        LD R7, SAVE7
+        RET
+
+; Reads a key and stores it in the first word of the buffer at R0.
+READKEY ST R7, SAVE7
+        ADD R1, R0, #0
+        GETC
+        STR R0, R1, #0
+        LD R7, SAVE7
+        RET
+
+SAVE1   .BLKW 1
+SAVE7   .BLKW 1
+COUNT   .BLKW 1
+BUFFER  .BLKW 8
+        .END